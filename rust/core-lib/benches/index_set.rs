@@ -0,0 +1,86 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![feature(test)]
+
+extern crate test;
+extern crate xi_core_lib;
+
+use test::Bencher;
+use xi_core_lib::index_set::remove_n_at;
+
+/// The previous implementation of `remove_n_at`, kept for comparison.
+fn remove_n_at_shifting<T: Clone>(v: &mut Vec<T>, index: usize, n: usize) {
+    if n == 1 {
+        v.remove(index);
+    } else if n > 1 {
+        let new_len = v.len() - n;
+        for i in index..new_len {
+            v[i] = v[i + n].clone();
+        }
+        v.truncate(new_len);
+    }
+}
+
+fn build_ranges(n: usize) -> Vec<(usize, usize)> {
+    (0..n).map(|i| (i * 2, i * 2 + 1)).collect()
+}
+
+fn bench_drain(b: &mut Bencher, size: usize) {
+    let v = build_ranges(size);
+    b.iter(|| {
+        let mut v = v.clone();
+        remove_n_at(&mut v, size / 4, size / 2);
+        v
+    });
+}
+
+fn bench_shifting(b: &mut Bencher, size: usize) {
+    let v = build_ranges(size);
+    b.iter(|| {
+        let mut v = v.clone();
+        remove_n_at_shifting(&mut v, size / 4, size / 2);
+        v
+    });
+}
+
+#[bench]
+fn remove_n_at_drain_10(b: &mut Bencher) {
+    bench_drain(b, 10);
+}
+
+#[bench]
+fn remove_n_at_drain_100(b: &mut Bencher) {
+    bench_drain(b, 100);
+}
+
+#[bench]
+fn remove_n_at_drain_1000(b: &mut Bencher) {
+    bench_drain(b, 1000);
+}
+
+#[bench]
+fn remove_n_at_shifting_10(b: &mut Bencher) {
+    bench_shifting(b, 10);
+}
+
+#[bench]
+fn remove_n_at_shifting_100(b: &mut Bencher) {
+    bench_shifting(b, 100);
+}
+
+#[bench]
+fn remove_n_at_shifting_1000(b: &mut Bencher) {
+    bench_shifting(b, 1000);
+}
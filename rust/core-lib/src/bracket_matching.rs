@@ -0,0 +1,250 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finding the bracket that matches the one at a given offset, and the
+//! brackets that enclose a range. The search is textual, but the caller can
+//! have brackets skipped; the scope-aware variants skip those that the
+//! syntax layers place in strings and comments.
+
+use xi_rope::rope::{Rope, RopeInfo};
+use xi_rope::tree::Cursor;
+
+use layers::Layers;
+
+/// The number of lines a search looks through for a bracket before giving
+/// up, so that a caret by an unbalanced bracket in a large buffer does not
+/// scan the rest of the buffer on every render.
+const MAX_SCAN_LINES: usize = 2_000;
+
+/// Returns the closing bracket for an opening bracket.
+fn closer_for(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        _ => None,
+    }
+}
+
+/// Returns the opening bracket for a closing bracket.
+fn opener_for(c: char) -> Option<char> {
+    match c {
+        ')' => Some('('),
+        ']' => Some('['),
+        '}' => Some('{'),
+        _ => None,
+    }
+}
+
+/// Like `find_matching_bracket`, skipping the brackets in strings and
+/// comments, as reported by the syntax plugins.
+pub fn scope_aware_bracket_match(rope: &Rope, layers: &Layers, offset: usize)
+                                 -> Option<(usize, usize)> {
+    find_matching_bracket(rope, offset, |ix| layers.is_string_or_comment(ix))
+}
+
+/// Like `find_enclosing_brackets`, skipping the brackets in strings and
+/// comments, as reported by the syntax plugins.
+pub fn scope_aware_enclosing_brackets(rope: &Rope, layers: &Layers, start: usize, end: usize)
+                                      -> Option<(usize, usize)> {
+    find_enclosing_brackets(rope, start, end, |ix| layers.is_string_or_comment(ix))
+}
+
+/// If the character starting at `offset` is a bracket, finds the bracket that
+/// matches it, scanning forward from an opening bracket or backward from a
+/// closing one. Brackets at offsets for which `skip` returns `true` are
+/// ignored, including the one at `offset`. Returns the offsets of the
+/// opening and closing brackets, in that order, or `None` if there is no
+/// bracket at `offset` or it is unbalanced, or its match is more than
+/// `MAX_SCAN_LINES` lines away.
+pub fn find_matching_bracket<F>(rope: &Rope, offset: usize, skip: F) -> Option<(usize, usize)>
+    where F: Fn(usize) -> bool
+{
+    if offset >= rope.len() {
+        return None;
+    }
+    let c = Cursor::new(rope, offset).next_codepoint();
+    match c {
+        Some(c) if closer_for(c).is_some() && !skip(offset) =>
+            scan(Cursor::new(rope, offset), true, &skip).map(|close| (offset, close)),
+        Some(c) if opener_for(c).is_some() && !skip(offset) =>
+            scan(Cursor::new(rope, offset + 1), false, &skip).map(|open| (open, offset)),
+        _ => None,
+    }
+}
+
+/// Finds the innermost pair of brackets enclosing the range from `start` to
+/// `end`, that is the closest opening bracket before `start` whose match is
+/// at or after `end`. Brackets at offsets for which `skip` returns `true`
+/// are ignored. Returns the offsets of the two brackets, or `None` if no
+/// balanced pair within `MAX_SCAN_LINES` lines of the range encloses it.
+pub fn find_enclosing_brackets<F>(rope: &Rope, start: usize, end: usize, skip: F)
+                                  -> Option<(usize, usize)>
+    where F: Fn(usize) -> bool
+{
+    let mut cursor = Cursor::new(rope, start);
+    let mut stack = Vec::new();
+    let mut lines = 0;
+    loop {
+        let c = cursor.prev_codepoint()?;
+        if c == '\n' {
+            lines += 1;
+            if lines > MAX_SCAN_LINES {
+                return None;
+            }
+        }
+        if (opener_for(c).is_some() || closer_for(c).is_some()) && skip(cursor.pos()) {
+            continue;
+        }
+        if let Some(open) = opener_for(c) {
+            stack.push(open);
+        } else if closer_for(c).is_some() {
+            match stack.pop() {
+                Some(open) if open != c => return None,
+                Some(_) => (),
+                None => match find_matching_bracket(rope, cursor.pos(), &skip) {
+                    Some((open, close)) if close >= end => return Some((open, close)),
+                    // the pair closes inside the range; look further out
+                    _ => (),
+                },
+            }
+        }
+    }
+}
+
+/// Scans from `cursor`, which is just before the starting bracket in the
+/// scan direction, for the bracket that balances it. A stack holds the
+/// bracket that ends each open pair; the scan stops early at the first
+/// mismatched bracket that is not skipped, or after `MAX_SCAN_LINES` lines.
+fn scan<F>(mut cursor: Cursor<RopeInfo>, forward: bool, skip: &F) -> Option<usize>
+    where F: Fn(usize) -> bool
+{
+    let (nest, unnest): (fn(char) -> Option<char>, fn(char) -> Option<char>) =
+        if forward { (closer_for, opener_for) } else { (opener_for, closer_for) };
+    let mut stack = Vec::new();
+    let mut lines = 0;
+    loop {
+        let c = if forward { cursor.next_codepoint() } else { cursor.prev_codepoint() }?;
+        if c == '\n' {
+            lines += 1;
+            if lines > MAX_SCAN_LINES {
+                return None;
+            }
+        }
+        // brackets are all one byte long
+        let pos = if forward { cursor.pos() - 1 } else { cursor.pos() };
+        if (nest(c).is_some() || unnest(c).is_some()) && skip(pos) {
+            continue;
+        }
+        if let Some(end) = nest(c) {
+            stack.push(end);
+        } else if unnest(c).is_some() && stack.pop() != Some(c) {
+            return None;
+        }
+        if stack.is_empty() {
+            return Some(pos);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::interval::Interval;
+    use xi_rope::spans::SpansBuilder;
+    use plugins::PluginPid;
+    use styles::ThemeStyleMap;
+
+    #[test]
+    fn matching_brackets() {
+        let text = Rope::from("fn a(b: [u8]) { c(); }");
+        assert_eq!(find_matching_bracket(&text, 4, |_| false), Some((4, 12)));
+        assert_eq!(find_matching_bracket(&text, 12, |_| false), Some((4, 12)));
+        assert_eq!(find_matching_bracket(&text, 8, |_| false), Some((8, 11)));
+        assert_eq!(find_matching_bracket(&text, 14, |_| false), Some((14, 21)));
+        assert_eq!(find_matching_bracket(&text, 21, |_| false), Some((14, 21)));
+        assert_eq!(find_matching_bracket(&text, 0, |_| false), None);
+        assert_eq!(find_matching_bracket(&text, text.len(), |_| false), None);
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        let text = Rope::from("(a[b)] {");
+        assert_eq!(find_matching_bracket(&text, 0, |_| false), None);
+        assert_eq!(find_matching_bracket(&text, 5, |_| false), None);
+        assert_eq!(find_matching_bracket(&text, 7, |_| false), None);
+    }
+
+    #[test]
+    fn enclosing_brackets() {
+        let text = Rope::from("fn a(b: [u8]) { c(); }");
+        assert_eq!(find_enclosing_brackets(&text, 10, 10, |_| false), Some((8, 11)));
+        assert_eq!(find_enclosing_brackets(&text, 9, 11, |_| false), Some((8, 11)));
+        assert_eq!(find_enclosing_brackets(&text, 8, 12, |_| false), Some((4, 12)));
+        assert_eq!(find_enclosing_brackets(&text, 7, 12, |_| false), Some((4, 12)));
+        assert_eq!(find_enclosing_brackets(&text, 18, 18, |_| false), Some((17, 18)));
+        assert_eq!(find_enclosing_brackets(&text, 16, 19, |_| false), Some((14, 21)));
+        assert_eq!(find_enclosing_brackets(&text, 2, 2, |_| false), None);
+        assert_eq!(find_enclosing_brackets(&text, 10, 16, |_| false), None);
+        let unbalanced = Rope::from("(a]b");
+        assert_eq!(find_enclosing_brackets(&unbalanced, 3, 3, |_| false), None);
+    }
+
+    #[test]
+    fn distant_brackets() {
+        let near = format!("({})", "\n".repeat(MAX_SCAN_LINES));
+        let text = Rope::from(near.as_str());
+        assert_eq!(find_matching_bracket(&text, 0, |_| false), Some((0, text.len() - 1)));
+        assert_eq!(find_matching_bracket(&text, text.len() - 1, |_| false),
+                   Some((0, text.len() - 1)));
+        assert_eq!(find_enclosing_brackets(&text, 1, 1, |_| false), Some((0, text.len() - 1)));
+
+        let far = format!("({})", "\n".repeat(MAX_SCAN_LINES + 1));
+        let text = Rope::from(far.as_str());
+        assert_eq!(find_matching_bracket(&text, 0, |_| false), None);
+        assert_eq!(find_matching_bracket(&text, text.len() - 1, |_| false), None);
+        assert_eq!(find_enclosing_brackets(&text, text.len() - 1, text.len() - 1, |_| false),
+                   None);
+    }
+
+    #[test]
+    fn brackets_in_strings_and_comments() {
+        let text = Rope::from("f(\"(\", x) // )");
+        let mut layers = Layers::default();
+        let style_map = ThemeStyleMap::new(None);
+        let scopes = vec![vec!["source.rust".to_string()],
+                          vec!["source.rust".to_string(), "string.quoted.double.rust".to_string()],
+                          vec!["source.rust".to_string(), "comment.line.double-slash.rust".to_string()]];
+        layers.add_scopes(PluginPid(1), scopes, &style_map);
+        let mut sb = SpansBuilder::new(text.len());
+        sb.add_span(Interval::new_closed_open(0, 2), 0);
+        sb.add_span(Interval::new_closed_open(2, 5), 1);
+        sb.add_span(Interval::new_closed_open(5, 10), 0);
+        sb.add_span(Interval::new_closed_open(10, text.len()), 2);
+        layers.update_layer(PluginPid(1), Interval::new_closed_closed(0, text.len()),
+                            sb.build());
+
+        assert!(!layers.is_string_or_comment(1));
+        assert!(layers.is_string_or_comment(3));
+        assert!(layers.is_string_or_comment(13));
+
+        assert_eq!(find_matching_bracket(&text, 1, |_| false), Some((1, 13)));
+        assert_eq!(scope_aware_bracket_match(&text, &layers, 1), Some((1, 8)));
+        assert_eq!(scope_aware_bracket_match(&text, &layers, 8), Some((1, 8)));
+        assert_eq!(scope_aware_bracket_match(&text, &layers, 3), None);
+        assert_eq!(scope_aware_bracket_match(&text, &layers, 13), None);
+        assert_eq!(scope_aware_enclosing_brackets(&text, &layers, 7, 7), Some((1, 8)));
+        assert_eq!(scope_aware_enclosing_brackets(&text, &layers, 12, 12), None);
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Breadcrumbs: the chain of symbols enclosing the caret, from the root of
+//! the document down, such as a class and one of its methods. They are
+//! found as for the sticky header, and sent with view updates.
+
+use std::mem;
+use std::time::{Duration, Instant};
+
+use xi_rope::Rope;
+use xi_rope::interval::Interval;
+
+use sticky_header::{enclosing_lines, line_interval};
+
+/// The breadcrumbs are found again at most this often as the caret moves.
+pub const BREADCRUMBS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BreadcrumbItem {
+    /// The name of the symbol.
+    pub label: String,
+    /// The utf-8 offsets of the start and end of the name.
+    pub range: (usize, usize),
+}
+
+/// Returns the symbols enclosing `offset`, outermost first, including one
+/// defined on its line. `definition_name` is passed the interval of a line,
+/// and returns that of the name of what the line defines, if anything.
+pub fn find_breadcrumbs<F>(text: &Rope, offset: usize, tab_size: usize,
+                           definition_name: F) -> Vec<BreadcrumbItem>
+    where F: Fn(Interval) -> Option<Interval>
+{
+    let line = text.line_of_offset(offset);
+    let mut lines = enclosing_lines(text, line, tab_size);
+    lines.push(line);
+    lines.into_iter()
+        .filter_map(|line| definition_name(line_interval(text, line)))
+        .map(|name| BreadcrumbItem {
+            label: text.slice_to_cow(name.start()..name.end()).into_owned(),
+            range: (name.start(), name.end()),
+        })
+        .collect()
+}
+
+/// What the breadcrumbs are found for: the caret, the revision of the
+/// text, and that of its styles.
+pub type BreadcrumbsKey = (usize, u64, u64);
+
+/// The breadcrumbs of a view.
+#[derive(Debug, Default)]
+pub struct Breadcrumbs {
+    items: Vec<BreadcrumbItem>,
+    key: Option<BreadcrumbsKey>,
+    /// When they were last found.
+    updated: Option<Instant>,
+    /// Whether a timer is set to find them again.
+    scheduled: bool,
+}
+
+impl Breadcrumbs {
+    pub fn items(&self) -> &[BreadcrumbItem] {
+        &self.items
+    }
+
+    /// Returns `true` if the breadcrumbs were found for another key.
+    pub fn is_stale(&self, key: BreadcrumbsKey) -> bool {
+        self.key != Some(key)
+    }
+
+    /// Returns `None` if the breadcrumbs may be found again at `now`, or
+    /// when they may be if they were found too recently.
+    pub fn next_update(&self, now: Instant) -> Option<Instant> {
+        self.updated.map(|updated| updated + BREADCRUMBS_INTERVAL).filter(|&due| due > now)
+    }
+
+    /// Notes that a timer is set to find the breadcrumbs again. Returns
+    /// `false` if one already was.
+    pub fn schedule(&mut self) -> bool {
+        !mem::replace(&mut self.scheduled, true)
+    }
+
+    pub fn timer_fired(&mut self) {
+        self.scheduled = false;
+    }
+
+    /// Sets the breadcrumbs found for `key` at `now`. Returns `true` if
+    /// they changed.
+    pub fn set(&mut self, items: Vec<BreadcrumbItem>, key: BreadcrumbsKey, now: Instant)
+               -> bool {
+        self.key = Some(key);
+        self.updated = Some(now);
+        if items == self.items {
+            return false;
+        }
+        self.items = items;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breadcrumbs() {
+        let text = Rope::from("class A:\n    def f(self):\n        pass\n\n    x = 1\n");
+        // names follow `class ` and `def `
+        let definition_name = |iv: Interval| {
+            let line = text.slice_to_cow(iv.start()..iv.end());
+            let indent = line.len() - line.trim_left().len();
+            ["class ", "def "].iter()
+                .find(|keyword| line[indent..].starts_with(*keyword))
+                .map(|keyword| {
+                    let start = iv.start() + indent + keyword.len();
+                    let len = line[start - iv.start()..].find(&[':', '('][..]).unwrap();
+                    Interval::new_closed_open(start, start + len)
+                })
+        };
+        let labels = |offset| find_breadcrumbs(&text, offset, 4, definition_name).into_iter()
+            .map(|item| item.label)
+            .collect::<Vec<_>>();
+        assert_eq!(labels(0), vec!["A"]);
+        assert_eq!(labels(35), vec!["A", "f"]);
+        assert_eq!(labels(text.len() - 2), vec!["A"]);
+        assert_eq!(find_breadcrumbs(&text, 15, 4, definition_name)[1].range, (17, 18));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut breadcrumbs = Breadcrumbs::default();
+        let now = Instant::now();
+        assert!(breadcrumbs.is_stale((0, 0, 0)));
+        assert_eq!(breadcrumbs.next_update(now), None);
+        assert!(!breadcrumbs.set(Vec::new(), (0, 0, 0), now));
+        assert!(!breadcrumbs.is_stale((0, 0, 0)));
+        assert_eq!(breadcrumbs.next_update(now), Some(now + BREADCRUMBS_INTERVAL));
+        assert_eq!(breadcrumbs.next_update(now + BREADCRUMBS_INTERVAL), None);
+        assert!(breadcrumbs.schedule());
+        assert!(!breadcrumbs.schedule());
+        breadcrumbs.timer_fired();
+        assert!(breadcrumbs.schedule());
+    }
+}
@@ -14,18 +14,29 @@
 
 //! Requests and notifications from the core to front-ends.
 
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use serde_json::{self, Value};
-use xi_rpc::{self, RpcPeer};
+use xi_rpc::{self, RemoteError, RpcPeer};
 
-use tabs::ViewId;
-use config::Table;
+use tabs::{BufferId, ViewId};
+use config::{SettingError, Table};
 use styles::ThemeSettings;
-use plugins::rpc::ClientPluginInfo;
-use plugins::Command;
+use code_actions::{CodeAction, WorkspaceEdit};
+use commands::KeybindingConflict;
+use definition::DefinitionResult;
+use hover::HoverInfo;
+use minimap::MinimapData;
+use sticky_header::StickyLine;
+use rename::RenameRange;
+use plugins::rpc::{ClientPluginInfo, CompletionList};
+use plugins::{Command, PluginStatus};
+use workspace_symbols::SymbolInfo;
 
 /// An interface to the frontend.
+#[derive(Clone)]
 pub struct Client(RpcPeer);
 
 #[derive(Serialize, Deserialize)]
@@ -66,6 +77,29 @@ impl Client {
                                      }));
     }
 
+    /// Reports the invalid settings of a config that was rejected, loaded
+    /// from `path` if it came from a file.
+    pub fn config_error(&self, path: Option<&Path>, errors: &[SettingError]) {
+        self.0.send_rpc_notification("config_error",
+                                     &json!({
+                                         "path": path,
+                                         "errors": errors,
+                                     }));
+    }
+
+    pub fn file_path_changed(&self, view_id: ViewId, path: &Path) {
+        self.0.send_rpc_notification("file_path_changed",
+                                     &json!({
+                                         "view_id": view_id,
+                                         "path": path,
+                                     }));
+    }
+
+    pub fn file_changed_externally(&self, view_id: ViewId) {
+        self.0.send_rpc_notification("file_changed_externally",
+                                     &json!({ "view_id": view_id }));
+    }
+
     pub fn available_themes(&self, theme_names: Vec<String>) {
         self.0.send_rpc_notification("available_themes",
                                      &json!({"themes": theme_names}))
@@ -79,6 +113,42 @@ impl Client {
                                      }));
     }
 
+    /// Tells the client of the keys that the `keybindings` setting binds
+    /// to more than one command.
+    pub fn keybinding_conflicts(&self, conflicts: &[KeybindingConflict]) {
+        self.0.send_rpc_notification("keybinding_conflicts",
+                                     &json!({"conflicts": conflicts}))
+    }
+
+    /// Asks the client to confirm closing `view_id`, the last view of a
+    /// buffer with unsaved changes.
+    pub fn confirm_close(&self, view_id: ViewId, buffer_id: BufferId, path: Option<&Path>) {
+        self.0.send_rpc_notification("confirm_close",
+                                     &json!({
+                                         "view_id": view_id,
+                                         "buffer_id": buffer_id,
+                                         "path": path,
+                                     }));
+    }
+
+    /// Tells the client that the buffer at `path` was auto-saved, as of the
+    /// revision `rev`.
+    pub fn auto_saved(&self, path: &Path, rev: u64) {
+        self.0.send_rpc_notification("auto_saved",
+                                     &json!({
+                                         "path": path,
+                                         "revision": rev,
+                                     }));
+    }
+
+    pub fn auto_save_failed(&self, path: &Path, error: &str) {
+        self.0.send_rpc_notification("auto_save_failed",
+                                     &json!({
+                                         "path": path,
+                                         "error": error,
+                                     }));
+    }
+
     /// Notify the client that a plugin has started.
     pub fn plugin_started(&self, view_id: ViewId, plugin: &str) {
         self.0.send_rpc_notification("plugin_started",
@@ -101,6 +171,60 @@ impl Client {
                                      }));
     }
 
+    /// Notify the client of a change in the state of a plugin.
+    pub fn plugin_status(&self, plugin: &str, status: &PluginStatus, message: Option<&str>) {
+        self.0.send_rpc_notification("plugin_status",
+                                     &json!({
+                                         "plugin_id": plugin,
+                                         "status": status,
+                                         "message": message,
+                                     }));
+    }
+
+    /// Sends the results so far of the file search `request_id`; `done` is
+    /// `true` once every file in the workspace has been searched.
+    pub fn fuzzy_file_results(&self, request_id: usize, paths: &[PathBuf], done: bool) {
+        self.0.send_rpc_notification("fuzzy_file_results",
+                                     &json!({
+                                         "request_id": request_id,
+                                         "paths": paths,
+                                         "done": done,
+                                     }));
+    }
+
+    /// Sends the results of the workspace symbol search `request_id`.
+    pub fn workspace_symbols(&self, request_id: usize, symbols: &[SymbolInfo]) {
+        self.0.send_rpc_notification("workspace_symbols",
+                                     &json!({
+                                         "request_id": request_id,
+                                         "symbols": symbols,
+                                     }));
+    }
+
+    /// Sends a partial result of the plugin request `request_id`.
+    pub fn plugin_partial_result(&self, view_id: ViewId, request_id: usize, value: &Value) {
+        self.0.send_rpc_notification("plugin_partial_result",
+                                     &json!({
+                                         "view_id": view_id,
+                                         "request_id": request_id,
+                                         "value": value,
+                                     }));
+    }
+
+    /// Sends the final result of the plugin request `request_id`.
+    pub fn plugin_request_complete(&self, view_id: ViewId, request_id: usize,
+                                   result: &Result<Value, RemoteError>) {
+        let mut params = json!({
+            "view_id": view_id,
+            "request_id": request_id,
+        });
+        match *result {
+            Ok(ref value) => params["result"] = value.clone(),
+            Err(ref err) => params["error"] = json!(err),
+        }
+        self.0.send_rpc_notification("plugin_request_complete", &params);
+    }
+
     /// Notify the client of the available plugins.
     pub fn available_plugins(&self, view_id: ViewId,
                              plugins: &[ClientPluginInfo]) {
@@ -178,8 +302,9 @@ impl Client {
             {   "view_id": view_id, "key": key }));
     }
 
-    pub fn show_hover(&self, view_id: ViewId, request_id: usize, result: String) {
-        self.0.send_rpc_notification("show_hover", &json!(
+    pub fn show_definition(&self, view_id: ViewId, request_id: usize,
+                           result: Option<&DefinitionResult>) {
+        self.0.send_rpc_notification("show_definition", &json!(
             {
                 "view_id": view_id,
                 "request_id": request_id,
@@ -188,6 +313,87 @@ impl Client {
         ))
     }
 
+    pub fn prepare_rename_result(&self, view_id: ViewId, request_id: usize,
+                                 result: Option<&RenameRange>) {
+        self.0.send_rpc_notification("prepare_rename_result", &json!(
+            {
+                "view_id": view_id,
+                "request_id": request_id,
+                "result": result
+            }
+        ))
+    }
+
+    /// Sends the edits made by the rename `request_id`, with the views opened
+    /// for files that were not open, or why it failed.
+    pub fn rename_result(&self, view_id: ViewId, request_id: usize,
+                         result: &Result<(WorkspaceEdit, BTreeMap<PathBuf, ViewId>),
+                                         RemoteError>) {
+        let mut params = json!({
+            "view_id": view_id,
+            "request_id": request_id,
+        });
+        match *result {
+            Ok((ref edit, ref opened)) => {
+                params["result"] = json!(edit);
+                params["opened"] = json!(opened);
+            }
+            Err(ref err) => params["error"] = json!(err),
+        }
+        self.0.send_rpc_notification("rename_result", &params);
+    }
+
+    /// Sends the minimap of a view, computed after a `get_minimap` request.
+    pub fn minimap(&self, view_id: ViewId, minimap: &MinimapData) {
+        self.0.send_rpc_notification("minimap", &json!({
+            "view_id": view_id,
+            "minimap": minimap,
+        }))
+    }
+
+    /// Sends the code actions for a `get_code_actions` request that was
+    /// answered with `Null`.
+    pub fn code_actions(&self, view_id: ViewId, actions: &[CodeAction]) {
+        self.0.send_rpc_notification("code_actions", &json!({
+            "view_id": view_id,
+            "actions": actions,
+        }))
+    }
+
+    /// Sends the sticky header of a view, after its first visible line has
+    /// changed.
+    pub fn sticky_header(&self, view_id: ViewId, lines: Option<&[StickyLine]>) {
+        self.0.send_rpc_notification("sticky_header", &json!({
+            "view_id": view_id,
+            "lines": lines,
+        }))
+    }
+
+    pub fn show_hover(&self, view_id: ViewId, request_id: usize,
+                      result: Option<&HoverInfo>) {
+        self.0.send_rpc_notification("show_hover", &json!(
+            {
+                "view_id": view_id,
+                "request_id": request_id,
+                "result": result.map(|info| info.contents.as_str()).unwrap_or(""),
+                "hover": result,
+            }
+        ))
+    }
+
+    pub fn show_completions(&self, view_id: ViewId, list: &CompletionList) {
+        self.0.send_rpc_notification("show_completions", &json!({
+            "view_id": view_id,
+            "list": list,
+        }))
+    }
+
+    pub fn hide_completions(&self, view_id: ViewId) {
+        self.0.send_rpc_notification("hide_completions", &json!({
+            "view_id": view_id,
+        }))
+    }
+
     pub fn schedule_idle(&self, token: usize) {
         self.0.schedule_idle(token)
     }
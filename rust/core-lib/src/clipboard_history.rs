@@ -0,0 +1,85 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The clipboard history: the text most recently copied or cut in any
+//! buffer, which can be pasted again. Killed text goes to the kill ring
+//! instead, for `yank`.
+
+use std::collections::VecDeque;
+
+/// The text most recently copied or cut, most recent first.
+#[derive(Debug)]
+pub struct ClipboardHistory {
+    entries: VecDeque<String>,
+    capacity: usize,
+}
+
+impl ClipboardHistory {
+    /// Creates an empty history keeping at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        ClipboardHistory { entries: VecDeque::new(), capacity: capacity.max(1) }
+    }
+
+    /// Sets the number of entries kept, dropping the oldest ones beyond it.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        self.entries.truncate(self.capacity);
+    }
+
+    /// Adds `text` as the most recent entry. Text that is already in the
+    /// history is moved to the front.
+    pub fn push(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(idx) = self.entries.iter().position(|entry| *entry == text) {
+            self.entries.remove(idx);
+        }
+        self.entries.push_front(text);
+        self.entries.truncate(self.capacity);
+    }
+
+    /// Returns the `index`-th most recent entry, 0 being the most recent.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Returns the entries, most recent first.
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_recent_first() {
+        let mut history = ClipboardHistory::new(3);
+        history.push("a".into());
+        history.push("".into());
+        history.push("b".into());
+        history.push("c".into());
+        assert_eq!(history.entries(), vec!["c", "b", "a"]);
+        history.push("a".into());
+        assert_eq!(history.entries(), vec!["a", "c", "b"]);
+        history.push("d".into());
+        assert_eq!(history.entries(), vec!["d", "a", "c"]);
+        assert_eq!(history.get(1), Some("a"));
+        assert!(history.get(3).is_none());
+        history.set_capacity(2);
+        assert_eq!(history.entries(), vec!["d", "a"]);
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Code actions: fixes and refactorings that plugins offer for a range of
+//! a buffer, such as a language server's quick fix for a diagnostic.
+//!
+//! The core asks for the actions at the last selection region whenever it
+//! moves or the buffer changes, so that the frontend can show a lightbulb
+//! when there are some; `get_code_actions` is answered from what was found,
+//! or once every provider has answered.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use xi_rpc::{Error as RpcError, RemoteError};
+
+use formatting::{FormatError, TextEdit};
+use plugins::PluginId;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeActionKind {
+    QuickFix,
+    Refactor,
+    RefactorExtract,
+    RefactorInline,
+    RefactorRewrite,
+    Source,
+    SourceOrganizeImports,
+    Other,
+}
+
+/// Edits to any number of files, each relative to the file's text when the
+/// action was offered.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct WorkspaceEdit {
+    pub changes: BTreeMap<PathBuf, Vec<TextEdit>>,
+}
+
+/// A command that the plugin offering an action runs itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<Value>,
+}
+
+/// An action offered by a plugin. Applying it makes its edit, if any, then
+/// has the plugin run its command, if any.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: CodeActionKind,
+    pub edit: Option<WorkspaceEdit>,
+    pub command: Option<Command>,
+}
+
+// The codes of the errors sent to the frontend, after those of formatting.
+pub const NO_SUCH_ACTION: i64 = 13;
+pub const READ_ONLY: i64 = 14;
+pub const INVALID_EDIT: i64 = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodeActionError {
+    /// The index is not that of an action last sent to the frontend.
+    NoSuchAction(usize),
+    /// A buffer to edit is read-only.
+    ReadOnly,
+    /// An edit was outside its buffer, or overlapped another.
+    InvalidEdit(String),
+}
+
+impl From<FormatError> for CodeActionError {
+    fn from(src: FormatError) -> CodeActionError {
+        match src {
+            FormatError::ReadOnly => CodeActionError::ReadOnly,
+            FormatError::InvalidEdit(msg) => CodeActionError::InvalidEdit(msg),
+            other => CodeActionError::InvalidEdit(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CodeActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodeActionError::NoSuchAction(index) => write!(f, "No code action {}", index),
+            CodeActionError::ReadOnly => write!(f, "Buffer is read-only"),
+            CodeActionError::InvalidEdit(ref msg) => write!(f, "Invalid code action edit: {}", msg),
+        }
+    }
+}
+
+impl From<CodeActionError> for RemoteError {
+    fn from(src: CodeActionError) -> RemoteError {
+        let code = match src {
+            CodeActionError::NoSuchAction(_) => NO_SUCH_ACTION,
+            CodeActionError::ReadOnly => READ_ONLY,
+            CodeActionError::InvalidEdit(_) => INVALID_EDIT,
+        };
+        RemoteError::custom(code, src.to_string(), None)
+    }
+}
+
+/// Returns what a plugin answered a request for code actions, or for a
+/// rename, with. On failure, returns the message of the error
+/// the plugin answered with, or why its answer could not be read.
+pub(crate) fn parse_response<T>(response: Result<Value, RpcError>) -> Result<T, String>
+    where T: DeserializeOwned
+{
+    match response {
+        Ok(value) => serde_json::from_value(value)
+            .map_err(|err| format!("bad response: {}", err)),
+        Err(RpcError::RemoteError(RemoteError::Custom { message, .. })) => Err(message),
+        Err(err) => Err(format!("{:?}", err)),
+    }
+}
+
+/// A range of a revision of the buffer, that actions are asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Query {
+    start: usize,
+    end: usize,
+    rev: u64,
+}
+
+/// The code actions known for a view.
+#[derive(Debug, Default)]
+pub struct CodeActions {
+    /// What the actions were last asked for.
+    query: Option<Query>,
+    /// The range of the last selection region when the core last asked
+    /// for its actions.
+    cursor: Option<Query>,
+    actions: Vec<(PluginId, CodeAction)>,
+    /// The providers that have yet to answer the query.
+    pending: BTreeSet<PluginId>,
+    /// The query that the frontend is waiting to be sent the actions for.
+    awaited: Option<Query>,
+    /// The actions last sent to the frontend, which `apply_code_action`
+    /// indexes, and the revision they apply to. The actions of plugins that
+    /// have stopped since are `None`, so that the indices stay the same.
+    shown: Option<(u64, Vec<Option<(PluginId, CodeAction)>>)>,
+}
+
+impl CodeActions {
+    /// Returns `true` if the actions are those for `start..end` at `rev`,
+    /// or have been asked for.
+    pub fn is_current(&self, start: usize, end: usize, rev: u64) -> bool {
+        self.query == Some(Query { start, end, rev })
+    }
+
+    /// Records that the last selection region covers `start..end` at `rev`.
+    /// Returns `true` if that has changed, and its actions should be asked
+    /// for.
+    pub fn cursor_moved(&mut self, start: usize, end: usize, rev: u64) -> bool {
+        let cursor = Some(Query { start, end, rev });
+        if self.cursor == cursor {
+            return false;
+        }
+        self.cursor = cursor;
+        true
+    }
+
+    /// Forgets the known actions, which are to be replaced by those that
+    /// `providers` offer for `start..end` at `rev`.
+    pub fn start<I>(&mut self, start: usize, end: usize, rev: u64, providers: I)
+        where I: IntoIterator<Item=PluginId>
+    {
+        self.query = Some(Query { start, end, rev });
+        self.actions.clear();
+        self.pending = providers.into_iter().collect();
+    }
+
+    /// Records the actions `plugin` offers for `start..end` at `rev`; a
+    /// provider that failed to answer offers none. Returns `false` if they
+    /// were asked for by a superseded query.
+    pub fn respond(&mut self, plugin: PluginId, start: usize, end: usize, rev: u64,
+                   actions: Vec<CodeAction>) -> bool {
+        if !self.is_current(start, end, rev) {
+            return false;
+        }
+        self.pending.remove(&plugin);
+        self.actions.extend(actions.into_iter().map(|action| (plugin, action)));
+        true
+    }
+
+    /// Returns `true` if there are actions for the last selection region.
+    pub fn is_available(&self) -> bool {
+        self.query.is_some() && self.query == self.cursor && !self.actions.is_empty()
+    }
+
+    /// Returns the actions for the query, which `get_shown` then indexes,
+    /// if every provider has answered. If not, returns `None`, and the
+    /// actions are to be sent once `take_awaited` returns them.
+    pub fn show(&mut self) -> Option<Vec<CodeAction>> {
+        let rev = match self.query {
+            Some(query) => query.rev,
+            None => return Some(Vec::new()),
+        };
+        if !self.pending.is_empty() {
+            self.awaited = self.query;
+            return None;
+        }
+        self.awaited = None;
+        self.shown = Some((rev, self.actions.iter().cloned().map(Some).collect()));
+        Some(self.actions.iter().map(|(_, action)| action.clone()).collect())
+    }
+
+    /// Returns the actions that the frontend is waiting for, once every
+    /// provider has answered. If the query they were asked for has been
+    /// superseded, the frontend is sent no actions.
+    pub fn take_awaited(&mut self) -> Option<Vec<CodeAction>> {
+        let awaited = self.awaited?;
+        if self.query != Some(awaited) {
+            self.awaited = None;
+            return Some(Vec::new());
+        }
+        if !self.pending.is_empty() {
+            return None;
+        }
+        self.show()
+    }
+
+    /// Returns the action at `index` in those last shown, with the plugin
+    /// that offered it and the revision its edit applies to.
+    pub fn get_shown(&self, index: usize) -> Option<(PluginId, u64, CodeAction)> {
+        let (rev, ref actions) = *self.shown.as_ref()?;
+        match actions.get(index) {
+            Some(&Some((plugin, ref action))) => Some((plugin, rev, action.clone())),
+            _ => None,
+        }
+    }
+
+    /// Forgets the actions offered by `plugin`, which has stopped.
+    pub fn remove_plugin(&mut self, plugin: PluginId) {
+        self.actions.retain(|&(p, _)| p != plugin);
+        self.pending.remove(&plugin);
+        if let Some((_, ref mut actions)) = self.shown {
+            for shown in actions.iter_mut() {
+                if shown.as_ref().map(|&(p, _)| p == plugin).unwrap_or(false) {
+                    *shown = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugins::PluginPid;
+
+    fn action(title: &str) -> CodeAction {
+        CodeAction { title: title.into(), kind: CodeActionKind::QuickFix, edit: None, command: None }
+    }
+
+    #[test]
+    fn queries() {
+        let providers = vec![PluginPid(1), PluginPid(2)];
+        let mut actions = CodeActions::default();
+        assert!(actions.cursor_moved(3, 3, 1));
+        assert!(!actions.cursor_moved(3, 3, 1));
+        actions.start(3, 3, 1, providers.clone());
+        assert!(actions.is_current(3, 3, 1));
+        assert!(!actions.is_available());
+        assert!(actions.respond(PluginPid(1), 3, 3, 1, vec![action("a")]));
+        assert!(actions.respond(PluginPid(2), 3, 3, 1, vec![action("b")]));
+        assert!(actions.is_available());
+
+        // an edit supersedes the query
+        assert!(actions.cursor_moved(3, 3, 2));
+        assert!(!actions.is_available());
+        actions.start(3, 3, 2, providers.clone());
+        assert!(!actions.respond(PluginPid(1), 3, 3, 1, vec![action("c")]));
+        actions.respond(PluginPid(1), 3, 3, 2, vec![]);
+        actions.respond(PluginPid(2), 3, 3, 2, vec![]);
+        assert_eq!(actions.show(), Some(vec![]));
+
+        // actions for a range other than the selection's are no lightbulb
+        actions.start(0, 5, 2, providers);
+        assert!(actions.respond(PluginPid(1), 0, 5, 2, vec![action("d")]));
+        assert!(!actions.is_available());
+    }
+
+    #[test]
+    fn awaited_actions() {
+        let mut actions = CodeActions::default();
+        actions.start(0, 2, 4, vec![PluginPid(1), PluginPid(2)]);
+        actions.respond(PluginPid(1), 0, 2, 4, vec![action("a")]);
+        assert_eq!(actions.show(), None);
+        assert_eq!(actions.take_awaited(), None);
+        actions.respond(PluginPid(2), 0, 2, 4, vec![action("b")]);
+        assert_eq!(actions.take_awaited(), Some(vec![action("a"), action("b")]));
+        assert_eq!(actions.take_awaited(), None);
+        assert_eq!(actions.get_shown(1), Some((PluginPid(2), 4, action("b"))));
+
+        // a provider that stops is not waited for
+        actions.start(0, 2, 5, vec![PluginPid(1), PluginPid(2)]);
+        actions.respond(PluginPid(1), 0, 2, 5, vec![action("c")]);
+        assert_eq!(actions.show(), None);
+        actions.remove_plugin(PluginPid(2));
+        assert_eq!(actions.take_awaited(), Some(vec![action("c")]));
+
+        // a superseded query is answered with no actions
+        actions.start(0, 2, 6, vec![PluginPid(1)]);
+        assert_eq!(actions.show(), None);
+        actions.start(1, 2, 6, vec![PluginPid(1)]);
+        assert_eq!(actions.take_awaited(), Some(vec![]));
+        assert_eq!(actions.take_awaited(), None);
+    }
+
+    #[test]
+    fn shown_actions() {
+        let mut actions = CodeActions::default();
+        assert_eq!(actions.show(), Some(vec![]));
+        actions.start(0, 2, 4, vec![PluginPid(1), PluginPid(2)]);
+        actions.respond(PluginPid(1), 0, 2, 4, vec![action("a"), action("b")]);
+        actions.respond(PluginPid(2), 0, 2, 4, vec![action("c")]);
+        let titles: Vec<_> = actions.show().unwrap().into_iter().map(|a| a.title).collect();
+        assert_eq!(titles, vec!["a", "b", "c"]);
+
+        // the shown actions outlive a new query
+        actions.start(0, 2, 5, vec![PluginPid(1), PluginPid(2)]);
+        assert_eq!(actions.get_shown(2), Some((PluginPid(2), 4, action("c"))));
+        assert_eq!(actions.get_shown(3), None);
+
+        // the indices of the actions of a stopped plugin are not reused
+        actions.remove_plugin(PluginPid(1));
+        assert_eq!(actions.get_shown(0), None);
+        assert_eq!(actions.get_shown(2), Some((PluginPid(2), 4, action("c"))));
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The command palette: the edit commands that can be found by name and
+//! run with arguments, without the frontend binding a key to each.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde_json::{self, Value};
+
+use xi_rpc::RemoteError;
+
+use fuzzy::fuzzy_score;
+use rpc::EditNotification;
+use tabs::ViewId;
+
+/// The commands of the palette, by the name of their edit method, with
+/// what they do.
+const COMMANDS: &[(&str, &str)] = &[
+    ("insert", "Insert text"),
+    ("paste_from_history", "Paste an entry of the clipboard history"),
+    ("delete_forward", "Delete forward"),
+    ("delete_backward", "Delete backward"),
+    ("delete_word_forward", "Delete the word after the cursor"),
+    ("delete_word_backward", "Delete the word before the cursor"),
+    ("delete_to_end_of_paragraph", "Kill to the end of the paragraph"),
+    ("delete_to_beginning_of_line", "Delete to the beginning of the line"),
+    ("insert_newline", "Insert a line break"),
+    ("insert_tab", "Insert a tab"),
+    ("move_word_left", "Move to the previous word"),
+    ("move_word_right", "Move to the next word"),
+    ("move_to_beginning_of_paragraph", "Move to the beginning of the paragraph"),
+    ("move_to_end_of_paragraph", "Move to the end of the paragraph"),
+    ("move_to_beginning_of_document", "Move to the beginning of the document"),
+    ("move_to_end_of_document", "Move to the end of the document"),
+    ("scroll_page_up", "Scroll up a page"),
+    ("scroll_page_down", "Scroll down a page"),
+    ("select_all", "Select all"),
+    ("deselect", "Collapse the selections to carets"),
+    ("expand_selection", "Expand the selection to the enclosing brackets"),
+    ("shrink_selection", "Shrink the selection back"),
+    ("add_selection_above", "Add a cursor on the line above"),
+    ("add_selection_below", "Add a cursor on the line below"),
+    ("selection_into_lines", "Split the selection into lines"),
+    ("select_word", "Select the word under the cursor"),
+    ("select_paragraph", "Select the paragraph"),
+    ("fold_range", "Fold a range of lines"),
+    ("unfold_range", "Unfold a range of lines"),
+    ("goto_line", "Go to a line"),
+    ("set_mark", "Set a mark at the cursor"),
+    ("jump_to_mark", "Jump to a mark"),
+    ("yank", "Yank the killed text"),
+    ("transpose", "Transpose characters"),
+    ("undo", "Undo"),
+    ("redo", "Redo"),
+    ("undo_all", "Undo all changes"),
+    ("redo_all", "Redo all changes"),
+    ("jump_to_undo_branch", "Jump to an undo branch"),
+    ("find", "Find"),
+    ("find_next", "Find next"),
+    ("find_previous", "Find previous"),
+    ("find_all", "Select all occurrences of the search"),
+    ("selection_for_find", "Use the selection for find"),
+    ("replace", "Set the replacement text"),
+    ("replace_next", "Replace next"),
+    ("replace_all", "Replace all"),
+    ("selection_for_replace", "Use the selection for replace"),
+    ("cancel_operation", "Cancel the current operation"),
+    ("uppercase", "Convert to uppercase"),
+    ("lowercase", "Convert to lowercase"),
+    ("capitalize", "Capitalize"),
+    ("indent", "Indent lines"),
+    ("outdent", "Outdent lines"),
+    ("duplicate_line", "Duplicate the line"),
+    ("move_line_up", "Move the line up"),
+    ("move_line_down", "Move the line down"),
+    ("join_lines", "Join lines"),
+    ("sort_lines", "Sort lines"),
+    ("sort_lines_case_insensitive", "Sort lines, ignoring case"),
+    ("toggle_comment", "Toggle comment"),
+    ("set_read_only", "Set whether the buffer is read-only"),
+    ("expand_snippet", "Insert a snippet"),
+    ("start_macro_record", "Start recording a macro"),
+    ("stop_macro_record", "Stop recording the macro"),
+    ("play_macro", "Play a macro"),
+];
+
+/// A command of the palette.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandInfo {
+    /// The name of the command's edit method.
+    pub name: String,
+    pub description: String,
+    /// The key bound to the command in the `keybindings` setting, if any.
+    pub keybinding: Option<String>,
+}
+
+/// A key bound to more than one command.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeybindingConflict {
+    pub key: String,
+    /// The commands bound to the key, in order of name.
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// No command has this name.
+    UnknownCommand(String),
+    /// The arguments are not those of the command.
+    InvalidArgs(String),
+    MissingView(ViewId),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandError::UnknownCommand(ref name) => write!(f, "Unknown command {:?}", name),
+            CommandError::InvalidArgs(ref msg) => write!(f, "Invalid arguments: {}", msg),
+            CommandError::MissingView(view_id) => write!(f, "missing view {:?}", view_id),
+        }
+    }
+}
+
+impl From<CommandError> for RemoteError {
+    fn from(src: CommandError) -> RemoteError {
+        let code = match src {
+            CommandError::UnknownCommand(_) => 21,
+            CommandError::InvalidArgs(_) => 22,
+            CommandError::MissingView(_) => 404,
+        };
+        RemoteError::custom(code, src.to_string(), None)
+    }
+}
+
+/// Returns the commands, with the keys `keybindings` binds to them. With a
+/// `query`, only those whose name or description match it are returned,
+/// best match first.
+pub fn all_commands(query: Option<&str>, keybindings: &HashMap<String, String>)
+                    -> Vec<CommandInfo> {
+    let mut commands = COMMANDS.iter()
+        .filter_map(|&(name, description)| {
+            let score = match query {
+                Some(query) => fuzzy_score(query, name).max(fuzzy_score(query, description))?,
+                None => 0,
+            };
+            Some((score, name, description))
+        })
+        .collect::<Vec<_>>();
+    // the sort is stable, so that equal matches stay in the table's order
+    commands.sort_by_key(|&(score, ..)| Reverse(score));
+    commands.into_iter()
+        .map(|(_, name, description)| CommandInfo {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            keybinding: keybindings.get(name).cloned(),
+        })
+        .collect()
+}
+
+/// Returns the keys that `keybindings` binds to more than one command, in
+/// order of key.
+pub fn keybinding_conflicts(keybindings: &HashMap<String, String>)
+                            -> Vec<KeybindingConflict> {
+    let mut by_key = BTreeMap::new();
+    for (command, key) in keybindings {
+        by_key.entry(key).or_insert_with(Vec::new).push(command.clone());
+    }
+    by_key.into_iter()
+        .filter(|(_, commands)| commands.len() > 1)
+        .map(|(key, mut commands)| {
+            commands.sort();
+            KeybindingConflict { key: key.clone(), commands }
+        })
+        .collect()
+}
+
+/// Returns the edit command `name` with the arguments `args`, the params
+/// of its edit method; `null` for a command that takes none.
+pub fn parse_command(name: &str, args: Value) -> Result<EditNotification, CommandError> {
+    if !COMMANDS.iter().any(|&(command, _)| command == name) {
+        return Err(CommandError::UnknownCommand(name.to_owned()));
+    }
+    let json = if args.is_null() {
+        json!({ "method": name })
+    } else {
+        json!({ "method": name, "params": args })
+    };
+    serde_json::from_value(json).map_err(|e| CommandError::InvalidArgs(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_edit_methods() {
+        for &(name, _) in COMMANDS {
+            match parse_command(name, Value::Null) {
+                Err(CommandError::InvalidArgs(ref msg)) if msg.contains("unknown variant") =>
+                    panic!("{} is not an edit method", name),
+                _ => (),
+            }
+        }
+        assert_eq!(parse_command("undo", Value::Null), Ok(EditNotification::Undo));
+        assert_eq!(parse_command("goto_line", json!({"line": 3})),
+                   Ok(EditNotification::GotoLine { line: 3, col: 0 }));
+        match parse_command("goto_line", json!({"col": 3})) {
+            Err(CommandError::InvalidArgs(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        // only the commands of the palette can be run
+        assert_eq!(parse_command("scroll", json!([0, 1])),
+                   Err(CommandError::UnknownCommand("scroll".into())));
+    }
+
+    #[test]
+    fn search() {
+        let mut keybindings = HashMap::new();
+        keybindings.insert("undo".to_owned(), "ctrl+z".to_owned());
+        let commands = all_commands(None, &keybindings);
+        assert_eq!(commands.len(), COMMANDS.len());
+        let undo = commands.iter().find(|command| command.name == "undo").unwrap();
+        assert_eq!(undo.keybinding, Some("ctrl+z".to_owned()));
+
+        let found = all_commands(Some("srtl"), &keybindings);
+        assert_eq!(found[0].name, "sort_lines");
+        assert!(found.iter().all(|command| command.keybinding.is_none()));
+        assert_eq!(all_commands(Some("redo"), &keybindings)[0].name, "redo");
+        assert!(all_commands(Some("zzz"), &keybindings).is_empty());
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut keybindings = HashMap::new();
+        keybindings.insert("undo".to_owned(), "ctrl+z".to_owned());
+        keybindings.insert("redo".to_owned(), "ctrl+y".to_owned());
+        assert!(keybinding_conflicts(&keybindings).is_empty());
+        keybindings.insert("yank".to_owned(), "ctrl+y".to_owned());
+        keybindings.insert("sort_lines".to_owned(), "ctrl+y".to_owned());
+        assert_eq!(keybinding_conflicts(&keybindings), vec![KeybindingConflict {
+            key: "ctrl+y".to_owned(),
+            commands: vec!["redo".to_owned(), "sort_lines".to_owned(), "yank".to_owned()],
+        }]);
+    }
+}
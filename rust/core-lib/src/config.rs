@@ -17,16 +17,22 @@ use std::error::Error;
 use std::fmt;
 use std::fs;
 use std::path::{PathBuf, Path};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::u32;
 
 use serde::de::Deserialize;
 use serde_json::{self, Value};
 use toml;
 
+use editorconfig;
 use syntax::{LanguageId, Languages};
 use tabs::{BufferId, ViewId};
 
+/// The name of the config files that hold the settings for the files in
+/// their directory and its subdirectories.
+pub(crate) const DIRECTORY_CONFIG_FILE_NAME: &str = ".xi-config.toml";
+
 /// Loads the included base config settings.
 fn load_base_config() -> Table {
 
@@ -75,6 +81,14 @@ pub enum ConfigDomain {
     /// The system's overrides for a particular buffer. Only used internally.
     #[serde(skip_deserializing)]
     SysOverride(BufferId),
+    /// The settings of a directory's config file, which apply to the files
+    /// beneath it. Only used internally.
+    #[serde(skip_deserializing)]
+    Directory(PathBuf),
+    /// The settings of the EditorConfig files that apply to a particular
+    /// buffer. Only used internally.
+    #[serde(skip_deserializing)]
+    EditorConfig(BufferId),
 }
 
 /// The external RPC sends `ViewId`s, which we convert to `BufferId`s
@@ -98,10 +112,84 @@ pub enum ConfigError {
     Parse(PathBuf, toml::de::Error),
     /// The config table contained unexpected values
     UnexpectedItem(serde_json::Error),
+    /// Settings in the config table were unknown, or had invalid values.
+    Invalid(Vec<SettingError>),
     /// An Io Error
     Io(io::Error),
 }
 
+/// A problem with a setting in a config table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingError {
+    /// The setting's key; empty if the problem is with the table as a
+    /// whole, such as it not being valid TOML.
+    pub key: String,
+    pub message: String,
+}
+
+/// The kinds of value that a setting can have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingKind {
+    Bool,
+    /// A non-negative integer, between the bounds inclusive.
+    Integer { min: u64, max: u64 },
+    /// A number, between the bounds inclusive.
+    Number { min: f64, max: f64 },
+    String,
+    /// One of a few strings.
+    Choice(&'static [&'static str]),
+    /// An array of strings.
+    StringArray,
+    /// A table of strings.
+    StringTable,
+}
+
+/// The kind of each known setting.
+const SETTING_KINDS: &[(&str, SettingKind)] = &[
+    ("tab_size", SettingKind::Integer { min: 1, max: 128 }),
+    ("translate_tabs_to_spaces", SettingKind::Bool),
+    ("use_tab_stops", SettingKind::Bool),
+    ("plugin_search_path", SettingKind::StringArray),
+    ("keybindings", SettingKind::StringTable),
+    ("font_face", SettingKind::String),
+    ("font_size", SettingKind::Number { min: 1.0, max: 1000.0 }),
+    ("line_ending", SettingKind::Choice(&["\n", "\r\n", "\r"])),
+    ("auto_indent", SettingKind::Bool),
+    ("indent_after_chars", SettingKind::String),
+    ("dedent_on_chars", SettingKind::String),
+    ("line_comment", SettingKind::String),
+    ("block_comment_start", SettingKind::String),
+    ("block_comment_end", SettingKind::String),
+    ("undo_grouping_interval_ms", SettingKind::Integer { min: 0, max: u32::MAX as u64 }),
+    ("clipboard_history_size", SettingKind::Integer { min: 1, max: u32::MAX as u64 }),
+    ("hover_timeout_ms", SettingKind::Integer { min: 0, max: u32::MAX as u64 }),
+    ("format_on_save", SettingKind::Bool),
+    ("format_on_save_timeout_ms", SettingKind::Integer { min: 0, max: u32::MAX as u64 }),
+    ("auto_save_delay_ms", SettingKind::Integer { min: 0, max: u32::MAX as u64 }),
+    ("scroll_past_end", SettingKind::Bool),
+    ("wrap_width", SettingKind::Integer { min: 0, max: u32::MAX as u64 }),
+    ("word_wrap", SettingKind::Bool),
+    ("trim_trailing_whitespace", SettingKind::Bool),
+    ("insert_final_newline", SettingKind::Bool),
+    ("trim_final_newlines", SettingKind::Bool),
+    ("end_of_line", SettingKind::Choice(&["", "lf", "crlf", "cr"])),
+    ("charset", SettingKind::Choice(&["", "utf-8", "utf-8-bom", "utf-16le", "utf-16be"])),
+];
+
+/// Describes the known settings: the kind of value each can have, and its
+/// default.
+#[derive(Debug)]
+pub struct ConfigSchema {
+    settings: Vec<SettingSchema>,
+}
+
+#[derive(Debug)]
+pub struct SettingSchema {
+    pub key: &'static str,
+    pub kind: SettingKind,
+    pub default: Value,
+}
+
 /// Represents the common pattern of default settings masked by
 /// user settings.
 #[derive(Debug)]
@@ -133,12 +221,20 @@ pub struct ConfigManager {
     buffer_tags: HashMap<BufferId, LanguageTag>,
     /// The configs for any open buffers
     buffer_configs: HashMap<BufferId, BufferConfig>,
+    /// The directories searched for directory config files for each
+    /// buffer, closest first.
+    buffer_dirs: HashMap<BufferId, Vec<PathBuf>>,
+    /// The directory up to which directory config files are searched for.
+    /// Without one, they are not searched for at all.
+    workspace_root: Option<PathBuf>,
     /// If using file-based config, this is the base config directory
     /// (perhaps `$HOME/.config/xi`, by default).
     config_dir: Option<PathBuf>,
     /// An optional client-provided path for bundled resources, such
     /// as plugins and themes.
     extras_dir: Option<PathBuf>,
+    /// The settings that config tables can contain.
+    schema: ConfigSchema,
 }
 
 /// A collection of config tables representing a hierarchy, with each
@@ -167,6 +263,21 @@ pub struct BufferItems {
     pub font_face: String,
     pub font_size: f32,
     pub auto_indent: bool,
+    pub indent_after_chars: String,
+    pub dedent_on_chars: String,
+    pub line_comment: String,
+    pub block_comment_start: String,
+    pub block_comment_end: String,
+    pub undo_grouping_interval_ms: u64,
+    pub hover_timeout_ms: u64,
+    pub format_on_save: bool,
+    pub format_on_save_timeout_ms: u64,
+    pub auto_save_delay_ms: u64,
+    pub trim_trailing_whitespace: bool,
+    pub insert_final_newline: bool,
+    pub trim_final_newlines: bool,
+    pub end_of_line: String,
+    pub charset: String,
     pub scroll_past_end: bool,
     pub wrap_width: usize,
     pub word_wrap: bool,
@@ -228,15 +339,19 @@ impl ConfigManager {
                extras_dir: Option<PathBuf>) -> Self
     {
         let base = load_base_config();
+        let schema = ConfigSchema::new(&base);
         let mut defaults = HashMap::new();
         defaults.insert(ConfigDomain::General, ConfigPair::with_base(base));
         ConfigManager {
             configs: defaults,
             buffer_tags: HashMap::new(),
             buffer_configs: HashMap::new(),
+            buffer_dirs: HashMap::new(),
+            workspace_root: None,
             languages: Languages::default(),
             config_dir,
             extras_dir,
+            schema,
         }
     }
 
@@ -257,9 +372,48 @@ impl ConfigManager {
             .collect()
     }
 
+    /// Returns the keys bound to commands in the general config, by the
+    /// name of the command. Unlike other settings, the `keybindings` of the
+    /// user config are merged into the defaults command by command; an empty
+    /// key unbinds a command.
+    pub(crate) fn keybindings(&self) -> HashMap<String, String> {
+        let pair = self.configs.get(&ConfigDomain::General);
+        let mut keybindings = pair.and_then(|pair| pair.base.as_ref())
+            .map(|base| keybindings_in(base))
+            .unwrap_or_default();
+        keybindings.extend(self.user_keybindings());
+        keybindings.retain(|_, key| !key.is_empty());
+        keybindings
+    }
+
+    /// Returns how many entries the clipboard history keeps. It is shared by
+    /// every buffer, so only the general config sets it.
+    pub(crate) fn clipboard_history_size(&self) -> usize {
+        self.configs.get(&ConfigDomain::General)
+            .and_then(|pair| pair.cache.get("clipboard_history_size"))
+            .and_then(Value::as_u64)
+            .map(|size| size as usize)
+            .unwrap_or(1)
+    }
+
+    /// Returns the `keybindings` of the user's general config.
+    pub(crate) fn user_keybindings(&self) -> HashMap<String, String> {
+        self.configs.get(&ConfigDomain::General)
+            .and_then(|pair| pair.user.as_ref())
+            .map(|user| keybindings_in(user))
+            .unwrap_or_default()
+    }
+
+    /// Sets the directory up to which directory config files are searched
+    /// for. The caller updates the open buffers for it with
+    /// `update_buffer_path`.
+    pub(crate) fn set_workspace_root(&mut self, root: Option<PathBuf>) {
+        self.workspace_root = root.map(|p| p.canonicalize().unwrap_or(p));
+    }
+
     /// Adds a new buffer to the config manager, and returns the initial config
     /// `Table` for that buffer. The `path` argument is used to determine
-    /// the buffer's default language.
+    /// the buffer's default language, and the directory configs that apply.
     ///
     /// # Note: The caller is responsible for ensuring the config manager is
     /// notified every time a buffer is added or removed.
@@ -272,11 +426,16 @@ impl ConfigManager {
         let lang = path.and_then(|p| self.language_for_path(p)).unwrap_or_default();
         let lang_tag = LanguageTag::new(lang);
         assert!(self.buffer_tags.insert(id, lang_tag).is_none());
+        if let Some(path) = path {
+            self.set_buffer_dirs(id, path);
+            self.set_editor_config(id, path);
+        }
         self.update_buffer_config(id)
             .expect("new buffer must always have config")
     }
 
-    /// Updates the default language for the given buffer.
+    /// Updates the default language and directory configs for the given
+    /// buffer.
     ///
     /// # Panics:
     ///
@@ -289,8 +448,10 @@ impl ConfigManager {
         let has_changed = self.buffer_tags.get_mut(&id)
             .map(|tag| tag.set_detected(lang))
             .unwrap();
+        let dirs_changed = self.set_buffer_dirs(id, path);
+        let editor_config_changed = self.set_editor_config(id, path);
 
-        if has_changed { self.update_buffer_config(id) } else { None }
+        if has_changed || dirs_changed || editor_config_changed { self.update_buffer_config(id) } else { None }
     }
 
     /// Instructs the `ConfigManager` to stop tracking a given buffer.
@@ -301,9 +462,101 @@ impl ConfigManager {
     pub(crate) fn remove_buffer(&mut self, id: BufferId) {
         self.buffer_tags.remove(&id).expect("remove key must exist");
         self.buffer_configs.remove(&id);
+        self.buffer_dirs.remove(&id);
+        self.configs.remove(&ConfigDomain::EditorConfig(id));
+        self.forget_unused_directory_configs();
         // TODO: remove any overrides
     }
 
+    /// Returns the directories searched for directory config files for the
+    /// given buffer, which should be watched for changes to them.
+    pub(crate) fn get_buffer_dirs(&self, id: BufferId) -> &[PathBuf] {
+        self.buffer_dirs.get(&id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Finds the directory configs for a buffer at `path`, loading any that
+    /// are not yet loaded. Returns `true` if they have changed.
+    fn set_buffer_dirs(&mut self, id: BufferId, path: &Path) -> bool {
+        let dirs = directories_to_search(path, self.workspace_root.as_ref());
+        for dir in &dirs {
+            let domain = ConfigDomain::Directory(dir.clone());
+            if self.configs.contains_key(&domain) { continue }
+            if let Some(table) = self.load_directory_config_file(dir) {
+                let mut pair = ConfigPair::with_base(None);
+                pair.set_table(table);
+                self.configs.insert(domain, pair);
+            }
+        }
+        let changed = self.buffer_dirs.get(&id) != Some(&dirs);
+        self.buffer_dirs.insert(id, dirs);
+        self.forget_unused_directory_configs();
+        changed
+    }
+
+    /// Reads the EditorConfig settings for a buffer at `path`. Returns `true`
+    /// if they have changed.
+    fn set_editor_config(&mut self, id: BufferId, path: &Path) -> bool {
+        let props = editorconfig::properties_for_path(path);
+        let table = editorconfig::table_from_properties(&props);
+        let domain = ConfigDomain::EditorConfig(id);
+        if let Err(e) = self.check_table(&table) {
+            error!("Error loading EditorConfig settings for {:?}: {:?}", path, e);
+            return self.configs.remove(&domain).is_some();
+        }
+        if table.is_empty() {
+            return self.configs.remove(&domain).is_some();
+        }
+        let changed = self.configs.get(&domain)
+            .map(|pair| pair.cache.as_ref() != &table)
+            .unwrap_or(true);
+        let mut pair = ConfigPair::with_base(None);
+        pair.set_table(table);
+        self.configs.insert(domain, pair);
+        changed
+    }
+
+    fn load_directory_config_file(&self, dir: &Path) -> Option<Table> {
+        let path = dir.join(DIRECTORY_CONFIG_FILE_NAME);
+        if !path.is_file() { return None; }
+        match try_load_from_file(&path).and_then(|t| self.check_table(&t).map(|_| t)) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                error!("Error loading config: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Reloads the directory config file at `path` after it has changed or
+    /// been removed. Returns the resulting buffer config changes, or a
+    /// `ConfigError` if the file cannot be loaded.
+    pub(crate) fn reload_directory_config(&mut self, path: &Path)
+        -> Result<Vec<(BufferId, Table)>, ConfigError>
+    {
+        let dir = match path.parent() {
+            Some(dir) => dir.canonicalize().unwrap_or_else(|_| dir.to_owned()),
+            None => return Ok(Vec::new()),
+        };
+        let domain = ConfigDomain::Directory(dir.clone());
+        if path.is_file() {
+            let table = try_load_from_file(path)?;
+            self.set_user_config(domain, table)
+        } else {
+            self.configs.remove(&domain);
+            Ok(self.update_all_buffer_configs())
+        }
+    }
+
+    /// Drops the directory configs that no open buffer is beneath.
+    fn forget_unused_directory_configs(&mut self) {
+        let in_use = self.buffer_dirs.values().flat_map(|dirs| dirs.iter())
+            .collect::<HashSet<_>>();
+        self.configs.retain(|domain, _| match domain {
+            ConfigDomain::Directory(dir) => in_use.contains(dir),
+            _ => true,
+        });
+    }
+
     /// Sets a specific language for the given buffer. This is used if the
     /// user selects a specific language in the frontend, for instance.
     #[allow(dead_code)]
@@ -341,6 +594,13 @@ impl ConfigManager {
 
         configs.push(self.configs.get(&ConfigDomain::General));
         lang.map(|s| configs.push(self.configs.get(&s.into())));
+        configs.push(self.configs.get(&ConfigDomain::EditorConfig(id)));
+        if let Some(dirs) = self.buffer_dirs.get(&id) {
+            // the closest directory's config is applied last, and wins
+            for dir in dirs.iter().rev() {
+                configs.push(self.configs.get(&ConfigDomain::Directory(dir.clone())));
+            }
+        }
         configs.push(self.configs.get(&ConfigDomain::SysOverride(id)));
         configs.push(self.configs.get(&ConfigDomain::UserOverride(id)));
 
@@ -479,7 +739,26 @@ impl ConfigManager {
         }
     }
 
+    /// Returns the problems with the settings in the TOML config `s`, or an
+    /// empty `Vec` if there are none.
+    pub fn validate_config(&self, s: &str) -> Vec<SettingError> {
+        match table_from_toml_str(s) {
+            Ok(table) => self.schema.validate(&table),
+            Err(e) => vec![SettingError { key: String::new(), message: e.to_string() }],
+        }
+    }
+
+    /// Checks that the known settings in `table` have valid values. Unknown
+    /// settings, such as those of a newer version, are only warned about.
     fn check_table(&self, table: &Table) -> Result<(), ConfigError> {
+        let (unknown, errors): (Vec<_>, Vec<_>) = self.schema.validate(table).into_iter()
+            .partition(|e| self.schema.get(&e.key).is_none());
+        for e in unknown {
+            warn!("ignoring unknown setting {}", e.key);
+        }
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid(errors));
+        }
         let defaults = self.configs.get(&ConfigDomain::General)
             .and_then(|pair| pair.base.clone())
             .expect("general domain must have defaults");
@@ -509,6 +788,97 @@ impl ConfigManager {
     }
 }
 
+/// Returns the `keybindings` table of `table`, if it has a valid one.
+fn keybindings_in(table: &Table) -> HashMap<String, String> {
+    table.get("keybindings").cloned()
+        .and_then(|keybindings| serde_json::from_value(keybindings).ok())
+        .unwrap_or_default()
+}
+
+impl ConfigSchema {
+    /// Creates the schema of the known settings, with the defaults in
+    /// `defaults`.
+    fn new(defaults: &Table) -> ConfigSchema {
+        let settings = SETTING_KINDS.iter()
+            .map(|&(key, kind)| {
+                let default = defaults.get(key).cloned().unwrap_or(Value::Null);
+                SettingSchema { key, kind, default }
+            })
+            .collect();
+        ConfigSchema { settings }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&SettingSchema> {
+        self.settings.iter().find(|s| s.key == key)
+    }
+
+    /// Checks every setting in `table`, returning the problems found.
+    /// `null` values, which clear a setting, are always valid.
+    pub fn validate(&self, table: &Table) -> Vec<SettingError> {
+        table.iter()
+            .filter(|(_, value)| !value.is_null())
+            .filter_map(|(key, value)| {
+                let message = match self.get(key) {
+                    Some(setting) => setting.kind.check(value).err()?,
+                    None => "unknown setting".to_owned(),
+                };
+                Some(SettingError { key: key.clone(), message })
+            })
+            .collect()
+    }
+}
+
+impl SettingKind {
+    /// Returns a message describing what was expected if `value` is not
+    /// of this kind.
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let valid = match *self {
+            SettingKind::Bool => value.is_boolean(),
+            SettingKind::Integer { min, max } =>
+                value.as_u64().map(|n| min <= n && n <= max).unwrap_or(false),
+            SettingKind::Number { min, max } =>
+                value.as_f64().map(|n| min <= n && n <= max).unwrap_or(false),
+            SettingKind::String => value.is_string(),
+            SettingKind::Choice(choices) =>
+                value.as_str().map(|s| choices.contains(&s)).unwrap_or(false),
+            SettingKind::StringArray => value.as_array()
+                .map(|items| items.iter().all(Value::is_string))
+                .unwrap_or(false),
+            SettingKind::StringTable => value.as_object()
+                .map(|items| items.values().all(Value::is_string))
+                .unwrap_or(false),
+        };
+        if valid { Ok(()) } else { Err(self.expected()) }
+    }
+
+    fn expected(&self) -> String {
+        match *self {
+            SettingKind::Bool => "expected true or false".to_owned(),
+            SettingKind::Integer { min, max } =>
+                format!("expected a whole number from {} to {}", min, max),
+            SettingKind::Number { min, max } =>
+                format!("expected a number from {} to {}", min, max),
+            SettingKind::String => "expected a string".to_owned(),
+            SettingKind::Choice(choices) => {
+                let choices = choices.iter().map(|c| format!("{:?}", c)).collect::<Vec<_>>();
+                format!("expected one of {}", choices.join(", "))
+            }
+            SettingKind::StringArray => "expected an array of strings".to_owned(),
+            SettingKind::StringTable => "expected a table of strings".to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
 impl TableStack {
     /// Create a single table representing the final config values.
     fn collate(&self) -> Table {
@@ -585,7 +955,8 @@ impl ConfigDomain {
         match self {
             ConfigDomain::General => "preferences",
             ConfigDomain::Language(lang) => lang.as_ref(),
-            ConfigDomain::UserOverride(_) | ConfigDomain::SysOverride(_) =>
+            ConfigDomain::UserOverride(_) | ConfigDomain::SysOverride(_) |
+                ConfigDomain::Directory(_) | ConfigDomain::EditorConfig(_) =>
                 "we don't have files",
         }
     }
@@ -645,6 +1016,10 @@ impl fmt::Display for ConfigError {
             Parse(ref p, ref e) => write!(f, "{} ({:?}), {:?}", self.description(), p, e),
             Io(ref e) => write!(f, "error loading config: {:?}", e),
             UnexpectedItem( ref e ) => write!(f, "{}", e),
+            Invalid(ref errors) => {
+                let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                write!(f, "invalid config: {}", errors.join("; "))
+            }
         }
     }
 }
@@ -657,6 +1032,7 @@ impl Error for ConfigError {
             Parse( _, ref e ) => e.description(),
             Io( ref e ) => e.description(),
             UnexpectedItem( ref e ) => e.description(),
+            Invalid( .. ) => "invalid config",
         }
     }
 }
@@ -681,6 +1057,35 @@ pub(crate) fn init_config_dir(dir: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Returns the directories to search for directory config files for a file
+/// at `path`, closest first: those from its own directory up to the
+/// workspace root. There are none if the file is not beneath the root, or
+/// there is no root. Directories are canonicalized, and each is only
+/// searched once, so symlinks cannot cause a cycle.
+fn directories_to_search(path: &Path, workspace_root: Option<&PathBuf>) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut visited = HashSet::new();
+    let start = match path.parent().and_then(|p| p.canonicalize().ok()) {
+        Some(dir) => dir,
+        None => return dirs,
+    };
+    let root = match workspace_root {
+        Some(root) if start.starts_with(root) => root,
+        _ => return dirs,
+    };
+    let mut next = Some(start);
+    while let Some(dir) = next {
+        let dir = match dir.canonicalize() {
+            Ok(dir) => dir,
+            Err(_) => break,
+        };
+        if !visited.insert(dir.clone()) { break }
+        next = if &dir == root { None } else { dir.parent().map(Path::to_owned) };
+        dirs.push(dir);
+    }
+    dirs
+}
+
 /// Attempts to load a config from a file. The config's domain is determined
 /// by the file name.
 pub(crate) fn try_load_from_file(path: &Path) -> Result<Table, ConfigError> {
@@ -826,6 +1231,16 @@ translate_tabs_to_spaces = true
         assert_eq!(manager.get_buffer_config(buf_id).items.font_face, "nice");
     }
 
+    #[test]
+    fn clipboard_history_size() {
+        let mut manager = ConfigManager::new(None, None);
+        assert_eq!(manager.clipboard_history_size(), 20);
+        let changes = json!({"clipboard_history_size": 5}).as_object().unwrap().to_owned();
+        let table = manager.table_for_update(ConfigDomain::General, changes);
+        manager.set_user_config(ConfigDomain::General, table).unwrap();
+        assert_eq!(manager.clipboard_history_size(), 5);
+    }
+
     #[test]
     fn lang_overrides() {
         let mut manager = ConfigManager::new(None, None);
@@ -880,6 +1295,166 @@ translate_tabs_to_spaces = true
         assert_eq!(config.items.font_size, 14.);
     }
 
+    #[test]
+    fn keybindings_merge() {
+        let mut manager = ConfigManager::new(None, None);
+        let mut base = load_base_config();
+        base.insert("keybindings".into(), json!({"undo": "ctrl+z", "redo": "ctrl+y"}));
+        manager.configs.insert(ConfigDomain::General, ConfigPair::with_base(base));
+        let changes = json!({"keybindings": {"yank": "ctrl+y", "redo": ""}})
+            .as_object().unwrap().to_owned();
+        let table = manager.table_for_update(ConfigDomain::General, changes);
+        manager.set_user_config(ConfigDomain::General, table).unwrap();
+
+        let keybindings = manager.keybindings();
+        assert_eq!(keybindings.len(), 2);
+        assert_eq!(keybindings["undo"], "ctrl+z");
+        assert_eq!(keybindings["yank"], "ctrl+y");
+        assert_eq!(manager.user_keybindings()["redo"], "");
+    }
+
+    #[test]
+    fn schema_covers_defaults() {
+        let manager = ConfigManager::new(None, None);
+        let defaults = load_base_config();
+        for (key, value) in defaults.iter() {
+            let setting = manager.schema.get(key).expect("every default has a schema");
+            assert_eq!(&setting.default, value);
+            assert_eq!(setting.kind.check(value), Ok(()), "bad default for {}", key);
+        }
+        for &(key, _) in SETTING_KINDS {
+            assert!(defaults.contains_key(key), "{} has no default", key);
+        }
+    }
+
+    #[test]
+    fn validation_errors() {
+        let mut manager = ConfigManager::new(None, None);
+        let errors = manager.validate_config(r#"
+tab_size = 0
+word_wrap = "yes"
+end_of_line = "lf"
+font_size = 12.5
+tab_sise = 4
+"#);
+        let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(errors, vec!["tab_sise: unknown setting",
+                                "tab_size: expected a whole number from 1 to 128",
+                                "word_wrap: expected true or false"]);
+        let errors = manager.validate_config("tab_size = ");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key, "");
+
+        // invalid tables are rejected as a whole
+        let table = table_from_toml_str("tab_size = 2\ncharset = \"latin1\"").unwrap();
+        match manager.set_user_config(ConfigDomain::General, table) {
+            Err(ConfigError::Invalid(errors)) => assert_eq!(errors[0].key, "charset"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        manager.add_buffer(BufferId(1), None);
+        assert_eq!(manager.get_buffer_config(BufferId(1)).items.tab_size, 4);
+
+        // but unknown settings do not stop the others from applying
+        let table = table_from_toml_str("tab_size = 2\ntab_sise = 4").unwrap();
+        assert!(manager.set_user_config(ConfigDomain::General, table).is_ok());
+        assert_eq!(manager.get_buffer_config(BufferId(1)).items.tab_size, 2);
+    }
+
+    #[test]
+    fn directory_configs() {
+        extern crate tempdir;
+        let tmp = tempdir::TempDir::new("xi-test-directory-configs").unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        let project = root.join("project");
+        let src = project.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(root.join(DIRECTORY_CONFIG_FILE_NAME), "tab_size = 3\nfont_size = 20").unwrap();
+        fs::write(project.join(DIRECTORY_CONFIG_FILE_NAME), "tab_size = 5").unwrap();
+        #[cfg(unix)]
+        ::std::os::unix::fs::symlink(&project, src.join("loop")).unwrap();
+
+        let mut manager = ConfigManager::new(None, None);
+        manager.set_workspace_root(Some(root.clone()));
+        let buf_id = BufferId(1);
+        manager.add_buffer(buf_id, Some(&src.join("main.rs")));
+        assert_eq!(manager.get_buffer_dirs(buf_id), &[src.clone(), project.clone(), root.clone()]);
+        // the closest config wins
+        let config = manager.get_buffer_config(buf_id).to_owned();
+        assert_eq!(config.items.tab_size, 5);
+        assert_eq!(config.items.font_size, 20.);
+
+        // a path through a symlink back up the tree is canonicalized
+        #[cfg(unix)]
+        {
+            let other_id = BufferId(2);
+            manager.add_buffer(other_id, Some(&src.join("loop/src/loop/lib.rs")));
+            assert_eq!(manager.get_buffer_dirs(other_id), &[project.clone(), root.clone()]);
+            manager.remove_buffer(other_id);
+        }
+
+        // changing and removing configs updates the buffer
+        fs::write(src.join(DIRECTORY_CONFIG_FILE_NAME), "tab_size = 7").unwrap();
+        let changes = manager.reload_directory_config(&src.join(DIRECTORY_CONFIG_FILE_NAME))
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 7);
+        fs::remove_file(project.join(DIRECTORY_CONFIG_FILE_NAME)).unwrap();
+        fs::remove_file(src.join(DIRECTORY_CONFIG_FILE_NAME)).unwrap();
+        manager.reload_directory_config(&src.join(DIRECTORY_CONFIG_FILE_NAME)).unwrap();
+        manager.reload_directory_config(&project.join(DIRECTORY_CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 3);
+
+        // directories above the workspace root are not searched
+        manager.set_workspace_root(Some(project.clone()));
+        manager.update_buffer_path(buf_id, &src.join("main.rs"));
+        assert_eq!(manager.get_buffer_dirs(buf_id), &[src.clone(), project.clone()]);
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 4);
+
+        // nor are any for files outside the root, or without a root
+        let outside_id = BufferId(3);
+        manager.add_buffer(outside_id, Some(&root.join("a.rs")));
+        assert!(manager.get_buffer_dirs(outside_id).is_empty());
+        fs::write(project.join(DIRECTORY_CONFIG_FILE_NAME), "tab_size = 6").unwrap();
+        manager.reload_directory_config(&project.join(DIRECTORY_CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 6);
+        manager.set_workspace_root(None);
+        let changes = manager.update_buffer_path(buf_id, &src.join("main.rs"));
+        assert!(changes.is_some());
+        assert!(manager.get_buffer_dirs(buf_id).is_empty());
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 4);
+    }
+
+    #[test]
+    fn editor_config_precedence() {
+        extern crate tempdir;
+        let tmp = tempdir::TempDir::new("xi-test-editor-config").unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        fs::write(root.join(editorconfig::EDITORCONFIG_FILE_NAME),
+                  "root = true\n[*.rs]\nindent_size = 2\nend_of_line = crlf\n").unwrap();
+
+        let mut manager = ConfigManager::new(None, None);
+        manager.set_workspace_root(Some(root.clone()));
+        manager.set_languages(Languages::new(&[rust_lang_def(None)]));
+        let rust_config = table_from_toml_str("tab_size = 8").unwrap();
+        manager.set_user_config(ConfigDomain::Language("Rust".into()), rust_config).unwrap();
+        let buf_id = BufferId(1);
+        manager.add_buffer(buf_id, Some(&root.join("main.rs")));
+        // EditorConfig settings take precedence over xi's
+        let config = manager.get_buffer_config(buf_id).to_owned();
+        assert_eq!(config.items.tab_size, 2);
+        assert_eq!(config.items.end_of_line, "crlf");
+
+        // but not over those of directory configs
+        fs::write(root.join(DIRECTORY_CONFIG_FILE_NAME), "tab_size = 3").unwrap();
+        manager.reload_directory_config(&root.join(DIRECTORY_CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 3);
+
+        // files the sections do not match are unaffected
+        manager.update_buffer_path(buf_id, &root.join("notes.txt"));
+        let config = manager.get_buffer_config(buf_id).to_owned();
+        assert_eq!(config.items.end_of_line, "");
+    }
+
     fn rust_lang_def<T: Into<Option<Table>>>(defaults: T) -> LanguageDefinition {
         LanguageDefinition::simple("Rust", &["rs"], "source.rust", defaults.into())
     }
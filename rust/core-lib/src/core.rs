@@ -103,7 +103,7 @@ impl Handler for XiCore {
         }
 
         // wait for client_started before setting up inner
-        if let &ClientStarted { ref config_dir, ref client_extras_dir } = &rpc {
+        if let &ClientStarted { ref config_dir, ref client_extras_dir, .. } = &rpc {
             assert!(self.is_waiting(), "client_started can only be sent once");
             let state = CoreState::new(ctx.get_peer(), config_dir.clone(),
                                       client_extras_dir.clone());
@@ -162,6 +162,66 @@ impl WeakXiCore {
             core.inner().plugin_update(plugin, view, response);
         }
     }
+
+    /// Handles a plugin's answer to the capabilities request sent at startup.
+    pub fn handle_plugin_capabilities(&self, plugin: PluginId,
+                                      response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_capabilities(plugin, response);
+        }
+    }
+
+    /// Handles a plugin's answer to a request for inlay hints.
+    pub fn handle_inlay_hints(&self, plugin: PluginId, view: ViewId,
+                              response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_inlay_hints(plugin, view, response);
+        }
+    }
+
+    /// Handles a plugin's answer to a request for the symbols of revision
+    /// `rev` of a buffer.
+    pub fn handle_document_symbols(&self, plugin: PluginId, view: ViewId, rev: u64,
+                                   response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_document_symbols(plugin, view, rev, response);
+        }
+    }
+
+    /// Handles a plugin's answer to a request for document highlights, made
+    /// when the view's highlights were at `generation`.
+    pub fn handle_document_highlights(&self, plugin: PluginId, view: ViewId, generation: u64,
+                                      response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_document_highlights(plugin, view, generation, response);
+        }
+    }
+
+    /// Handles a plugin's answer to a request for what a rename would change.
+    pub fn handle_prepare_rename(&self, plugin: PluginId, view: ViewId, request_id: usize,
+                                 response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_prepare_rename(plugin, view, request_id, response);
+        }
+    }
+
+    /// Handles a plugin's answer to a request for the edits of a rename in
+    /// revision `rev` of a buffer.
+    pub fn handle_rename(&self, plugin: PluginId, view: ViewId, request_id: usize, rev: u64,
+                         response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_rename(plugin, view, request_id, rev, response);
+        }
+    }
+
+    /// Handles a plugin's answer to a request for the code actions for the
+    /// range from `start` to `end` of revision `rev` of a buffer.
+    pub fn handle_code_actions(&self, plugin: PluginId, view: ViewId, start: usize, end: usize,
+                               rev: u64, response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_code_actions(plugin, view, start, end, rev, response);
+        }
+    }
 }
 
 /// Handler for messages originating from plugins.
@@ -0,0 +1,186 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Go to definition: finding where the symbol at a position is defined, by
+//! asking the plugins that provide definitions.
+//!
+//! Requests are identified by the frontend's `request_id`. A request is
+//! answered by the first provider to respond with a location, or with nothing
+//! once every provider has failed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use xi_rpc::RemoteError;
+
+use plugins::PluginPid;
+use tabs::ViewId;
+
+/// What to look for: the definition of a symbol, or of its type.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DefinitionKind {
+    Definition,
+    TypeDefinition,
+}
+
+/// A position in a file, as found by a definition provider.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    /// The utf-8 offset in the line.
+    pub col: usize,
+}
+
+/// A definition found for the frontend: where it is, and the view opened
+/// on it, with the caret at the definition.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DefinitionResult {
+    pub location: Location,
+    pub view_id: ViewId,
+    /// Whether the view is for peeking at the definition, rather than for
+    /// navigating to it.
+    pub peek: bool,
+}
+
+struct PendingDefinition {
+    position: usize,
+    kind: DefinitionKind,
+    /// Whether the definition should be shown without navigating to it.
+    peek: bool,
+    /// The providers yet to respond.
+    waiting: BTreeSet<PluginPid>,
+}
+
+/// The definition providers registered for a view, and the requests waiting
+/// on them.
+#[derive(Default)]
+pub struct DefinitionRequests {
+    providers: BTreeSet<PluginPid>,
+    pending: BTreeMap<usize, PendingDefinition>,
+}
+
+impl DefinitionRequests {
+    pub fn register_provider(&mut self, plugin: PluginPid) {
+        self.providers.insert(plugin);
+    }
+
+    pub fn remove_provider(&mut self, plugin: PluginPid) {
+        self.providers.remove(&plugin);
+    }
+
+    pub fn is_provider(&self, plugin: PluginPid) -> bool {
+        self.providers.contains(&plugin)
+    }
+
+    /// Records a request for `position` sent to `providers`.
+    pub fn start(&mut self, request_id: usize, position: usize, kind: DefinitionKind,
+                 peek: bool, providers: &[PluginPid]) {
+        let waiting = providers.iter().cloned().collect();
+        self.pending.insert(request_id, PendingDefinition { position, kind, peek, waiting });
+    }
+
+    /// Handles a provider's response. Returns whether the request was a peek
+    /// and the location found, if this response settles the request, or
+    /// `None` if it is still waiting on other providers or is not pending.
+    pub fn respond(&mut self, request_id: usize, plugin: PluginPid,
+                   result: Result<Location, RemoteError>)
+                   -> Option<(bool, Option<Location>)> {
+        let location = match result {
+            Ok(location) => Some(location),
+            Err(err) => {
+                warn!("definition provider error for request {}: {:?}", request_id, err);
+                None
+            }
+        };
+        let peek = {
+            let pending = self.pending.get_mut(&request_id)?;
+            if !pending.waiting.remove(&plugin) {
+                return None;
+            }
+            if location.is_none() && !pending.waiting.is_empty() {
+                return None;
+            }
+            pending.peek
+        };
+        self.pending.remove(&request_id);
+        Some((peek, location))
+    }
+
+    /// Moves the requests waiting on `old`, a plugin that has crashed, to
+    /// `new`, its restarted instance. Returns the requests to send again, as
+    /// `(request_id, position, kind)`.
+    pub fn replace_plugin(&mut self, old: PluginPid, new: PluginPid)
+                          -> Vec<(usize, usize, DefinitionKind)> {
+        self.pending.iter_mut()
+            .filter(|(_, pending)| pending.waiting.contains(&old))
+            .map(|(&request_id, pending)| {
+                pending.waiting.remove(&old);
+                pending.waiting.insert(new);
+                (request_id, pending.position, pending.kind)
+            })
+            .collect()
+    }
+
+    /// Stops waiting on `plugin`, which will not respond. Returns the
+    /// requests that no provider is left to answer.
+    pub fn abandon_plugin(&mut self, plugin: PluginPid) -> Vec<usize> {
+        let mut abandoned = Vec::new();
+        for (&request_id, pending) in &mut self.pending {
+            if pending.waiting.remove(&plugin) && pending.waiting.is_empty() {
+                abandoned.push(request_id);
+            }
+        }
+        for request_id in &abandoned {
+            self.pending.remove(request_id);
+        }
+        abandoned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_location_wins() {
+        let location = Location { path: "src/lib.rs".into(), line: 3, col: 4 };
+        let err = RemoteError::custom(1, "not found", None);
+        let mut requests = DefinitionRequests::default();
+        let providers = [PluginPid(1), PluginPid(2)];
+        requests.start(1, 0, DefinitionKind::Definition, false, &providers);
+        requests.start(2, 0, DefinitionKind::Definition, true, &providers);
+        assert_eq!(requests.respond(1, PluginPid(1), Err(err.clone())), None);
+        assert_eq!(requests.respond(2, PluginPid(1), Ok(location.clone())),
+                   Some((true, Some(location.clone()))));
+        assert_eq!(requests.respond(2, PluginPid(2), Ok(location.clone())), None);
+        assert_eq!(requests.respond(1, PluginPid(2), Err(err)), Some((false, None)));
+    }
+
+    #[test]
+    fn crashed_providers() {
+        let location = Location { path: "src/lib.rs".into(), line: 3, col: 4 };
+        let mut requests = DefinitionRequests::default();
+        requests.start(1, 5, DefinitionKind::TypeDefinition, false, &[PluginPid(1)]);
+        requests.start(2, 7, DefinitionKind::Definition, false, &[PluginPid(2)]);
+        assert_eq!(requests.replace_plugin(PluginPid(1), PluginPid(3)),
+                   vec![(1, 5, DefinitionKind::TypeDefinition)]);
+        assert_eq!(requests.respond(1, PluginPid(1), Ok(location.clone())), None);
+        assert_eq!(requests.respond(1, PluginPid(3), Ok(location.clone())),
+                   Some((false, Some(location))));
+        assert_eq!(requests.abandon_plugin(PluginPid(2)), vec![2]);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Diagnostics, such as compiler errors and lint warnings, pushed by plugins
+//! and shown inline with the text they apply to.
+//!
+//! Each plugin's diagnostics are kept by source, so that a language server
+//! can replace its compiler errors without disturbing a linter's warnings.
+
+use std::collections::BTreeMap;
+use std::mem;
+
+use xi_rope::delta::{Delta, Transformer};
+use xi_rope::rope::RopeInfo;
+
+use plugins::PluginPid;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiagnosticSpan {
+    /// The utf-8 offsets of the text the diagnostic applies to.
+    pub start: usize,
+    pub end: usize,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    /// What produced the diagnostic, such as "rustc" or "clippy".
+    pub source: String,
+}
+
+/// The diagnostics for a buffer, from all plugins and sources.
+#[derive(Default)]
+pub struct Diagnostics {
+    spans: BTreeMap<(PluginPid, String), Vec<DiagnosticSpan>>,
+}
+
+impl Diagnostics {
+    /// Adds diagnostics from `plugin` to those already present. Spans that
+    /// are reversed, or that don't lie within the `text_len` bytes of the
+    /// text, are dropped.
+    pub fn add(&mut self, plugin: PluginPid, spans: Vec<DiagnosticSpan>, text_len: usize) {
+        for span in spans {
+            if span.start > span.end || span.end > text_len {
+                warn!("dropping diagnostic {}..{} from {:?}, outside the text",
+                      span.start, span.end, plugin);
+                continue;
+            }
+            self.spans.entry((plugin, span.source.clone())).or_default().push(span);
+        }
+    }
+
+    /// Removes the diagnostics `plugin` has added from `source`.
+    pub fn clear(&mut self, plugin: PluginPid, source: &str) {
+        self.spans.remove(&(plugin, source.to_owned()));
+    }
+
+    pub fn remove_plugin(&mut self, plugin: PluginPid) {
+        let keys = self.spans.keys()
+            .filter(|&&(p, _)| p == plugin)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.spans.remove(&key);
+        }
+    }
+
+    /// Moves diagnostics to follow `delta`. Text inserted at either end of a
+    /// span is not part of it, and a span whose text is deleted is dropped.
+    pub fn update(&mut self, delta: &Delta<RopeInfo>) {
+        let mut transformer = Transformer::new(delta);
+        for spans in self.spans.values_mut() {
+            let old = mem::replace(spans, Vec::new());
+            *spans = old.into_iter().filter_map(|mut span| {
+                let start = transformer.transform(span.start, true);
+                let end = transformer.transform(span.end, false).max(start);
+                if start == end && span.start < span.end {
+                    return None;
+                }
+                span.start = start;
+                span.end = end;
+                Some(span)
+            }).collect();
+        }
+        let emptied = self.spans.iter()
+            .filter(|&(_, spans)| spans.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in emptied {
+            self.spans.remove(&key);
+        }
+    }
+
+    /// Returns the diagnostics overlapping `start..end`, from all sources,
+    /// in order of their start. Empty spans at `start` are included.
+    pub fn spans_in_range(&self, start: usize, end: usize) -> Vec<&DiagnosticSpan> {
+        let mut result: Vec<&DiagnosticSpan> = self.spans.values()
+            .flat_map(|spans| spans.iter())
+            .filter(|span| span.start < end && (span.end > start || span.start == start))
+            .collect();
+        result.sort_by_key(|span| (span.start, span.end));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::interval::Interval;
+
+    fn span(start: usize, end: usize, source: &str) -> DiagnosticSpan {
+        DiagnosticSpan {
+            start,
+            end,
+            severity: DiagnosticSeverity::Error,
+            message: "oops".into(),
+            source: source.into(),
+        }
+    }
+
+    #[test]
+    fn merge_sources() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.add(PluginPid(1), vec![span(10, 12, "rustc"), span(2, 4, "clippy")], 20);
+        diagnostics.add(PluginPid(1), vec![span(4, 6, "rustc")], 20);
+        assert_eq!(diagnostics.spans_in_range(0, 20),
+                   vec![&span(2, 4, "clippy"), &span(4, 6, "rustc"), &span(10, 12, "rustc")]);
+        assert_eq!(diagnostics.spans_in_range(4, 10), vec![&span(4, 6, "rustc")]);
+
+        diagnostics.clear(PluginPid(1), "rustc");
+        assert_eq!(diagnostics.spans_in_range(0, 20), vec![&span(2, 4, "clippy")]);
+        // sources belong to the plugin that added them
+        diagnostics.clear(PluginPid(2), "clippy");
+        assert_eq!(diagnostics.spans_in_range(0, 20).len(), 1);
+        diagnostics.remove_plugin(PluginPid(1));
+        assert!(diagnostics.spans_in_range(0, 20).is_empty());
+    }
+
+    #[test]
+    fn follow_edits() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.add(PluginPid(1), vec![span(2, 4, "a"), span(6, 8, "a"), span(10, 10, "a")], 20);
+        // insert at the start of the first span, and delete the second
+        let mut builder = ::xi_rope::delta::Builder::new(20);
+        builder.replace(Interval::new_closed_open(2, 2), "xx".into());
+        builder.replace(Interval::new_closed_open(6, 8), "".into());
+        diagnostics.update(&builder.build());
+        assert_eq!(diagnostics.spans_in_range(0, 20), vec![&span(4, 6, "a"), &span(10, 10, "a")]);
+    }
+
+    #[test]
+    fn reject_bad_spans() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.add(PluginPid(1), vec![span(4, 2, "a"), span(8, 11, "a"), span(8, 10, "a")],
+                        10);
+        assert_eq!(diagnostics.spans_in_range(0, 10), vec![&span(8, 10, "a")]);
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Highlighting the references to the symbol at the caret, as found by the
+//! plugins that provide document highlights. The highlights are cleared
+//! whenever the selection changes.
+
+use std::mem;
+
+use xi_rope::delta::{Delta, Transformer};
+use xi_rope::rope::RopeInfo;
+
+/// How a highlighted range refers to the symbol.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HighlightKind {
+    /// A textual occurrence.
+    Text,
+    /// A read of the symbol, such as a use of a variable.
+    Read,
+    /// A write to the symbol, such as an assignment.
+    Write,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DocumentHighlight {
+    /// The utf-8 offsets of the start and end of the range.
+    pub range: (usize, usize),
+    pub kind: HighlightKind,
+}
+
+/// The highlights shown in a view.
+#[derive(Debug, Default)]
+pub struct DocumentHighlights {
+    /// Advanced whenever the highlights are cleared, so that the answers to
+    /// requests made before are dropped.
+    generation: u64,
+    ranges: Vec<DocumentHighlight>,
+}
+
+impl DocumentHighlights {
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The highlights, in increasing order of offset.
+    pub fn ranges(&self) -> &[DocumentHighlight] {
+        &self.ranges
+    }
+
+    /// Forgets the highlights, and any being asked for. Returns the
+    /// highlights forgotten.
+    pub fn clear(&mut self) -> Vec<DocumentHighlight> {
+        self.generation += 1;
+        mem::replace(&mut self.ranges, Vec::new())
+    }
+
+    /// Moves the highlights to follow `delta`.
+    pub fn apply_delta(&mut self, delta: &Delta<RopeInfo>) {
+        let mut transformer = Transformer::new(delta);
+        for highlight in self.ranges.iter_mut() {
+            let (start, end) = highlight.range;
+            highlight.range = (transformer.transform(start, true),
+                               transformer.transform(end, false));
+        }
+    }
+
+    /// Adds the highlights found for a request made at `generation`, in a
+    /// buffer of length `len`; ranges outside of it are dropped. Returns
+    /// the highlights added, or `None` if the request has been superseded.
+    pub fn add(&mut self, generation: u64, highlights: Vec<DocumentHighlight>, len: usize)
+               -> Option<Vec<DocumentHighlight>> {
+        if generation != self.generation {
+            return None;
+        }
+        let added: Vec<_> = highlights.into_iter()
+            .filter(|h| h.range.0 <= h.range.1 && h.range.1 <= len)
+            .collect();
+        self.ranges.extend(added.iter().cloned());
+        self.ranges.sort_by_key(|h| h.range);
+        Some(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::interval::Interval;
+
+    fn highlight(start: usize, end: usize, kind: HighlightKind) -> DocumentHighlight {
+        DocumentHighlight { range: (start, end), kind }
+    }
+
+    #[test]
+    fn highlights() {
+        let mut highlights = DocumentHighlights::default();
+        let generation = highlights.generation();
+        let found = vec![highlight(10, 12, HighlightKind::Read),
+                         highlight(2, 4, HighlightKind::Write),
+                         highlight(18, 30, HighlightKind::Text)];
+        assert_eq!(highlights.add(generation, found, 20).map(|added| added.len()), Some(2));
+        assert_eq!(highlights.ranges(), &[highlight(2, 4, HighlightKind::Write),
+                                          highlight(10, 12, HighlightKind::Read)]);
+
+        assert_eq!(highlights.clear().len(), 2);
+        assert!(highlights.ranges().is_empty());
+        // answers to a request made before the clear are dropped
+        assert_eq!(highlights.add(generation, vec![highlight(0, 1, HighlightKind::Text)], 20),
+                   None);
+        assert!(highlights.ranges().is_empty());
+    }
+
+    #[test]
+    fn highlights_follow_edits() {
+        let mut highlights = DocumentHighlights::default();
+        let generation = highlights.generation();
+        let found = vec![highlight(2, 4, HighlightKind::Write),
+                         highlight(10, 12, HighlightKind::Read)];
+        highlights.add(generation, found, 20);
+        // insert three bytes at 6
+        let delta = Delta::simple_edit(Interval::new_closed_open(6, 6), "abc".into(), 20);
+        highlights.apply_delta(&delta);
+        assert_eq!(highlights.ranges(), &[highlight(2, 4, HighlightKind::Write),
+                                          highlight(13, 15, HighlightKind::Read)]);
+    }
+}
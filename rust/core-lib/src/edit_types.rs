@@ -18,6 +18,8 @@
 //! This simplifies code elsewhere, and makes it easier to route events to
 //! the editor or view as appropriate.
 
+use definition::DefinitionKind;
+use find::SearchScope;
 use movement::Movement;
 use rpc::{Position, GestureType, LineRange, EditNotification, MouseAction, SelectionModifier};
 use view::Size;
@@ -27,15 +29,24 @@ use view::Size;
 pub(crate) enum ViewEvent {
     Move(Movement),
     ModifySelection(Movement),
+    ModifyRectSelection(Movement),
     SelectAll,
+    Deselect,
+    ShrinkSelection,
     Scroll(LineRange),
+    SetViewport { height: usize },
+    FoldRange { start_line: usize, end_line: usize },
+    UnfoldRange { start_line: usize, end_line: usize },
     AddSelectionAbove,
     AddSelectionBelow,
     Click(MouseAction),
     Drag(MouseAction),
     Gesture { line: u64, col: u64, ty: GestureType },
-    GotoLine { line: u64 },
-    Find { chars: String, case_sensitive: bool, regex: bool, whole_words: bool },
+    GotoLine { line: u64, col: u64 },
+    SetMark { name: char },
+    JumpToMark { name: char, line: bool },
+    Find { chars: String, case_sensitive: bool, regex: bool, whole_words: bool,
+           scope: SearchScope },
     FindNext { wrap_around: bool, allow_same: bool, modify_selection: SelectionModifier },
     FindPrevious { wrap_around: bool, allow_same: bool, modify_selection: SelectionModifier },
     FindAll,
@@ -45,6 +56,8 @@ pub(crate) enum ViewEvent {
     Replace { chars: String, preserve_case: bool },
     SelectionForReplace,
     SelectionIntoLines,
+    SelectWord,
+    SelectParagraph,
 }
 
 /// Events that modify the buffer
@@ -54,6 +67,8 @@ pub(crate) enum BufferEvent {
     Transpose,
     Undo,
     Redo,
+    UndoAll,
+    RedoAll,
     Uppercase,
     Lowercase,
     Capitalize,
@@ -67,6 +82,12 @@ pub(crate) enum BufferEvent {
     ReplaceNext,
     ReplaceAll,
     DuplicateLine,
+    MoveLineUp,
+    MoveLineDown,
+    JoinLines,
+    SortLines { case_sensitive: bool },
+    ToggleComment,
+    JumpToUndoBranch { id: usize },
 }
 
 /// An event that needs special handling
@@ -74,9 +95,22 @@ pub(crate) enum SpecialEvent {
     DebugRewrap,
     DebugWrapWidth,
     DebugPrintSpans,
+    ExpandSelection,
     Resize(Size),
     RequestLines(LineRange),
     RequestHover { request_id: usize, position: Option<Position> },
+    RequestDefinition { request_id: usize, position: Option<Position>,
+                        kind: DefinitionKind, peek: bool },
+    PrepareRename { request_id: usize, position: Option<Position> },
+    RenameSymbol { request_id: usize, position: Option<Position>, new_name: String },
+    RequestDocumentHighlight { position: Option<Position> },
+    SetReadOnly(bool),
+    AcceptCompletion(usize),
+    PasteFromHistory(usize),
+    ExpandSnippet(String),
+    StartMacroRecord(char),
+    StopMacroRecord,
+    PlayMacro { register: char, count: usize },
 }
 
 pub(crate) enum EventDomain {
@@ -111,6 +145,8 @@ impl From<EditNotification> for EventDomain {
                 BufferEvent::Insert(chars).into(),
             Paste { chars } =>
                 BufferEvent::Paste(chars).into(),
+            PasteFromHistory { index } =>
+                SpecialEvent::PasteFromHistory(index).into(),
             DeleteForward =>
                 BufferEvent::Delete {
                     movement: Movement::Right,
@@ -195,11 +231,21 @@ impl From<EditNotification> for EventDomain {
             PageDownAndModifySelection =>
                 ViewEvent::ModifySelection(Movement::DownPage).into(),
             SelectAll => ViewEvent::SelectAll.into(),
+            Deselect => ViewEvent::Deselect.into(),
+            ExpandSelection => SpecialEvent::ExpandSelection.into(),
+            ShrinkSelection => ViewEvent::ShrinkSelection.into(),
             AddSelectionAbove => ViewEvent::AddSelectionAbove.into(),
             AddSelectionBelow => ViewEvent::AddSelectionBelow.into(),
             Scroll(range) => ViewEvent::Scroll(range).into(),
+            SetViewport { height, .. } => ViewEvent::SetViewport { height }.into(),
+            FoldRange { start_line, end_line } =>
+                ViewEvent::FoldRange { start_line, end_line }.into(),
+            UnfoldRange { start_line, end_line } =>
+                ViewEvent::UnfoldRange { start_line, end_line }.into(),
             Resize(size) => SpecialEvent::Resize(size).into(),
-            GotoLine { line } => ViewEvent::GotoLine { line }.into(),
+            GotoLine { line, col } => ViewEvent::GotoLine { line, col }.into(),
+            SetMark { name } => ViewEvent::SetMark { name }.into(),
+            JumpToMark { name, line } => ViewEvent::JumpToMark { name, line }.into(),
             RequestLines(range) => SpecialEvent::RequestLines(range).into(),
             Yank => BufferEvent::Yank.into(),
             Transpose => BufferEvent::Transpose.into(),
@@ -209,8 +255,10 @@ impl From<EditNotification> for EventDomain {
                 ViewEvent::Gesture { line, col, ty }.into(),
             Undo => BufferEvent::Undo.into(),
             Redo => BufferEvent::Redo.into(),
-            Find { chars, case_sensitive, regex, whole_words } =>
-                ViewEvent::Find { chars, case_sensitive, regex, whole_words }.into(),
+            UndoAll => BufferEvent::UndoAll.into(),
+            RedoAll => BufferEvent::RedoAll.into(),
+            Find { chars, case_sensitive, regex, whole_words, scope } =>
+                ViewEvent::Find { chars, case_sensitive, regex, whole_words, scope }.into(),
             FindNext { wrap_around, allow_same, modify_selection } =>
                 ViewEvent::FindNext { wrap_around, allow_same, modify_selection }.into(),
             FindPrevious { wrap_around, allow_same, modify_selection } =>
@@ -235,8 +283,50 @@ impl From<EditNotification> for EventDomain {
             SelectionForReplace => ViewEvent::SelectionForReplace.into(),
             RequestHover { request_id, position } =>
                 SpecialEvent::RequestHover { request_id, position }.into(),
+            GotoDefinition { request_id, position } =>
+                SpecialEvent::RequestDefinition {
+                    request_id, position, kind: DefinitionKind::Definition, peek: false,
+                }.into(),
+            GotoTypeDefinition { request_id, position } =>
+                SpecialEvent::RequestDefinition {
+                    request_id, position, kind: DefinitionKind::TypeDefinition, peek: false,
+                }.into(),
+            PeekDefinition { request_id, position } =>
+                SpecialEvent::RequestDefinition {
+                    request_id, position, kind: DefinitionKind::Definition, peek: true,
+                }.into(),
+            DocumentHighlight { position } =>
+                SpecialEvent::RequestDocumentHighlight { position }.into(),
+            PrepareRename { request_id, position } =>
+                SpecialEvent::PrepareRename { request_id, position }.into(),
+            RenameSymbol { request_id, position, new_name } =>
+                SpecialEvent::RenameSymbol { request_id, position, new_name }.into(),
             SelectionIntoLines => ViewEvent::SelectionIntoLines.into(),
+            SelectWord => ViewEvent::SelectWord.into(),
+            SelectParagraph => ViewEvent::SelectParagraph.into(),
             DuplicateLine => BufferEvent::DuplicateLine.into(),
+            MoveLineUp => BufferEvent::MoveLineUp.into(),
+            MoveLineDown => BufferEvent::MoveLineDown.into(),
+            JoinLines => BufferEvent::JoinLines.into(),
+            SortLines => BufferEvent::SortLines { case_sensitive: true }.into(),
+            SortLinesCaseInsensitive =>
+                BufferEvent::SortLines { case_sensitive: false }.into(),
+            ToggleComment => BufferEvent::ToggleComment.into(),
+            JumpToUndoBranch { id } => BufferEvent::JumpToUndoBranch { id }.into(),
+            SetReadOnly { read_only } => SpecialEvent::SetReadOnly(read_only).into(),
+            AcceptCompletion { index } => SpecialEvent::AcceptCompletion(index).into(),
+            ExpandSnippet { snippet } => SpecialEvent::ExpandSnippet(snippet).into(),
+            StartMacroRecord { register } => SpecialEvent::StartMacroRecord(register).into(),
+            StopMacroRecord => SpecialEvent::StopMacroRecord.into(),
+            PlayMacro { register, count } => SpecialEvent::PlayMacro { register, count }.into(),
+            MoveUpAndModifyRectSelection =>
+                ViewEvent::ModifyRectSelection(Movement::Up).into(),
+            MoveDownAndModifyRectSelection =>
+                ViewEvent::ModifyRectSelection(Movement::Down).into(),
+            MoveLeftAndModifyRectSelection =>
+                ViewEvent::ModifyRectSelection(Movement::Left).into(),
+            MoveRightAndModifyRectSelection =>
+                ViewEvent::ModifyRectSelection(Movement::Right).into(),
         }
     }
 }
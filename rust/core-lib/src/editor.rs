@@ -13,8 +13,11 @@
 // limitations under the License.
 
 use std::borrow::{Borrow, Cow};
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::collections::BTreeSet;
+use std::fmt;
+use std::mem;
+use std::time::{Duration, Instant};
 
 use serde_json::Value;
 
@@ -24,20 +27,27 @@ use xi_rope::delta::{self, Delta, Transformer};
 use xi_rope::engine::{Engine, RevId, RevToken};
 use xi_rope::spans::SpansBuilder;
 use xi_trace::trace_block;
+use xi_rpc::RemoteError;
 use xi_rope::tree::Cursor;
 
 use config::BufferItems;
 use event_context::MAX_SIZE_LIMIT;
+use find::{self, FindOptions};
+use formatting::{delta_from_edits, FormatError, TextEdit};
+use diagnostics::Diagnostics;
+use gutter::Gutter;
 use edit_types::BufferEvent;
 use layers::Layers;
 use movement::{Movement, region_movement};
 use plugins::PluginId;
 use plugins::rpc::{PluginEdit, ScopeSpan, TextUnit, GetDataResponse};
 use selection::{Selection, SelRegion};
+use snippet::Snippet;
 use styles::ThemeStyleMap;
+use undo_tree::{BranchId, UndoTree};
 use view::{View, Replace};
 use rpc::SelectionModifier;
-use word_boundaries::WordCursor;
+use word_boundaries::{WordCursor, count_words};
 
 #[cfg(not(feature = "ledger"))]
 pub struct SyncStore;
@@ -64,11 +74,9 @@ pub struct Editor {
     /// The revision of the last save.
     pristine_rev_id: RevId,
     undo_group_id: usize,
-    /// Undo groups that may still be toggled
-    live_undos: Vec<usize>,
-    /// The index of the current undo; subsequent undos are currently 'undone'
-    /// (but may be redone)
-    cur_undo: usize,
+    /// Undo groups that may still be toggled. Groups off the path to the
+    /// current one are currently 'undone' (but may be redone)
+    undo_tree: UndoTree,
     /// undo groups that are undone
     undos: BTreeSet<usize>,
     /// undo groups that are no longer live and should be gc'ed
@@ -76,6 +84,10 @@ pub struct Editor {
 
     this_edit_type: EditType,
     last_edit_type: EditType,
+    /// When the last edit was made, and how long a pause after it starts
+    /// a new undo group.
+    last_edit_time: Instant,
+    undo_grouping_interval: Duration,
 
     revs_in_flight: usize,
 
@@ -86,6 +98,60 @@ pub struct Editor {
     last_synced_rev: RevId,
 
     layers: Layers,
+    gutter: Gutter,
+    diagnostics: Diagnostics,
+
+    /// Statistics for `document_stats`, and the revision they're for.
+    stats: Option<(u64, DocumentStats)>,
+
+    /// The count last returned by `count_occurrences`, with the revision,
+    /// pattern and options it is for.
+    occurrence_count: Option<(u64, String, FindOptions, usize)>,
+
+    /// When set, edits are refused. Kept across reloads of the file.
+    read_only: bool,
+
+    /// Between `start_undo_group` and `end_undo_group`, the group that
+    /// every edit goes into, once the first has been made.
+    forced_undo_group: Option<Option<usize>>,
+
+    /// When to auto-save the buffer, if an edit was made since it last was.
+    auto_save_due: Option<Instant>,
+}
+
+/// Counts reported by the `document_stats` request.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct DocumentStats {
+    pub word_count: usize,
+    pub char_count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditorError {
+    /// The buffer is read-only, and the edit was not made.
+    ReadOnly,
+    /// An offset given for the edit is past the end of the buffer, or
+    /// not on a character boundary.
+    InvalidOffset(usize),
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EditorError::ReadOnly => write!(f, "Buffer is read-only"),
+            EditorError::InvalidOffset(offset) => write!(f, "Invalid offset {}", offset),
+        }
+    }
+}
+
+impl From<EditorError> for RemoteError {
+    fn from(src: EditorError) -> RemoteError {
+        let code = match src {
+            EditorError::ReadOnly => 8,
+            EditorError::InvalidOffset(_) => 25,
+        };
+        RemoteError::custom(code, src.to_string(), None)
+    }
 }
 
 impl Editor {
@@ -112,16 +178,24 @@ impl Editor {
             // GC only works on undone edits or prefixes of the visible edits,
             // but initial file loading can create an edit with undo group 0,
             // so we want to collect that as part of the prefix.
-            live_undos: vec![0],
-            cur_undo: 1,
+            undo_tree: UndoTree::new(0),
             undos: BTreeSet::new(),
             gc_undos: BTreeSet::new(),
             last_edit_type: EditType::Other,
             this_edit_type: EditType::Other,
+            last_edit_time: Instant::now(),
+            undo_grouping_interval: Duration::from_millis(1000),
             layers: Layers::default(),
+            gutter: Gutter::default(),
+            diagnostics: Diagnostics::default(),
             revs_in_flight: 0,
             sync_store: None,
             last_synced_rev: last_rev_id,
+            stats: None,
+            occurrence_count: None,
+            read_only: false,
+            forced_undo_group: None,
+            auto_save_due: None,
         }
     }
 
@@ -137,6 +211,22 @@ impl Editor {
         &mut self.layers
     }
 
+    pub(crate) fn get_gutter(&self) -> &Gutter {
+        &self.gutter
+    }
+
+    pub(crate) fn get_gutter_mut(&mut self) -> &mut Gutter {
+        &mut self.gutter
+    }
+
+    pub(crate) fn get_diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub(crate) fn get_diagnostics_mut(&mut self) -> &mut Diagnostics {
+        &mut self.diagnostics
+    }
+
     pub(crate) fn get_head_rev_token(&self) -> u64 {
         self.engine.get_head_rev_id().token()
     }
@@ -146,7 +236,19 @@ impl Editor {
     }
 
     pub(crate) fn get_active_undo_group(&self) -> usize {
-        *self.live_undos.last().unwrap_or(&0)
+        self.undo_tree.current()
+    }
+
+    /// Puts the edits made until `end_undo_group` into a single new undo
+    /// group, whatever their kind.
+    pub(crate) fn start_undo_group(&mut self) {
+        self.forced_undo_group = Some(None);
+    }
+
+    pub(crate) fn end_undo_group(&mut self) {
+        self.forced_undo_group = None;
+        // the next edit starts a group of its own
+        self.last_edit_type = EditType::Other;
     }
 
     pub(crate) fn update_edit_type(&mut self) {
@@ -154,22 +256,77 @@ impl Editor {
         self.this_edit_type = EditType::Other
     }
 
+    /// Puts off auto-saving until `due`. Returns `true` if no auto-save was
+    /// pending, and a timer should be set.
+    pub(crate) fn schedule_auto_save(&mut self, due: Instant) -> bool {
+        mem::replace(&mut self.auto_save_due, Some(due)).is_none()
+    }
+
+    /// Returns when the pending auto-save is due, if there is one.
+    pub(crate) fn auto_save_due(&self) -> Option<Instant> {
+        self.auto_save_due
+    }
+
+    /// Notes that the pending auto-save was made, or given up.
+    pub(crate) fn clear_auto_save(&mut self) {
+        self.auto_save_due = None;
+    }
+
     pub(crate) fn set_pristine(&mut self) {
         self.pristine_rev_id = self.engine.get_head_rev_id();
     }
 
+    /// Marks the text as of `rev`, which may have been edited since, as the
+    /// text that was saved.
+    pub(crate) fn set_pristine_rev(&mut self, rev: RevId) {
+        self.pristine_rev_id = rev;
+    }
+
+    pub(crate) fn get_head_rev_id(&self) -> RevId {
+        self.engine.get_head_rev_id()
+    }
+
     pub(crate) fn is_pristine(&self) -> bool {
         self.engine.is_equivalent_revision(self.pristine_rev_id,
                                            self.engine.get_head_rev_id())
     }
 
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     /// Sets this Editor's contents to `text`, preserving undo state and cursor
     /// position when possible.
+    ///
+    /// Only the region between the longest common prefix and suffix of the
+    /// old and new contents is replaced, so that selections outside it are
+    /// kept, and the reload can be undone like any other edit.
     pub fn reload(&mut self, text: Rope) {
-        let mut builder = delta::Builder::new(self.text.len());
-        let all_iv = Interval::new_closed_open(0, self.text.len());
-        builder.replace(all_iv, text);
-        self.add_delta(builder.build());
+        let (old, new) = (String::from(&self.text), String::from(&text));
+        let mut prefix = old.bytes().zip(new.bytes()).take_while(|&(a, b)| a == b).count();
+        while !old.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let max_suffix = old.len().min(new.len()) - prefix;
+        let mut suffix = old.bytes().rev().zip(new.bytes().rev())
+            .take(max_suffix)
+            .take_while(|&(a, b)| a == b)
+            .count();
+        while !old.is_char_boundary(old.len() - suffix) {
+            suffix -= 1;
+        }
+
+        if prefix + suffix < old.len().max(new.len()) {
+            self.this_edit_type = EditType::Other;
+            let mut builder = delta::Builder::new(self.text.len());
+            let iv = Interval::new_closed_open(prefix, old.len() - suffix);
+            builder.replace(iv, text.subseq(Interval::new_closed_open(prefix, new.len() - suffix)));
+            self.add_delta(builder.build());
+        }
         self.set_pristine();
     }
 
@@ -210,33 +367,42 @@ impl Editor {
     /// `commit_delta` call.
     fn add_delta(&mut self, delta: Delta<RopeInfo>) {
         let head_rev_id = self.engine.get_head_rev_id();
+        self.add_delta_at(head_rev_id.token(), delta);
+    }
+
+    /// Records a delta made against the revision `rev`, which the engine
+    /// rebases over any edits since.
+    fn add_delta_at(&mut self, rev: RevToken, delta: Delta<RopeInfo>) {
         let undo_group;
 
-        if !self.this_edit_type.breaks_undo_group(self.last_edit_type)
-            && !self.live_undos.is_empty()
-        {
-            undo_group = *self.live_undos.last().unwrap();
+        let paused = self.last_edit_time.elapsed() > self.undo_grouping_interval;
+        if let Some(Some(group)) = self.forced_undo_group {
+            undo_group = group;
+        } else if self.forced_undo_group.is_none() && !paused
+            && !self.this_edit_type.breaks_undo_group(self.last_edit_type) {
+            undo_group = self.undo_tree.current();
         } else {
             undo_group = self.undo_group_id;
-            self.gc_undos.extend(&self.live_undos[self.cur_undo..]);
-            self.live_undos.truncate(self.cur_undo);
-            self.live_undos.push(undo_group);
-            if self.live_undos.len() <= MAX_UNDOS {
-                self.cur_undo += 1;
-            } else {
-                self.gc_undos.insert(self.live_undos.remove(0));
-            }
+            self.undo_tree.push(undo_group);
+            self.gc_undos.extend(self.undo_tree.prune(MAX_UNDOS));
             self.undo_group_id += 1;
+            if self.forced_undo_group.is_some() {
+                self.forced_undo_group = Some(Some(undo_group));
+            }
         }
         self.last_edit_type = self.this_edit_type;
+        self.last_edit_time = Instant::now();
         let priority = 0x10000;
-        self.engine.edit_rev(priority, undo_group, head_rev_id.token(), delta);
+        self.engine.edit_rev(priority, undo_group, rev, delta);
         self.text = self.engine.get_head().clone();
     }
 
     /// generates a delta from a plugin's response and applies it to the buffer.
-    pub fn apply_plugin_edit(&mut self, edit: PluginEdit) {
+    pub fn apply_plugin_edit(&mut self, edit: PluginEdit) -> Result<(), EditorError> {
         let _t = trace_block("Editor::apply_plugin_edit", &["core"]);
+        if self.read_only {
+            return Err(EditorError::ReadOnly);
+        }
         let undo_group = edit.undo_group;
         if let Some(undo_group) = undo_group {
             // non-async edits modify their associated revision
@@ -249,13 +415,15 @@ impl Editor {
         else {
             self.add_delta(edit.delta);
         }
+        Ok(())
     }
 
     /// Commits the current delta. If the buffer has changed, returns
-    /// a 3-tuple containing the delta representing the changes, the previous
-    /// buffer, and a bool indicating whether selections should be preserved.
+    /// a 4-tuple containing the delta representing the changes, the previous
+    /// buffer, a bool indicating whether selections should be preserved, and
+    /// a bool indicating whether selections should move after insertions.
     pub(crate) fn commit_delta(&mut self)
-        -> Option<(Delta<RopeInfo>, Rope, bool)> {
+        -> Option<(Delta<RopeInfo>, Rope, bool, bool)> {
         let _t = trace_block("Editor::commit_delta", &["core"]);
 
         if self.engine.get_head_rev_id() == self.last_rev_id {
@@ -269,12 +437,19 @@ impl Editor {
         let last_text = self.engine.get_rev(last_token)
             .expect("last_rev not found");
 
-        let keep_selections = self.this_edit_type == EditType::Transpose;
+        let keep_selections = self.this_edit_type == EditType::Transpose
+            || self.this_edit_type == EditType::SortLines
+            || self.this_edit_type == EditType::ToggleComment;
+        // when lines move up, the line they pass over is reinserted at the
+        // end of the selection, which must stay before it
+        let after = self.this_edit_type != EditType::MoveLineUp;
         self.layers.update_all(&delta);
+        self.gutter.update(&delta, &last_text, &self.text);
+        self.diagnostics.update(&delta);
 
         self.last_rev_id = self.engine.get_head_rev_id();
         self.sync_state_changed();
-        Some((delta, last_text, keep_selections))
+        Some((delta, last_text, keep_selections, after))
     }
 
     #[cfg(not(target_os = "fuchsia"))]
@@ -440,7 +615,60 @@ impl Editor {
 
     fn insert_newline(&mut self, view: &View, config: &BufferItems) {
         self.this_edit_type = EditType::InsertNewline;
-        self.insert(view, &config.line_ending);
+        if !config.auto_indent {
+            self.insert(view, &config.line_ending);
+            return;
+        }
+
+        let mut builder = delta::Builder::new(self.text.len());
+        for region in view.sel_regions() {
+            let text = self.newline_with_indent(config, region.min());
+            let iv = Interval::new_closed_open(region.min(), region.max());
+            builder.replace(iv, Rope::from(text));
+        }
+        self.add_delta(builder.build());
+    }
+
+    /// Returns the text to insert for a newline at `offset`: the line ending,
+    /// followed by the leading whitespace of the current line, plus one
+    /// more level if the line ends in one of `indent_after_chars`.
+    fn newline_with_indent(&self, config: &BufferItems, offset: usize) -> String {
+        let line_start = self.text.offset_of_line(self.text.line_of_offset(offset));
+        let before = self.text.slice_to_cow(line_start..offset);
+        let indent_len = before.find(|c| c != ' ' && c != '\t')
+            .unwrap_or_else(|| before.len());
+
+        let mut result = config.line_ending.clone();
+        result.push_str(&before[..indent_len]);
+        let increase = before.trim_right().chars().next_back()
+            .map(|c| config.indent_after_chars.contains(c))
+            .unwrap_or(false);
+        if increase {
+            result.push_str(self.get_tab_text(config, None));
+        }
+        result
+    }
+
+    /// If only whitespace precedes `offset` on its line, returns the offset
+    /// one indentation level earlier; otherwise returns `offset` unchanged.
+    fn dedent_start(&self, config: &BufferItems, offset: usize) -> usize {
+        let line_start = self.text.offset_of_line(self.text.line_of_offset(offset));
+        let before = self.text.slice_to_cow(line_start..offset);
+        if before.is_empty() || !before.chars().all(|c| c == ' ' || c == '\t') {
+            return offset;
+        }
+        if before.ends_with('\t') {
+            return offset - 1;
+        }
+
+        let tab_size = config.tab_size.max(1);
+        let width = before.chars().fold(0, |width, c| match c {
+            '\t' => width + tab_size - width % tab_size,
+            _ => width + 1,
+        });
+        let trailing_spaces = before.len() - before.trim_right_matches(' ').len();
+        let n = ((width - 1) % tab_size + 1).min(trailing_spaces);
+        offset - n
     }
 
     fn insert_tab(&mut self, view: &View, config: &BufferItems) {
@@ -496,7 +724,7 @@ impl Editor {
         }
         match direction {
             IndentDirection::In =>  self.indent(view, lines, tab_text),
-            IndentDirection::Out => self.outdent(view, lines, tab_text)
+            IndentDirection::Out => self.outdent(view, lines, config.tab_size)
          };
 
     }
@@ -509,27 +737,35 @@ impl Editor {
             builder.replace(interval, Rope::from(tab_text));
 
         }
-        self.this_edit_type = EditType::InsertChars;
         self.add_delta(builder.build());
     }
 
-    fn outdent(&mut self, view: &View, lines: BTreeSet<usize>, tab_text: &str) {
+    /// Removes up to one level (`tab_size` columns) of leading whitespace
+    /// from each line, whether it is made of spaces, tabs, or both.
+    fn outdent(&mut self, view: &View, lines: BTreeSet<usize>, tab_size: usize) {
+        let tab_size = tab_size.max(1);
         let mut builder = delta::Builder::new(self.text.len());
         for line in lines {
             let offset = view.line_col_to_offset(&self.text, line, 0);
-            let tab_offset = view.line_col_to_offset(&self.text, line,
-                                                     tab_text.len());
-            let interval = Interval::new_closed_open(offset, tab_offset);
-            let leading_slice = self.text.slice_to_cow(interval.start()..interval.end());
-            if leading_slice == tab_text {
-                builder.delete(interval);
-            } else if let Some(first_char_col) = leading_slice.find(|c: char| !c.is_whitespace()) {
-                let first_char_offset = view.line_col_to_offset(&self.text, line, first_char_col);
-                let interval = Interval::new_closed_open(offset, first_char_offset);
-                builder.delete(interval);
-            }
-        }
-        self.this_edit_type = EditType::Delete;
+            let line_end = view.line_col_to_offset(&self.text, line, tab_size);
+            let leading_slice = self.text.slice_to_cow(offset..line_end);
+            let mut width = 0;
+            let mut len = 0;
+            for c in leading_slice.chars() {
+                match c {
+                    ' ' => width += 1,
+                    '\t' => width += tab_size - width % tab_size,
+                    _ => break,
+                }
+                len += 1;
+                if width >= tab_size {
+                    break;
+                }
+            }
+            if len > 0 {
+                builder.delete(Interval::new_closed_open(offset, offset + len));
+            }
+        }
         self.add_delta(builder.build());
     }
 
@@ -544,9 +780,24 @@ impl Editor {
         tab_text
     }
 
-    fn do_insert(&mut self, view: &View, chars: &str) {
+    fn do_insert(&mut self, view: &View, config: &BufferItems, chars: &str) {
         self.this_edit_type = EditType::InsertChars;
-        self.insert(view, chars);
+        let dedent = config.auto_indent && chars.chars().count() == 1
+            && config.dedent_on_chars.contains(chars);
+        if !dedent {
+            self.insert(view, chars);
+            return;
+        }
+
+        let mut builder = delta::Builder::new(self.text.len());
+        let mut last_end = 0;
+        for region in view.sel_regions() {
+            let start = self.dedent_start(config, region.min()).max(last_end);
+            let iv = Interval::new_closed_open(start, region.max());
+            builder.replace(iv, Rope::from(chars));
+            last_end = region.max();
+        }
+        self.add_delta(builder.build());
     }
 
     fn do_paste(&mut self, view: &View, chars: &str) {
@@ -564,10 +815,139 @@ impl Editor {
         }
     }
 
-    pub(crate) fn do_cut(&mut self, view: &mut View) -> Value {
+    /// Replaces the last selection with the text of `snippet`, returning
+    /// the offset it was inserted at.
+    pub(crate) fn expand_snippet(&mut self, view: &View, snippet: &Snippet)
+        -> Result<usize, EditorError>
+    {
+        if self.read_only {
+            return Err(EditorError::ReadOnly);
+        }
+        let region = *view.sel_regions().last().unwrap();
+        let iv = Interval::new_closed_open(region.min(), region.max());
+        let mut builder = delta::Builder::new(self.text.len());
+        builder.replace(iv, snippet.text.as_str().into());
+        self.add_delta(builder.build());
+        Ok(region.min())
+    }
+
+    /// Replaces the text from `start` to the last caret with `text`, or
+    /// inserts it at `start` if the caret is before it. `start` must be a
+    /// character boundary in the buffer.
+    pub(crate) fn insert_completion(&mut self, view: &View, start: usize, text: &str)
+        -> Result<(), EditorError>
+    {
+        if self.read_only {
+            return Err(EditorError::ReadOnly);
+        }
+        if start > self.text.len() || !self.text.is_codepoint_boundary(start) {
+            return Err(EditorError::InvalidOffset(start));
+        }
+        let caret = view.sel_regions().last().map_or(start, |region| region.end);
+        let iv = Interval::new_closed_open(start, max(start, caret));
+        let mut builder = delta::Builder::new(self.text.len());
+        builder.replace(iv, text.into());
+        self.add_delta(builder.build());
+        Ok(())
+    }
+
+    /// Makes edits to revision `rev`, such as a formatting provider's or a
+    /// code action's, as one undo group, rebased over anything typed since.
+    pub(crate) fn apply_text_edits(&mut self, rev: RevToken, edits: &[TextEdit])
+        -> Result<(), FormatError>
+    {
+        if let Some(delta) = self.delta_for_text_edits(rev, edits)? {
+            self.this_edit_type = EditType::Other;
+            self.add_delta_at(rev, delta);
+        }
+        Ok(())
+    }
+
+    /// Removes the whitespace at the ends of lines, adds a line break at the
+    /// end of the text and removes the extra ones there, if `config` asks
+    /// for them, as one undo group.
+    /// The lines the carets of `view` are on keep their whitespace, so as
+    /// not to disturb typing. Returns `true` if the text has changed.
+    pub(crate) fn clean_up_for_save(&mut self, view: &View, config: &BufferItems) -> bool {
+        let mut builder = delta::Builder::new(self.text.len());
+        let mut changed = false;
+        if config.trim_trailing_whitespace {
+            let caret_lines = view.sel_regions().iter()
+                .map(|region| self.text.line_of_offset(region.end))
+                .collect::<BTreeSet<_>>();
+            let mut offset = 0;
+            for (line_num, line) in self.text.lines_raw(..).enumerate() {
+                let content = line.trim_right_matches(&['\n', '\r'][..]);
+                let trimmed = content.trim_right_matches(&[' ', '\t'][..]);
+                if trimmed.len() < content.len() && !caret_lines.contains(&line_num) {
+                    builder.delete(Interval::new_closed_open(offset + trimmed.len(),
+                                                             offset + content.len()));
+                    changed = true;
+                }
+                offset += line.len();
+            }
+        }
+        let len = self.text.len();
+        if config.trim_final_newlines {
+            let mut content_end = len;
+            while content_end > 0 {
+                match self.text.byte_at(content_end - 1) {
+                    b'\n' | b'\r' => content_end -= 1,
+                    _ => break,
+                }
+            }
+            // keep the first line break after the content
+            let mut keep_end = content_end;
+            if self.text.slice_to_cow(content_end..len).starts_with("\r\n") {
+                keep_end += 2;
+            } else if keep_end < len {
+                keep_end += 1;
+            }
+            if keep_end < len {
+                builder.delete(Interval::new_closed_open(keep_end, len));
+                changed = true;
+            }
+        }
+        if config.insert_final_newline && len > 0 && self.text.byte_at(len - 1) != b'\n' {
+            builder.replace(Interval::new_closed_open(len, len), Rope::from(&config.line_ending));
+            changed = true;
+        }
+        if changed {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(builder.build());
+        }
+        changed
+    }
+
+    /// Checks that `apply_text_edits` would succeed, without editing.
+    pub(crate) fn check_text_edits(&self, rev: RevToken, edits: &[TextEdit])
+        -> Result<(), FormatError>
+    {
+        self.delta_for_text_edits(rev, edits).map(|_| ())
+    }
+
+    fn delta_for_text_edits(&self, rev: RevToken, edits: &[TextEdit])
+        -> Result<Option<Delta<RopeInfo>>, FormatError>
+    {
+        if self.read_only {
+            return Err(FormatError::ReadOnly);
+        }
+        if edits.is_empty() {
+            return Ok(None);
+        }
+        match self.get_rev(rev) {
+            Some(text) => delta_from_edits(&text, edits).map(Some),
+            None => Err(FormatError::InvalidEdit("unknown revision".into())),
+        }
+    }
+
+    pub(crate) fn do_cut(&mut self, view: &mut View) -> Result<Value, EditorError> {
+        if self.read_only {
+            return Err(EditorError::ReadOnly);
+        }
         let result = self.do_copy(view);
         self.delete_sel_regions(&view.sel_regions());
-        result
+        Ok(result)
     }
 
     pub(crate) fn do_copy(&self, view: &View) -> Value {
@@ -579,23 +959,114 @@ impl Editor {
     }
 
     fn do_undo(&mut self) {
-        if self.cur_undo > 1 {
-            self.cur_undo -= 1;
-            assert!(self.undos.insert(self.live_undos[self.cur_undo]));
+        if let Some(group) = self.undo_tree.undo() {
+            assert!(self.undos.insert(group));
             self.this_edit_type = EditType::Undo;
             self.update_undos();
         }
     }
 
     fn do_redo(&mut self) {
-        if self.cur_undo < self.live_undos.len() {
-            assert!(self.undos.remove(&self.live_undos[self.cur_undo]));
-            self.cur_undo += 1;
+        if let Some(group) = self.undo_tree.redo() {
+            assert!(self.undos.remove(&group));
             self.this_edit_type = EditType::Redo;
             self.update_undos();
         }
     }
 
+    /// Undoes every live undo group, updating the text once.
+    fn do_undo_all(&mut self) {
+        let mut changed = false;
+        while let Some(group) = self.undo_tree.undo() {
+            assert!(self.undos.insert(group));
+            changed = true;
+        }
+        if changed {
+            self.this_edit_type = EditType::Undo;
+            self.update_undos();
+        }
+    }
+
+    /// Redoes undo groups until there is nothing left to redo, updating
+    /// the text once.
+    fn do_redo_all(&mut self) {
+        let mut changed = false;
+        while let Some(group) = self.undo_tree.redo() {
+            assert!(self.undos.remove(&group));
+            changed = true;
+        }
+        if changed {
+            self.this_edit_type = EditType::Redo;
+            self.update_undos();
+        }
+    }
+
+    /// Moves to the state just after undo group `id`, undoing and redoing
+    /// groups as needed. Does nothing if `id` is not a live undo group.
+    fn jump_to_undo_branch(&mut self, id: BranchId) {
+        if let Some((undone, redone)) = self.undo_tree.jump_to_branch(id) {
+            if undone.is_empty() && redone.is_empty() {
+                return;
+            }
+            for group in undone {
+                assert!(self.undos.insert(group));
+            }
+            for group in redone {
+                assert!(self.undos.remove(&group));
+            }
+            self.this_edit_type = EditType::Undo;
+            self.update_undos();
+        }
+    }
+
+    /// Returns the word and character counts of the buffer. These are
+    /// computed at most once per revision.
+    pub(crate) fn document_stats(&mut self) -> DocumentStats {
+        let rev = self.get_head_rev_token();
+        match self.stats {
+            Some((computed_rev, stats)) if computed_rev == rev => stats,
+            _ => {
+                let char_count = self.text.iter_chunks(..self.text.len())
+                    .map(|chunk| chunk.chars().count())
+                    .sum();
+                let stats = DocumentStats { word_count: count_words(&self.text), char_count };
+                self.stats = Some((rev, stats));
+                stats
+            }
+        }
+    }
+
+    pub fn word_count(&mut self) -> usize {
+        self.document_stats().word_count
+    }
+
+    pub fn char_count(&mut self) -> usize {
+        self.document_stats().char_count
+    }
+
+    /// Returns the number of non-overlapping matches of `pattern` in the
+    /// buffer. The count is kept until the text, pattern or options change.
+    pub(crate) fn count_occurrences(&mut self, pattern: &str, options: FindOptions) -> usize {
+        let rev = self.get_head_rev_token();
+        if let Some((counted_rev, ref counted, counted_options, count)) = self.occurrence_count {
+            if counted_rev == rev && counted == pattern && counted_options == options {
+                return count;
+            }
+        }
+        let count = find::count_occurrences(&self.text, pattern, options);
+        self.occurrence_count = Some((rev, pattern.to_owned(), options, count));
+        count
+    }
+
+    /// Returns the current undo group and the live undo groups, each with
+    /// its parent and children.
+    pub(crate) fn list_undo_branches(&self) -> Value {
+        json!({
+            "current": self.undo_tree.current(),
+            "branches": self.undo_tree.branches(),
+        })
+    }
+
     fn update_undos(&mut self) {
         self.engine.undo(self.undos.clone());
         self.text = self.engine.get_head().clone();
@@ -650,8 +1121,19 @@ impl Editor {
     }
 
     fn replace(&mut self, view: &mut View, replace_all: bool) {
-        if let Some(Replace { chars, .. }) = view.get_replace() {
-            // todo: implement preserve case
+        if let Some(Replace { chars, preserve_case }) = view.get_replace() {
+            if replace_all {
+                // all replacements go into one delta, so one undo reverts them
+                if let Some(delta) = view.replace_all_delta(&self.text, &chars, preserve_case) {
+                    let (iv, new_len) = delta.summary();
+                    if !iv.is_empty() || new_len > 0 {
+                        self.this_edit_type = EditType::Other;
+                        self.add_delta(delta);
+                    }
+                }
+                return;
+            }
+
             // store old selection because in case nothing is found the selection will be preserved
             let mut old_selection = Selection::new();
             for &region in view.sel_regions() {
@@ -659,13 +1141,16 @@ impl Editor {
             }
             view.collapse_selections(&self.text);
 
-            if replace_all {
-                view.do_find_all(&self.text);
-            } else {
-                view.do_find_next(&self.text, false, true, true, &SelectionModifier::Set);
-            }
+            view.do_find_next(&self.text, false, true, true, &SelectionModifier::Set);
 
             match last_selection_region(view.sel_regions()) {
+                Some(region) if preserve_case => {
+                    let replacement = {
+                        let matched = self.text.slice_to_cow(region.min()..region.max());
+                        find::preserve_case(&matched, &chars)
+                    };
+                    self.insert(view, replacement)
+                }
                 Some(_) => self.insert(view, chars),
                 None => return,
             };
@@ -761,13 +1246,304 @@ impl Editor {
             }
         }
 
-        self.this_edit_type = EditType::Other;
+        self.this_edit_type = EditType::DuplicateLine;
+        self.add_delta(builder.build());
+    }
+
+    /// Returns the ranges of lines touched by the selections, merging
+    /// ranges that overlap, and also those that are adjacent if
+    /// `merge_adjacent` is set. A selection ending at the start of a line
+    /// does not include that line.
+    fn selected_line_blocks(&self, view: &View, merge_adjacent: bool) -> Vec<(usize, usize)> {
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        for region in view.sel_regions() {
+            let first = self.text.line_of_offset(region.min());
+            let mut last = self.text.line_of_offset(region.max());
+            if last > first && self.text.offset_of_line(last) == region.max() {
+                last -= 1;
+            }
+            if let Some(block) = blocks.last_mut() {
+                if first <= block.1 + merge_adjacent as usize {
+                    block.1 = block.1.max(last);
+                    continue;
+                }
+            }
+            blocks.push((first, last));
+        }
+        blocks
+    }
+
+    /// Returns the offset where the line ending of the line ending at
+    /// `line_end` begins, or `line_end` if the line has no ending.
+    fn line_ending_start(&self, line_end: usize) -> usize {
+        let ending = self.text.slice_to_cow(line_end.saturating_sub(2)..line_end);
+        if ending.ends_with("\r\n") {
+            line_end - 2
+        } else if ending.ends_with('\n') {
+            line_end - 1
+        } else {
+            line_end
+        }
+    }
+
+    /// Joins the lines touched by each selection, replacing each line break
+    /// and the indentation after it with a single space. A selection within
+    /// one line joins that line with the next.
+    fn join_lines(&mut self, view: &View) {
+        let last_line = self.text.line_of_offset(self.text.len());
+        // ranges of lines to join, as (first, last) inclusive
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for region in view.sel_regions() {
+            let first = self.text.line_of_offset(region.min());
+            let last = self.text.line_of_offset(region.max());
+            let last = if last == first { min(first + 1, last_line) } else { last };
+            if let Some(range) = ranges.last_mut() {
+                if first <= range.1 {
+                    range.1 = range.1.max(last);
+                    continue;
+                }
+            }
+            ranges.push((first, last));
+        }
+
+        let mut builder = delta::Builder::new(self.text.len());
+        for (first, last) in ranges {
+            for line in first..last {
+                let next_start = self.text.offset_of_line(line + 1);
+                let break_start = self.line_ending_start(next_start);
+                let next_end = self.text.offset_of_line(line + 2);
+                let next_line = self.text.slice_to_cow(next_start..next_end);
+                let indent = next_line.len() - next_line.trim_left_matches(|c| c == ' ' || c == '\t').len();
+                let content_start = next_start + indent;
+                let joiner = match next_line[indent..].chars().next() {
+                    None | Some('\n') | Some('\r') => "",
+                    _ => " ",
+                };
+                builder.replace(Interval::new_closed_open(break_start, content_start),
+                                Rope::from(joiner));
+            }
+        }
+        if !builder.is_empty() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(builder.build());
+        }
+    }
+
+    /// Sorts the lines touched by each selection. Each selection is sorted
+    /// separately; selections sharing a line are sorted together. Afterwards
+    /// each sorted selection covers all of its lines.
+    fn sort_lines(&mut self, view: &mut View, config: &BufferItems, case_sensitive: bool) {
+        let mut builder = delta::Builder::new(self.text.len());
+        let mut sorted_ranges = Vec::new();
+        for (first, last) in self.selected_line_blocks(view, false) {
+            if first == last {
+                continue;
+            }
+            // the final line ending is left in place, which keeps the
+            // selections of adjacent blocks apart
+            let start = self.text.offset_of_line(first);
+            let end = self.line_ending_start(self.text.offset_of_line(last + 1));
+            let text = self.text.slice_to_cow(start..end);
+            let mut lines: Vec<&str> = text.lines().collect();
+            if case_sensitive {
+                lines.sort();
+            } else {
+                // lowercase each line once, rather than on every comparison
+                let mut keyed: Vec<(String, &str)> = lines.iter()
+                    .map(|line| (line.to_lowercase(), *line))
+                    .collect();
+                keyed.sort_by(|a, b| a.0.cmp(&b.0));
+                lines = keyed.into_iter().map(|(_, line)| line).collect();
+            }
+            let sorted = lines.join(&config.line_ending);
+            if sorted != text {
+                builder.replace(Interval::new_closed_open(start, end), Rope::from(sorted));
+                sorted_ranges.push((start, end));
+            }
+        }
+        if builder.is_empty() {
+            return;
+        }
+
+        // Selections are preserved across this edit, so widening them
+        // to the replaced ranges makes them cover the sorted lines.
+        let mut new_sel = Selection::new();
+        for &region in view.sel_regions() {
+            let range = sorted_ranges.iter()
+                .find(|&&(start, end)| region.min() >= start && region.max() <= end);
+            match range {
+                Some(&(start, end)) => new_sel.add_region(SelRegion::new(start, end)),
+                None => new_sel.add_region(region),
+            }
+        }
+        view.set_selection(&self.text, new_sel);
+        self.this_edit_type = EditType::SortLines;
+        self.add_delta(builder.build());
+    }
+
+    /// Adds or removes comments using the markers from the buffer's language
+    /// config. If every selection is a non-empty range within one line and
+    /// the language has block comments, each selection is wrapped in (or
+    /// unwrapped from) a block comment; otherwise each selected line gets a
+    /// line comment. Comments are removed only if all targets have them.
+    fn toggle_comment(&mut self, view: &View, config: &BufferItems) {
+        let inline = view.sel_regions().iter().all(|region| !region.is_caret()
+            && self.text.line_of_offset(region.min()) == self.text.line_of_offset(region.max()));
+        let has_block = !config.block_comment_start.is_empty()
+            && !config.block_comment_end.is_empty();
+
+        let builder = if inline && has_block {
+            self.toggle_block_comment(view, config)
+        } else if !config.line_comment.is_empty() {
+            self.toggle_line_comment(view, config)
+        } else {
+            return;
+        };
+        if !builder.is_empty() {
+            self.this_edit_type = EditType::ToggleComment;
+            self.add_delta(builder.build());
+        }
+    }
+
+    fn toggle_block_comment(&self, view: &View, config: &BufferItems) -> delta::Builder<RopeInfo> {
+        let start_marker = config.block_comment_start.as_str();
+        let end_marker = config.block_comment_end.as_str();
+        let is_commented = |region: &SelRegion| {
+            let text = self.text.slice_to_cow(region.min()..region.max());
+            text.len() >= start_marker.len() + end_marker.len()
+                && text.starts_with(start_marker) && text.ends_with(end_marker)
+        };
+        let uncomment = view.sel_regions().iter().all(&is_commented);
+
+        let mut builder = delta::Builder::new(self.text.len());
+        for region in view.sel_regions() {
+            let (start, end) = (region.min(), region.max());
+            if uncomment {
+                builder.delete(Interval::new_closed_open(start, start + start_marker.len()));
+                builder.delete(Interval::new_closed_open(end - end_marker.len(), end));
+            } else if !is_commented(region) {
+                builder.replace(Interval::new_closed_open(start, start), Rope::from(start_marker));
+                builder.replace(Interval::new_closed_open(end, end), Rope::from(end_marker));
+            }
+        }
+        builder
+    }
+
+    fn toggle_line_comment(&self, view: &View, config: &BufferItems) -> delta::Builder<RopeInfo> {
+        let marker = config.line_comment.as_str();
+        // for each non-blank line: its start offset, the length of its
+        // indentation, and the length of its comment marker (and the space
+        // after it) if it has one. Blank lines are left alone.
+        let mut lines = Vec::new();
+        for (first, last) in self.selected_line_blocks(view, false) {
+            for line in first..=last {
+                let start = self.text.offset_of_line(line);
+                let end = self.line_ending_start(self.text.offset_of_line(line + 1));
+                let text = self.text.slice_to_cow(start..end);
+                let content = text.trim_left_matches(|c| c == ' ' || c == '\t');
+                if content.is_empty() {
+                    continue;
+                }
+                let comment_len = if content.starts_with(marker) {
+                    let space = content[marker.len()..].starts_with(' ') as usize;
+                    Some(marker.len() + space)
+                } else {
+                    None
+                };
+                lines.push((start, text.len() - content.len(), comment_len));
+            }
+        }
+
+        let mut builder = delta::Builder::new(self.text.len());
+        if lines.iter().all(|&(_, _, comment_len)| comment_len.is_some()) {
+            for &(start, indent, comment_len) in &lines {
+                let marker_start = start + indent;
+                let marker_end = marker_start + comment_len.unwrap();
+                builder.delete(Interval::new_closed_open(marker_start, marker_end));
+            }
+        } else {
+            // markers line up at the smallest indentation of the lines
+            let indent = lines.iter().map(|&(_, indent, _)| indent).min().unwrap_or(0);
+            let text = format!("{} ", marker);
+            for &(start, _, _) in &lines {
+                let iv = Interval::new_closed_open(start + indent, start + indent);
+                builder.replace(iv, Rope::from(text.as_str()));
+            }
+        }
+        builder
+    }
+
+    /// Moves the lines touched by the selections up by one line. This is a
+    /// no-op if the first line is selected.
+    fn move_line_up(&mut self, view: &View) {
+        let blocks = self.selected_line_blocks(view, true);
+        if blocks.first().map(|b| b.0 == 0).unwrap_or(true) {
+            return;
+        }
+        let mut builder = delta::Builder::new(self.text.len());
+        for (first, last) in blocks {
+            // the line above the block is removed and reinserted after it
+            let prev_start = self.text.offset_of_line(first - 1);
+            let start = self.text.offset_of_line(first);
+            let end = self.text.offset_of_line(last + 1);
+            let prev = self.text.slice(prev_start..start);
+            builder.delete(Interval::new_closed_open(prev_start, start));
+            let iv = Interval::new_closed_open(end, end);
+            if self.line_ending_start(end) == end {
+                // the block is the last line, so it borrows the line ending
+                let prev_body_end = self.line_ending_start(start);
+                let ending = self.text.slice(prev_body_end..start);
+                let body = self.text.slice(prev_start..prev_body_end);
+                builder.replace(iv, ending + body);
+            } else {
+                builder.replace(iv, prev);
+            }
+        }
+        self.this_edit_type = EditType::MoveLineUp;
+        self.add_delta(builder.build());
+    }
+
+    /// Moves the lines touched by the selections down by one line. This is
+    /// a no-op if the last line is selected.
+    fn move_line_down(&mut self, view: &View) {
+        let blocks = self.selected_line_blocks(view, true);
+        let last_line = self.text.line_of_offset(self.text.len());
+        if blocks.last().map(|b| b.1 >= last_line).unwrap_or(true) {
+            return;
+        }
+        let mut builder = delta::Builder::new(self.text.len());
+        for (first, last) in blocks {
+            // the line below the block is removed and reinserted before it
+            let start = self.text.offset_of_line(first);
+            let end = self.text.offset_of_line(last + 1);
+            let next_end = self.text.offset_of_line(last + 2);
+            let iv = Interval::new_closed_open(start, start);
+            if self.line_ending_start(next_end) == next_end {
+                // the next line is the last line, so the block gives up its
+                // line ending
+                let body_end = self.line_ending_start(end);
+                let ending = self.text.slice(body_end..end);
+                let next = self.text.slice(end..next_end);
+                builder.replace(iv, next + ending);
+                builder.delete(Interval::new_closed_open(body_end, next_end));
+            } else {
+                builder.replace(iv, self.text.slice(end..next_end));
+                builder.delete(Interval::new_closed_open(end, next_end));
+            }
+        }
+        self.this_edit_type = EditType::MoveLineDown;
         self.add_delta(builder.build());
     }
 
     pub(crate) fn do_edit(&mut self, view: &mut View, kill_ring: &mut Rope,
-                          config: &BufferItems, cmd: BufferEvent) {
+                          config: &BufferItems, cmd: BufferEvent)
+                          -> Result<(), EditorError> {
         use self::BufferEvent::*;
+        if self.read_only {
+            return Err(EditorError::ReadOnly);
+        }
+        self.undo_grouping_interval =
+            Duration::from_millis(config.undo_grouping_interval_ms);
         match cmd {
             Delete { movement, kill } =>
                 self.delete_by_movement(view, movement, kill, kill_ring),
@@ -775,6 +1551,8 @@ impl Editor {
             Transpose => self.do_transpose(view),
             Undo => self.do_undo(),
             Redo => self.do_redo(),
+            UndoAll => self.do_undo_all(),
+            RedoAll => self.do_redo_all(),
             Uppercase => self.transform_text(view, |s| s.to_uppercase()),
             Lowercase => self.transform_text(view, |s| s.to_lowercase()),
             Capitalize => self.capitalize_text(view),
@@ -782,13 +1560,20 @@ impl Editor {
             Outdent => self.modify_indent(view, config, IndentDirection::Out),
             InsertNewline => self.insert_newline(view, config),
             InsertTab => self.insert_tab(view, config),
-            Insert(chars) => self.do_insert(view, &chars),
+            Insert(chars) => self.do_insert(view, config, &chars),
             Paste(chars) => self.do_paste(view, &chars),
             Yank => self.yank(view, kill_ring),
             ReplaceNext => self.replace(view, false),
             ReplaceAll => self.replace(view, true),
             DuplicateLine => self.duplicate_line(view, config),
+            MoveLineUp => self.move_line_up(view),
+            MoveLineDown => self.move_line_down(view),
+            JoinLines => self.join_lines(view),
+            SortLines { case_sensitive } => self.sort_lines(view, config, case_sensitive),
+            ToggleComment => self.toggle_comment(view, config),
+            JumpToUndoBranch { id } => self.jump_to_undo_branch(id),
         }
+        Ok(())
     }
 
     pub fn theme_changed(&mut self, style_map: &ThemeStyleMap) {
@@ -799,9 +1584,10 @@ impl Editor {
         self.text.measure::<LinesMetric>() + 1
     }
 
+    /// Returns the interval of the text whose styles changed.
     pub fn update_spans(&mut self, view: &mut View, plugin: PluginId,
                         start: usize, len: usize, spans: Vec<ScopeSpan>,
-                        rev: RevToken) {
+                        rev: RevToken) -> Interval {
         let _t = trace_block("Editor::update_spans", &["core"]);
         // TODO: more protection against invalid input
         let mut start = start;
@@ -826,6 +1612,7 @@ impl Editor {
         let iv = Interval::new_closed_closed(start, end_offset);
         self.layers.update_layer(plugin, iv, spans);
         view.invalidate_styles(&self.text, start, end_offset);
+        iv
     }
 
     pub(crate) fn get_rev(&self, rev: RevToken) -> Option<Cow<Rope>> {
@@ -885,6 +1672,15 @@ pub enum EditType {
     Undo,
     Redo,
     Transpose,
+    /// Duplication of the lines containing the selections.
+    DuplicateLine,
+    /// Moving the lines containing the selections up or down.
+    MoveLineUp,
+    MoveLineDown,
+    /// Sorting of the lines containing the selections.
+    SortLines,
+    /// Adding or removing comments.
+    ToggleComment,
 }
 
 impl EditType {
@@ -892,6 +1688,12 @@ impl EditType {
     fn breaks_undo_group(self, previous: EditType) -> bool {
         self == EditType::Other
         || self == EditType::Transpose
+        || self == EditType::Indent
+        || self == EditType::DuplicateLine
+        || self == EditType::MoveLineUp
+        || self == EditType::MoveLineDown
+        || self == EditType::SortLines
+        || self == EditType::ToggleComment
         || self != previous
     }
 }
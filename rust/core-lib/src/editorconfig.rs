@@ -0,0 +1,497 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for [EditorConfig](https://editorconfig.org) files.
+//!
+//! The `.editorconfig` files in the directories above a file are read from
+//! the closest one up to the first marked `root = true`. Their sections
+//! matching the file apply from the farthest file to the closest, and
+//! within a file from top to bottom, later properties overriding earlier
+//! ones. The properties are then converted to a config table.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use config::Table;
+use file::CharacterEncoding;
+
+/// The name of EditorConfig files.
+pub(crate) const EDITORCONFIG_FILE_NAME: &str = ".editorconfig";
+
+/// Property names and values. Both are lowercase, apart from the values
+/// of properties that are not in the spec.
+pub type Properties = BTreeMap<String, String>;
+
+/// The properties whose values are case-insensitive.
+const KNOWN_PROPERTIES: &[&str] = &[
+    "indent_style", "indent_size", "tab_width", "end_of_line", "charset",
+    "trim_trailing_whitespace", "insert_final_newline", "root",
+];
+
+/// A parsed `.editorconfig` file.
+#[derive(Debug, Default)]
+pub struct EditorConfigFile {
+    /// Whether files in parent directories are not to be read.
+    pub root: bool,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+struct Section {
+    glob: Glob,
+    properties: Vec<(String, String)>,
+}
+
+/// A section name, matched against the path of a file relative to the
+/// directory of the `.editorconfig` file.
+#[derive(Debug)]
+struct Glob(Vec<Token>);
+
+#[derive(Debug, Clone)]
+enum Token {
+    Literal(char),
+    /// `?`: any character but `/`.
+    Any,
+    /// `*`: any characters but `/`.
+    Star,
+    /// `**`: any characters.
+    DoubleStar,
+    /// `**/`, and the start of names without a `/`: any number of
+    /// directories.
+    Directories,
+    /// `[name]` or `[!name]`: any character in, or not in, the ranges.
+    Class { negated: bool, ranges: Vec<(char, char)> },
+    /// `{s1,s2,s3}`: any of the alternatives.
+    Alternatives(Vec<Vec<Token>>),
+    /// `{num1..num2}`: an integer between the two, inclusive.
+    Range(i64, i64),
+}
+
+impl EditorConfigFile {
+    /// Parses the contents of a file. Lines that are not understood are
+    /// ignored, as the spec asks.
+    pub fn parse(text: &str) -> EditorConfigFile {
+        let mut file = EditorConfigFile::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let glob = Glob::parse(&line[1..line.len() - 1]);
+                file.sections.push(Section { glob, properties: Vec::new() });
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(idx) => (line[..idx].trim().to_lowercase(), line[idx + 1..].trim()),
+                None => continue,
+            };
+            let value = if KNOWN_PROPERTIES.contains(&key.as_str()) {
+                value.to_lowercase()
+            } else {
+                value.to_owned()
+            };
+            match file.sections.last_mut() {
+                Some(section) => section.properties.push((key, value)),
+                None if key == "root" => file.root = value == "true",
+                None => (),
+            }
+        }
+        file
+    }
+
+    /// Applies the properties of the sections matching `rel_path`, the path
+    /// of a file relative to this file's directory, to `props`.
+    fn apply(&self, rel_path: &str, props: &mut Properties) {
+        for section in self.sections.iter().filter(|s| s.glob.matches(rel_path)) {
+            for (key, value) in &section.properties {
+                if value == "unset" {
+                    props.remove(key);
+                } else {
+                    props.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Returns the EditorConfig properties for the file at `path`, reading the
+/// `.editorconfig` files above it. Directories are canonicalized, and each
+/// is only read once, so symlinks cannot cause a cycle.
+pub fn properties_for_path(path: &Path) -> Properties {
+    let file_path = match path.parent().and_then(|p| p.canonicalize().ok()) {
+        Some(dir) => dir.join(path.file_name().unwrap_or_default()),
+        None => return Properties::new(),
+    };
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    let mut next = file_path.parent().map(Path::to_owned);
+    while let Some(dir) = next {
+        if !visited.insert(dir.clone()) { break }
+        next = dir.parent().map(Path::to_owned);
+        let contents = match fs::read_to_string(dir.join(EDITORCONFIG_FILE_NAME)) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        let file = EditorConfigFile::parse(&contents);
+        let is_root = file.root;
+        files.push((dir, file));
+        if is_root { break }
+    }
+
+    let mut props = Properties::new();
+    for (dir, file) in files.iter().rev() {
+        let rel_path = match file_path.strip_prefix(dir) {
+            Ok(rel_path) => rel_path,
+            Err(_) => continue,
+        };
+        let rel_path = rel_path.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        file.apply(&rel_path, &mut props);
+    }
+    resolve_indentation(&mut props);
+    props
+}
+
+/// Fills in the indentation properties implied by others, as the spec asks.
+fn resolve_indentation(props: &mut Properties) {
+    let is_tab_style = props.get("indent_style").map(String::as_str) == Some("tab");
+    if is_tab_style && !props.contains_key("indent_size") {
+        props.insert("indent_size".into(), "tab".into());
+    }
+    let indent_size = props.get("indent_size").cloned();
+    match indent_size {
+        Some(ref size) if size == "tab" => {
+            if let Some(width) = props.get("tab_width").cloned() {
+                props.insert("indent_size".into(), width);
+            }
+        }
+        Some(size) => {
+            props.entry("tab_width".into()).or_insert(size);
+        }
+        None => (),
+    }
+}
+
+/// Converts EditorConfig properties to the buffer config settings they
+/// correspond to. Properties that are not understood are left out.
+pub fn table_from_properties(props: &Properties) -> Table {
+    let mut table = Table::new();
+    let get = |key: &str| props.get(key).map(String::as_str);
+    match get("indent_style") {
+        Some("space") => { table.insert("translate_tabs_to_spaces".into(), true.into()); }
+        Some("tab") => { table.insert("translate_tabs_to_spaces".into(), false.into()); }
+        _ => (),
+    }
+    let tab_size = get("indent_size").and_then(|s| s.parse::<usize>().ok())
+        .or_else(|| get("tab_width").and_then(|s| s.parse::<usize>().ok()))
+        .filter(|&size| size > 0);
+    if let Some(size) = tab_size {
+        table.insert("tab_size".into(), size.into());
+    }
+    match get("end_of_line") {
+        Some(eol) if ["lf", "crlf", "cr"].contains(&eol) => {
+            table.insert("end_of_line".into(), eol.into());
+        }
+        _ => (),
+    }
+    // latin1 is not supported
+    if let Some(charset) = get("charset").filter(|c| CharacterEncoding::from_charset(c).is_some()) {
+        table.insert("charset".into(), charset.into());
+    }
+    for &key in &["trim_trailing_whitespace", "insert_final_newline"] {
+        match get(key) {
+            Some("true") => { table.insert(key.into(), Value::Bool(true)); }
+            Some("false") => { table.insert(key.into(), Value::Bool(false)); }
+            _ => (),
+        }
+    }
+    table
+}
+
+impl Glob {
+    fn parse(pattern: &str) -> Glob {
+        let chars = pattern.chars().collect::<Vec<_>>();
+        let mut tokens = Vec::new();
+        // names without a slash match files in any directory
+        match chars.iter().position(|&c| c == '/') {
+            Some(0) => tokens.extend(parse_tokens(&chars[1..])),
+            Some(_) => tokens.extend(parse_tokens(&chars)),
+            None => {
+                tokens.push(Token::Directories);
+                tokens.extend(parse_tokens(&chars));
+            }
+        }
+        Glob(tokens)
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let chars = path.chars().collect::<Vec<_>>();
+        match_tokens(&self.0, &chars)
+    }
+}
+
+fn parse_tokens(chars: &[char]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Literal(chars[i + 1]));
+                i += 2;
+                continue;
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    tokens.push(Token::Directories);
+                    i += 3;
+                } else {
+                    tokens.push(Token::DoubleStar);
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Any),
+            '[' => if let Some((token, len)) = parse_class(&chars[i..]) {
+                tokens.push(token);
+                i += len;
+                continue;
+            } else {
+                tokens.push(Token::Literal('['));
+            },
+            '{' => if let Some((mut braced, len)) = parse_braces(&chars[i..]) {
+                tokens.append(&mut braced);
+                i += len;
+                continue;
+            } else {
+                tokens.push(Token::Literal('{'));
+            },
+            c => tokens.push(Token::Literal(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parses the class at the start of `chars`, returning it and its length,
+/// or `None` if it is not closed.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 1;
+    let negated = chars.get(i) == Some(&'!');
+    if negated { i += 1; }
+    let mut ranges = Vec::new();
+    while i < chars.len() {
+        let c = match chars[i] {
+            ']' if !ranges.is_empty() => return Some((Token::Class { negated, ranges }, i + 1)),
+            // a slash ends the class, which is then taken literally
+            '/' => return None,
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                chars[i]
+            }
+            c => c,
+        };
+        if chars.get(i + 1) == Some(&'-') && i + 2 < chars.len() && chars[i + 2] != ']' {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Parses the braces at the start of `chars`, returning their tokens and
+/// length, or `None` if they are not closed.
+fn parse_braces(chars: &[char]) -> Option<(Vec<Token>, usize)> {
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut start = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            ',' if depth == 1 => {
+                alternatives.push(&chars[start..i]);
+                start = i + 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    let inner = &chars[start..i];
+                    let len = i + 1;
+                    if alternatives.is_empty() {
+                        let inner = inner.iter().collect::<String>();
+                        if let Some(range) = parse_range(&inner) {
+                            return Some((vec![range], len));
+                        }
+                        // a single name in braces is taken literally
+                        let mut tokens = vec![Token::Literal('{')];
+                        tokens.extend(parse_tokens(&chars[1..i]));
+                        tokens.push(Token::Literal('}'));
+                        return Some((tokens, len));
+                    }
+                    alternatives.push(inner);
+                    let alternatives = alternatives.into_iter().map(parse_tokens).collect();
+                    return Some((vec![Token::Alternatives(alternatives)], len));
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+fn parse_range(inner: &str) -> Option<Token> {
+    let idx = inner.find("..")?;
+    let start = inner[..idx].parse().ok()?;
+    let end = inner[idx + 2..].parse().ok()?;
+    Some(Token::Range(start, end))
+}
+
+fn match_tokens(tokens: &[Token], path: &[char]) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return path.is_empty(),
+    };
+    match *token {
+        Token::Literal(c) => path.first() == Some(&c) && match_tokens(rest, &path[1..]),
+        Token::Any => path.first().filter(|&&c| c != '/').is_some()
+            && match_tokens(rest, &path[1..]),
+        Token::Class { negated, ref ranges } => match path.first() {
+            Some(&c) if c != '/' => {
+                let in_class = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+                in_class != negated && match_tokens(rest, &path[1..])
+            }
+            _ => false,
+        },
+        Token::Star => {
+            let max = path.iter().position(|&c| c == '/').unwrap_or(path.len());
+            (0..=max).any(|i| match_tokens(rest, &path[i..]))
+        }
+        Token::DoubleStar => (0..=path.len()).any(|i| match_tokens(rest, &path[i..])),
+        Token::Directories => (0..=path.len())
+            .filter(|&i| i == 0 || path[i - 1] == '/')
+            .any(|i| match_tokens(rest, &path[i..])),
+        Token::Alternatives(ref alternatives) => alternatives.iter().any(|alt| {
+            let mut tokens = alt.clone();
+            tokens.extend(rest.iter().cloned());
+            match_tokens(&tokens, path)
+        }),
+        Token::Range(lo, hi) => {
+            let sign = (path.first() == Some(&'-')) as usize;
+            let digits = path[sign..].iter().take_while(|c| c.is_ascii_digit()).count();
+            (1..=digits).any(|len| {
+                let num = path[..sign + len].iter().collect::<String>();
+                let in_range = num.parse::<i64>().map(|n| lo <= n && n <= hi).unwrap_or(false);
+                in_range && match_tokens(rest, &path[sign + len..])
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        Glob::parse(glob).matches(path)
+    }
+
+    #[test]
+    fn globs() {
+        assert!(matches("*", "main.rs"));
+        assert!(matches("*", "src/main.rs"));
+        assert!(matches("*.rs", "src/bin/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(!matches("src/*.rs", "lib/src/main.rs"));
+        assert!(matches("/src/**.rs", "src/bin/main.rs"));
+        assert!(matches("src/**/main.rs", "src/main.rs"));
+        assert!(matches("src/**/main.rs", "src/a/b/main.rs"));
+        assert!(matches("?.c", "a.c"));
+        assert!(!matches("?.c", "ab.c"));
+        assert!(matches("*.{js,ts}", "index.ts"));
+        assert!(!matches("*.{js,ts}", "index.rs"));
+        assert!(matches("{package.json,.travis.yml}", ".travis.yml"));
+        assert!(matches("{a}", "{a}"));
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[!abc].txt", "b.txt"));
+        assert!(matches("[a-c]x", "cx"));
+        assert!(matches("file{1..10}.txt", "file7.txt"));
+        assert!(!matches("file{1..10}.txt", "file11.txt"));
+        assert!(matches("lib/{a,b/{c,d}}.py", "lib/b/d.py"));
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+    }
+
+    #[test]
+    fn sections_and_inheritance() {
+        let tmp = tempdir::TempDir::new("xi-test-editorconfig").unwrap();
+        let root = tmp.path();
+        let src = root.join("project/src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(root.join(EDITORCONFIG_FILE_NAME),
+                  "[*]\nindent_style = space\ntab_width = 8\n").unwrap();
+        fs::write(root.join("project").join(EDITORCONFIG_FILE_NAME), r#"
+# the outer file still applies
+[*]
+end_of_line = LF
+insert_final_newline = true
+
+; later sections win
+[*.rs]
+indent_size = 4
+trim_trailing_whitespace = true
+
+[Makefile]
+indent_style = tab
+"#).unwrap();
+
+        let props = properties_for_path(&src.join("main.rs"));
+        assert_eq!(props.get("indent_style").map(String::as_str), Some("space"));
+        assert_eq!(props.get("indent_size").map(String::as_str), Some("4"));
+        assert_eq!(props.get("tab_width").map(String::as_str), Some("8"));
+        assert_eq!(props.get("end_of_line").map(String::as_str), Some("lf"));
+        let table = table_from_properties(&props);
+        assert_eq!(table.get("tab_size"), Some(&4.into()));
+        assert_eq!(table.get("translate_tabs_to_spaces"), Some(&true.into()));
+        assert_eq!(table.get("trim_trailing_whitespace"), Some(&true.into()));
+        assert_eq!(table.get("insert_final_newline"), Some(&true.into()));
+
+        let props = properties_for_path(&root.join("project/Makefile"));
+        // tab indentation is as wide as a tab
+        assert_eq!(props.get("indent_size").map(String::as_str), Some("8"));
+        let table = table_from_properties(&props);
+        assert_eq!(table.get("translate_tabs_to_spaces"), Some(&false.into()));
+        assert_eq!(table.get("tab_size"), Some(&8.into()));
+
+        // a root file stops the search
+        fs::write(root.join("project").join(EDITORCONFIG_FILE_NAME),
+                  "root = true\n[*.rs]\nindent_size = unset\n").unwrap();
+        let props = properties_for_path(&src.join("main.rs"));
+        assert!(props.is_empty());
+    }
+}
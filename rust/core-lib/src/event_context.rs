@@ -15,34 +15,53 @@
 //! A container for the state relevant to a single event.
 
 use std::cell::RefCell;
+use std::cmp::{max, min};
 use std::iter;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use serde_json::{self, Value};
 
 use xi_rope::Rope;
+use xi_rope::engine::RevId;
 use xi_rope::interval::Interval;
 use xi_rope::rope::LinesMetric;
 use xi_rpc::{RemoteError, Error as RpcError};
 use xi_trace::trace_block;
 
-use rpc::{EditNotification, EditRequest, LineRange, Position as ClientPosition};
+use rpc::{EditNotification, EditRequest, LineRange, Position as ClientPosition, PositionRange};
 use plugins::rpc::{ClientPluginInfo, PluginBufferInfo, PluginNotification,
-                   PluginRequest, PluginUpdate, Hover};
+                   PluginRequest, PluginUpdate, PluginCapability, Hover, CompletionList};
 
 use styles::ThemeStyleMap;
+use clipboard_history::ClipboardHistory;
 use config::{BufferItems, Table};
 
 use WeakXiCore;
-use tabs::{BufferId, PluginId, ViewId, RENDER_VIEW_IDLE_MASK};
-use editor::Editor;
+use tabs::{BufferId, PluginId, ViewId, AUTO_SAVE_TIMER_MASK, BREADCRUMBS_TIMER_MASK,
+           FORMAT_TIMEOUT_MASK, HOVER_TIMEOUT_MASK, RENDER_VIEW_IDLE_MASK};
+use editor::{Editor, EditorError};
+use find::SearchScope;
 use file::FileInfo;
-use edit_types::{EventDomain, SpecialEvent};
+use formatting::{FormatError, TextEdit};
+use inlay_hints::InlayHint;
+use definition::DefinitionKind;
+use document_highlight::DocumentHighlight;
+use edit_types::{BufferEvent, EventDomain, SpecialEvent, ViewEvent};
 use client::Client;
+use breadcrumbs::find_breadcrumbs;
+use macros::{Macros, MAX_PLAY_COUNT};
+use code_actions::{self, CodeAction};
+use minimap::MinimapKey;
+use rename::RenameError;
+use session::ViewSession;
 use plugins::Plugin;
-use selection::SelRegion;
+use plugin_requests::{PluginRequests, RequestKind};
 use syntax::LanguageId;
+use syntax_context::SyntaxContext;
+use snippet::{Snippet, SnippetSession};
+use sticky_header::{enclosing_definition, sticky_lines, StickyLine};
+use streaming::StreamHandler;
 use view::View;
 use width_cache::WidthCache;
 
@@ -73,6 +92,9 @@ pub struct EventContext<'a> {
     pub(crate) style_map: &'a RefCell<ThemeStyleMap>,
     pub(crate) width_cache: &'a RefCell<WidthCache>,
     pub(crate) kill_ring: &'a RefCell<Rope>,
+    pub(crate) clipboard_history: &'a RefCell<ClipboardHistory>,
+    pub(crate) macros: &'a RefCell<Macros>,
+    pub(crate) plugin_requests: &'a RefCell<PluginRequests>,
     pub(crate) weak_core: &'a WeakXiCore,
 }
 
@@ -99,26 +121,51 @@ impl<'a> EventContext<'a> {
         f(&mut view, editor.get_buffer())
     }
 
-    fn with_each_plugin<F: FnMut(&&Plugin)>(&self, f: F) {
-        self.plugins.iter().for_each(f)
+    /// Executes a closure with a mutable reference to each of the other views
+    /// of the buffer, and a reference to the current text.
+    fn with_siblings<F>(&self, mut f: F) where F: FnMut(&mut View, &Rope) {
+        let editor = self.editor.borrow();
+        for sibling in &self.siblings {
+            f(&mut sibling.borrow_mut(), editor.get_buffer());
+        }
     }
 
     pub(crate) fn do_edit(&mut self, cmd: EditNotification) {
         use self::EventDomain as E;
+        self.macros.borrow_mut().record(&cmd);
         let event: EventDomain = cmd.into();
+        let in_snippet = self.view.borrow().has_snippet();
         match event {
+            // while a snippet is being filled in, tab moves between its stops
+            E::Buffer(BufferEvent::InsertTab) if in_snippet =>
+                self.with_view(|view, text| view.move_to_tab_stop(text, true)),
+            E::Buffer(BufferEvent::Outdent) if in_snippet =>
+                self.with_view(|view, text| view.move_to_tab_stop(text, false)),
+            E::View(ViewEvent::Find { chars, case_sensitive, regex, whole_words,
+                                      scope: SearchScope::CurrentFunction }) => {
+                self.find_in_function(chars, case_sensitive, regex, whole_words);
+                self.editor.borrow_mut().update_edit_type();
+            }
             E::View(cmd) => {
-                    self.with_view(|view, text| view.do_edit(text, cmd));
+                    let config = self.config;
+                    self.with_view(|view, text| view.do_edit(text, config, cmd));
                     self.editor.borrow_mut().update_edit_type();
                 },
-            E::Buffer(cmd) => self.with_editor(
-                |ed, view, k_ring, conf| ed.do_edit(view, k_ring, conf, cmd)),
+            E::Buffer(cmd) => self.do_buffer_edit(cmd),
             E::Special(cmd) => self.do_special(cmd),
         }
         self.after_edit("core");
         self.render_if_needed();
     }
 
+    fn do_buffer_edit(&mut self, cmd: BufferEvent) {
+        let result = self.with_editor(
+            |ed, view, k_ring, conf| ed.do_edit(view, k_ring, conf, cmd));
+        if let Err(err) = result {
+            warn!("edit not made: {}", err);
+        }
+    }
+
     fn do_special(&mut self, cmd: SpecialEvent) {
         match cmd {
             SpecialEvent::Resize(size) => {
@@ -135,10 +182,38 @@ impl<'a> EventContext<'a> {
                     let iv = Interval::new_closed_open(sel.min(), sel.max());
                     ed.get_layers().debug_print_spans(iv);
                 }),
+            SpecialEvent::ExpandSelection => self.with_editor(
+                |ed, view, _, _| view.expand_selection(ed.get_buffer(), ed.get_layers())),
             SpecialEvent::RequestLines(LineRange { first, last }) =>
                 self.do_request_lines(first as usize, last as usize),
             SpecialEvent::RequestHover{ request_id, position } =>
-                self.do_request_hover(request_id, position)
+                self.do_request_hover(request_id, position),
+            SpecialEvent::RequestDefinition { request_id, position, kind, peek } =>
+                self.do_request_definition(request_id, position, kind, peek),
+            SpecialEvent::RequestDocumentHighlight { position } =>
+                self.do_request_document_highlight(position),
+            SpecialEvent::PrepareRename { request_id, position } =>
+                self.do_prepare_rename(request_id, position),
+            SpecialEvent::RenameSymbol { request_id, position, new_name } =>
+                self.do_rename_symbol(request_id, position, &new_name),
+            SpecialEvent::AcceptCompletion(index) => self.do_accept_completion(index),
+            SpecialEvent::PasteFromHistory(index) => {
+                let text = self.clipboard_history.borrow().get(index).map(String::from);
+                if let Some(text) = text {
+                    self.do_buffer_edit(BufferEvent::Paste(text));
+                }
+            }
+            SpecialEvent::ExpandSnippet(snippet) => self.do_expand_snippet(&snippet),
+            SpecialEvent::StartMacroRecord(register) =>
+                self.macros.borrow_mut().start_recording(register),
+            SpecialEvent::StopMacroRecord => self.macros.borrow_mut().stop_recording(),
+            SpecialEvent::PlayMacro { register, count } => self.do_play_macro(register, count),
+            SpecialEvent::SetReadOnly(read_only) => {
+                self.with_editor(|ed, _, _, _| ed.set_read_only(read_only));
+                // resend lines so the frontend sees the new flag
+                self.with_view(|view, text| view.set_dirty(text));
+                self.with_siblings(|view, text| view.set_dirty(text));
+            }
         }
     }
 
@@ -146,8 +221,34 @@ impl<'a> EventContext<'a> {
                                ) -> Result<Value, RemoteError> {
         use self::EditRequest::*;
         let result = match cmd {
-            Cut => Ok(self.with_editor(|ed, view, _, _| ed.do_cut(view))),
-            Copy => Ok(self.with_editor(|ed, view, _, _| ed.do_copy(view))),
+            Cut => {
+                let result = self.with_editor(|ed, view, _, _| ed.do_cut(view));
+                if let Ok(Value::String(ref text)) = result {
+                    self.clipboard_history.borrow_mut().push(text.clone());
+                }
+                result.map_err(|e| e.into())
+            }
+            Copy => {
+                let result = self.with_editor(|ed, view, _, _| ed.do_copy(view));
+                if let Value::String(ref text) = result {
+                    self.clipboard_history.borrow_mut().push(text.clone());
+                }
+                Ok(result)
+            }
+            ListUndoBranches => Ok(self.editor.borrow().list_undo_branches()),
+            DocumentStats => Ok(json!(self.editor.borrow_mut().document_stats())),
+            CountOccurrences { pattern, options } =>
+                Ok(json!(self.editor.borrow_mut().count_occurrences(&pattern, options))),
+            RequestInlayHints { first_line, last_line } =>
+                Ok(self.do_request_inlay_hints(first_line, last_line)),
+            FormatDocument => self.do_format_document().map(|()| Value::Null)
+                .map_err(|e| e.into()),
+            GetCodeActions { range } => Ok(self.do_get_code_actions(range)),
+            GetMinimap { target_width, target_height } =>
+                Ok(self.do_get_minimap(target_width, target_height)),
+            GetStickyHeader => Ok(json!(self.sticky_header())),
+            // handled by `CoreState`, as the edits may be to other buffers
+            ApplyCodeAction { .. } => Ok(Value::Null),
         };
         self.after_edit("core");
         self.render_if_needed();
@@ -158,16 +259,26 @@ impl<'a> EventContext<'a> {
                                  cmd: PluginNotification) {
         use self::PluginNotification::*;
         match cmd {
-            AddScopes { scopes } => {
+            AddScopes { scopes, priority } => {
                 let mut ed = self.editor.borrow_mut();
                 let style_map = self.style_map.borrow();
+                if let Some(priority) = priority {
+                    ed.get_layers_mut().set_priority(plugin, priority, &style_map);
+                }
                 ed.get_layers_mut().add_scopes(plugin, scopes, &style_map);
             }
-            UpdateSpans { start, len, spans, rev } => self.with_editor(
-                |ed, view, _, _| ed.update_spans(view, plugin, start,
-                                           len, spans, rev)),
-            Edit { edit } => self.with_editor(
-                |ed, _, _, _| ed.apply_plugin_edit(edit)),
+            UpdateSpans { start, len, spans, rev } => {
+                let iv = self.with_editor(|ed, view, _, _| ed.update_spans(view, plugin, start,
+                                                                         len, spans, rev));
+                self.with_siblings(|view, text| view.invalidate_styles(text, iv.start(),
+                                                                       iv.end()));
+            }
+            Edit { edit } => {
+                let result = self.with_editor(|ed, _, _, _| ed.apply_plugin_edit(edit));
+                if let Err(err) = result {
+                    warn!("edit from plugin {} not made: {}", plugin, err);
+                }
+            }
             Alert { msg } => self.client.alert(&msg),
             AddStatusItem { key, value, alignment }  => {
             	let plugin_name = &self.plugins.iter().find(|p| p.id == plugin).unwrap().name;
@@ -176,23 +287,69 @@ impl<'a> EventContext<'a> {
             UpdateStatusItem { key, value } => self.client.update_status_item(
                                                         self.view_id, &key, &value),
             RemoveStatusItem { key } => self.client.remove_status_item(self.view_id, &key),
-            ShowHover { request_id, result } => self.do_show_hover(request_id, result),
+            RegisterHoverProvider =>
+                self.view.borrow_mut().hover_requests_mut().register_provider(plugin),
+            RegisterDefinitionProvider =>
+                self.view.borrow_mut().definition_requests_mut().register_provider(plugin),
+            // handled by `CoreState`, which routes responses to the view that
+            // made the request, can open the file found, save the formatted
+            // buffer, and knows about plugins apart from any view
+            ShowHover { .. } | ShowDefinition { .. } | ReportProgress { .. }
+                | FormatEdits { .. } => (),
+            PartialResult { token, value } => self.do_partial_result(plugin, token, value),
+            Complete { token, result } => self.do_complete_stream(plugin, &token, result),
+            ShowCompletions { list } => self.do_show_completions(list),
+            HideCompletions => {
+                self.view.borrow_mut().set_completions(None);
+                self.client.hide_completions(self.view_id);
+            }
+            SetGutterAnnotations { lines } => {
+                self.with_editor(|ed, view, _, _| {
+                    ed.get_gutter_mut().set_plugin_annotations(plugin, lines);
+                    view.set_dirty(ed.get_buffer());
+                });
+                self.with_siblings(|view, text| view.set_dirty(text));
+            }
+            AddDiagnostics { spans } => {
+                self.with_editor(|ed, view, _, _| {
+                    let len = ed.get_buffer().len();
+                    ed.get_diagnostics_mut().add(plugin, spans, len);
+                    view.set_dirty(ed.get_buffer());
+                });
+                self.with_siblings(|view, text| view.set_dirty(text));
+            }
+            ClearDiagnostics { source } => {
+                self.with_editor(|ed, view, _, _| {
+                    ed.get_diagnostics_mut().clear(plugin, &source);
+                    view.set_dirty(ed.get_buffer());
+                });
+                self.with_siblings(|view, text| view.set_dirty(text));
+            }
         };
         self.after_edit(&plugin.to_string());
         self.render_if_needed();
     }
 
     pub(crate) fn do_plugin_cmd_sync(&mut self, _plugin: PluginId,
-                                      cmd: PluginRequest) -> Value {
+                                      cmd: PluginRequest) -> Result<Value, RemoteError> {
         use self::PluginRequest::*;
         match cmd {
             LineCount =>
-                json!(self.editor.borrow().plugin_n_lines()),
+                Ok(json!(self.editor.borrow().plugin_n_lines())),
             GetData { start, unit, max_size, rev } =>
-                json!(self.editor.borrow()
-                      .plugin_get_data(start, unit, max_size, rev)),
+                Ok(json!(self.editor.borrow()
+                         .plugin_get_data(start, unit, max_size, rev))),
             GetSelections =>
-                json!("not implemented"),
+                Ok(json!("not implemented")),
+            GetSyntaxContext { offset } => {
+                let ed = self.editor.borrow();
+                let text = ed.get_buffer();
+                if offset > text.len() || !text.is_codepoint_boundary(offset) {
+                    return Err(EditorError::InvalidOffset(offset).into());
+                }
+                Ok(json!(SyntaxContext::new(text, ed.get_layers(),
+                                            self.language.clone(), offset)))
+            }
         }
     }
 
@@ -201,7 +358,7 @@ impl<'a> EventContext<'a> {
     fn after_edit(&mut self, author: &str) {
         let _t = trace_block("EventContext::after_edit", &["core"]);
         let mut ed = self.editor.borrow_mut();
-        let (delta, last_text, keep_sels) = match ed.commit_delta() {
+        let (delta, last_text, keep_sels, after) = match ed.commit_delta() {
             Some(edit_info) => edit_info,
             None => return,
         };
@@ -209,7 +366,7 @@ impl<'a> EventContext<'a> {
         let iter_views = iter::once(&self.view).chain(self.siblings.iter());
         iter_views.for_each(|view| view.borrow_mut()
                             .after_edit(ed.get_buffer(), &last_text, &delta,
-                                        self.client, &mut width_cache, keep_sels));
+                                        self.client, &mut width_cache, keep_sels, after));
 
         let new_len = delta.new_document_len();
         let nb_lines = ed.get_buffer().measure::<LinesMetric>() + 1;
@@ -223,8 +380,9 @@ impl<'a> EventContext<'a> {
         let v: Value = serde_json::to_value(&ed.get_edit_type()).unwrap();
         let edit_type_str = v.as_str().unwrap().to_string();
 
+        let plugin_view_id = self.plugin_view_id();
         let update = PluginUpdate::new(
-                self.view_id,
+                plugin_view_id,
                 ed.get_head_rev_token(),
                 delta,
                 new_len,
@@ -242,14 +400,20 @@ impl<'a> EventContext<'a> {
             ed.increment_revs_in_flight();
             let weak_core = self.weak_core.clone();
             let id = plugin.id;
-            let view_id = self.view_id;
             plugin.update(&update, move |resp| {
-                weak_core.handle_plugin_update(id, view_id, resp);
+                weak_core.handle_plugin_update(id, plugin_view_id, resp);
             });
         });
         ed.dec_revs_in_flight();
         ed.update_edit_type();
 
+        if self.config.auto_save_delay_ms > 0 && self.info.is_some() {
+            let due = Instant::now() + Duration::from_millis(self.config.auto_save_delay_ms);
+            if ed.schedule_auto_save(due) {
+                self.client.schedule_timer(due, AUTO_SAVE_TIMER_MASK | self.buffer_id.0);
+            }
+        }
+
          //if we have no plugins we always render immediately.
         if !self.plugins.is_empty() {
             let mut view = self.view.borrow_mut();
@@ -279,11 +443,33 @@ impl<'a> EventContext<'a> {
     /// Flushes any changes in the views out to the frontend.
     fn render(&mut self) {
         let _t = trace_block("EventContext::render", &["core"]);
-        let ed = self.editor.borrow();
-        //TODO: render other views
-        self.view.borrow_mut()
-            .render_if_dirty(ed.get_buffer(), self.client, self.style_map,
-                             ed.get_layers().get_merged(), ed.is_pristine())
+        self.update_code_actions();
+        self.update_breadcrumbs();
+        {
+            let ed = self.editor.borrow();
+            let mut view = self.view.borrow_mut();
+            view.update_bracket_highlights(ed.get_buffer(), ed.get_layers());
+            view.render_if_dirty(ed.get_buffer(), self.client, self.style_map,
+                                 ed.get_layers().get_merged(), ed.get_gutter(),
+                                 ed.get_diagnostics(), ed.is_pristine(), ed.is_read_only());
+            // the other views of the buffer show its edits too
+            for sibling in &self.siblings {
+                let mut sibling = sibling.borrow_mut();
+                sibling.update_bracket_highlights(ed.get_buffer(), ed.get_layers());
+                sibling.render_if_dirty(ed.get_buffer(), self.client, self.style_map,
+                                        ed.get_layers().get_merged(), ed.get_gutter(),
+                                        ed.get_diagnostics(), ed.is_pristine(),
+                                        ed.is_read_only());
+            }
+        }
+        let scrolled = {
+            let view = self.view.borrow();
+            view.sticky_header_line().map_or(false, |line| line != view.first_line())
+        };
+        if scrolled {
+            let lines = self.sticky_header();
+            self.client.sticky_header(self.view_id, lines.as_ref().map(Vec::as_slice));
+        }
     }
 }
 
@@ -295,9 +481,8 @@ impl<'a> EventContext<'a> {
 impl<'a> EventContext<'a> {
 
     pub(crate) fn finish_init(&mut self, config: &Table) {
-        if !self.plugins.is_empty() {
-            let info = self.plugin_info();
-            self.plugins.iter().for_each(|plugin| plugin.new_buffer(&info));
+        if self.plugin_view_id() == self.view_id {
+            self.open_in_plugins();
         }
 
         let available_plugins = self.plugins.iter().map(|plugin|
@@ -308,17 +493,30 @@ impl<'a> EventContext<'a> {
                                       &available_plugins);
 
         self.client.config_changed(self.view_id, config);
+        let tab_size = self.config.tab_size;
+        self.with_view(|view, text| view.set_tab_size(text, tab_size));
         self.update_wrap_state();
         self.render()
     }
 
-    pub(crate) fn after_save(&mut self, path: &Path) {
+    /// Restores the state of the view saved with a session, once it has
+    /// been set up.
+    pub(crate) fn restore_session(&mut self, session: &ViewSession) {
+        self.with_view(|view, text| view.restore_session(text, session));
+        self.render()
+    }
+
+    /// Tells plugins and the frontend that the buffer was saved to `path`,
+    /// as of `rev`.
+    pub(crate) fn after_save(&mut self, path: &Path, rev: RevId) {
         // notify plugins
-        self.plugins.iter().for_each(
-            |plugin| plugin.did_save(self.view_id, path)
-            );
+        if self.plugin_view_id() == self.view_id {
+            self.plugins.iter().for_each(
+                |plugin| plugin.did_save(self.view_id, path)
+                );
+        }
 
-        self.editor.borrow_mut().set_pristine();
+        self.editor.borrow_mut().set_pristine_rev(rev);
         self.with_view(|view, text| view.set_dirty(text));
         self.render()
     }
@@ -327,28 +525,53 @@ impl<'a> EventContext<'a> {
     pub(crate) fn close_view(&self) -> bool {
         // we probably want to notify plugins _before_ we close the view
         // TODO: determine what plugins we're stopping
-        self.plugins.iter().for_each(|plug| plug.close_view(self.view_id));
+        if self.plugin_view_id() == self.view_id {
+            self.plugins.iter().for_each(|plug| plug.close_view(self.view_id));
+        }
         self.siblings.is_empty()
     }
 
+    /// The view through which plugins see the buffer: the first opened of
+    /// those showing it. Plugins are told of only one view of each buffer,
+    /// so that they are not sent each of its edits more than once.
+    pub(crate) fn plugin_view_id(&self) -> ViewId {
+        self.siblings.iter()
+            .map(|view| view.borrow().get_view_id())
+            .fold(self.view_id, min)
+    }
+
+    /// Tells the plugins about the buffer, through this view.
+    pub(crate) fn open_in_plugins(&mut self) {
+        if !self.plugins.is_empty() {
+            let info = self.plugin_info();
+            self.plugins.iter().for_each(|plugin| plugin.new_buffer(&info));
+        }
+    }
+
+    /// Applies the settings in `changes` to the view, and passes them on to
+    /// the frontend and plugins. Only the lines that the changes affect are
+    /// sent again.
     pub(crate) fn config_changed(&mut self, changes: &Table) {
-        if changes.contains_key("wrap_width")
-            || changes.contains_key("word_wrap") {
+        let (word_wrap, wrap_width) = (self.config.word_wrap, self.config.wrap_width);
+        if self.view.borrow().needs_rewrap(word_wrap, wrap_width) {
             self.update_wrap_state();
         }
 
+        if changes.contains_key("tab_size") {
+            let tab_size = self.config.tab_size;
+            self.with_view(|view, text| view.set_tab_size(text, tab_size));
+        }
+
         self.client.config_changed(self.view_id, &changes);
-        self.plugins.iter()
-            .for_each(|plug| plug.config_changed(self.view_id, &changes));
+        if self.plugin_view_id() == self.view_id {
+            self.plugins.iter()
+                .for_each(|plug| plug.config_changed(self.view_id, &changes));
+        }
         self.render()
     }
 
     pub(crate) fn reload(&mut self, text: Rope) {
-        //TODO: It would be nice if we could preserve the existing selections,
-        //but to do that correctly we would need to compute a real delta between
-        //the old and new buffers, so that selections could be transformed
         self.with_editor(|ed, view, _, _| {
-            view.set_selection(ed.get_buffer(), SelRegion::caret(0));
             view.unset_find();
             ed.reload(text);
         });
@@ -360,10 +583,7 @@ impl<'a> EventContext<'a> {
     pub(crate) fn plugin_info(&mut self) -> PluginBufferInfo {
         let ed = self.editor.borrow();
         let nb_lines = ed.get_buffer().measure::<LinesMetric>() + 1;
-        let views: Vec<ViewId> = iter::once(&self.view)
-            .chain(self.siblings.iter())
-            .map(|v| v.borrow().get_view_id())
-            .collect();
+        let views = [self.plugin_view_id()];
 
         let changes = serde_json::to_value(self.config).unwrap();
         let path = self.info.map(|info| info.path.to_owned());
@@ -383,8 +603,21 @@ impl<'a> EventContext<'a> {
         self.client.plugin_stopped(self.view_id, &plugin.name, 0);
         self.with_editor(|ed, view, _, _| {
             ed.get_layers_mut().remove_layer(plugin.id);
+            ed.get_gutter_mut().remove_plugin(plugin.id);
+            ed.get_diagnostics_mut().remove_plugin(plugin.id);
+            view.hover_requests_mut().remove_provider(plugin.id);
+            view.definition_requests_mut().remove_provider(plugin.id);
+            view.format_requests_mut().abandon_plugin(plugin.id);
+            view.code_actions_mut().remove_plugin(plugin.id);
+            view.inlay_hints_mut().remove_plugin(plugin.id);
             view.set_dirty(ed.get_buffer());
         });
+        self.send_awaited_code_actions();
+        let streams = self.view.borrow_mut().streaming_requests_mut().remove_plugin(plugin.id);
+        for handler in streams {
+            let err = RemoteError::custom(1, format!("plugin {} stopped", plugin.name), None);
+            handler.complete(self, Err(err));
+        }
         self.render();
     }
 
@@ -420,26 +653,678 @@ impl<'a> EventContext<'a> {
         let mut view = self.view.borrow_mut();
         let ed = self.editor.borrow();
         view.request_lines(ed.get_buffer(), self.client, self.style_map,
-                           ed.get_layers().get_merged(), first, last,
-                           ed.is_pristine())
+                           ed.get_layers().get_merged(), ed.get_gutter(),
+                           ed.get_diagnostics(), first, last, ed.is_pristine(),
+                           ed.is_read_only())
+    }
+
+    /// Returns whether `plugin` has registered as a provider of `kind`.
+    /// Plugins register with the view they see the buffer through, which
+    /// may be a sibling of this one.
+    fn is_registered_provider(&self, plugin: PluginId, kind: RequestKind) -> bool {
+        iter::once(&self.view).chain(self.siblings.iter()).any(|view| {
+            let view = view.borrow();
+            match kind {
+                RequestKind::Hover => view.hover_requests().is_provider(plugin),
+                RequestKind::Definition => view.definition_requests().is_provider(plugin),
+                RequestKind::Format => false,
+            }
+        })
     }
 
+    /// Asks the hover providers for information about `position`. The
+    /// frontend is answered with `show_hover`: with nothing straight away if
+    /// there are no providers, or with nothing after `hover_timeout_ms` if
+    /// none of them has responded.
     fn do_request_hover(&mut self, request_id: usize, position: Option<ClientPosition>) {
-        if let Some(position) = self.get_resolved_position(position) {
-            self.with_each_plugin(|p| p.get_hover(self.view_id, request_id, position))
+        let position = match self.get_resolved_position(position) {
+            Some(position) => position,
+            None => return,
+        };
+        let providers: Vec<_> = self.plugins.iter()
+            .filter(|p| p.has_capability(PluginCapability::HoverProvider)
+                    || self.is_registered_provider(p.id, RequestKind::Hover))
+            .collect();
+        if providers.is_empty() {
+            self.client.show_hover(self.view_id, request_id, None);
+            return;
+        }
+        let deadline = Instant::now() + Duration::from_millis(self.config.hover_timeout_ms);
+        let ids: Vec<_> = providers.iter().map(|p| p.id).collect();
+        self.view.borrow_mut().hover_requests_mut().start(request_id, position, &ids, deadline);
+        let plugin_view_id = self.plugin_view_id();
+        let id = self.plugin_request_id(RequestKind::Hover, request_id);
+        providers.iter().for_each(|p| p.get_hover(plugin_view_id, id, position));
+        let view_id: usize = self.view_id.into();
+        self.client.schedule_timer(deadline, HOVER_TIMEOUT_MASK | view_id);
+    }
+
+    pub(crate) fn do_show_hover(&mut self, plugin: PluginId, request_id: usize,
+                                hover: Result<Hover, RemoteError>) {
+        let settled = self.view.borrow_mut().hover_requests_mut()
+            .respond(request_id, plugin, hover);
+        if let Some(info) = settled {
+            self.finish_plugin_request(RequestKind::Hover, request_id);
+            self.client.show_hover(self.view_id, request_id, info.as_ref());
+        }
+    }
+
+    /// Returns the id to send this view's request `request_id` to plugins
+    /// with, so that the response comes back to this view.
+    fn plugin_request_id(&self, kind: RequestKind, request_id: usize) -> usize {
+        self.plugin_requests.borrow_mut().start(self.view_id, kind, request_id)
+    }
+
+    fn finish_plugin_request(&self, kind: RequestKind, request_id: usize) {
+        self.plugin_requests.borrow_mut().finish(self.view_id, kind, request_id);
+    }
+
+    /// Returns the known hints for `first_line..=last_line`, and asks plugins
+    /// for new ones unless a request is already in flight.
+    fn do_request_inlay_hints(&mut self, first_line: usize, last_line: usize) -> Value {
+        let hints = {
+            let ed = self.editor.borrow();
+            let text = ed.get_buffer();
+            let max_line = text.measure::<LinesMetric>() + 1;
+            let start = text.offset_of_line(min(first_line, max_line));
+            let end = text.offset_of_line(min(last_line + 1, max_line));
+            json!(self.view.borrow().inlay_hints().hints_in_range(start, end))
+        };
+        self.send_inlay_hint_requests(first_line, last_line);
+        hints
+    }
+
+    fn send_inlay_hint_requests(&mut self, first_line: usize, last_line: usize) {
+        let providers: Vec<_> = self.plugins.iter()
+            .filter(|p| p.has_capability(PluginCapability::InlayHintProvider))
+            .collect();
+        let send = self.view.borrow_mut().inlay_hints_mut()
+            .request(first_line, last_line, providers.len());
+        if !send {
+            return;
+        }
+        providers.iter().for_each(|plugin| {
+            let weak_core = self.weak_core.clone();
+            let id = plugin.id;
+            let view_id = self.view_id;
+            plugin.get_inlay_hints(self.plugin_view_id(), first_line, last_line, move |resp| {
+                weak_core.handle_inlay_hints(id, view_id, resp);
+            });
+        });
+    }
+
+    pub(crate) fn do_inlay_hints(&mut self, plugin: PluginId, response: Result<Value, RpcError>) {
+        let hints = match response.map(serde_json::from_value::<Vec<InlayHint>>) {
+            Ok(Ok(hints)) => Some(hints),
+            Ok(Err(err)) => {
+                warn!("bad inlay hints from plugin {}: {:?}", plugin, err);
+                None
+            }
+            Err(err) => {
+                warn!("inlay hint request to plugin {} failed: {:?}", plugin, err);
+                None
+            }
+        };
+        let received = hints.is_some();
+        let next = self.view.borrow_mut().inlay_hints_mut().respond(plugin, hints);
+        if received {
+            self.with_view(|view, text| view.set_dirty(text));
+            self.render_if_needed();
+        }
+        if let Some((first_line, last_line)) = next {
+            self.send_inlay_hint_requests(first_line, last_line);
+        }
+    }
+
+    /// Asks the definition providers where the symbol at `position` is
+    /// defined. If there are none, the frontend is answered straight away.
+    fn do_request_definition(&mut self, request_id: usize, position: Option<ClientPosition>,
+                             kind: DefinitionKind, peek: bool) {
+        let position = match self.get_resolved_position(position) {
+            Some(position) => position,
+            None => return,
+        };
+        let providers: Vec<_> = self.plugins.iter()
+            .filter(|p| p.has_capability(PluginCapability::DefinitionProvider)
+                    || self.is_registered_provider(p.id, RequestKind::Definition))
+            .collect();
+        if providers.is_empty() {
+            self.client.show_definition(self.view_id, request_id, None);
+            return;
+        }
+        let ids: Vec<_> = providers.iter().map(|p| p.id).collect();
+        self.view.borrow_mut().definition_requests_mut()
+            .start(request_id, position, kind, peek, &ids);
+        let plugin_view_id = self.plugin_view_id();
+        let id = self.plugin_request_id(RequestKind::Definition, request_id);
+        providers.iter().for_each(|p| p.get_definition(plugin_view_id, id, position, kind));
+    }
+
+    /// Plays the macro in `register` `count` times, up to `MAX_PLAY_COUNT`,
+    /// each time as a single undo group; a macro played by another is part
+    /// of the other's group. Playing stops early if too many commands have
+    /// been played.
+    fn do_play_macro(&mut self, register: char, count: usize) {
+        let commands = self.macros.borrow_mut().start_playing(register);
+        let commands = match commands {
+            Some(commands) => commands,
+            None => return,
+        };
+        let grouped = !self.macros.borrow().is_nested();
+        'play: for _ in 0..min(count, MAX_PLAY_COUNT) {
+            if grouped {
+                self.editor.borrow_mut().start_undo_group();
+            }
+            for cmd in &commands {
+                if !self.macros.borrow_mut().play_command() {
+                    if grouped {
+                        self.editor.borrow_mut().end_undo_group();
+                    }
+                    break 'play;
+                }
+                self.do_edit(cmd.clone());
+            }
+            if grouped {
+                self.editor.borrow_mut().end_undo_group();
+            }
+        }
+        self.macros.borrow_mut().stop_playing();
+    }
+
+    /// Replaces the document highlights with the references to the symbol at
+    /// `position`, as found by the document highlight providers.
+    fn do_request_document_highlight(&mut self, position: Option<ClientPosition>) {
+        let position = match self.get_resolved_position(position) {
+            Some(position) => position,
+            None => return,
+        };
+        let generation = self.with_view(|view, text| view.clear_document_highlights(text));
+        for plugin in self.plugins.iter()
+            .filter(|p| p.has_capability(PluginCapability::DocumentHighlightProvider)) {
+            let weak_core = self.weak_core.clone();
+            let (id, view_id) = (plugin.id, self.view_id);
+            plugin.get_document_highlights(self.plugin_view_id(), position, move |resp| {
+                weak_core.handle_document_highlights(id, view_id, generation, resp);
+            });
+        }
+        self.render_if_needed();
+    }
+
+    pub(crate) fn do_document_highlights(&mut self, plugin: PluginId, generation: u64,
+                                         response: Result<Value, RpcError>) {
+        let highlights = match response.map(serde_json::from_value::<Vec<DocumentHighlight>>) {
+            Ok(Ok(highlights)) => highlights,
+            Ok(Err(err)) => {
+                warn!("bad document highlights from plugin {}: {:?}", plugin, err);
+                return;
+            }
+            Err(err) => {
+                warn!("document highlight request to plugin {} failed: {:?}", plugin, err);
+                return;
+            }
+        };
+        self.with_view(|view, text| view.add_document_highlights(text, generation, highlights));
+        self.render_if_needed();
+    }
+
+    /// Asks the first rename provider what a rename at `position` would
+    /// change. If there is none, the frontend is answered straight away.
+    fn do_prepare_rename(&mut self, request_id: usize, position: Option<ClientPosition>) {
+        let position = match self.get_resolved_position(position) {
+            Some(position) => position,
+            None => return,
+        };
+        let provider = self.plugins.iter()
+            .find(|p| p.has_capability(PluginCapability::RenameProvider));
+        let provider = match provider {
+            Some(provider) => provider,
+            None => return self.client.prepare_rename_result(self.view_id, request_id, None),
+        };
+        let weak_core = self.weak_core.clone();
+        let (id, view_id) = (provider.id, self.view_id);
+        provider.prepare_rename(self.plugin_view_id(), position, move |resp| {
+            weak_core.handle_prepare_rename(id, view_id, request_id, resp);
+        });
+    }
+
+    /// Asks the first rename provider for the edits that rename the symbol
+    /// at `position`. They are made by `CoreState` when they arrive.
+    fn do_rename_symbol(&mut self, request_id: usize, position: Option<ClientPosition>,
+                        new_name: &str) {
+        let position = match self.get_resolved_position(position) {
+            Some(position) => position,
+            None => return,
+        };
+        let provider = self.plugins.iter()
+            .find(|p| p.has_capability(PluginCapability::RenameProvider));
+        let err = if new_name.is_empty() {
+            RenameError::InvalidName
+        } else if self.editor.borrow().is_read_only() {
+            RenameError::ReadOnly
+        } else if let Some(provider) = provider {
+            let weak_core = self.weak_core.clone();
+            let (id, view_id) = (provider.id, self.view_id);
+            let rev = self.editor.borrow().get_head_rev_token();
+            provider.rename(self.plugin_view_id(), position, new_name, move |resp| {
+                weak_core.handle_rename(id, view_id, request_id, rev, resp);
+            });
+            return;
+        } else {
+            RenameError::NoProvider
+        };
+        self.client.rename_result(self.view_id, request_id, &Err(err.into()));
+    }
+
+    fn do_format_document(&mut self) -> Result<(), FormatError> {
+        self.send_format_request(None)
+    }
+
+    /// Trims trailing whitespace and adds a final line break before the
+    /// buffer is saved, if the config asks for them.
+    pub(crate) fn clean_up_before_save(&mut self) {
+        if self.editor.borrow().is_read_only() {
+            return;
+        }
+        if self.with_editor(|ed, view, _, config| ed.clean_up_for_save(view, config)) {
+            self.after_edit("core");
+            self.render_if_needed();
+        }
+    }
+
+    /// Asks the first formatting provider to reformat the buffer before it
+    /// is saved to `path`. Returns `false` if the buffer can't be
+    /// formatted, and should be saved straight away.
+    pub(crate) fn format_before_save(&mut self, path: &Path) -> bool {
+        let deadline = Instant::now()
+            + Duration::from_millis(self.config.format_on_save_timeout_ms);
+        if self.send_format_request(Some((path.to_owned(), deadline))).is_err() {
+            return false;
+        }
+        let view_id: usize = self.view_id.into();
+        self.client.schedule_timer(deadline, FORMAT_TIMEOUT_MASK | view_id);
+        true
+    }
+
+    /// Asks the first formatting provider to reformat the buffer. Its edits
+    /// arrive with `format_edits`.
+    fn send_format_request(&mut self, save: Option<(PathBuf, Instant)>)
+        -> Result<(), FormatError>
+    {
+        if self.editor.borrow().is_read_only() {
+            return Err(FormatError::ReadOnly);
+        }
+        let provider = self.plugins.iter()
+            .find(|p| p.has_capability(PluginCapability::FormattingProvider))
+            .ok_or(FormatError::NoProvider)?;
+        let rev = self.editor.borrow().get_head_rev_token();
+        if let Some(superseded) = self.view.borrow().format_requests().pending_id() {
+            self.finish_plugin_request(RequestKind::Format, superseded);
+        }
+        let request_id = self.view.borrow_mut().format_requests_mut()
+            .start(provider.id, rev, save);
+        let id = self.plugin_request_id(RequestKind::Format, request_id);
+        provider.format_document(self.plugin_view_id(), id);
+        Ok(())
+    }
+
+    /// Applies a formatting provider's edits as a single undo group. Edits
+    /// for a superseded request are dropped. Returns the path to save the
+    /// buffer to, if a save was waiting on the edits.
+    pub(crate) fn do_format_edits(&mut self, plugin: PluginId, request_id: usize,
+                                  result: Result<Vec<TextEdit>, RemoteError>)
+        -> Option<PathBuf>
+    {
+        let pending = self.view.borrow_mut().format_requests_mut().respond(request_id, plugin)?;
+        self.finish_plugin_request(RequestKind::Format, request_id);
+        let rev = pending.rev;
+        let result = result
+            .map_err(FormatError::from_provider)
+            .and_then(|edits| self.with_editor(|ed, _, _, _| ed.apply_text_edits(rev, &edits)));
+        match result {
+            Ok(()) => self.after_edit("core"),
+            Err(err) => self.client.alert(err.to_string()),
+        }
+        pending.save.map(|(path, _)| path)
+    }
+
+    /// Gives up waiting to format the buffer before saving it, if the
+    /// provider has not answered in time. Returns the path to save to.
+    pub(crate) fn expire_format_request(&mut self) -> Option<PathBuf> {
+        let request_id = self.view.borrow().format_requests().pending_id()?;
+        let path = self.view.borrow_mut().format_requests_mut().expire(Instant::now())?;
+        self.finish_plugin_request(RequestKind::Format, request_id);
+        self.client.alert("Formatting timed out; saving without formatting");
+        Some(path)
+    }
+
+    /// Asks for the code actions at the last selection region, if it has
+    /// moved or the buffer has changed since they were last asked for.
+    fn update_code_actions(&mut self) {
+        let has_providers = self.plugins.iter()
+            .any(|p| p.has_capability(PluginCapability::CodeActionProvider));
+        if !has_providers {
+            return;
+        }
+        let rev = self.editor.borrow().get_head_rev_token();
+        let (start, end) = {
+            let view = self.view.borrow();
+            let region = view.sel_regions().last().unwrap();
+            (region.min(), region.max())
+        };
+        if self.view.borrow_mut().code_actions_mut().cursor_moved(start, end, rev) {
+            self.send_code_action_requests(start, end);
+        }
+    }
+
+    /// Asks the code action providers for the actions for `start..end`,
+    /// unless they are already known or asked for.
+    fn send_code_action_requests(&mut self, start: usize, end: usize) {
+        let rev = self.editor.borrow().get_head_rev_token();
+        if self.view.borrow().code_actions().is_current(start, end, rev) {
+            return;
+        }
+        let providers = self.plugins.iter()
+            .filter(|p| p.has_capability(PluginCapability::CodeActionProvider))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.view.borrow_mut().code_actions_mut()
+            .start(start, end, rev, providers.iter().map(|p| p.id));
+        for plugin in providers {
+            let weak_core = self.weak_core.clone();
+            let (id, view_id) = (plugin.id, self.view_id);
+            plugin.get_code_actions(self.plugin_view_id(), start, end, move |resp| {
+                weak_core.handle_code_actions(id, view_id, start, end, rev, resp);
+            });
+        }
+        self.send_awaited_code_actions();
+    }
+
+    /// Returns the code actions for `range`, or for the last selection
+    /// region, if every provider has offered its actions. If not, returns
+    /// `Null`, and they are sent with `code_actions` once they have.
+    fn do_get_code_actions(&mut self, range: Option<PositionRange>) -> Value {
+        let (start, end) = self.with_view(|view, text| match range {
+            Some(PositionRange { start, end }) => {
+                let start = view.line_col_to_offset(text, start.line, start.column);
+                let end = view.line_col_to_offset(text, end.line, end.column);
+                (min(start, end), max(start, end))
+            }
+            None => {
+                let region = view.sel_regions().last().unwrap();
+                (region.min(), region.max())
+            }
+        });
+        self.send_code_action_requests(start, end);
+        match self.view.borrow_mut().code_actions_mut().show() {
+            Some(actions) => json!(actions),
+            None => Value::Null,
+        }
+    }
+
+    /// Sends the code actions that a `get_code_actions` answered with
+    /// `Null` was waiting for, once they are known.
+    fn send_awaited_code_actions(&mut self) {
+        let actions = self.view.borrow_mut().code_actions_mut().take_awaited();
+        if let Some(actions) = actions {
+            self.client.code_actions(self.view_id, &actions);
+        }
+    }
+
+    /// Searches for `chars` within the function, class or such enclosing
+    /// the last caret, or the whole text if there is none.
+    fn find_in_function(&mut self, chars: String, case_sensitive: bool, regex: bool,
+                        whole_words: bool) {
+        let ed = self.editor.borrow();
+        let mut view = self.view.borrow_mut();
+        let text = ed.get_buffer();
+        let line = text.line_of_offset(view.sel_regions().last().unwrap().end);
+        let scope = enclosing_definition(text, line, self.config.tab_size,
+                                         |iv| ed.get_layers().definition_name(iv));
+        view.do_find(text, chars, case_sensitive, regex, whole_words, scope.into_iter().collect());
+    }
+
+    /// Finds the symbols enclosing the caret again if it has moved or the
+    /// text has changed, unless they were found too recently, in which case
+    /// a timer is set to find them later.
+    fn update_breadcrumbs(&mut self) {
+        let ed = self.editor.borrow();
+        let mut view = self.view.borrow_mut();
+        let text = ed.get_buffer();
+        let caret = view.sel_regions().last().unwrap().end;
+        let key = (caret, ed.get_head_rev_token(), ed.get_layers().revision());
+        if !view.breadcrumbs().is_stale(key) {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(due) = view.breadcrumbs().next_update(now) {
+            if view.breadcrumbs_mut().schedule() {
+                let view_id: usize = self.view_id.into();
+                self.client.schedule_timer(due, BREADCRUMBS_TIMER_MASK | view_id);
+            }
+            return;
+        }
+        let items = find_breadcrumbs(text, caret, self.config.tab_size,
+                                     |iv| ed.get_layers().definition_name(iv));
+        view.set_breadcrumbs(text, items, key, now);
+    }
+
+    /// Finds the breadcrumbs again, now that enough time has passed since
+    /// they were last found.
+    pub(crate) fn breadcrumbs_timer_fired(&mut self) {
+        self.view.borrow_mut().breadcrumbs_mut().timer_fired();
+        self.render_if_needed();
+    }
+
+    /// Returns the definitions enclosing the first visible line, and
+    /// notes that the frontend wants them when that line changes.
+    fn sticky_header(&self) -> Option<Vec<StickyLine>> {
+        let ed = self.editor.borrow();
+        let mut view = self.view.borrow_mut();
+        let text = ed.get_buffer();
+        let first_line = view.first_line();
+        view.set_sticky_header_line(first_line);
+        let first_line = text.line_of_offset(view.offset_of_line(text, first_line));
+        let lines = sticky_lines(text, first_line, self.config.tab_size,
+                                 |iv| ed.get_layers().definition_name(iv));
+        if lines.is_empty() { None } else { Some(lines) }
+    }
+
+    /// Returns the minimap of the document if it is cached, or starts
+    /// computing it, to be sent to the frontend when done.
+    fn do_get_minimap(&mut self, target_width: usize, target_height: usize) -> Value {
+        let ed = self.editor.borrow();
+        let view = self.view.borrow();
+        let text = ed.get_buffer();
+        let key = MinimapKey {
+            target_width,
+            target_height,
+            len: text.len(),
+            styles_revision: ed.get_layers().revision(),
+            default_color: self.style_map.borrow().get_default_style().fg_color.unwrap_or(0),
+        };
+        if let Some(minimap) = view.minimap().get(&key) {
+            return json!(minimap);
+        }
+        let (client, view_id) = (self.client.clone(), self.view_id);
+        let styles = ed.get_layers().get_merged().clone();
+        view.minimap().compute_in_background(key, text.clone(), styles, self.config.tab_size,
+                                             move |minimap| client.minimap(view_id, minimap));
+        Value::Null
+    }
+
+    pub(crate) fn do_code_actions(&mut self, plugin: PluginId, start: usize, end: usize,
+                                  rev: u64, response: Result<Value, RpcError>) {
+        // a provider that fails offers no actions, and is not waited for
+        let actions = match code_actions::parse_response::<Vec<CodeAction>>(response) {
+            Ok(actions) => actions,
+            Err(msg) => {
+                warn!("code action request to plugin {} failed: {}", plugin, msg);
+                Vec::new()
+            }
+        };
+        let accepted = self.view.borrow_mut().code_actions_mut()
+            .respond(plugin, start, end, rev, actions);
+        if accepted {
+            self.send_awaited_code_actions();
+            self.render_if_needed();
+        }
+    }
+
+    /// Makes a code action's or a rename's edits to this buffer, as one undo
+    /// group. They apply to revision `rev`, or to the current text if it is
+    /// `None`.
+    pub(crate) fn apply_text_edits(&mut self, rev: Option<u64>, edits: &[TextEdit])
+        -> Result<(), FormatError>
+    {
+        let rev = rev.unwrap_or_else(|| self.editor.borrow().get_head_rev_token());
+        self.with_editor(|ed, _, _, _| ed.apply_text_edits(rev, edits))?;
+        self.after_edit("core");
+        self.render_if_needed();
+        Ok(())
+    }
+
+    /// Moves the caret to `line` and `col` without rendering, so that it can
+    /// be used on views that have not been sent to the frontend yet.
+    pub(crate) fn goto_line_col(&mut self, line: usize, col: usize) {
+        let config = self.config;
+        self.with_view(|view, text| view.do_edit(text, config, ViewEvent::GotoLine {
+            line: line as u64,
+            col: col as u64,
+        }));
+    }
+
+    /// Sends the requests that were waiting on `crashed` to `plugin`, the
+    /// instance that replaces it.
+    pub(crate) fn resend_plugin_requests(&mut self, crashed: PluginId, plugin: &Plugin) {
+        let (hovers, definitions) = {
+            let mut view = self.view.borrow_mut();
+            (view.hover_requests_mut().replace_plugin(crashed, plugin.id),
+             view.definition_requests_mut().replace_plugin(crashed, plugin.id))
+        };
+        for (request_id, position) in hovers {
+            let id = self.plugin_request_id(RequestKind::Hover, request_id);
+            plugin.get_hover(self.plugin_view_id(), id, position);
+        }
+        for (request_id, position, kind) in definitions {
+            let id = self.plugin_request_id(RequestKind::Definition, request_id);
+            plugin.get_definition(self.plugin_view_id(), id, position, kind);
+        }
+        if !plugin.has_capability(PluginCapability::InlayHintProvider) {
+            return;
+        }
+        if let Some((first_line, last_line)) = self.view.borrow_mut().inlay_hints_mut().retry() {
+            let weak_core = self.weak_core.clone();
+            let (id, view_id) = (plugin.id, self.view_id);
+            plugin.get_inlay_hints(self.plugin_view_id(), first_line, last_line, move |resp| {
+                weak_core.handle_inlay_hints(id, view_id, resp);
+            });
+        }
+    }
+
+    /// Answers, with nothing, the requests that only `plugin`, which will
+    /// not be restarted, had left to answer.
+    pub(crate) fn abandon_plugin_requests(&mut self, plugin: PluginId) {
+        let (hovers, definitions) = {
+            let mut view = self.view.borrow_mut();
+            (view.hover_requests_mut().abandon_plugin(plugin),
+             view.definition_requests_mut().abandon_plugin(plugin))
+        };
+        for request_id in hovers {
+            self.finish_plugin_request(RequestKind::Hover, request_id);
+            self.client.show_hover(self.view_id, request_id, None);
+        }
+        for request_id in definitions {
+            self.finish_plugin_request(RequestKind::Definition, request_id);
+            self.client.show_definition(self.view_id, request_id, None);
         }
     }
 
-    fn do_show_hover(&mut self, request_id: usize, hover: Result<Hover, RemoteError>) {
-        match hover {
-            Ok(hover) => {
-                // TODO: Get Range from hover here and use it to highlight text
-                self.client.show_hover(self.view_id, request_id, hover.content)
-            },
-            Err(err) => warn!("Hover Response from Client Error {:?}", err)
+    /// Sends `method` to `plugin` as a streaming request, whose results are
+    /// given to `handler`.
+    pub(crate) fn start_streaming_request(&mut self, plugin: &Plugin, method: &str,
+                                          params: &Value, handler: Box<dyn StreamHandler>) {
+        let token = self.view.borrow_mut().streaming_requests_mut().start(plugin.id, handler);
+        plugin.streaming_request(self.plugin_view_id(), &token, method, params);
+    }
+
+    fn do_partial_result(&mut self, plugin: PluginId, token: String, value: Value) {
+        let handler = self.view.borrow_mut().streaming_requests_mut().take(plugin, &token);
+        match handler {
+            Some(mut handler) => {
+                handler.partial_result(self, value);
+                self.view.borrow_mut().streaming_requests_mut().resume(plugin, token, handler);
+            }
+            None => warn!("partial result from {} for unknown request {}", plugin, token),
+        }
+    }
+
+    fn do_complete_stream(&mut self, plugin: PluginId, token: &str,
+                          result: Result<Value, RemoteError>) {
+        let handler = self.view.borrow_mut().streaming_requests_mut().take(plugin, token);
+        match handler {
+            Some(handler) => handler.complete(self, result),
+            None => warn!("completion from {} for unknown request {}", plugin, token),
+        }
+    }
+
+    /// Answers the hover requests that providers did not respond to in time.
+    pub(crate) fn expire_hover_requests(&mut self) {
+        let expired = self.view.borrow_mut().hover_requests_mut().expire(Instant::now());
+        for request_id in expired {
+            self.finish_plugin_request(RequestKind::Hover, request_id);
+            self.client.show_hover(self.view_id, request_id, None);
         }
     }
      
+    fn do_show_completions(&mut self, list: CompletionList) {
+        let offset = list.trigger_offset;
+        let valid = {
+            let editor = self.editor.borrow();
+            let text = editor.get_buffer();
+            offset <= text.len() && text.is_codepoint_boundary(offset)
+        };
+        if !valid {
+            warn!("completions with invalid trigger offset {} ignored", offset);
+            return;
+        }
+        self.client.show_completions(self.view_id, &list);
+        self.view.borrow_mut().set_completions(Some(list));
+    }
+
+    fn do_accept_completion(&mut self, index: usize) {
+        let completion = self.view.borrow_mut().take_completions()
+            .and_then(|list| list.items.get(index)
+                      .map(|item| (list.trigger_offset, item.insert_text.clone())));
+        let (trigger_offset, insert_text) = match completion {
+            Some(completion) => completion,
+            None => {
+                warn!("no completion at index {}", index);
+                return;
+            }
+        };
+        let result = self.with_editor(|ed, view, _, _| {
+            ed.insert_completion(view, trigger_offset, &insert_text)
+        });
+        if let Err(err) = result {
+            warn!("completion not inserted: {}", err);
+        }
+        self.client.hide_completions(self.view_id);
+    }
+
+    fn do_expand_snippet(&mut self, source: &str) {
+        let snippet = Snippet::parse(source);
+        let result = self.with_editor(|ed, view, _, _| ed.expand_snippet(view, &snippet));
+        match result {
+            Ok(offset) => {
+                // update the view for the insertion before selecting in it
+                self.after_edit("core");
+                let session = SnippetSession::new(&snippet, offset);
+                self.with_view(|view, text| view.start_snippet(text, session));
+            }
+            Err(err) => warn!("snippet not expanded: {}", err),
+        }
+    }
+
     /// Gives the requested position in UTF-8 offset format to be sent to plugin
     /// If position is `None`, it tries to get the current Caret Position and use
     /// that instead
@@ -465,6 +1350,9 @@ mod tests {
         client: Client,
         core_ref: WeakXiCore,
         kill_ring: RefCell<Rope>,
+        clipboard_history: RefCell<ClipboardHistory>,
+        macros: RefCell<Macros>,
+        plugin_requests: RefCell<PluginRequests>,
         style_map: RefCell<ThemeStyleMap>,
         width_cache: RefCell<WidthCache>,
         config_manager: ConfigManager,
@@ -481,10 +1369,14 @@ mod tests {
             let client = Client::new(Box::new(DummyPeer));
             let core_ref = dummy_weak_core();
             let kill_ring = RefCell::new(Rope::from(""));
+            let clipboard_history = RefCell::new(
+                ClipboardHistory::new(config_manager.clipboard_history_size()));
+            let macros = RefCell::new(Macros::default());
+            let plugin_requests = RefCell::new(PluginRequests::default());
             let style_map = RefCell::new(ThemeStyleMap::new(None));
             let width_cache = RefCell::new(WidthCache::new());
-            ContextHarness { view, editor, client, core_ref, kill_ring,
-                             style_map, width_cache, config_manager }
+            ContextHarness { view, editor, client, core_ref, kill_ring, clipboard_history,
+                             macros, plugin_requests, style_map, width_cache, config_manager }
         }
 
         /// Renders the text and selections. cursors are represented with
@@ -524,6 +1416,9 @@ mod tests {
                 plugins: Vec::new(),
                 client: &self.client,
                 kill_ring: &self.kill_ring,
+                clipboard_history: &self.clipboard_history,
+                macros: &self.macros,
+                plugin_requests: &self.plugin_requests,
                 style_map: &self.style_map,
                 width_cache: &self.width_cache,
                 weak_core: &self.core_ref,
@@ -659,19 +1554,51 @@ mod tests {
 
 
     #[test]
-    fn delete_tests() {
+    fn add_selection_by_display_column() {
         use rpc::GestureType::*;
-        let initial_text = "\
-        this is a string\n\
-        that has three\n\
-        lines.";
+        let initial_text = "a\tb\n\t  x\n\u{4e00}\u{4e8c}\u{4e09}\nabcdefgh";
         let harness = ContextHarness::new(initial_text);
         let mut ctx = harness.make_context();
-        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 3, ty: PointSelect });
+        assert_eq!(harness.debug_render(), "a\tb|\n\t  x\n\u{4e00}\u{4e8c}\u{4e09}\nabcdefgh");
 
-        ctx.do_edit(EditNotification::MoveRight);
-        assert_eq!(harness.debug_render(),"\
-        t|his is a string\n\
+        // "a\tb" is five columns wide with the default tab size of 4
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        assert_eq!(harness.debug_render(), "a\tb|\n\t | x\n\u{4e00}\u{4e8c}\u{4e09}\nabcdefgh");
+
+        // column 5 falls inside the second wide character
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        assert_eq!(harness.debug_render(), "a\tb|\n\t | x\n\u{4e00}\u{4e8c}|\u{4e09}\nabcdefgh");
+
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        assert_eq!(harness.debug_render(), "a\tb|\n\t | x\n\u{4e00}\u{4e8c}|\u{4e09}\nabcd|efgh");
+
+        // no-op on the last line
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        assert_eq!(harness.debug_render(), "a\tb|\n\t | x\n\u{4e00}\u{4e8c}|\u{4e09}\nabcd|efgh");
+
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 8, ty: PointSelect });
+        ctx.do_edit(EditNotification::AddSelectionAbove);
+        assert_eq!(harness.debug_render(), "a\tb\n\t  x\n\u{4e00}\u{4e8c}\u{4e09}|\nabcdefgh|");
+        ctx.do_edit(EditNotification::AddSelectionAbove);
+        ctx.do_edit(EditNotification::AddSelectionAbove);
+        assert_eq!(harness.debug_render(), "a\tb|\n\t  |x\n\u{4e00}\u{4e8c}\u{4e09}|\nabcdefgh|");
+    }
+
+    #[test]
+    fn delete_tests() {
+        use rpc::GestureType::*;
+        let initial_text = "\
+        this is a string\n\
+        that has three\n\
+        lines.";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+
+        ctx.do_edit(EditNotification::MoveRight);
+        assert_eq!(harness.debug_render(),"\
+        t|his is a string\n\
         that has three\n\
         lines." );
 
@@ -712,6 +1639,293 @@ mod tests {
         |\nlines." );
     }
 
+    #[test]
+    fn duplicate_line_test() {
+        use rpc::GestureType::*;
+        let initial_text = "\
+        this is a string\n\
+        that has three\n\
+        lines.";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+
+        // two cursors on one line only duplicate it once
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 5, ty: ToggleSel });
+        ctx.do_edit(EditNotification::DuplicateLine);
+        assert_eq!(harness.debug_render(),"\
+        this is a string\n\
+        |this |is a string\n\
+        that has three\n\
+        lines." );
+
+        // the last line has no newline of its own
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 2, ty: PointSelect });
+        ctx.do_edit(EditNotification::DuplicateLine);
+        assert_eq!(harness.debug_render(),"\
+        this is a string\n\
+        this is a string\n\
+        that has three\n\
+        lines.\n\
+        li|nes." );
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(),"\
+        this is a string\n\
+        this is a string\n\
+        that has three\n\
+        li|nes." );
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), initial_text.replace("lines", "li|nes"));
+
+        ctx.do_edit(EditNotification::Redo);
+        assert_eq!(harness.debug_render(),"\
+        this is a string\n\
+        this is a string\n\
+        that has three\n\
+        li|nes." );
+    }
+
+    #[test]
+    fn move_word_unicode_test() {
+        let harness = ContextHarness::new("naïve café—東京。\u{3000}e\u{301}t");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveWordRight);
+        assert_eq!(harness.debug_render(), "naïve| café—東京。\u{3000}e\u{301}t");
+        ctx.do_edit(EditNotification::MoveWordRight);
+        assert_eq!(harness.debug_render(), "naïve café|—東京。\u{3000}e\u{301}t");
+        ctx.do_edit(EditNotification::MoveWordRight);
+        assert_eq!(harness.debug_render(), "naïve café—東京|。\u{3000}e\u{301}t");
+        ctx.do_edit(EditNotification::MoveWordRight);
+        assert_eq!(harness.debug_render(), "naïve café—東京。|\u{3000}e\u{301}t");
+        ctx.do_edit(EditNotification::MoveWordRight);
+        assert_eq!(harness.debug_render(), "naïve café—東京。\u{3000}e\u{301}t|");
+
+        ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection);
+        assert_eq!(harness.debug_render(), "naïve café—東京。\u{3000}[|e\u{301}t]");
+        ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection);
+        assert_eq!(harness.debug_render(), "naïve café—[|東京。\u{3000}e\u{301}t]");
+    }
+
+    #[test]
+    fn select_word_and_paragraph_test() {
+        use rpc::GestureType::*;
+        let harness = ContextHarness::new("one two\nthree\n\nfour five\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 5, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 1, ty: ToggleSel });
+        ctx.do_edit(EditNotification::SelectWord);
+        assert_eq!(harness.debug_render(), "one [two|]\nthree\n\n[four|] five\n");
+
+        ctx.do_edit(EditNotification::SelectParagraph);
+        assert_eq!(harness.debug_render(), "[one two\nthree\n|]\n[four five\n|]");
+
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::SelectParagraph);
+        assert_eq!(harness.debug_render(), "one two\nthree\n[\n|]four five\n");
+    }
+
+    #[test]
+    fn find_all_from_selection_test() {
+        use rpc::GestureType::*;
+        let harness = ContextHarness::new("foo bar foo foobar\nfoo");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::FindAll);
+        assert_eq!(harness.debug_render(), "[foo|] bar [foo|] foobar\n[foo|]");
+
+        // an active query is used as is
+        ctx.do_edit(EditNotification::Find { chars: "ba".into(), case_sensitive: false,
+                                             regex: false, whole_words: false,
+                                             scope: SearchScope::WholeFile });
+        ctx.do_edit(EditNotification::FindAll);
+        assert_eq!(harness.debug_render(), "foo [ba|]r foo foo[ba|]r\nfoo");
+    }
+
+    #[test]
+    fn find_in_selection_test() {
+        use rpc::GestureType::*;
+        use rpc::SelectionModifier;
+        let harness = ContextHarness::new("foo foo\nfoo foo\nfoo");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: LineSelect });
+        ctx.do_edit(EditNotification::Find { chars: "foo".into(), case_sensitive: false,
+                                             regex: false, whole_words: false,
+                                             scope: SearchScope::Selection });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 5, ty: PointSelect });
+        let find_next = EditNotification::FindNext {
+            wrap_around: true, allow_same: false, modify_selection: SelectionModifier::Set };
+        ctx.do_edit(find_next.clone());
+        assert_eq!(harness.debug_render(), "foo foo\nfoo [foo|]\nfoo");
+        // the search wraps around within the selection
+        ctx.do_edit(find_next);
+        assert_eq!(harness.debug_render(), "foo foo\n[foo|] foo\nfoo");
+
+        ctx.do_edit(EditNotification::Replace { chars: "bar".into(), preserve_case: false });
+        ctx.do_edit(EditNotification::ReplaceAll);
+        assert_eq!(harness.debug_render(), "foo foo\nbar| bar\nfoo");
+    }
+
+    #[test]
+    fn replace_all_test() {
+        let harness = ContextHarness::new("foo bar foo\nfoo");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Find { chars: "foo".into(), case_sensitive: false,
+                                             regex: false, whole_words: false,
+                                             scope: SearchScope::WholeFile });
+        ctx.do_edit(EditNotification::Replace { chars: "baz".into(), preserve_case: false });
+        ctx.do_edit(EditNotification::ReplaceAll);
+        assert_eq!(harness.debug_render(), "baz| bar baz\nbaz");
+
+        // a single undo reverts all the replacements
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "foo| bar foo\nfoo");
+    }
+
+    #[test]
+    fn move_line_test() {
+        use rpc::GestureType::*;
+        let harness = ContextHarness::new("one\ntwo\nthree\nfour");
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 2, ty: PointSelect });
+        ctx.do_edit(EditNotification::MoveLineUp);
+        assert_eq!(harness.debug_render(), "tw|o\none\nthree\nfour");
+
+        // moving the first line up is a no-op
+        ctx.do_edit(EditNotification::MoveLineUp);
+        assert_eq!(harness.debug_render(), "tw|o\none\nthree\nfour");
+
+        // a selection ending at the start of a line does not move that line
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 0, ty: RangeSelect });
+        assert_eq!(harness.debug_render(), "two\n[one\nthree\n|]four");
+        ctx.do_edit(EditNotification::MoveLineDown);
+        assert_eq!(harness.debug_render(), "two\nfour\n[one\nthree|]");
+
+        // moving the last line down is a no-op
+        ctx.do_edit(EditNotification::MoveLineDown);
+        assert_eq!(harness.debug_render(), "two\nfour\n[one\nthree|]");
+
+        ctx.do_edit(EditNotification::MoveLineUp);
+        assert_eq!(harness.debug_render(), "two\n[one\nthree|]\nfour");
+
+        // carets on adjacent lines move together
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 1, ty: ToggleSel });
+        ctx.do_edit(EditNotification::MoveLineDown);
+        assert_eq!(harness.debug_render(), "three\nt|wo\no|ne\nfour");
+        ctx.do_edit(EditNotification::MoveLineDown);
+        assert_eq!(harness.debug_render(), "three\nfour\nt|wo\no|ne");
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "three\nt|wo\no|ne\nfour");
+    }
+
+    #[test]
+    fn rect_selection_test() {
+        use rpc::GestureType::*;
+        use rpc::MouseAction;
+        let harness = ContextHarness::new("abcdef\nab\nabcdef\nabcdef");
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: RectSelect });
+        ctx.do_edit(EditNotification::Drag(MouseAction { line: 2, column: 4, flags: 0, click_count: None }));
+        assert_eq!(harness.debug_render(), "a[bcd|]ef\na[b|]\na[bcd|]ef\nabcdef");
+
+        ctx.do_edit(EditNotification::MoveDownAndModifyRectSelection);
+        ctx.do_edit(EditNotification::MoveLeftAndModifyRectSelection);
+        assert_eq!(harness.debug_render(), "a[bc|]def\na[b|]\na[bc|]def\na[bc|]def");
+
+        ctx.do_edit(EditNotification::Insert { chars: "X".into() });
+        assert_eq!(harness.debug_render(), "aX|def\naX|\naX|def\naX|def");
+
+        // a selection of aligned carets can be extended as a rectangle
+        ctx.do_edit(EditNotification::MoveRightAndModifyRectSelection);
+        assert_eq!(harness.debug_render(), "aX[d|]ef\naX|\naX[d|]ef\naX[d|]ef");
+        ctx.do_edit(EditNotification::MoveUpAndModifyRectSelection);
+        assert_eq!(harness.debug_render(), "aX[d|]ef\naX|\naX[d|]ef\naXdef");
+
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "aX|ef\na|\naX|ef\naXdef");
+
+        // an ordinary gesture leaves rectangle mode
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Drag(MouseAction { line: 1, column: 1, flags: 0, click_count: None }));
+        assert_eq!(harness.debug_render(), "[aXef\na|]\naXef\naXdef");
+    }
+
+    #[test]
+    fn rect_selection_display_cols_test() {
+        use rpc::GestureType::*;
+        use rpc::MouseAction;
+        let harness = ContextHarness::new("a\tbc\n\u{4e00}bcd\nabcdefg");
+        let mut ctx = harness.make_context();
+
+        // the rectangle spans the same columns on screen, with the tab four
+        // columns wide and the wide character two
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 2, ty: RectSelect });
+        ctx.do_edit(EditNotification::Drag(MouseAction { line: 0, column: 3, flags: 0, click_count: None }));
+        assert_eq!(harness.debug_render(), "a[\tb|]c\n\u{4e00}[bcd|]\nab[cde|]fg");
+
+        ctx.do_edit(EditNotification::MoveLeftAndModifyRectSelection);
+        assert_eq!(harness.debug_render(), "a[\t|]bc\n\u{4e00}[bc|]d\nab[cd|]efg");
+
+        ctx.do_edit(EditNotification::Insert { chars: "X".into() });
+        assert_eq!(harness.debug_render(), "aX|bc\n\u{4e00}X|d\nabX|efg");
+    }
+
+    #[test]
+    fn join_lines_test() {
+        use rpc::GestureType::*;
+        let harness = ContextHarness::new("fn main() {\n    foo();\n\n    bar();\n}");
+        let mut ctx = harness.make_context();
+
+        // a caret joins its line with the next, dropping the indentation
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 2, ty: PointSelect });
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "fn| main() { foo();\n\n    bar();\n}");
+
+        // a selection joins all of its lines; empty lines add no space
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 5, ty: RangeSelect });
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "[fn main() { foo(); b|]ar();\n}");
+
+        // the whole join is one undo step
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "[fn main() { foo();\n\n    b|]ar();\n}");
+
+        // joining the last line does nothing
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "fn main() { foo();\n\n    bar();\n}|");
+    }
+
+    #[test]
+    fn sort_lines_test() {
+        use rpc::GestureType::*;
+        use rpc::MouseAction;
+        let harness = ContextHarness::new("pear\napple\nBanana\ncherry\nfig\ndate");
+        let mut ctx = harness.make_context();
+
+        // each selection is sorted on its own
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 1, ty: RangeSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 0, ty: ToggleSel });
+        ctx.do_edit(EditNotification::Drag(MouseAction { line: 5, column: 2, flags: 0, click_count: None }));
+        ctx.do_edit(EditNotification::SortLines);
+        assert_eq!(harness.debug_render(), "[Banana\napple\npear|]\n[cherry\ndate\nfig|]");
+
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::SortLinesCaseInsensitive);
+        assert_eq!(harness.debug_render(), "[apple\nBanana\ncherry\ndate\nfig\npear|]");
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "Banana\napple\npear\ncherry\ndate\nfig|");
+    }
+
     #[test]
     fn simple_indentation_test() {
         use rpc::GestureType::*;
@@ -747,6 +1961,639 @@ mod tests {
         assert_eq!(harness.debug_render(),"[|    hello\n]world");
     }
 
+    #[test]
+    fn outdent_mixed_whitespace_test() {
+        use rpc::GestureType::*;
+        let harness = ContextHarness::new("\t\tone\n  \ttwo\n  three\n  ");
+        let mut ctx = harness.make_context();
+
+        // the selection starts inside the leading whitespace
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 2, ty: RangeSelect });
+        ctx.do_edit(EditNotification::Outdent);
+        assert_eq!(harness.debug_render(), "[\tone\ntwo\nthree\n|]");
+
+        ctx.do_edit(EditNotification::Indent);
+        assert_eq!(harness.debug_render(), "    [\tone\n    two\n    three\n|]");
+
+        // each command is a single undo step
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "[\tone\ntwo\nthree\n|]");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "\t[\tone\n  \ttwo\n  three\n  |]");
+    }
+
+    #[test]
+    fn undo_branches_test() {
+        let harness = ContextHarness::new("");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        ctx.do_edit(EditNotification::InsertNewline);
+        ctx.do_edit(EditNotification::Undo);
+        // the new edit starts a branch, and keeps the undone newline
+        ctx.do_edit(EditNotification::Insert { chars: "b".into() });
+        assert_eq!(harness.debug_render(), "ab|");
+
+        let branches = ctx.do_edit_sync(EditRequest::ListUndoBranches).unwrap();
+        assert_eq!(branches["current"], json!(3));
+        assert_eq!(branches["branches"][1],
+                   json!({"id": 1, "parent": 0, "children": [2, 3]}));
+
+        ctx.do_edit(EditNotification::JumpToUndoBranch { id: 2 });
+        assert_eq!(harness.debug_render(), "a\n|");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "a|");
+        ctx.do_edit(EditNotification::JumpToUndoBranch { id: 3 });
+        assert_eq!(harness.debug_render(), "ab|");
+    }
+
+    #[test]
+    fn reload_test() {
+        let harness = ContextHarness::new("one\ntwo\nthree");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        // only the changed middle line is replaced, so the caret stays put
+        ctx.reload("one\n2\nthree".into());
+        assert_eq!(harness.debug_render(), "one\n2\nthree|");
+        assert!(ctx.editor.borrow().is_pristine());
+        ctx.reload("one\n2\nthree".into());
+        assert_eq!(harness.debug_render(), "one\n2\nthree|");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "one\ntwo\nthree|");
+    }
+
+    #[test]
+    fn scroll_anchor_test() {
+        let text: String = (0..30).map(|i| format!("{}\n", i)).collect();
+        let harness = ContextHarness::new(&text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::GotoLine { line: 15, col: 0 });
+        assert_eq!(harness.view.borrow().first_line(), 10);
+        // lines added above the viewport push it down with its text
+        ctx.reload(format!("a\nb\n{}", text).into());
+        assert_eq!(harness.view.borrow().first_line(), 12);
+        assert_eq!(harness.debug_render().lines().nth(17), Some("|15"));
+        ctx.reload(text.as_str().into());
+        assert_eq!(harness.view.borrow().first_line(), 10);
+    }
+
+    #[test]
+    fn set_viewport_test() {
+        use config::ConfigDomain;
+        let text: String = (0..30).map(|i| format!("line {}\n", i)).collect();
+        let mut harness = ContextHarness::new(&text);
+        {
+            let mut ctx = harness.make_context();
+            ctx.do_edit(EditNotification::GotoLine { line: 15, col: 0 });
+            ctx.do_edit(EditNotification::SetViewport { width: 40, height: 20 });
+        }
+        assert_eq!(harness.view.borrow().first_line(), 10);
+        assert_eq!(harness.view.borrow().scroll_height(), 20);
+
+        // wrapping the lines keeps the text at the top of the view in place
+        let changes = json!({"wrap_width": 5}).as_object().unwrap().to_owned();
+        let changes = harness.config_manager.set_user_config(ConfigDomain::General, changes)
+            .unwrap();
+        harness.make_context().config_changed(&changes[0].1);
+        let view = harness.view.borrow();
+        assert_eq!(view.first_line(), 20);
+        assert_eq!(view.offset_of_line(&text.as_str().into(), view.first_line()),
+                   "line 0\n".len() * 10);
+    }
+
+    #[test]
+    fn fold_range_test() {
+        let harness = ContextHarness::new("a\nb\nc\nd\ne");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::FoldRange { start_line: 0, end_line: 3 });
+        {
+            let view = harness.view.borrow();
+            let text = ctx.editor.borrow().get_buffer().clone();
+            assert_eq!(view.folds(), &[(0, 3)]);
+            // "b" and "c" are on the placeholder line
+            let lines = (0..text.len()).map(|offset| view.line_of_offset(&text, offset))
+                .collect::<Vec<_>>();
+            assert_eq!(lines, vec![0, 0, 1, 1, 1, 1, 2, 2, 3]);
+            let offsets = (0..4).map(|line| view.offset_of_line(&text, line))
+                .collect::<Vec<_>>();
+            assert_eq!(offsets, vec![0, 2, 6, 8]);
+        }
+
+        // edits above a fold move it, as do edits of its first line
+        ctx.do_edit(EditNotification::Insert { chars: "x\n".into() });
+        ctx.do_edit(EditNotification::MoveToRightEndOfLine);
+        ctx.do_edit(EditNotification::Insert { chars: "!".into() });
+        assert_eq!(harness.view.borrow().folds(), &[(1, 4)]);
+        // edits of its last line leave it be; that is the fourth visible line
+        ctx.do_edit(EditNotification::GotoLine { line: 3, col: 0 });
+        ctx.do_edit(EditNotification::Insert { chars: "y".into() });
+        assert_eq!(harness.view.borrow().folds(), &[(1, 4)]);
+        // but edits of the lines it hides, from its placeholder, unfold it
+        ctx.do_edit(EditNotification::GotoLine { line: 2, col: 0 });
+        ctx.do_edit(EditNotification::Insert { chars: "z".into() });
+        assert!(harness.view.borrow().folds().is_empty());
+        assert_eq!(harness.debug_render(), "x\na!\nz|b\nc\nyd\ne");
+
+        ctx.do_edit(EditNotification::FoldRange { start_line: 0, end_line: 2 });
+        ctx.do_edit(EditNotification::FoldRange { start_line: 2, end_line: 5 });
+        // folds that would hide nothing are ignored
+        ctx.do_edit(EditNotification::FoldRange { start_line: 5, end_line: 6 });
+        assert_eq!(harness.view.borrow().folds(), &[(0, 2), (2, 5)]);
+        // a fold overlapping others replaces them
+        ctx.do_edit(EditNotification::FoldRange { start_line: 1, end_line: 4 });
+        assert_eq!(harness.view.borrow().folds(), &[(1, 4)]);
+        ctx.do_edit(EditNotification::UnfoldRange { start_line: 3, end_line: 3 });
+        assert!(harness.view.borrow().folds().is_empty());
+    }
+
+    #[test]
+    fn macro_test() {
+        let harness = ContextHarness::new("a\nb\nc");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::StartMacroRecord { register: 'q' });
+        ctx.do_edit(EditNotification::Insert { chars: "- ".into() });
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::MoveToLeftEndOfLine);
+        ctx.do_edit(EditNotification::StopMacroRecord);
+        assert_eq!(harness.debug_render(), "- a\n|b\nc");
+
+        ctx.do_edit(EditNotification::PlayMacro { register: 'q', count: 2 });
+        assert_eq!(harness.debug_render(), "- a\n- b\n|- c");
+        // each time the macro was played is undone separately
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "- a\n- b\n|c");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "- a\nb\n|c");
+        // an empty register plays nothing
+        ctx.do_edit(EditNotification::PlayMacro { register: 'w', count: 1 });
+        assert_eq!(harness.debug_render(), "- a\nb\n|c");
+
+        // a macro playing another is recorded as such, and undone as one
+        ctx.do_edit(EditNotification::StartMacroRecord { register: 'w' });
+        ctx.do_edit(EditNotification::PlayMacro { register: 'q', count: 1 });
+        ctx.do_edit(EditNotification::Insert { chars: "!".into() });
+        ctx.do_edit(EditNotification::StopMacroRecord);
+        assert_eq!(harness.debug_render(), "- a\nb\n!|- c");
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::PlayMacro { register: 'w', count: 1 });
+        assert_eq!(harness.debug_render(), "- - a\n!|b\n!- c");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "- a\n|b\n!- c");
+
+        // huge counts are capped
+        ctx.do_edit(EditNotification::PlayMacro { register: 'q', count: usize::max_value() });
+    }
+
+    #[test]
+    fn marks_test() {
+        let harness = ContextHarness::new("one\n  two three\nfour");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::GotoLine { line: 1, col: 6 });
+        ctx.do_edit(EditNotification::SetMark { name: 'a' });
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        // the mark follows its text
+        ctx.do_edit(EditNotification::Insert { chars: "zero\n".into() });
+        ctx.do_edit(EditNotification::JumpToMark { name: 'a', line: false });
+        assert_eq!(harness.debug_render(), "zero\none\n  two |three\nfour");
+        ctx.do_edit(EditNotification::JumpToMark { name: 'a', line: true });
+        assert_eq!(harness.debug_render(), "zero\none\n  |two three\nfour");
+        // unset marks are ignored
+        ctx.do_edit(EditNotification::JumpToMark { name: 'b', line: false });
+        assert_eq!(harness.debug_render(), "zero\none\n  |two three\nfour");
+    }
+
+    #[test]
+    fn paste_from_history_test() {
+        let harness = ContextHarness::new("one two");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveWordRightAndModifySelection);
+        assert_eq!(ctx.do_edit_sync(EditRequest::Copy), Ok(json!("one")));
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::DeleteToEndOfParagraph);
+        ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+        ctx.do_edit(EditNotification::SelectAll);
+        assert_eq!(ctx.do_edit_sync(EditRequest::Cut), Ok(json!("x")));
+        // the killed text is kept apart, for yanking
+        assert_eq!(harness.clipboard_history.borrow().entries(), vec!["x", "one"]);
+
+        ctx.do_edit(EditNotification::PasteFromHistory { index: 1 });
+        // there is no third entry
+        ctx.do_edit(EditNotification::PasteFromHistory { index: 2 });
+        assert_eq!(harness.debug_render(), "one|");
+        // yanking pastes the last kill, not the last copy
+        ctx.do_edit(EditNotification::Yank);
+        assert_eq!(harness.debug_render(), "oneone two|");
+    }
+
+    #[test]
+    fn read_only_test() {
+        let harness = ContextHarness::new("one");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::SetReadOnly { read_only: true });
+        ctx.do_edit(EditNotification::Insert { chars: "two".into() });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert!(ctx.do_edit_sync(EditRequest::Cut).is_err());
+        assert_eq!(harness.debug_render(), "[one|]");
+        assert_eq!(ctx.do_edit_sync(EditRequest::Copy), Ok(json!("one")));
+
+        // the flag survives a reload
+        ctx.reload("three".into());
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "two".into() });
+        assert_eq!(harness.debug_render(), "three|");
+
+        ctx.do_edit(EditNotification::SetReadOnly { read_only: false });
+        ctx.do_edit(EditNotification::Insert { chars: "two".into() });
+        assert_eq!(harness.debug_render(), "threetwo|");
+    }
+
+    #[test]
+    fn accept_completion_test() {
+        use plugins::rpc::{CompletionItem, CompletionKind};
+        let harness = ContextHarness::new("let x = ve");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        let item = |text: &str| CompletionItem {
+            label: text.into(),
+            kind: CompletionKind::Function,
+            insert_text: text.into(),
+            detail: None,
+        };
+        ctx.do_show_completions(CompletionList {
+            items: vec![item("vec!"), item("verify")],
+            trigger_offset: 8,
+        });
+        // edits before the trigger move it along
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "  ".into() });
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "r".into() });
+        ctx.do_edit(EditNotification::AcceptCompletion { index: 1 });
+        assert_eq!(harness.debug_render(), "  let x = verify|");
+        // the list is gone once used
+        ctx.do_edit(EditNotification::AcceptCompletion { index: 0 });
+        assert_eq!(harness.debug_render(), "  let x = verify|");
+
+        // lists whose trigger is not a character boundary in the buffer
+        // are not shown
+        ctx.do_edit(EditNotification::Insert { chars: " é".into() });
+        for &trigger_offset in &[18, 100] {
+            ctx.do_show_completions(CompletionList {
+                items: vec![item("vec!")],
+                trigger_offset,
+            });
+            ctx.do_edit(EditNotification::AcceptCompletion { index: 0 });
+            assert_eq!(harness.debug_render(), "  let x = verify é|");
+        }
+    }
+
+    #[test]
+    fn snippet_test() {
+        let harness = ContextHarness::new("");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::ExpandSnippet {
+            snippet: "fn ${1:name}($2) -> ${1:name} {\n$0\n}".into(),
+        });
+        assert_eq!(harness.debug_render(), "fn [name|]() -> [name|] {\n\n}");
+        ctx.do_edit(EditNotification::Insert { chars: "f".into() });
+        assert_eq!(harness.debug_render(), "fn f|() -> f| {\n\n}");
+        ctx.do_edit(EditNotification::InsertTab);
+        assert_eq!(harness.debug_render(), "fn f(|) -> f {\n\n}");
+        ctx.do_edit(EditNotification::Outdent);
+        assert_eq!(harness.debug_render(), "fn [f|]() -> [f|] {\n\n}");
+        ctx.do_edit(EditNotification::InsertTab);
+        ctx.do_edit(EditNotification::InsertTab);
+        // the final tab stop ends the snippet
+        assert_eq!(harness.debug_render(), "fn f() -> f {\n|\n}");
+        ctx.do_edit(EditNotification::InsertTab);
+        assert_eq!(harness.debug_render(), "fn f() -> f {\n    |\n}");
+
+        // as does typing outside the tab stops
+        ctx.do_edit(EditNotification::ExpandSnippet { snippet: "($1)".into() });
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        ctx.do_edit(EditNotification::InsertTab);
+        assert_eq!(harness.debug_render(), "a   |fn f() -> f {\n    ()\n}");
+    }
+
+    #[test]
+    fn document_stats_test() {
+        let harness = ContextHarness::new("It's a\n¡día «muy» largo!");
+        let mut ctx = harness.make_context();
+        let stats = ctx.do_edit_sync(EditRequest::DocumentStats).unwrap();
+        assert_eq!(stats, json!({"word_count": 5, "char_count": 24}));
+
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: " ok".into() });
+        let stats = ctx.do_edit_sync(EditRequest::DocumentStats).unwrap();
+        assert_eq!(stats, json!({"word_count": 6, "char_count": 27}));
+        assert_eq!(ctx.editor.borrow_mut().word_count(), 6);
+    }
+
+    #[test]
+    fn count_occurrences_test() {
+        use find::FindOptions;
+        let harness = ContextHarness::new("foo Foo\nfoo");
+        let mut ctx = harness.make_context();
+        let count = |ctx: &mut EventContext, case_sensitive| {
+            let options = FindOptions { case_sensitive, ..FindOptions::default() };
+            let cmd = EditRequest::CountOccurrences { pattern: "foo".into(), options };
+            ctx.do_edit_sync(cmd).unwrap()
+        };
+        assert_eq!(count(&mut ctx, false), json!(3));
+        assert_eq!(count(&mut ctx, true), json!(2));
+        // counting neither moves the caret nor starts a search
+        assert_eq!(harness.debug_render(), "|foo Foo\nfoo");
+        assert!(harness.view.borrow_mut().find_status(false).is_empty());
+
+        ctx.do_edit(EditNotification::Insert { chars: "foo".into() });
+        assert_eq!(count(&mut ctx, true), json!(3));
+    }
+
+    #[test]
+    fn minimap_test() {
+        use std::thread;
+        let harness = ContextHarness::new("one\ntwo");
+        let mut ctx = harness.make_context();
+        let request = || EditRequest::GetMinimap { target_width: 10, target_height: 10 };
+        // computed in the background, then cached
+        assert_eq!(ctx.do_edit_sync(request()), Ok(Value::Null));
+        let mut minimap = Value::Null;
+        for _ in 0..100 {
+            minimap = ctx.do_edit_sync(request()).unwrap();
+            if !minimap.is_null() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!((&minimap["width"], &minimap["height"]), (&json!(3), &json!(2)));
+
+        ctx.do_edit(EditNotification::Insert { chars: "!".into() });
+        assert_eq!(ctx.do_edit_sync(request()), Ok(Value::Null));
+    }
+
+    #[test]
+    fn split_view_providers_test() {
+        use plugins::PluginPid;
+        let harness = ContextHarness::new("");
+        let buffer_id = harness.view.borrow().get_buffer_id();
+        // plugins register through the view they see the buffer through,
+        // which is not the one making the request
+        let plugin_view = RefCell::new(View::new(ViewId(3), buffer_id));
+        plugin_view.borrow_mut().definition_requests_mut().register_provider(PluginPid(1));
+        plugin_view.borrow_mut().hover_requests_mut().register_provider(PluginPid(2));
+        let mut ctx = harness.make_context();
+        assert!(!ctx.is_registered_provider(PluginPid(1), RequestKind::Definition));
+
+        ctx.siblings.push(&plugin_view);
+        assert!(ctx.is_registered_provider(PluginPid(1), RequestKind::Definition));
+        assert!(!ctx.is_registered_provider(PluginPid(2), RequestKind::Definition));
+        assert!(ctx.is_registered_provider(PluginPid(2), RequestKind::Hover));
+    }
+
+    #[test]
+    fn syntax_context_test() {
+        use plugins::PluginPid;
+        let harness = ContextHarness::new("let é = 1;");
+        let mut ctx = harness.make_context();
+        let request = |offset| PluginRequest::GetSyntaxContext { offset };
+        let context = ctx.do_plugin_cmd_sync(PluginPid(1), request(3)).unwrap();
+        assert_eq!(context["prefix"], json!("let"));
+        // offsets inside a character or past the end are rejected
+        for &offset in &[5, 100] {
+            assert!(ctx.do_plugin_cmd_sync(PluginPid(1), request(offset)).is_err());
+        }
+    }
+
+    #[test]
+    fn sticky_header_and_breadcrumbs_test() {
+        use plugins::PluginPid;
+        use xi_rope::spans::SpansBuilder;
+        let text = "fn f() {\n    let x = 1;\n    g(x);\n}\n";
+        let harness = ContextHarness::new(text);
+        {
+            let mut ed = harness.editor.borrow_mut();
+            let scopes = vec![vec!["source.rust".to_string()],
+                              vec!["source.rust".to_string(),
+                                   "entity.name.function.rust".to_string()]];
+            ed.get_layers_mut().add_scopes(PluginPid(1), scopes, &harness.style_map.borrow());
+            let mut sb = SpansBuilder::new(text.len());
+            sb.add_span(Interval::new_closed_open(0, 3), 0);
+            sb.add_span(Interval::new_closed_open(3, 4), 1);
+            sb.add_span(Interval::new_closed_open(4, text.len()), 0);
+            ed.get_layers_mut().update_layer(PluginPid(1),
+                                             Interval::new_closed_closed(0, text.len()),
+                                             sb.build());
+        }
+        let mut ctx = harness.make_context();
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetStickyHeader), Ok(Value::Null));
+        // the breadcrumbs are found straight away at first
+        let labels = || harness.view.borrow().breadcrumbs().items().iter()
+            .map(|item| item.label.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(labels(), vec!["f"]);
+        ctx.do_edit(EditNotification::Scroll(LineRange { first: 2, last: 3 }));
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetStickyHeader),
+                   Ok(json!([{"line_number": 0, "text": "fn f() {", "scope_depth": 0}])));
+        assert_eq!(harness.view.borrow().sticky_header_line(), Some(2));
+        ctx.do_edit(EditNotification::Scroll(LineRange { first: 3, last: 4 }));
+        assert_eq!(harness.view.borrow().sticky_header_line(), Some(3));
+
+        // then at most once every `BREADCRUMBS_INTERVAL`
+        ctx.do_edit(EditNotification::GotoLine { line: 3, col: 0 });
+        assert_eq!(labels(), vec!["f"]);
+        ::std::thread::sleep(::breadcrumbs::BREADCRUMBS_INTERVAL);
+        ctx.breadcrumbs_timer_fired();
+        assert!(labels().is_empty());
+    }
+
+    #[test]
+    fn goto_line_test() {
+        let harness = ContextHarness::new("one\ntwo\nthree");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::GotoLine { line: 1, col: 2 });
+        assert_eq!(harness.debug_render(), "one\ntw|o\nthree");
+        ctx.do_edit(EditNotification::GotoLine { line: 0, col: 10 });
+        assert_eq!(harness.debug_render(), "one|\ntwo\nthree");
+        ctx.do_edit(EditNotification::GotoLine { line: 10, col: 0 });
+        assert_eq!(harness.debug_render(), "one\ntwo\nthree|");
+    }
+
+    #[test]
+    fn undo_all_test() {
+        let harness = ContextHarness::new("a");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "b".into() });
+        ctx.do_edit(EditNotification::InsertNewline);
+        ctx.do_edit(EditNotification::Insert { chars: "c".into() });
+        ctx.do_edit(EditNotification::UndoAll);
+        assert_eq!(harness.debug_render(), "a|");
+        ctx.do_edit(EditNotification::UndoAll);
+        assert_eq!(harness.debug_render(), "a|");
+        ctx.do_edit(EditNotification::RedoAll);
+        assert_eq!(harness.debug_render(), "ab\nc|");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "ab\n|");
+    }
+
+    #[test]
+    fn undo_grouping_interval_test() {
+        use config::ConfigDomain;
+        use std::thread;
+        let mut harness = ContextHarness::new("");
+        {
+            let mut ctx = harness.make_context();
+            ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+            ctx.do_edit(EditNotification::Insert { chars: "b".into() });
+            ctx.do_edit(EditNotification::Undo);
+            assert_eq!(harness.debug_render(), "|");
+        }
+
+        let changes = json!({"undo_grouping_interval_ms": 1}).as_object().unwrap().to_owned();
+        harness.config_manager.set_user_config(ConfigDomain::General, changes).unwrap();
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        thread::sleep(Duration::from_millis(10));
+        // after a pause, typing starts a new undo group
+        ctx.do_edit(EditNotification::Insert { chars: "b".into() });
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "a|");
+    }
+
+    #[test]
+    fn config_changes_rewrap_only_when_needed() {
+        use config::ConfigDomain;
+        let mut harness = ContextHarness::new("one two three");
+        assert!(!harness.view.borrow().needs_rewrap(false, 0));
+        let changes = json!({"wrap_width": 5}).as_object().unwrap().to_owned();
+        let changes = harness.config_manager.set_user_config(ConfigDomain::General, changes)
+            .unwrap();
+        assert!(harness.view.borrow().needs_rewrap(false, 5));
+        harness.make_context().config_changed(&changes[0].1);
+        assert!(!harness.view.borrow().needs_rewrap(false, 5));
+        assert!(harness.view.borrow().needs_rewrap(false, 0));
+        assert!(harness.view.borrow().needs_rewrap(true, 5));
+
+        harness.view.borrow_mut().rewrap(&Rope::from("one two three"), 0);
+        assert!(!harness.view.borrow().needs_rewrap(false, 0));
+    }
+
+    #[test]
+    fn clean_up_before_save() {
+        use config::ConfigDomain;
+        let mut harness = ContextHarness::new("fn main() {  \n\t\n}\t");
+        {
+            // nothing happens by default
+            let mut ctx = harness.make_context();
+            ctx.clean_up_before_save();
+            assert_eq!(harness.debug_render(), "|fn main() {  \n\t\n}\t");
+        }
+
+        let changes = json!({"trim_trailing_whitespace": true, "insert_final_newline": true})
+            .as_object().unwrap().to_owned();
+        harness.config_manager.set_user_config(ConfigDomain::General, changes).unwrap();
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.clean_up_before_save();
+        // the line of the caret keeps its whitespace
+        assert_eq!(harness.debug_render(), "fn main() {\n|\t\n}\n");
+        // the clean up is one undo group
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "fn main() {  \n|\t\n}\t");
+    }
+
+    #[test]
+    fn trim_final_newlines() {
+        use config::ConfigDomain;
+        let mut harness = ContextHarness::new("a\n\r\n\n");
+        let changes = json!({"trim_final_newlines": true}).as_object().unwrap().to_owned();
+        harness.config_manager.set_user_config(ConfigDomain::General, changes).unwrap();
+        let mut ctx = harness.make_context();
+        ctx.clean_up_before_save();
+        assert_eq!(harness.debug_render(), "|a\n");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "|a\n\r\n\n");
+
+        // a text that already ends with one line break is left alone
+        let mut harness = ContextHarness::new("a\r\n");
+        let changes = json!({"trim_final_newlines": true, "insert_final_newline": true})
+            .as_object().unwrap().to_owned();
+        harness.config_manager.set_user_config(ConfigDomain::General, changes).unwrap();
+        harness.make_context().clean_up_before_save();
+        assert_eq!(harness.debug_render(), "|a\r\n");
+        assert!(harness.editor.borrow().is_pristine());
+    }
+
+    #[test]
+    fn auto_indent_test() {
+        use config::ConfigDomain;
+        let mut harness = ContextHarness::new("fn main() {");
+        let changes = json!({"auto_indent": true}).as_object().unwrap().to_owned();
+        harness.config_manager.set_user_config(ConfigDomain::General, changes).unwrap();
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::InsertNewline);
+        assert_eq!(harness.debug_render(), "fn main() {\n    |");
+
+        ctx.do_edit(EditNotification::Insert { chars: "foo();".into() });
+        ctx.do_edit(EditNotification::InsertNewline);
+        assert_eq!(harness.debug_render(), "fn main() {\n    foo();\n    |");
+
+        // a closing brace at the start of a line removes one level
+        ctx.do_edit(EditNotification::Insert { chars: "}".into() });
+        assert_eq!(harness.debug_render(), "fn main() {\n    foo();\n}|");
+
+        // but not when it follows other text
+        ctx.do_edit(EditNotification::InsertNewline);
+        ctx.do_edit(EditNotification::Insert { chars: "  x}".into() });
+        assert_eq!(harness.debug_render(), "fn main() {\n    foo();\n}\n  x}|");
+    }
+
+    #[test]
+    fn toggle_comment_test() {
+        use config::ConfigDomain;
+        use rpc::GestureType::*;
+        let mut harness = ContextHarness::new("fn a() {\n\n    b();\n    // c();\n}");
+        let changes = json!({
+            "line_comment": "//",
+            "block_comment_start": "/*",
+            "block_comment_end": "*/",
+        }).as_object().unwrap().to_owned();
+        harness.config_manager.set_user_config(ConfigDomain::General, changes).unwrap();
+        let mut ctx = harness.make_context();
+
+        // the blank line is skipped, and markers align to the least indentation
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 0, ty: RangeSelect });
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "[// fn a() {\n\n//     b();\n|]    // c();\n}");
+
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "[fn a() {\n\n    b();\n|]    // c();\n}");
+
+        // the lines are only uncommented when all of them are commented
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 4, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 4, ty: ToggleSel });
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "fn a() {\n\n    // |b();\n    // |// c();\n}");
+        ctx.do_edit(EditNotification::Undo);
+
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 4, ty: PointSelect });
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "fn a() {\n\n    b();\n    |c();\n}");
+
+        // inline ranges get block comments
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 4, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 7, ty: RangeSelect });
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "fn a() {\n\n    [/*b()*/|];\n    c();\n}");
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "fn a() {\n\n    [b()|];\n    c();\n}");
+    }
+
     #[test]
     fn multiline_indentation_test() {
         use rpc::GestureType::*;
@@ -839,4 +2686,124 @@ mod tests {
         that has three\n\
         |lines." );
     }
+
+    #[test]
+    fn format_edits_rebased_and_undone_together() {
+        use formatting::EditPosition;
+        use plugins::PluginPid;
+        let harness = ContextHarness::new("fn  main(){\n}");
+        let mut ctx = harness.make_context();
+        let rev = harness.editor.borrow().get_head_rev_token();
+        let request_id = harness.view.borrow_mut().format_requests_mut()
+            .start(PluginPid(1), rev, None);
+        // typed while the provider was formatting
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+
+        let edit = |line, col, end_col, new_text: &str| TextEdit {
+            start: EditPosition { line, col },
+            end: EditPosition { line, col: end_col },
+            new_text: new_text.into(),
+        };
+        let edits = vec![edit(0, 10, 10, " "), edit(0, 2, 4, " ")];
+        assert_eq!(ctx.do_format_edits(PluginPid(1), request_id, Ok(edits.clone())), None);
+        assert_eq!(harness.debug_render(), "fn main() {\n}x|");
+
+        // a stale request is ignored
+        ctx.do_format_edits(PluginPid(1), request_id, Ok(edits));
+        assert_eq!(harness.debug_render(), "fn main() {\n}x|");
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "fn  main(){\n}x|");
+    }
+
+    #[test]
+    fn rename_edits_checked_then_made() {
+        use formatting::{EditPosition, FormatError};
+        let harness = ContextHarness::new("let a = 1;\nf(a);");
+        let mut ctx = harness.make_context();
+        let rev = harness.editor.borrow().get_head_rev_token();
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: " a".into() });
+
+        let edit = |line, col, new_text: &str| TextEdit {
+            start: EditPosition { line, col },
+            end: EditPosition { line, col: col + 1 },
+            new_text: new_text.into(),
+        };
+        let edits = vec![edit(0, 4, "count"), edit(1, 2, "count")];
+        let invalid = vec![edit(0, 4, "count"), edit(3, 0, "count")];
+        assert!(harness.editor.borrow().check_text_edits(rev, &edits).is_ok());
+        assert!(harness.editor.borrow().check_text_edits(rev, &invalid).is_err());
+        assert_eq!(harness.debug_render(), "let a = 1;\nf(a); a|");
+
+        // rebased over the typing, and undone in one step
+        assert_eq!(ctx.apply_text_edits(Some(rev), &edits), Ok(()));
+        assert_eq!(harness.debug_render(), "let count = 1;\nf(count); a|");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "let a = 1;\nf(a); a|");
+
+        harness.editor.borrow_mut().set_read_only(true);
+        assert_eq!(harness.editor.borrow().check_text_edits(rev, &edits),
+                   Err(FormatError::ReadOnly));
+    }
+
+    #[test]
+    fn deselect_keeps_cursors() {
+        use rpc::GestureType::*;
+        let harness = ContextHarness::new("one two\nthree four");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 4, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: ToggleSel });
+        ctx.do_edit(EditNotification::MoveWordRightAndModifySelection);
+        assert_eq!(harness.debug_render(), "one [two|]\n[three|] four");
+        ctx.do_edit(EditNotification::Deselect);
+        assert_eq!(harness.debug_render(), "one two|\nthree| four");
+
+        // the caret is left at the focus of a backwards selection
+        ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection);
+        assert_eq!(harness.debug_render(), "one [|two]\n[|three] four");
+        ctx.do_edit(EditNotification::Deselect);
+        assert_eq!(harness.debug_render(), "one |two\n|three four");
+
+        ctx.do_edit(EditNotification::SelectAll);
+        assert_eq!(harness.debug_render(), "[one two\nthree four|]");
+        ctx.do_edit(EditNotification::Deselect);
+        assert_eq!(harness.debug_render(), "one two\nthree four|");
+    }
+
+    #[test]
+    fn expand_and_shrink_selection() {
+        use rpc::GestureType::*;
+        let harness = ContextHarness::new("f(a, [b, c]) {}");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 6, ty: PointSelect });
+        ctx.do_edit(EditNotification::ExpandSelection);
+        assert_eq!(harness.debug_render(), "f(a, [[b, c|]]) {}");
+        ctx.do_edit(EditNotification::ExpandSelection);
+        assert_eq!(harness.debug_render(), "f(a, [[b, c]|]) {}");
+        ctx.do_edit(EditNotification::ExpandSelection);
+        assert_eq!(harness.debug_render(), "f([a, [b, c]|]) {}");
+        ctx.do_edit(EditNotification::ExpandSelection);
+        assert_eq!(harness.debug_render(), "f[(a, [b, c])|] {}");
+        ctx.do_edit(EditNotification::ExpandSelection);
+        assert_eq!(harness.debug_render(), "[f(a, [b, c]) {}|]");
+        // the whole buffer can't grow, so there is nothing to shrink back to
+        ctx.do_edit(EditNotification::ExpandSelection);
+        ctx.do_edit(EditNotification::ShrinkSelection);
+        assert_eq!(harness.debug_render(), "f[(a, [b, c])|] {}");
+        ctx.do_edit(EditNotification::ShrinkSelection);
+        ctx.do_edit(EditNotification::ShrinkSelection);
+        ctx.do_edit(EditNotification::ShrinkSelection);
+        ctx.do_edit(EditNotification::ShrinkSelection);
+        assert_eq!(harness.debug_render(), "f(a, [|b, c]) {}");
+        ctx.do_edit(EditNotification::ShrinkSelection);
+        assert_eq!(harness.debug_render(), "f(a, [|b, c]) {}");
+
+        // moving forgets the expansions
+        ctx.do_edit(EditNotification::ExpandSelection);
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::ShrinkSelection);
+        assert_eq!(harness.debug_render(), "f(a, [b, c|]) {}");
+    }
 }
@@ -14,16 +14,21 @@
 
 //! Interactions with the file system.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
 use std::time::SystemTime;
 
 use xi_rpc::RemoteError;
 use xi_rope::Rope;
+use xi_rope::engine::RevId;
+use xi_rope::tree::TreeBuilder;
 
 use tabs::BufferId;
 
@@ -33,11 +38,16 @@ use tabs::OPEN_FILE_EVENT_TOKEN;
 use watcher::FileWatcher;
 
 const UTF8_BOM: &str = "\u{feff}";
+const UTF16_LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16_BE_BOM: &[u8] = b"\xFE\xFF";
 
 /// Tracks all state related to open files.
 pub struct FileManager {
     open_files: HashMap<PathBuf, BufferId>,
     file_info: HashMap<BufferId, FileInfo>,
+    /// The buffers being written by a `SaveWorker`, with the number of
+    /// writes of each not yet finished.
+    saving: HashMap<BufferId, usize>,
     /// A monitor of filesystem events, for things like reloading changed files.
     #[cfg(feature = "notify")]
     watcher: FileWatcher,
@@ -46,29 +56,54 @@ pub struct FileManager {
 #[derive(Debug)]
 pub struct FileInfo {
     pub encoding: CharacterEncoding,
+    pub line_ending: LineEnding,
     pub path: PathBuf,
     pub mod_time: Option<SystemTime>,
     pub has_changed: bool,
 }
 
+#[derive(Debug)]
 pub enum FileError {
     Io(io::Error, PathBuf),
     UnknownEncoding(PathBuf),
     HasChanged(PathBuf),
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The encoding of a file on disk. Buffers are always UTF-8 internally;
+/// other encodings are transcoded on load and save.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CharacterEncoding {
     Utf8,
-    Utf8WithBom
+    Utf8WithBom,
+    /// UTF-16, little-endian, with a byte order mark.
+    Utf16Le,
+    /// UTF-16, big-endian, with a byte order mark.
+    Utf16Be,
+}
+
+/// The line ending of a file on disk. Buffers of `CrLf` and `Cr` files
+/// store `\n` internally; line endings are converted on load and save.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
 }
 
+/// The number of line breaks looked at when detecting a file's line ending.
+const LINE_ENDING_SAMPLE: usize = 512;
+
+/// The size of the blocks in which UTF-8 files are read. A file is never
+/// held in memory as a whole apart from its rope.
+const READ_CHUNK_SIZE: usize = 1 << 20;
+
 impl FileManager {
     #[cfg(feature = "notify")]
     pub fn new(watcher: FileWatcher) -> Self {
         FileManager {
             open_files: HashMap::new(),
             file_info: HashMap::new(),
+            saving: HashMap::new(),
             watcher,
         }
     }
@@ -78,6 +113,7 @@ impl FileManager {
         FileManager {
             open_files: HashMap::new(),
             file_info: HashMap::new(),
+            saving: HashMap::new(),
         }
     }
 
@@ -98,6 +134,10 @@ impl FileManager {
     /// This state is stashed.
     pub fn check_file(&mut self, path: &Path, id: BufferId) -> bool {
         if let Some(info) = self.file_info.get_mut(&id) {
+            // a save in the background is not a change made by someone else
+            if self.saving.contains_key(&id) {
+                return info.has_changed;
+            }
             let mod_t = get_mod_time(path);
             if mod_t != info.mod_time {
                 info.has_changed = true
@@ -132,23 +172,32 @@ impl FileManager {
         }
     }
 
-    pub fn save(&mut self, path: &Path, text: &Rope, id: BufferId)
+    /// Writes `text` to `path`. The file keeps the encoding and line ending
+    /// it was opened with, or is UTF-8 with `\n` line breaks if it is new,
+    /// unless others are given.
+    pub fn save(&mut self, path: &Path, text: &Rope, id: BufferId,
+                encoding: Option<CharacterEncoding>, line_ending: Option<LineEnding>)
         -> Result<(), FileError>
     {
         let is_existing = self.file_info.contains_key(&id);
         if is_existing {
-            self.save_existing(path, text, id)
+            self.save_existing(path, text, id, encoding, line_ending)
         } else {
-            self.save_new(path, text, id)
+            self.save_new(path, text, id, encoding, line_ending)
         }
     }
 
-    fn save_new(&mut self, path: &Path, text: &Rope, id: BufferId)
+    fn save_new(&mut self, path: &Path, text: &Rope, id: BufferId,
+                encoding: Option<CharacterEncoding>, line_ending: Option<LineEnding>)
         -> Result<(), FileError>
     {
-        try_save(path, text, CharacterEncoding::Utf8).map_err(|e| FileError::Io(e, path.to_owned()))?;
+        let encoding = encoding.unwrap_or(CharacterEncoding::Utf8);
+        let line_ending = line_ending.unwrap_or(LineEnding::Lf);
+        try_save(path, text, encoding, line_ending)
+            .map_err(|e| FileError::Io(e, path.to_owned()))?;
         let info = FileInfo {
-            encoding: CharacterEncoding::Utf8,
+            encoding,
+            line_ending,
             path: path.to_owned(),
             mod_time: get_mod_time(path),
             has_changed: false,
@@ -160,41 +209,205 @@ impl FileManager {
         Ok(())
     }
 
-    fn save_existing(&mut self, path: &Path, text: &Rope, id: BufferId)
+    fn save_existing(&mut self, path: &Path, text: &Rope, id: BufferId,
+                     encoding: Option<CharacterEncoding>, line_ending: Option<LineEnding>)
         -> Result<(), FileError>
     {
         let prev_path = self.file_info.get(&id).unwrap().path.clone();
         if prev_path != path {
-            self.save_new(path, text, id)?;
+            let (prev_encoding, prev_line_ending) = self.file_info.get(&id)
+                .map(|info| (info.encoding, info.line_ending))
+                .unwrap();
+            self.save_new(path, text, id, encoding.or(Some(prev_encoding)),
+                          line_ending.or(Some(prev_line_ending)))?;
             self.open_files.remove(&prev_path);
             #[cfg(feature = "notify")]
             self.watcher.unwatch(&prev_path, OPEN_FILE_EVENT_TOKEN);
         } else if self.file_info.get(&id).unwrap().has_changed {
             return Err(FileError::HasChanged(path.to_owned()));
         } else {
-            let encoding = self.file_info.get(&id).unwrap().encoding;
-            try_save(path, text, encoding).map_err(|e| FileError::Io(e, path.to_owned()))?;
-            self.file_info.get_mut(&id).unwrap()
-                .mod_time = get_mod_time(path);
+            let info = self.file_info.get_mut(&id).unwrap();
+            let encoding = encoding.unwrap_or(info.encoding);
+            let line_ending = line_ending.unwrap_or(info.line_ending);
+            try_save(path, text, encoding, line_ending)
+                .map_err(|e| FileError::Io(e, path.to_owned()))?;
+            info.encoding = encoding;
+            info.line_ending = line_ending;
+            info.mod_time = get_mod_time(path);
+            // the save may have replaced the file, so watch the new one
+            #[cfg(feature = "notify")]
+            {
+                let watched = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+                self.watcher.unwatch(&watched, OPEN_FILE_EVENT_TOKEN);
+                self.watcher.watch(&watched, false, OPEN_FILE_EVENT_TOKEN);
+            }
         }
         Ok(())
     }
+
+    /// Prepares the write of `text`, the buffer `id` as of `rev`, to the
+    /// file it was opened from, for a `SaveWorker`. Until the write is
+    /// finished with `finish_background_save`, changes to the file are
+    /// taken to be the write's.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer has no file.
+    pub(crate) fn start_background_save(&mut self, id: BufferId, text: &Rope, rev: RevId,
+                                        encoding: Option<CharacterEncoding>,
+                                        line_ending: Option<LineEnding>)
+        -> Result<BackgroundSave, FileError>
+    {
+        let info = self.file_info.get(&id).expect("saved buffer has a file");
+        if info.has_changed {
+            return Err(FileError::HasChanged(info.path.clone()));
+        }
+        *self.saving.entry(id).or_insert(0) += 1;
+        Ok(BackgroundSave {
+            buffer_id: id,
+            path: info.path.clone(),
+            rev,
+            text: text.clone(),
+            encoding: encoding.unwrap_or(info.encoding),
+            line_ending: line_ending.unwrap_or(info.line_ending),
+        })
+    }
+
+    /// Records the outcome of a write prepared by `start_background_save`.
+    /// Returns `false` if the buffer has been closed or saved elsewhere
+    /// since, in which case the write is of no concern to it.
+    pub(crate) fn finish_background_save(&mut self, save: &BackgroundSave, success: bool)
+        -> bool
+    {
+        let still_saving = match self.saving.get_mut(&save.buffer_id) {
+            Some(count) => {
+                *count -= 1;
+                *count > 0
+            }
+            None => false,
+        };
+        if !still_saving {
+            self.saving.remove(&save.buffer_id);
+        }
+        let info = match self.file_info.get_mut(&save.buffer_id) {
+            Some(ref info) if info.path != save.path => return false,
+            Some(info) => info,
+            None => return false,
+        };
+        if success {
+            info.encoding = save.encoding;
+            info.line_ending = save.line_ending;
+            info.mod_time = get_mod_time(&save.path);
+            // the save may have replaced the file, so watch the new one
+            #[cfg(feature = "notify")]
+            {
+                let watched = save.path.canonicalize().unwrap_or_else(|_| save.path.clone());
+                self.watcher.unwatch(&watched, OPEN_FILE_EVENT_TOKEN);
+                self.watcher.watch(&watched, false, OPEN_FILE_EVENT_TOKEN);
+            }
+        }
+        true
+    }
+}
+
+/// A buffer's text, as of `rev`, to be written by a `SaveWorker`.
+pub(crate) struct BackgroundSave {
+    pub buffer_id: BufferId,
+    pub path: PathBuf,
+    pub rev: RevId,
+    text: Rope,
+    encoding: CharacterEncoding,
+    line_ending: LineEnding,
+}
+
+/// Writes files on a thread of its own, so that saving a large buffer
+/// doesn't stall the core. `notify` is called, on that thread, after each
+/// write, whose outcome is then collected with `finished`.
+pub(crate) struct SaveWorker {
+    saves: Sender<BackgroundSave>,
+    done: Receiver<(BackgroundSave, io::Result<()>)>,
+    /// The number of saves not yet collected.
+    pending: usize,
+}
+
+impl SaveWorker {
+    pub(crate) fn new<F>(notify: F) -> SaveWorker
+        where F: Fn() + Send + 'static
+    {
+        let (saves, to_save) = channel::<BackgroundSave>();
+        let (finished, done) = channel();
+        thread::spawn(move || {
+            for save in to_save {
+                let result = try_save(&save.path, &save.text, save.encoding, save.line_ending);
+                if finished.send((save, result)).is_err() {
+                    break;
+                }
+                notify();
+            }
+        });
+        SaveWorker { saves, done, pending: 0 }
+    }
+
+    pub(crate) fn save(&mut self, save: BackgroundSave) {
+        if self.saves.send(save).is_ok() {
+            self.pending += 1;
+        }
+    }
+
+    /// Returns the saves that have been written, or have failed, since
+    /// this was last called.
+    pub(crate) fn finished(&mut self) -> Vec<(BackgroundSave, io::Result<()>)> {
+        let done: Vec<_> = self.done.try_iter().collect();
+        self.pending -= done.len();
+        done
+    }
+
+    /// Like `finished`, but first waits for every save to be written.
+    pub(crate) fn wait(&mut self) -> Vec<(BackgroundSave, io::Result<()>)> {
+        let done: Vec<_> = self.done.iter().take(self.pending).collect();
+        self.pending = 0;
+        done
+    }
 }
 
+/// Loads the file at `path` without opening it in a buffer, as to check
+/// edits to a file that is not open before opening it.
+pub(crate) fn load_unopened(path: &Path) -> Result<(Rope, FileInfo), FileError> {
+    try_load_file(path)
+}
+
+/// Loads the file at `path` into a rope. The rope holds all of the text:
+/// files are not memory-mapped or paged in on demand, so opening one needs
+/// memory for the whole of it. UTF-8 files are streamed into the rope a
+/// block at a time; UTF-16 files are read whole and then converted.
 fn try_load_file<P>(path: P) -> Result<(Rope, FileInfo), FileError>
 where P: AsRef<Path>
 {
-    // TODO: support for non-utf8
     // it's arguable that the rope crate should have file loading functionality
     let mut f = File::open(path.as_ref()).map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
     let mod_time = f.metadata().map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?.modified().ok();
-    let mut bytes = Vec::new();
-    f.read_to_end(&mut bytes).map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
+    let mut bytes = Vec::with_capacity(READ_CHUNK_SIZE);
+    (&mut f).take(READ_CHUNK_SIZE as u64).read_to_end(&mut bytes)
+        .map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
 
     let encoding = CharacterEncoding::guess(&bytes);
-    let rope = try_decode(bytes, encoding, path.as_ref())?;
+    let (rope, line_ending) = match encoding {
+        CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom => {
+            if encoding == CharacterEncoding::Utf8WithBom {
+                bytes.drain(..UTF8_BOM.len());
+            }
+            load_utf8(bytes, f, path.as_ref())?
+        }
+        CharacterEncoding::Utf16Le | CharacterEncoding::Utf16Be => {
+            f.read_to_end(&mut bytes).map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
+            let text = decode_utf16(&bytes, encoding, path.as_ref())?;
+            let line_ending = LineEnding::detect(&text);
+            (Rope::from(line_ending.normalize(&text)), line_ending)
+        }
+    };
     let info = FileInfo {
         encoding,
+        line_ending,
         mod_time,
         path: path.as_ref().to_owned(),
         has_changed: false,
@@ -202,37 +415,261 @@ where P: AsRef<Path>
     Ok((rope, info))
 }
 
-fn try_save(path: &Path, text: &Rope, encoding: CharacterEncoding)
-    -> io::Result<()>
+/// Saves `text` to `path`, atomically where possible: the text is written
+/// to a temporary file in the same directory, which is then renamed over
+/// `path`, so that a crash mid-write can't leave a truncated file.
+///
+/// If the temporary file can't be created or renamed (for instance, if the
+/// directory isn't writable, or the rename crosses devices), falls back to
+/// writing `path` in place.
+fn try_save(path: &Path, text: &Rope, encoding: CharacterEncoding,
+            line_ending: LineEnding) -> io::Result<()>
+{
+    // write through symlinks, rather than replacing them
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let tmp_path = temp_path_for(&path);
+
+    let result = File::create(&tmp_path)
+        .and_then(|mut f| {
+            if let Ok(metadata) = fs::metadata(&path) {
+                copy_permissions(&f, &metadata)?;
+            }
+            write_contents(&mut f, text, encoding, line_ending)?;
+            f.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, &path));
+
+    if let Err(e) = result {
+        debug!("atomic save of {:?} failed, writing in place: {}", path, e);
+        let _ = fs::remove_file(&tmp_path);
+        let mut f = File::create(&path)?;
+        write_contents(&mut f, text, encoding, line_ending)?;
+        f.sync_all()?;
+    }
+    Ok(())
+}
+
+fn write_contents(f: &mut File, text: &Rope, encoding: CharacterEncoding,
+                  line_ending: LineEnding) -> io::Result<()>
 {
-    let mut f = File::create(path)?;
+    match encoding {
+        CharacterEncoding::Utf8WithBom => f.write_all(UTF8_BOM.as_bytes())?,
+        CharacterEncoding::Utf16Le => f.write_all(UTF16_LE_BOM)?,
+        CharacterEncoding::Utf16Be => f.write_all(UTF16_BE_BOM)?,
+        CharacterEncoding::Utf8 => (),
+    }
+
+    let mut after_cr = false;
+    for chunk in text.iter_chunks(..text.len()) {
+        let chunk = line_ending.apply(chunk, &mut after_cr);
         match encoding {
-            CharacterEncoding::Utf8WithBom => f.write_all(UTF8_BOM.as_bytes())?,
-            CharacterEncoding::Utf8 => (),
+            CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom =>
+                f.write_all(chunk.as_bytes())?,
+            CharacterEncoding::Utf16Le | CharacterEncoding::Utf16Be => {
+                let mut bytes = Vec::with_capacity(chunk.len() * 2);
+                for unit in chunk.encode_utf16() {
+                    let (hi, lo) = ((unit >> 8) as u8, unit as u8);
+                    if encoding == CharacterEncoding::Utf16Le {
+                        bytes.extend_from_slice(&[lo, hi]);
+                    } else {
+                        bytes.extend_from_slice(&[hi, lo]);
+                    }
+                }
+                f.write_all(&bytes)?;
+            }
         }
+    }
+    Ok(())
+}
 
-        for chunk in text.iter_chunks(..text.len()) {
-            f.write_all(chunk.as_bytes())?;
+impl LineEnding {
+    /// Returns the line ending of an EditorConfig-style `end_of_line` name,
+    /// such as `crlf`, or `None` if it is not one.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            "cr" => Some(LineEnding::Cr),
+            _ => None,
         }
-        Ok(())
+    }
+
+    /// Returns the most common line ending among the first line breaks
+    /// in `text`, preferring `Lf` in case of a tie.
+    fn detect(text: &str) -> LineEnding {
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        let bytes = text.as_bytes();
+        let breaks = bytes.iter().enumerate()
+            .filter(|&(i, &b)| b == b'\n' || (b == b'\r' && bytes.get(i + 1) != Some(&b'\n')))
+            .take(LINE_ENDING_SAMPLE);
+        for (i, &b) in breaks {
+            match b {
+                b'\r' => cr += 1,
+                _ if i > 0 && bytes[i - 1] == b'\r' => crlf += 1,
+                _ => lf += 1,
+            }
+        }
+        if crlf > lf && crlf >= cr {
+            LineEnding::CrLf
+        } else if cr > lf && cr > crlf {
+            LineEnding::Cr
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    /// Converts the line breaks of text in this line ending to `\n`.
+    fn normalize<'a>(self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            LineEnding::Lf => Cow::from(text),
+            LineEnding::CrLf if text.contains('\r') => Cow::from(text.replace("\r\n", "\n")),
+            LineEnding::Cr if text.contains('\r') =>
+                Cow::from(text.replace("\r\n", "\n").replace('\r', "\n")),
+            _ => Cow::from(text),
+        }
+    }
+
+    /// Converts the `\n` line breaks in a chunk of a buffer to this line
+    /// ending, undoing `normalize`: a `\r` before a `\n` is a stray one,
+    /// kept as it is. With `Cr`, a `\r\n` stays a single line break;
+    /// `after_cr` tracks whether the previous chunk ended with `\r`.
+    fn apply<'a>(self, chunk: &'a str, after_cr: &mut bool) -> Cow<'a, str> {
+        let result = match self {
+            LineEnding::Lf => Cow::from(chunk),
+            _ if !chunk.contains('\n') => Cow::from(chunk),
+            _ => {
+                let mut s = String::with_capacity(chunk.len() + chunk.len() / 8);
+                let mut prev_cr = *after_cr;
+                for c in chunk.chars() {
+                    match (c, self) {
+                        ('\n', LineEnding::CrLf) => s.push_str("\r\n"),
+                        ('\n', LineEnding::Cr) if prev_cr => (),
+                        ('\n', LineEnding::Cr) => s.push('\r'),
+                        _ => s.push(c),
+                    }
+                    prev_cr = c == '\r';
+                }
+                Cow::from(s)
+            }
+        };
+        if !chunk.is_empty() {
+            *after_cr = chunk.ends_with('\r');
+        }
+        result
+    }
 }
 
-fn try_decode(bytes: Vec<u8>,
-              encoding: CharacterEncoding, path: &Path) -> Result<Rope, FileError> {
-    match encoding {
-        CharacterEncoding::Utf8 =>
-            Ok(Rope::from(str::from_utf8(&bytes).map_err(|_e| FileError::UnknownEncoding(path.to_owned()))?)),
-        CharacterEncoding::Utf8WithBom => {
-            let s = String::from_utf8(bytes).map_err(|_e| FileError::UnknownEncoding(path.to_owned()))?;
-            Ok(Rope::from(&s[UTF8_BOM.len()..]))
+/// Returns a hidden path next to `path`, for writing before a rename.
+fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.xi-save~", name))
+}
+
+/// Gives `f` the permissions and, where allowed, the ownership described
+/// by `metadata`.
+#[cfg(unix)]
+fn copy_permissions(f: &File, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    use libc;
+
+    f.set_permissions(metadata.permissions())?;
+    // changing the owner usually needs privileges; keeping the group often
+    // doesn't. Failure to do either isn't a reason not to save.
+    let fd = f.as_raw_fd();
+    unsafe {
+        if libc::fchown(fd, metadata.uid(), metadata.gid()) != 0 {
+            libc::fchown(fd, !0, metadata.gid());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_permissions(f: &File, metadata: &fs::Metadata) -> io::Result<()> {
+    f.set_permissions(metadata.permissions())
+}
+
+/// Builds a rope from UTF-8 text, of which `bytes` is the start and
+/// `rest` is the remainder, reading and converting a block at a time.
+/// The line ending is detected from the first block with a line break.
+fn load_utf8<R: Read>(mut bytes: Vec<u8>, mut rest: R, path: &Path)
+    -> Result<(Rope, LineEnding), FileError>
+{
+    let mut line_ending = None;
+    let mut builder = TreeBuilder::new();
+    let mut buf = vec![0; READ_CHUNK_SIZE];
+    loop {
+        let mut valid = match str::from_utf8(&bytes) {
+            Ok(s) => s.len(),
+            // a block can end in the middle of a character
+            Err(ref e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err(FileError::UnknownEncoding(path.to_owned())),
+        };
+        // likewise, a block can end between `\r` and `\n`
+        if valid > 0 && bytes[valid - 1] == b'\r' {
+            valid -= 1;
+        }
+        {
+            let text = str::from_utf8(&bytes[..valid]).unwrap();
+            if line_ending.is_none() && text.contains(&['\n', '\r'][..]) {
+                line_ending = Some(LineEnding::detect(text));
+            }
+            builder.push_str(&line_ending.unwrap_or(LineEnding::Lf).normalize(text));
         }
+        bytes.drain(..valid);
+
+        let n = rest.read(&mut buf).map_err(|e| FileError::Io(e, path.to_owned()))?;
+        if n == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buf[..n]);
+    }
+
+    // all that can be left over is a held back `\r`
+    let text = str::from_utf8(&bytes).map_err(|_e| FileError::UnknownEncoding(path.to_owned()))?;
+    let line_ending = line_ending.unwrap_or_else(|| LineEnding::detect(text));
+    builder.push_str(&line_ending.normalize(text));
+    Ok((builder.build(), line_ending))
+}
+
+fn decode_utf16(bytes: &[u8], encoding: CharacterEncoding, path: &Path)
+    -> Result<String, FileError>
+{
+    let units = &bytes[UTF16_LE_BOM.len()..];
+    if units.len() % 2 == 1 {
+        return Err(FileError::UnknownEncoding(path.to_owned()));
     }
+    let units = units.chunks(2)
+        .map(|pair| match encoding {
+            CharacterEncoding::Utf16Le => u16::from(pair[0]) | u16::from(pair[1]) << 8,
+            _ => u16::from(pair[0]) << 8 | u16::from(pair[1]),
+        })
+        .collect::<Vec<_>>();
+    String::from_utf16(&units).map_err(|_e| FileError::UnknownEncoding(path.to_owned()))
 }
 
 impl CharacterEncoding {
+    /// Returns the encoding of an EditorConfig-style `charset` name, such as
+    /// `utf-8-bom`, or `None` if it is not supported.
+    pub fn from_charset(charset: &str) -> Option<Self> {
+        match charset {
+            "utf-8" => Some(CharacterEncoding::Utf8),
+            "utf-8-bom" => Some(CharacterEncoding::Utf8WithBom),
+            "utf-16le" => Some(CharacterEncoding::Utf16Le),
+            "utf-16be" => Some(CharacterEncoding::Utf16Be),
+            _ => None,
+        }
+    }
+
     fn guess(s: &[u8]) -> Self {
         if s.starts_with(UTF8_BOM.as_bytes()) {
             CharacterEncoding::Utf8WithBom
+        } else if s.starts_with(UTF16_LE_BOM) {
+            CharacterEncoding::Utf16Le
+        } else if s.starts_with(UTF16_BE_BOM) {
+            CharacterEncoding::Utf16Be
         } else {
             CharacterEncoding::Utf8
         }
@@ -281,3 +718,167 @@ impl fmt::Display for FileError {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+
+    #[test]
+    fn save_replaces_contents() {
+        let tmp = tempdir::TempDir::new("xi-test-save").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "a much longer original text").unwrap();
+
+        try_save(&path, &Rope::from("new"), CharacterEncoding::Utf8, LineEnding::Lf).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        try_save(&path, &Rope::from("bom"), CharacterEncoding::Utf8WithBom, LineEnding::Lf).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"\xEF\xBB\xBFbom");
+        // the temporary file is gone
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn background_saves() {
+        use std::sync::mpsc::channel;
+        use xi_rope::engine::Engine;
+
+        let tmp = tempdir::TempDir::new("xi-test-background-save").unwrap();
+        let rev = Engine::new(Rope::from("")).get_head_rev_id();
+        let save = |name: &str, text: &str| BackgroundSave {
+            buffer_id: BufferId(1),
+            path: tmp.path().join(name),
+            rev,
+            text: Rope::from(text),
+            encoding: CharacterEncoding::Utf8,
+            line_ending: LineEnding::CrLf,
+        };
+        let (tx, rx) = channel();
+        let mut worker = SaveWorker::new(move || tx.send(()).unwrap());
+        worker.save(save("a.txt", "a\n"));
+        rx.recv().unwrap();
+        let done = worker.finished();
+        assert_eq!(done.len(), 1);
+        assert!(done[0].1.is_ok());
+        assert_eq!(fs::read_to_string(tmp.path().join("a.txt")).unwrap(), "a\r\n");
+
+        // waiting collects every save, in order
+        worker.save(save("b.txt", "b"));
+        worker.save(save("missing/c.txt", "c"));
+        let done = worker.wait();
+        assert_eq!(done.iter().map(|d| d.1.is_ok()).collect::<Vec<_>>(), vec![true, false]);
+        assert!(worker.finished().is_empty());
+    }
+
+    #[cfg(feature = "notify")]
+    #[test]
+    fn overlapping_background_saves() {
+        use std::sync::mpsc::channel;
+        use xi_rope::engine::Engine;
+
+        let tmp = tempdir::TempDir::new("xi-test-overlapping-saves").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "").unwrap();
+        let (tx, _rx) = channel::<bool>();
+        let mut manager = FileManager::new(FileWatcher::new(tx));
+        let id = BufferId(1);
+        manager.open(&path, id).unwrap();
+        let rev = Engine::new(Rope::from("")).get_head_rev_id();
+
+        // an edit during a slow write queues a second one
+        let first = manager.start_background_save(id, &Rope::from("a"), rev, None, None)
+            .unwrap();
+        let second = manager.start_background_save(id, &Rope::from("ab"), rev, None, None)
+            .unwrap();
+        try_save(&path, &first.text, first.encoding, first.line_ending).unwrap();
+        assert!(manager.finish_background_save(&first, true));
+        // the second write isn't taken for someone else's change
+        fs::write(&path, "a longer text, so the file is seen to change").unwrap();
+        assert!(!manager.check_file(&path, id));
+        try_save(&path, &second.text, second.encoding, second.line_ending).unwrap();
+        assert!(manager.finish_background_save(&second, true));
+        assert!(!manager.check_file(&path, id));
+
+        // once both are finished, changes are someone else's. File times
+        // may be coarser than the time between the writes.
+        thread::sleep(::std::time::Duration::from_millis(50));
+        fs::write(&path, "changed elsewhere, with yet another length").unwrap();
+        assert!(manager.check_file(&path, id));
+    }
+
+    #[test]
+    fn utf16_round_trip() {
+        let tmp = tempdir::TempDir::new("xi-test-utf16").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, b"\xFF\xFEh\x00\xe9\x00\x3d\xd8\x00\xde").unwrap();
+
+        let (rope, info) = try_load_file(&path).unwrap();
+        assert_eq!(String::from(&rope), "hé😀");
+        assert_eq!(info.encoding, CharacterEncoding::Utf16Le);
+
+        try_save(&path, &Rope::from("hé😀!"), CharacterEncoding::Utf16Be, LineEnding::Lf).unwrap();
+        assert_eq!(fs::read(&path).unwrap(),
+                   b"\xFE\xFF\x00h\x00\xe9\xd8\x3d\xde\x00\x00!".to_vec());
+        let (rope, info) = try_load_file(&path).unwrap();
+        assert_eq!(String::from(&rope), "hé😀!");
+        assert_eq!(info.encoding, CharacterEncoding::Utf16Be);
+
+        // an odd number of bytes isn't UTF-16
+        fs::write(&path, b"\xFE\xFF\x00").unwrap();
+        assert!(try_load_file(&path).is_err());
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(LineEnding::detect("a\nb\r\nc"), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), LineEnding::CrLf);
+        assert_eq!(LineEnding::detect("a\rb\rc\r\n"), LineEnding::Cr);
+        assert_eq!(LineEnding::detect("abc"), LineEnding::Lf);
+        assert_eq!(LineEnding::CrLf.normalize("a\r\r\nb\r"), "a\r\nb\r");
+
+        let tmp = tempdir::TempDir::new("xi-test-line-endings").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "one\r\ntwo\r\n").unwrap();
+        let (rope, info) = try_load_file(&path).unwrap();
+        assert_eq!(String::from(&rope), "one\ntwo\n");
+        assert_eq!(info.line_ending, LineEnding::CrLf);
+
+        // line breaks and characters split between blocks
+        let text = format!("{}\r\n{}\r\né", "a".repeat(READ_CHUNK_SIZE - 1),
+                           "b".repeat(READ_CHUNK_SIZE - 3));
+        fs::write(&path, &text).unwrap();
+        let (rope, info) = try_load_file(&path).unwrap();
+        assert!(String::from(&rope) == text.replace("\r\n", "\n"));
+        assert_eq!(info.line_ending, LineEnding::CrLf);
+        fs::write(&path, b"ab\xE9").unwrap();
+        assert!(try_load_file(&path).is_err());
+
+        // a stray `\r` before a line break is kept
+        fs::write(&path, "one\r\r\ntwo\r\n").unwrap();
+        let (rope, info) = try_load_file(&path).unwrap();
+        assert_eq!(String::from(&rope), "one\r\ntwo\n");
+        assert_eq!(info.line_ending, LineEnding::CrLf);
+        try_save(&path, &rope, info.encoding, info.line_ending).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\r\r\ntwo\r\n");
+
+        // with `Cr`, a `\r\n` already in the buffer isn't doubled
+        let text = Rope::concat(Rope::from("one\ntwo\r"), Rope::from("\nthree\n"));
+        try_save(&path, &text, CharacterEncoding::Utf8, LineEnding::Cr).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\rtwo\rthree\r");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempdir::TempDir::new("xi-test-save-permissions").unwrap();
+        let path = tmp.path().join("script.sh");
+        fs::write(&path, "true").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+
+        try_save(&path, &Rope::from("false"), CharacterEncoding::Utf8, LineEnding::Lf).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A fuzzy file finder. The paths of the files in a workspace are indexed
+//! by their trigrams, so that a search first scores the paths containing
+//! every trigram of the query, which it likely matches without gaps. The
+//! paths are also indexed by the characters they contain, which any match
+//! needs, to find the matches with gaps when there are too few of those.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use fuzzy::fuzzy_score;
+
+/// How many files the scan adds to the index between notifications.
+const SCAN_BATCH_SIZE: usize = 1000;
+
+/// How many results a search returns, unless it asks for some other number.
+pub const DEFAULT_SEARCH_LIMIT: usize = 50;
+
+type Trigram = [char; 3];
+
+#[derive(Debug)]
+struct Entry {
+    /// The path relative to the root, for display and matching.
+    relative: String,
+    trigrams: Vec<Trigram>,
+    chars: Vec<char>,
+}
+
+/// The files under a directory. Adding or removing a file costs the same
+/// however many files are indexed.
+#[derive(Debug)]
+pub struct FileIndex {
+    root: PathBuf,
+    /// Indexed by file id; a removed file leaves a `None` for reuse.
+    entries: Vec<Option<Entry>>,
+    ids: HashMap<String, usize>,
+    free: Vec<usize>,
+    /// The ids of the files containing each (lowercased) trigram.
+    trigrams: HashMap<Trigram, HashSet<usize>>,
+    /// The ids of the files containing each (lowercased) character.
+    chars: HashMap<char, HashSet<usize>>,
+}
+
+impl FileIndex {
+    pub fn new(root: PathBuf) -> FileIndex {
+        FileIndex {
+            root,
+            entries: Vec::new(),
+            ids: HashMap::new(),
+            free: Vec::new(),
+            trigrams: HashMap::new(),
+            chars: HashMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Adds the file at `path`, unless it is outside the root or hidden.
+    pub fn add(&mut self, path: &Path) {
+        let relative = match self.relative(path) {
+            Some(relative) => relative,
+            None => return,
+        };
+        if self.ids.contains_key(&relative) {
+            return;
+        }
+        let entry = Entry {
+            trigrams: trigrams(&relative),
+            chars: chars(&relative),
+            relative: relative.clone(),
+        };
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => {
+                self.entries.push(None);
+                self.entries.len() - 1
+            }
+        };
+        insert_postings(&mut self.trigrams, &entry.trigrams, id);
+        insert_postings(&mut self.chars, &entry.chars, id);
+        self.entries[id] = Some(entry);
+        self.ids.insert(relative, id);
+    }
+
+    /// Removes the file at `path`, or every file under it if it was a
+    /// directory.
+    pub fn remove(&mut self, path: &Path) {
+        let relative = match self.relative(path) {
+            Some(relative) => relative,
+            None => return,
+        };
+        if let Some(id) = self.ids.get(&relative).cloned() {
+            return self.remove_id(id);
+        }
+        let prefix = format!("{}/", relative);
+        let ids: Vec<_> = self.ids.iter()
+            .filter(|(path, _)| path.starts_with(&prefix))
+            .map(|(_, &id)| id)
+            .collect();
+        ids.into_iter().for_each(|id| self.remove_id(id));
+    }
+
+    fn remove_id(&mut self, id: usize) {
+        let entry = match self.entries[id].take() {
+            Some(entry) => entry,
+            None => return,
+        };
+        remove_postings(&mut self.trigrams, &entry.trigrams, id);
+        remove_postings(&mut self.chars, &entry.chars, id);
+        self.ids.remove(&entry.relative);
+        self.free.push(id);
+    }
+
+    /// Returns the path of `path` relative to the root, with `/` between
+    /// components, if it is under the root and not hidden.
+    fn relative(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let components: Vec<_> = relative.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        if components.is_empty() || components.iter().any(|c| c.starts_with('.')) {
+            return None;
+        }
+        Some(components.join("/"))
+    }
+
+    /// Returns up to `limit` files matching `query`, best match first.
+    /// The files containing every trigram of the query are scored first;
+    /// the other files containing every character of it only if fewer than
+    /// `limit` of those match.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<PathBuf> {
+        let query_chars = chars(query);
+        let query_trigrams = trigrams(query);
+        let mut matches = if query_chars.is_empty() {
+            self.score(query, self.ids.values().cloned())
+        } else if query_trigrams.is_empty() {
+            self.score(query, candidates(&self.chars, &query_chars).into_iter())
+        } else {
+            let mut matches =
+                self.score(query, candidates(&self.trigrams, &query_trigrams).into_iter());
+            if matches.len() < limit {
+                let scored: HashSet<usize> = matches.iter().map(|&(_, id)| id).collect();
+                let rest = candidates(&self.chars, &query_chars).into_iter()
+                    .filter(|id| !scored.contains(id));
+                matches.extend(self.score(query, rest));
+            }
+            matches
+        };
+        matches.sort_by(|a, b| {
+            let (a_path, b_path) = (self.relative_path(a.1), self.relative_path(b.1));
+            b.0.cmp(&a.0)
+                .then_with(|| a_path.len().cmp(&b_path.len()))
+                .then_with(|| a_path.cmp(b_path))
+        });
+        matches.into_iter()
+            .take(limit)
+            .map(|(_, id)| self.root.join(self.relative_path(id)))
+            .collect()
+    }
+
+    fn relative_path(&self, id: usize) -> &str {
+        self.entries[id].as_ref().map(|e| e.relative.as_str()).unwrap_or("")
+    }
+
+    /// Scores the files in `ids` against `query`. Matches in the file name
+    /// count for more than matches in the directories.
+    fn score<I>(&self, query: &str, ids: I) -> Vec<(u32, usize)>
+        where I: Iterator<Item=usize>
+    {
+        ids.filter_map(|id| {
+            let relative = &self.entries[id].as_ref()?.relative;
+            let score = fuzzy_score(query, relative)?;
+            let name = relative.rsplit('/').next().unwrap_or("");
+            Some((score + fuzzy_score(query, name).unwrap_or(0), id))
+        }).collect()
+    }
+}
+
+fn insert_postings<K: Hash + Eq + Copy>(postings: &mut HashMap<K, HashSet<usize>>,
+                                         keys: &[K], id: usize) {
+    for key in keys {
+        postings.entry(*key).or_default().insert(id);
+    }
+}
+
+fn remove_postings<K: Hash + Eq>(postings: &mut HashMap<K, HashSet<usize>>,
+                                 keys: &[K], id: usize) {
+    for key in keys {
+        let empty = match postings.get_mut(key) {
+            Some(ids) => {
+                ids.remove(&id);
+                ids.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            postings.remove(key);
+        }
+    }
+}
+
+/// Returns the files with postings for every one of `keys`, which must not
+/// be empty, starting from the key in the fewest files.
+fn candidates<K: Hash + Eq>(postings: &HashMap<K, HashSet<usize>>, keys: &[K]) -> Vec<usize> {
+    let mut lists = Vec::with_capacity(keys.len());
+    for key in keys {
+        match postings.get(key) {
+            Some(ids) => lists.push(ids),
+            None => return Vec::new(),
+        }
+    }
+    lists.sort_by_key(|ids| ids.len());
+    match lists.split_first() {
+        Some((first, rest)) =>
+            first.iter().filter(|id| rest.iter().all(|ids| ids.contains(id))).cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Returns the distinct trigrams of `s`, lowercased as `fuzzy_score`
+/// compares them.
+fn trigrams(s: &str) -> Vec<Trigram> {
+    let chars: Vec<char> = s.chars().flat_map(char::to_lowercase).collect();
+    let mut trigrams: Vec<Trigram> = chars.windows(3).map(|w| [w[0], w[1], w[2]]).collect();
+    trigrams.sort();
+    trigrams.dedup();
+    trigrams
+}
+
+/// Returns the distinct characters of `s`, lowercased as `fuzzy_score`
+/// compares them.
+fn chars(s: &str) -> Vec<char> {
+    let mut chars: Vec<char> = s.chars().flat_map(char::to_lowercase).collect();
+    chars.sort();
+    chars.dedup();
+    chars
+}
+
+/// Adds the files under `root`, skipping hidden ones, to `index`, until
+/// `stop` is set. `notify` is called after each batch of files.
+fn scan<F: Fn()>(root: &Path, index: &Mutex<FileIndex>, stop: &AtomicBool, notify: F) {
+    let mut dirs = vec![root.to_owned()];
+    let mut batch = Vec::with_capacity(SCAN_BATCH_SIZE);
+    while let Some(dir) = dirs.pop() {
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("could not index {:?}: {}", dir, err);
+                continue;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(ty) if ty.is_dir() => dirs.push(entry.path()),
+                Ok(ty) if ty.is_file() => batch.push(entry.path()),
+                _ => (),
+            }
+            if batch.len() == SCAN_BATCH_SIZE {
+                let mut index = index.lock().unwrap();
+                batch.drain(..).for_each(|path| index.add(&path));
+                drop(index);
+                notify();
+            }
+        }
+    }
+    let mut index = index.lock().unwrap();
+    batch.drain(..).for_each(|path| index.add(&path));
+}
+
+/// A search whose results are sent again as the scan finds more files.
+#[derive(Debug)]
+pub struct FileSearch {
+    pub request_id: usize,
+    pub query: String,
+    pub limit: usize,
+    /// The results last sent.
+    pub results: Vec<PathBuf>,
+}
+
+/// Called, on a scanning thread, whenever more files have been indexed.
+type Notify = Arc<Mutex<Box<dyn Fn() + Send>>>;
+
+/// An index of a workspace, built on a background thread.
+pub struct FileFinder {
+    index: Arc<Mutex<FileIndex>>,
+    notify: Notify,
+    scanned: Arc<AtomicBool>,
+    /// Tells the scan to stop when the finder is dropped.
+    stop: Arc<AtomicBool>,
+    /// The searches to update until the scan is finished.
+    pub searches: Vec<FileSearch>,
+}
+
+impl FileFinder {
+    /// Starts indexing `root`. `notify` is called, on a scanning thread,
+    /// whenever more files have been indexed, and when the scan is done.
+    pub fn spawn<F>(root: PathBuf, notify: F) -> FileFinder
+        where F: Fn() + Send + 'static
+    {
+        let index = Arc::new(Mutex::new(FileIndex::new(root.clone())));
+        let notify: Notify = Arc::new(Mutex::new(Box::new(notify)));
+        let scanned = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (index_clone, notify_clone, scanned_clone, stop_clone) =
+            (index.clone(), notify.clone(), scanned.clone(), stop.clone());
+        thread::spawn(move || {
+            let notify = || (notify_clone.lock().unwrap())();
+            scan(&root, &index_clone, &stop_clone, notify);
+            scanned_clone.store(true, Ordering::SeqCst);
+            notify();
+        });
+        FileFinder { index, notify, scanned, stop, searches: Vec::new() }
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self.index.lock().unwrap().root().to_owned()
+    }
+
+    /// Returns `true` once every file under the root has been indexed.
+    pub fn is_scanned(&self) -> bool {
+        self.scanned.load(Ordering::SeqCst)
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Vec<PathBuf> {
+        self.index.lock().unwrap().search(query, limit)
+    }
+
+    /// Adds the file at `path`, or the files under it if it is a directory,
+    /// which are scanned in the background.
+    pub fn add(&self, path: &Path) {
+        if path.is_dir() {
+            let (path, index, stop) = (path.to_owned(), self.index.clone(), self.stop.clone());
+            let notify = self.notify.clone();
+            thread::spawn(move || {
+                scan(&path, &index, &stop, || ());
+                (notify.lock().unwrap())();
+            });
+        } else if path.is_file() {
+            self.index.lock().unwrap().add(path)
+        }
+    }
+
+    pub fn remove(&self, path: &Path) {
+        self.index.lock().unwrap().remove(path)
+    }
+}
+
+impl Drop for FileFinder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn index(paths: &[&str]) -> FileIndex {
+        let mut index = FileIndex::new("/ws".into());
+        paths.iter().for_each(|path| index.add(Path::new(path)));
+        index
+    }
+
+    fn names(paths: Vec<PathBuf>) -> Vec<String> {
+        paths.iter().map(|p| p.strip_prefix("/ws").unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn search() {
+        let index = index(&["/ws/src/tabs.rs", "/ws/src/tab_stops.rs", "/ws/docs/table.md",
+                            "/ws/.git/tabs", "/elsewhere/tabs.rs"]);
+        assert_eq!(index.len(), 3);
+        assert_eq!(names(index.search("tabs", 10)), vec!["src/tabs.rs", "src/tab_stops.rs"]);
+        // only a subsequence
+        assert_eq!(index.search("tbrs", 10).len(), 2);
+        assert_eq!(names(index.search("tab", 1)), vec!["src/tabs.rs"]);
+        assert_eq!(index.search("xyz", 10), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn trigram_candidates() {
+        let index = index(&["/ws/src/tab_stops.rs", "/ws/src/tabs.rs"]);
+        assert_eq!(trigrams("TabS"), vec![['a', 'b', 's'], ['t', 'a', 'b']]);
+        assert_eq!(candidates(&index.trigrams, &trigrams("tabs")).len(), 1);
+        // with enough files containing the trigrams, the others aren't scored
+        assert_eq!(names(index.search("tabs", 1)), vec!["src/tabs.rs"]);
+        assert_eq!(names(index.search("tabs", 2)), vec!["src/tabs.rs", "src/tab_stops.rs"]);
+    }
+
+    #[test]
+    fn updates() {
+        let mut index = index(&["/ws/src/a.rs", "/ws/src/b.rs", "/ws/README"]);
+        index.remove(Path::new("/ws/src"));
+        assert_eq!(names(index.search("", 10)), vec!["README"]);
+        // the freed ids are reused
+        index.add(Path::new("/ws/lib/c.rs"));
+        index.add(Path::new("/ws/lib/c.rs"));
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.entries.len(), 3);
+        assert_eq!(names(index.search("c.rs", 10)), vec!["lib/c.rs"]);
+        index.remove(Path::new("/ws/lib/c.rs"));
+        index.remove(Path::new("/ws/README"));
+        assert!(index.is_empty());
+        assert!(index.trigrams.is_empty());
+        assert!(index.chars.is_empty());
+    }
+
+    #[test]
+    fn background_scan() {
+        extern crate tempdir;
+
+        let tmp = tempdir::TempDir::new("xi-file-index-test").unwrap();
+        let root = tmp.path().to_owned();
+        fs::create_dir_all(root.join("src/.hidden")).unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/.hidden/x.rs"), "").unwrap();
+        fs::write(root.join("Cargo.toml"), "").unwrap();
+
+        let (tx, rx) = channel();
+        let finder = FileFinder::spawn(root.clone(), move || tx.send(()).unwrap());
+        // there are too few files for a batch, so the scan notifies once
+        rx.recv().unwrap();
+        assert!(finder.is_scanned());
+        assert_eq!(finder.search("rs", 10), vec![root.join("src/main.rs")]);
+        assert_eq!(finder.search("", 10).len(), 2);
+
+        // an added directory is scanned in the background too
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::write(root.join("tests/a.rs"), "").unwrap();
+        finder.add(&root.join("tests"));
+        rx.recv().unwrap();
+        finder.remove(&root.join("Cargo.toml"));
+        assert_eq!(finder.search("", 10), vec![root.join("tests/a.rs"), root.join("src/main.rs")]);
+    }
+}
@@ -16,14 +16,16 @@
 
 use std::cmp::{min,max};
 
-use xi_rope::delta::{Delta, DeltaRegion};
+use xi_rope::delta::{self, Delta, DeltaRegion, Transformer};
 use xi_rope::find::{find, is_multiline_regex, CaseMatching};
-use xi_rope::rope::{Rope, LinesMetric, RopeInfo};
+use xi_rope::rope::{Rope, LinesMetric, LinesRaw, RopeInfo};
 use xi_rope::tree::Cursor;
 use xi_rope::interval::Interval;
 use selection::{Selection, SelRegion};
 use xi_rope::tree::Metric;
 use regex::{RegexBuilder, Regex};
+#[cfg(feature = "fancy-regex")]
+use fancy_regex::Regex as FancyRegex;
 use word_boundaries::WordCursor;
 
 const REGEX_SIZE_LIMIT: usize = 1000000;
@@ -47,6 +49,38 @@ pub struct FindStatus {
     matches: usize
 }
 
+/// Where a search looks for matches.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchScope {
+    WholeFile,
+    /// Within the selections made when searching, or the whole file if
+    /// they are all carets.
+    Selection,
+    /// Within the innermost function, class or such enclosing the last
+    /// caret, or the whole file if there is none. The definition is found
+    /// from the syntax, but where it ends is guessed from indentation: it
+    /// runs to the next line that is not more indented than its first.
+    CurrentFunction,
+}
+
+impl Default for SearchScope {
+    fn default() -> SearchScope {
+        SearchScope::WholeFile
+    }
+}
+
+/// The options of a search, for `count_occurrences`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FindOptions {
+    #[serde(default)]
+    pub case_sensitive: bool,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub whole_words: bool,
+}
+
 /// Contains logic to search text
 pub struct Find {
     // todo: link to search query so that search results can be correlated back to query
@@ -59,10 +93,18 @@ pub struct Find {
     case_matching: CaseMatching,
     /// The search query should be considered as regular expression
     regex: Option<Regex>,
+    /// The search query as a regular expression with lookaheads or
+    /// lookbehinds, which `regex` does not support. Either this or
+    /// `regex` is set for a regular expression.
+    #[cfg(feature = "fancy-regex")]
+    fancy_regex: Option<FancyRegex>,
     /// Query matches only whole words.
     whole_words: bool,
     /// The set of all known find occurrences (highlights)
     occurrences: Selection,
+    /// The ranges the search is restricted to, following the edits made
+    /// in them, or empty for the whole text.
+    scope: Vec<Interval>,
 }
 
 impl Find {
@@ -72,8 +114,11 @@ impl Find {
             search_string: None,
             case_matching: CaseMatching::CaseInsensitive,
             regex: None,
+            #[cfg(feature = "fancy-regex")]
+            fancy_regex: None,
             whole_words: false,
             occurrences: Selection::new(),
+            scope: Vec::new(),
         }
     }
 
@@ -81,6 +126,11 @@ impl Find {
         &self.occurrences
     }
 
+    /// Returns `true` if there is a search query.
+    pub fn is_active(&self) -> bool {
+        self.search_string.is_some()
+    }
+
     pub fn hls_dirty(&self) -> bool {
         self.hls_dirty
     }
@@ -98,7 +148,7 @@ impl Find {
             FindStatus {
                 chars: self.search_string.clone(),
                 case_sensitive: Some(self.case_matching == CaseMatching::Exact),
-                is_regex: Some(self.regex.is_some()),
+                is_regex: Some(self.is_regex()),
                 whole_words: Some(self.whole_words),
                 matches: self.occurrences.len(),
             }
@@ -112,6 +162,15 @@ impl Find {
     pub fn update_highlights(&mut self, text: &Rope, delta: &Delta<RopeInfo>) {
         // update search highlights for changed regions
         if self.search_string.is_some() {
+            if !self.scope.is_empty() {
+                // text inserted at either end of a range is within it
+                let mut transformer = Transformer::new(delta);
+                for iv in &mut self.scope {
+                    *iv = Interval::new_closed_open(transformer.transform(iv.start(), false),
+                                                    transformer.transform(iv.end(), true));
+                }
+            }
+
             // invalidate occurrences around deletion positions
             for DeltaRegion{ old_offset, new_offset: _, len } in delta.iter_deletions() {
                 self.occurrences.delete_range(old_offset, old_offset + len, false);
@@ -137,7 +196,7 @@ impl Find {
 
             // invalidate all search results from the point of the last valid search result until ...
             let is_multi_line = LinesMetric::next(self.search_string.as_ref().unwrap(), 0).is_some();
-            let is_multi_line_regex = self.regex.is_some() && is_multiline_regex(self.search_string.as_ref().unwrap());
+            let is_multi_line_regex = self.is_regex() && is_multiline_regex(self.search_string.as_ref().unwrap());
 
             if is_multi_line || is_multi_line_regex {
                 // ... the end of the file
@@ -159,12 +218,17 @@ impl Find {
         }
     }
 
-    /// Set search parameters and executes the search.
+    /// Set search parameters and executes the search, restricted to the
+    /// ranges of `scope` unless it is empty.
     pub fn do_find(&mut self, text: &Rope, search_string: &str, case_sensitive: bool,
-                   is_regex: bool, whole_words: bool) {
+                   is_regex: bool, whole_words: bool, scope: Vec<Interval>) {
         if search_string.len() == 0 {
             self.unset();
         }
+        if scope != self.scope {
+            self.unset();
+            self.scope = scope;
+        }
 
         self.set_find(search_string, case_sensitive, is_regex, whole_words);
         self.update_find(text, 0, text.len(), false);
@@ -187,7 +251,7 @@ impl Find {
 
         if let Some(ref s) = self.search_string {
             if s == search_string && case_matching == self.case_matching &&
-                self.regex.is_some() == is_regex && self.whole_words == whole_words {
+                self.is_regex() == is_regex && self.whole_words == whole_words {
                 // search parameters did not change
                 return;
             }
@@ -210,6 +274,80 @@ impl Find {
                     .ok()
             }
         };
+
+        #[cfg(feature = "fancy-regex")]
+        {
+            self.fancy_regex = None;
+            if is_regex && has_lookaround(search_string) {
+                let flags = match case_matching {
+                    CaseMatching::CaseInsensitive => "(?i)",
+                    CaseMatching::Exact => "",
+                };
+                self.fancy_regex = FancyRegex::new(&format!("{}{}", flags, search_string)).ok();
+            }
+        }
+    }
+
+    /// Returns `true` if the search query is a regular expression.
+    fn is_regex(&self) -> bool {
+        #[cfg(feature = "fancy-regex")]
+        {
+            if self.fancy_regex.is_some() {
+                return true;
+            }
+        }
+        self.regex.is_some()
+    }
+
+    /// Finds the next match of the search query from `cursor`, as
+    /// `xi_rope::find::find` does, in `text` or the start of it that the
+    /// cursor is over. `haystack` is kept over the matches of one search of
+    /// the same text.
+    #[cfg_attr(not(feature = "fancy-regex"), allow(unused_variables))]
+    fn find_match(&self, text: &Rope, cursor: &mut Cursor<RopeInfo>, lines: &mut LinesRaw,
+                  search_string: &str, haystack: &mut Haystack) -> Option<usize> {
+        #[cfg(feature = "fancy-regex")]
+        {
+            if let Some(ref regex) = self.fancy_regex {
+                return find_fancy(cursor, search_string, regex, haystack)
+                    .or_else(|| self.find_at_end(text, cursor, search_string));
+            }
+        }
+        find(cursor, lines, self.case_matching, search_string, &self.regex)
+            .or_else(|| self.find_at_end(text, cursor, search_string))
+    }
+
+    /// Finds the empty match at the end of `text` that the search, matching
+    /// the text a line at a time from the cursor, stops short of: as with
+    /// `regex`, `x*` matches three times in `ab`. On success, the cursor is
+    /// left at the end of the text.
+    fn find_at_end(&self, text: &Rope, cursor: &mut Cursor<RopeInfo>, search_string: &str)
+        -> Option<usize>
+    {
+        let len = text.len();
+        if !self.is_regex() || cursor.total_len() != len {
+            return None;
+        }
+        let context_start = if is_multiline_regex(search_string) {
+            0
+        } else {
+            text.offset_of_line(text.line_of_offset(len))
+        };
+        let context = text.slice_to_cow(context_start..len);
+        let at = context.len();
+        let found = self.regex.as_ref()
+            .and_then(|regex| regex.find_at(&context, at))
+            .is_some();
+        #[cfg(feature = "fancy-regex")]
+        let found = found || self.fancy_regex.as_ref()
+            .and_then(|regex| regex.captures_from_pos(&context, at).ok())
+            .and_then(|captures| captures)
+            .is_some();
+        if !found {
+            return None;
+        }
+        cursor.set(len);
+        Some(len)
     }
 
     /// Execute the search on the provided text in the range provided by `start` and `end`.
@@ -234,12 +372,19 @@ impl Find {
         let sub_text = text.subseq(Interval::new_closed_open(0, to));
         let mut find_cursor = Cursor::new(&sub_text, from);
         let mut raw_lines = text.lines_raw(from..to);
+        let mut haystack = Haystack::default();
 
-        while let Some(start) = find(&mut find_cursor, &mut raw_lines, self.case_matching,
-                                     &search_string, &self.regex) {
+        while let Some(start) = self.find_match(text, &mut find_cursor, &mut raw_lines,
+                                                search_string, &mut haystack) {
             let end = find_cursor.pos();
 
-            if self.whole_words && !self.is_matching_whole_words(text, start, end) {
+            if !self.is_in_scope(start, end)
+                || (self.whole_words && !self.is_matching_whole_words(text, start, end)) {
+                // an empty match must not be found again
+                match resume_after(text, start, end) {
+                    Some(next) => find_cursor.set(next),
+                    None => break,
+                }
                 raw_lines = text.lines_raw(find_cursor.pos()..to);
                 continue;
             }
@@ -262,12 +407,11 @@ impl Find {
             // continues at next position. Otherwise, search will result in overflow since
             // search will always repeat at current cursor position.
             if start == end {
-                // determine whether end of text is reached and stop search or increase
-                // cursor manually
-                if end + 1 >= text.len() {
-                    break;
-                } else {
-                    find_cursor.set(end + 1);
+                // determine whether end of text is reached and stop search or move
+                // the cursor to the next codepoint
+                match resume_after(text, start, end) {
+                    Some(next) => find_cursor.set(next),
+                    None => break,
                 }
             }
 
@@ -278,6 +422,127 @@ impl Find {
         self.hls_dirty = true;
     }
 
+    /// Returns the first match of the search query at or after `from`.
+    ///
+    /// Unlike `update_find`, this does not record occurrences: the scan
+    /// resumes from `from` and stops at the first match, reusing the
+    /// compiled regex, so only as much text is scanned as necessary.
+    pub fn find_next(&self, text: &Rope, from: usize) -> Option<(usize, usize)> {
+        self.next_match_in(text, from, text.len(), &mut Haystack::default())
+    }
+
+    /// Returns the last match of the search query ending at or before `from`.
+    ///
+    /// Queries that cannot span lines are searched for one line at a time,
+    /// moving backwards from `from`, so the scan stops at the closest line
+    /// with a match. Other queries scan the text from the beginning.
+    pub fn find_prev(&self, text: &Rope, from: usize) -> Option<(usize, usize)> {
+        let multiline = match self.search_string {
+            Some(ref s) => s.contains('\n') || (self.is_regex() && is_multiline_regex(s)),
+            None => return None,
+        };
+        if multiline {
+            return self.last_match_in(text, 0, from);
+        }
+
+        let mut line = text.line_of_offset(from);
+        loop {
+            let start = text.offset_of_line(line);
+            let end = min(text.offset_of_line(line + 1), from);
+            if let Some(found) = self.last_match_in(text, start, end) {
+                return Some(found);
+            }
+            if line == 0 {
+                return None;
+            }
+            line -= 1;
+        }
+    }
+
+    /// Builds a single delta that replaces every non-overlapping match of
+    /// the search query within its scope with `replacement`, found in one
+    /// pass over `text`.
+    /// If `preserve_case` is set, each replacement follows the case pattern
+    /// of its match (see `preserve_case`).
+    pub fn replace_all(&self, text: &Rope, replacement: &str, preserve_case: bool)
+        -> Delta<RopeInfo>
+    {
+        let mut builder = delta::Builder::new(text.len());
+        let mut from = 0;
+        let mut haystack = Haystack::default();
+        while let Some((start, end)) = self.next_match_in(text, from, text.len(), &mut haystack) {
+            if self.is_in_scope(start, end) {
+                let replacement = if preserve_case {
+                    self::preserve_case(&text.slice_to_cow(start..end), replacement)
+                } else {
+                    replacement.to_owned()
+                };
+                builder.replace(Interval::new_closed_open(start, end), Rope::from(replacement));
+            }
+            from = match resume_after(text, start, end) {
+                Some(from) => from,
+                None => break,
+            };
+        }
+        builder.build()
+    }
+
+    /// Returns the number of non-overlapping matches of the search query
+    /// within its scope.
+    pub fn count_matches(&self, text: &Rope) -> usize {
+        let mut count = 0;
+        let mut from = 0;
+        let mut haystack = Haystack::default();
+        while let Some((start, end)) = self.next_match_in(text, from, text.len(), &mut haystack) {
+            if self.is_in_scope(start, end) {
+                count += 1;
+            }
+            from = match resume_after(text, start, end) {
+                Some(from) => from,
+                None => break,
+            };
+        }
+        count
+    }
+
+    /// Returns the first match lying within `from..to`. `haystack` is kept
+    /// over the calls made with the same `text` and `to`.
+    fn next_match_in(&self, text: &Rope, mut from: usize, to: usize, haystack: &mut Haystack)
+        -> Option<(usize, usize)>
+    {
+        let search_string = match self.search_string {
+            Some(ref s) if !s.is_empty() => s,
+            _ => return None,
+        };
+        let sub_text = text.subseq(Interval::new_closed_open(0, to));
+        while from <= to {
+            let mut find_cursor = Cursor::new(&sub_text, from);
+            let mut raw_lines = text.lines_raw(from..to);
+            let start = self.find_match(text, &mut find_cursor, &mut raw_lines, search_string,
+                                        haystack)?;
+            let end = find_cursor.pos();
+            if !self.whole_words || self.is_matching_whole_words(text, start, end) {
+                return Some((start, end));
+            }
+            from = resume_after(text, start, end)?;
+        }
+        None
+    }
+
+    /// Returns the last of the non-overlapping matches lying within `from..to`.
+    fn last_match_in(&self, text: &Rope, mut from: usize, to: usize) -> Option<(usize, usize)> {
+        let mut last = None;
+        let mut haystack = Haystack::default();
+        while let Some((start, end)) = self.next_match_in(text, from, to, &mut haystack) {
+            last = Some((start, end));
+            from = match resume_after(text, start, end) {
+                Some(from) => from,
+                None => break,
+            };
+        }
+        last
+    }
+
     /// Return the occurrence closest to the provided selection `sel`. If searched is reversed then
     /// the occurrence closest to the start of the selection is returned. `wrapped` indicates that
     /// if the end of the text is reached the search continues from the start.
@@ -324,10 +589,22 @@ impl Find {
         }
     }
 
-    /// Checks if the start and end of a match is matching whole words.
+    /// Returns `true` if the match from `start` to `end` lies within one of
+    /// the ranges of the scope.
+    fn is_in_scope(&self, start: usize, end: usize) -> bool {
+        self.scope.is_empty()
+            || self.scope.iter().any(|iv| iv.start() <= start && end <= iv.end())
+    }
+
+    /// Checks if the start and end of a match is matching whole words. An
+    /// empty match is not a word.
     fn is_matching_whole_words(&self, text: &Rope, start: usize, end: usize) -> bool {
-        let mut word_end_cursor = WordCursor::new(text, end - 1);
-        let mut word_start_cursor = WordCursor::new(text, start + 1);
+        if start == end {
+            return false;
+        }
+        let mut word_end_cursor = WordCursor::new(text, text.prev_codepoint_offset(end).unwrap());
+        let mut word_start_cursor =
+            WordCursor::new(text, text.next_codepoint_offset(start).unwrap());
 
         if let Some(start_boundary) = word_start_cursor.prev_boundary() {
             if start_boundary != start {
@@ -343,4 +620,329 @@ impl Find {
 
         true
     }
-}
\ No newline at end of file
+}
+
+/// Returns where to search on from after the match `start..end`: its end,
+/// or the next codepoint after an empty match, so that it is not found
+/// again. Returns `None` once the end of the text is reached.
+fn resume_after(text: &Rope, start: usize, end: usize) -> Option<usize> {
+    if end > start { Some(end) } else { text.next_codepoint_offset(end) }
+}
+
+/// Returns the number of non-overlapping matches of `pattern` in `text`.
+pub fn count_occurrences(text: &Rope, pattern: &str, options: FindOptions) -> usize {
+    let mut find = Find::new();
+    find.set_find(pattern, options.case_sensitive, options.regex, options.whole_words);
+    find.count_matches(text)
+}
+
+/// Returns `true` if `pattern` has a lookahead or a lookbehind outside of
+/// a character class.
+#[cfg(feature = "fancy-regex")]
+fn has_lookaround(pattern: &str) -> bool {
+    let mut in_class = false;
+    let mut chars = pattern.char_indices();
+    while let Some((ix, c)) = chars.next() {
+        match c {
+            '\\' => { chars.next(); }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '(' if !in_class => {
+                let rest = &pattern[ix + 1..];
+                if ["?=", "?!", "?<=", "?<!"].iter().any(|group| rest.starts_with(group)) {
+                    return true;
+                }
+            }
+            _ => (),
+        }
+    }
+    false
+}
+
+/// The text a fancy regex is matched against, copied out of the rope. It
+/// is kept over the matches of one search, so that a find-all copies the
+/// text once, or each line once, rather than again for every match.
+#[derive(Default)]
+struct Haystack {
+    /// The offset of `text` in the rope.
+    #[cfg(feature = "fancy-regex")]
+    start: usize,
+    #[cfg(feature = "fancy-regex")]
+    text: String,
+}
+
+#[cfg(feature = "fancy-regex")]
+impl Haystack {
+    /// Returns the text of `start..end`, copying it unless it is the range
+    /// copied last.
+    fn get(&mut self, root: &Rope, start: usize, end: usize) -> &str {
+        if self.start != start || self.start + self.text.len() != end {
+            self.start = start;
+            self.text = root.slice_to_cow(start..end).into_owned();
+        }
+        &self.text
+    }
+}
+
+/// Finds the next match of `regex` from `cursor`, a line at a time unless
+/// `search_string` can match line breaks, as `xi_rope::find::find` does
+/// for the `regex` crate. On success, the cursor is left at the end of the
+/// match and its start is returned.
+///
+/// So that lookbehinds see the text before the cursor, each line is matched
+/// from its start, or the whole text from its start, with only matches from
+/// the cursor on being taken. That text is kept in `haystack`.
+#[cfg(feature = "fancy-regex")]
+fn find_fancy(cursor: &mut Cursor<RopeInfo>, search_string: &str, regex: &FancyRegex,
+              haystack: &mut Haystack) -> Option<usize> {
+    let root = cursor.root();
+    let len = cursor.total_len();
+    let multi_line = is_multiline_regex(search_string);
+    while cursor.pos() < len {
+        let start = cursor.pos();
+        let (context_start, context_end) = if multi_line {
+            (0, len)
+        } else {
+            let line = root.line_of_offset(start);
+            (root.offset_of_line(line), root.offset_of_line(line + 1))
+        };
+        let found = regex.captures_from_pos(haystack.get(root, context_start, context_end),
+                                            start - context_start).ok()
+            .and_then(|captures| captures.and_then(|c| c.pos(0)));
+        if let Some((match_start, match_end)) = found {
+            cursor.set(context_start + match_end);
+            return Some(context_start + match_start);
+        }
+        cursor.set(context_end);
+    }
+    None
+}
+
+/// Applies the case pattern of `matched` to `replacement`. An all-caps match
+/// gives an upper case replacement (`FOO` → `BAR`), a lower case match gives
+/// a lower case one (`foo` → `bar`), and a Title case match (an upper case
+/// letter followed only by lower case ones) capitalizes the first letter of
+/// the replacement, leaving the rest as given (`Foo` → `Bar`). Any other
+/// mixed-case match, or one without cased letters, leaves the replacement
+/// unchanged (`fOo` → `bar`).
+pub fn preserve_case(matched: &str, replacement: &str) -> String {
+    let mut cased = matched.chars().filter(|c| c.is_lowercase() || c.is_uppercase());
+    let first = match cased.next() {
+        Some(c) => c,
+        None => return replacement.to_owned(),
+    };
+    let rest_lower = cased.clone().all(char::is_lowercase);
+    let rest_upper = cased.all(char::is_uppercase);
+
+    if first.is_uppercase() && rest_upper {
+        replacement.to_uppercase()
+    } else if first.is_lowercase() && rest_lower {
+        replacement.to_lowercase()
+    } else if first.is_uppercase() && rest_lower {
+        let mut chars = replacement.chars();
+        match chars.next() {
+            Some(c) => c.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    } else {
+        replacement.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_next_and_prev() {
+        let text = Rope::from("one two\nthree two2\ntwo");
+        let mut find = Find::new();
+        find.set_find("two", true, false, false);
+        assert_eq!(find.find_next(&text, 0), Some((4, 7)));
+        assert_eq!(find.find_next(&text, 5), Some((14, 17)));
+        assert_eq!(find.find_next(&text, 19), Some((19, 22)));
+        assert_eq!(find.find_next(&text, 20), None);
+        assert_eq!(find.find_prev(&text, text.len()), Some((19, 22)));
+        assert_eq!(find.find_prev(&text, 19), Some((14, 17)));
+        assert_eq!(find.find_prev(&text, 16), Some((4, 7)));
+        assert_eq!(find.find_prev(&text, 6), None);
+
+        find.set_find("two", true, false, true);
+        assert_eq!(find.find_next(&text, 5), Some((19, 22)));
+        assert_eq!(find.find_prev(&text, 19), Some((4, 7)));
+    }
+
+    #[test]
+    fn find_next_and_prev_empty() {
+        let text = Rope::from("é\néé");
+        let mut find = Find::new();
+        find.set_find("x*", true, true, false);
+        assert_eq!(find.find_next(&text, 0), Some((0, 0)));
+        assert_eq!(find.find_next(&text, 2), Some((2, 2)));
+        assert_eq!(find.find_next(&text, 7), Some((7, 7)));
+        assert_eq!(find.find_prev(&text, text.len()), Some((7, 7)));
+        assert_eq!(find.find_prev(&text, 5), Some((3, 3)));
+
+        find.set_find("x*", true, true, true);
+        assert_eq!(find.find_next(&text, 0), None);
+        assert_eq!(find.find_prev(&text, text.len()), None);
+
+        find.set_find("é", true, false, true);
+        assert_eq!(find.find_next(&text, 2), None);
+        assert_eq!(find.find_prev(&text, text.len()), Some((0, 2)));
+    }
+
+    #[test]
+    fn replace_all() {
+        let text = Rope::from("one two\nthree two2\ntwo");
+        let mut find = Find::new();
+        find.set_find("two", true, false, false);
+        let delta = find.replace_all(&text, "2", false);
+        assert_eq!(String::from(delta.apply(&text)), "one 2\nthree 22\n2");
+
+        find.set_find("o+", true, true, false);
+        let delta = find.replace_all(&text, "", false);
+        assert_eq!(String::from(delta.apply(&text)), "ne tw\nthree tw2\ntw");
+
+        find.set_find("x", true, false, false);
+        assert_eq!(find.replace_all(&text, "y", false).summary().1, 0);
+
+        let text = Rope::from("foo Foo FOO fOo");
+        find.set_find("foo", false, false, false);
+        let delta = find.replace_all(&text, "bar", true);
+        assert_eq!(String::from(delta.apply(&text)), "bar Bar BAR bar");
+
+        // empty matches step over whole codepoints
+        let text = Rope::from("é\n");
+        find.set_find("x*", true, true, false);
+        let delta = find.replace_all(&text, "-", false);
+        assert_eq!(String::from(delta.apply(&text)), "-é-\n-");
+    }
+
+    #[test]
+    fn scope() {
+        let text = Rope::from("two\none two\ntwo two");
+        let mut find = Find::new();
+        let scope = vec![Interval::new_closed_open(4, 11), Interval::new_closed_open(12, 16)];
+        find.do_find(&text, "two", true, false, false, scope);
+        let occurrences = find.occurrences().iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(occurrences, vec![(8, 11), (12, 15)]);
+        let delta = find.replace_all(&text, "2", false);
+        assert_eq!(String::from(delta.apply(&text)), "two\none 2\n2 two");
+
+        // the scope follows edits, and grows with text typed at its end
+        let mut builder = delta::Builder::new(text.len());
+        builder.replace(Interval::new_closed_open(0, 0), Rope::from("two"));
+        builder.replace(Interval::new_closed_open(16, 16), Rope::from("two"));
+        let delta = builder.build();
+        let text = delta.apply(&text);
+        find.update_highlights(&text, &delta);
+        let occurrences = find.occurrences().iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(occurrences, vec![(11, 14), (15, 18), (19, 22)]);
+
+        find.do_find(&text, "two", true, false, false, Vec::new());
+        assert_eq!(find.occurrences().len(), 6);
+
+        // empty matches, in and out of scope, step over whole codepoints
+        let text = Rope::from("éé éé");
+        find.do_find(&text, "x*", true, true, false, vec![Interval::new_closed_open(5, 9)]);
+        let occurrences = find.occurrences().iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(occurrences, vec![(5, 5), (7, 7), (9, 9)]);
+    }
+
+    #[cfg(feature = "fancy-regex")]
+    #[test]
+    fn lookaround() {
+        assert!(has_lookaround("foo(?=bar)"));
+        assert!(has_lookaround("(?<!\\.)txt"));
+        assert!(!has_lookaround("\\(?=x"));
+        assert!(!has_lookaround("[(?=]"));
+        assert!(!has_lookaround("(?:a)(?i)b"));
+
+        let text = Rope::from("foobar foobaz\nFOObar");
+        let mut find = Find::new();
+        find.do_find(&text, "foo(?=bar)", false, true, false, Vec::new());
+        let occurrences = find.occurrences().iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(occurrences, vec![(0, 3), (14, 17)]);
+        find.do_find(&text, "(?<!foo)ba.", true, true, false, Vec::new());
+        let occurrences = find.occurrences().iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(occurrences, vec![(17, 20)]);
+        find.do_find(&text, "(?<=o)ba.", true, true, false, Vec::new());
+        assert_eq!(find.occurrences().len(), 2);
+        assert_eq!(find.find_prev(&text, text.len()), Some((10, 13)));
+
+        // a lookbehind sees the text before where the search resumes
+        let text = Rope::from("bbb\nbb");
+        find.do_find(&text, "(?<=b)b", true, true, false, Vec::new());
+        let occurrences = find.occurrences().iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(occurrences, vec![(1, 2), (2, 3), (5, 6)]);
+        assert_eq!(find.find_next(&text, 2), Some((2, 3)));
+        let options = FindOptions { regex: true, ..FindOptions::default() };
+        assert_eq!(count_occurrences(&text, "(?<=b)b", options), 3);
+
+        // queries that span lines are matched against the whole text
+        let text = Rope::from("bbb\nbb\nb\na\nb");
+        find.do_find(&text, "(?<=b)[[:space:]]b", true, true, false, Vec::new());
+        let occurrences = find.occurrences().iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(occurrences, vec![(3, 5), (6, 8)]);
+        assert_eq!(count_occurrences(&text, "(?<=b)[[:space:]]b", options), 2);
+        let replaced = find.replace_all(&text, "-", false).apply(&text);
+        assert_eq!(String::from(replaced), "bbb-b-\na\nb");
+    }
+
+    #[test]
+    fn count() {
+        let text = Rope::from("aaa Aa\naaaa");
+        let options = FindOptions::default();
+        assert_eq!(count_occurrences(&text, "aa", options), 4);
+        assert_eq!(count_occurrences(&text, "aa", FindOptions { case_sensitive: true, ..options }), 3);
+        assert_eq!(count_occurrences(&text, "a+", FindOptions { regex: true, ..options }), 3);
+        assert_eq!(count_occurrences(&text, "aa", FindOptions { whole_words: true, ..options }), 1);
+        assert_eq!(count_occurrences(&text, "", options), 0);
+
+        // empty matches step over whole codepoints
+        let text = Rope::from("é x é");
+        let options = FindOptions { regex: true, ..options };
+        assert_eq!(count_occurrences(&text, "z*", options), 6);
+        assert_eq!(count_occurrences(&text, "é", options), 2);
+
+        // the count agrees with find-all, which finds empty matches at the
+        // end of the text
+        let text = Rope::from("ab\ncd");
+        let mut find = Find::new();
+        for &query in &["x*", "d*", "$", "\\b"] {
+            find.do_find(&text, query, true, true, false, Vec::new());
+            assert_eq!(count_occurrences(&text, query, options), find.occurrences().len());
+            assert_eq!(find.occurrences().last().map(|r| r.end), Some(text.len()));
+        }
+        assert_eq!(count_occurrences(&text, "x*", options), 6);
+        assert_eq!(count_occurrences(&Rope::from(""), "x*", options), 1);
+    }
+
+    #[test]
+    fn preserve_case_patterns() {
+        assert_eq!(preserve_case("foo", "barBaz"), "barbaz");
+        assert_eq!(preserve_case("FOO", "barBaz"), "BARBAZ");
+        assert_eq!(preserve_case("Foo", "barBaz"), "BarBaz");
+        assert_eq!(preserve_case("F", "bar"), "BAR");
+        assert_eq!(preserve_case("fOO", "barBaz"), "barBaz");
+        assert_eq!(preserve_case("_1", "barBaz"), "barBaz");
+        assert_eq!(preserve_case("Foo", ""), "");
+    }
+
+    #[test]
+    fn find_next_and_prev_regex() {
+        let text = Rope::from("a1 b22\nc333");
+        let mut find = Find::new();
+        find.set_find("[0-9]+", true, true, false);
+        assert_eq!(find.find_next(&text, 0), Some((1, 2)));
+        assert_eq!(find.find_next(&text, 2), Some((4, 6)));
+        assert_eq!(find.find_prev(&text, text.len()), Some((8, 11)));
+        assert_eq!(find.find_prev(&text, 7), Some((4, 6)));
+
+        find.set_find("2\\nc", true, true, false);
+        assert_eq!(find.find_next(&text, 0), Some((5, 8)));
+        assert_eq!(find.find_prev(&text, text.len()), Some((5, 8)));
+        assert_eq!(find.find_prev(&text, 7), None);
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reformatting a buffer with the edits sent by a formatting provider,
+//! such as a plugin backed by a language server.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use xi_rope::delta::{Builder, Delta};
+use xi_rope::interval::Interval;
+use xi_rope::rope::{LinesMetric, Rope, RopeInfo};
+use xi_rpc::RemoteError;
+
+use plugins::PluginId;
+
+/// A position in a buffer: a line, and a utf-8 offset in it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct EditPosition {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// A replacement of the text from `start` to `end`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub start: EditPosition,
+    pub end: EditPosition,
+    pub new_text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    /// No plugin for the buffer provides formatting.
+    NoProvider,
+    /// The buffer is read-only.
+    ReadOnly,
+    /// The provider failed to format the buffer.
+    Provider(String),
+    /// An edit was outside the buffer, or overlapped another.
+    InvalidEdit(String),
+}
+
+impl FormatError {
+    /// Wraps the error a provider answered with.
+    pub fn from_provider(err: RemoteError) -> FormatError {
+        match err {
+            RemoteError::Custom { message, .. } => FormatError::Provider(message),
+            other => FormatError::Provider(format!("{:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::NoProvider => write!(f, "No formatting provider for this buffer"),
+            FormatError::ReadOnly => write!(f, "Buffer is read-only"),
+            FormatError::Provider(ref msg) => write!(f, "Formatting failed: {}", msg),
+            FormatError::InvalidEdit(ref msg) => write!(f, "Invalid formatting edit: {}", msg),
+        }
+    }
+}
+
+impl From<FormatError> for RemoteError {
+    fn from(src: FormatError) -> RemoteError {
+        let code = match src {
+            FormatError::NoProvider => 9,
+            FormatError::ReadOnly => 10,
+            FormatError::Provider(_) => 11,
+            FormatError::InvalidEdit(_) => 12,
+        };
+        RemoteError::custom(code, src.to_string(), None)
+    }
+}
+
+/// The format request in flight for a view. A new request supersedes the
+/// one before it, whose edits are then ignored.
+#[derive(Debug, Default)]
+pub struct FormatRequests {
+    next_id: usize,
+    pending: Option<PendingFormat>,
+}
+
+#[derive(Debug)]
+pub struct PendingFormat {
+    request_id: usize,
+    plugin: PluginId,
+    /// The revision the provider formats.
+    pub rev: u64,
+    /// Where to save the buffer once it is formatted, and when to give up
+    /// waiting and save it as it is.
+    pub save: Option<(PathBuf, Instant)>,
+}
+
+impl FormatRequests {
+    /// Records a request to `plugin` to format revision `rev`, returning
+    /// its id. A save waiting on a superseded request waits on this one
+    /// instead, unless `save` replaces it.
+    pub fn start(&mut self, plugin: PluginId, rev: u64,
+                 save: Option<(PathBuf, Instant)>) -> usize {
+        let request_id = self.next_id;
+        self.next_id += 1;
+        let save = save.or_else(|| self.pending.take().and_then(|p| p.save));
+        self.pending = Some(PendingFormat { request_id, plugin, rev, save });
+        request_id
+    }
+
+    /// Returns the id of the request in flight, if any.
+    pub fn pending_id(&self) -> Option<usize> {
+        self.pending.as_ref().map(|p| p.request_id)
+    }
+
+    /// Returns the request that `request_id` answers, if it is the one in
+    /// flight.
+    pub fn respond(&mut self, request_id: usize, plugin: PluginId) -> Option<PendingFormat> {
+        match self.pending {
+            Some(ref p) if p.request_id == request_id && p.plugin == plugin => (),
+            _ => return None,
+        }
+        self.pending.take()
+    }
+
+    /// Gives up on a request that a save has waited on past its deadline,
+    /// returning the path to save to.
+    pub fn expire(&mut self, now: Instant) -> Option<PathBuf> {
+        match self.pending {
+            Some(PendingFormat { save: Some((_, deadline)), .. }) if deadline <= now => (),
+            _ => return None,
+        }
+        self.pending.take().and_then(|p| p.save).map(|(path, _)| path)
+    }
+
+    /// Drops the request in flight to `plugin`, unless a save is waiting
+    /// on it; that is left to expire.
+    pub fn abandon_plugin(&mut self, plugin: PluginId) {
+        match self.pending {
+            Some(ref p) if p.plugin == plugin && p.save.is_none() => (),
+            _ => return,
+        }
+        self.pending = None;
+    }
+}
+
+/// Converts a position to an offset in `text`, if it is in the buffer.
+fn offset_of_position(text: &Rope, position: EditPosition) -> Result<usize, FormatError> {
+    let invalid = || FormatError::InvalidEdit(format!("no position {}:{}",
+                                                     position.line, position.col));
+    if position.line > text.measure::<LinesMetric>() {
+        return Err(invalid());
+    }
+    let line_start = text.offset_of_line(position.line);
+    let line_end = text.offset_of_line(position.line + 1);
+    let offset = line_start + position.col;
+    if offset > line_end || !text.is_codepoint_boundary(offset) {
+        return Err(invalid());
+    }
+    Ok(offset)
+}
+
+/// Builds a single delta, against `text`, making every one of `edits`. The
+/// edits are all relative to `text`, so applying them together means none
+/// of them moves another; they must not overlap.
+pub fn delta_from_edits(text: &Rope, edits: &[TextEdit]) -> Result<Delta<RopeInfo>, FormatError> {
+    let mut ranges = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let start = offset_of_position(text, edit.start)?;
+        let end = offset_of_position(text, edit.end)?;
+        if end < start {
+            return Err(FormatError::InvalidEdit(format!("edit ends before it starts: {:?}", edit)));
+        }
+        ranges.push((start, end, edit.new_text.as_str()));
+    }
+    // a stable sort, so that insertions at one offset keep their order
+    ranges.sort_by_key(|&(start, _, _)| start);
+
+    let mut builder = Builder::new(text.len());
+    let mut last_end = 0;
+    for (start, end, new_text) in ranges {
+        if start < last_end {
+            return Err(FormatError::InvalidEdit("edits overlap".into()));
+        }
+        builder.replace(Interval::new_closed_open(start, end), new_text.into());
+        last_end = end;
+    }
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use plugins::PluginPid;
+
+    fn edit(start: (usize, usize), end: (usize, usize), new_text: &str) -> TextEdit {
+        TextEdit {
+            start: EditPosition { line: start.0, col: start.1 },
+            end: EditPosition { line: end.0, col: end.1 },
+            new_text: new_text.into(),
+        }
+    }
+
+    #[test]
+    fn single_delta() {
+        let text = Rope::from("fn  main(){\nlet é=1;\n}");
+        // out of order, as providers may send them
+        let edits = vec![
+            edit((1, 0), (1, 0), "    "),
+            edit((0, 2), (0, 4), " "),
+            edit((0, 10), (0, 10), " "),
+            edit((1, 6), (1, 7), " = "),
+        ];
+        let delta = delta_from_edits(&text, &edits).unwrap();
+        assert_eq!(String::from(delta.apply(&text)), "fn main() {\n    let é = 1;\n}");
+
+        // insertions at one offset keep their order
+        let edits = vec![edit((2, 1), (2, 1), "\n"), edit((2, 1), (2, 1), "// end")];
+        let delta = delta_from_edits(&text, &edits).unwrap();
+        assert!(String::from(delta.apply(&text)).ends_with("}\n// end"));
+    }
+
+    #[test]
+    fn invalid_edits() {
+        let text = Rope::from("ab\né\n");
+        assert!(delta_from_edits(&text, &[edit((3, 0), (3, 0), "x")]).is_err());
+        assert!(delta_from_edits(&text, &[edit((0, 4), (0, 4), "x")]).is_err());
+        // inside the é
+        assert!(delta_from_edits(&text, &[edit((1, 1), (1, 1), "x")]).is_err());
+        assert!(delta_from_edits(&text, &[edit((0, 2), (0, 1), "x")]).is_err());
+        let overlapping = [edit((0, 0), (0, 2), "x"), edit((0, 1), (1, 0), "y")];
+        assert_eq!(delta_from_edits(&text, &overlapping).err(),
+                   Some(FormatError::InvalidEdit("edits overlap".into())));
+        // the empty line at the end
+        assert!(delta_from_edits(&text, &[edit((2, 0), (2, 0), "x")]).is_ok());
+    }
+
+    #[test]
+    fn requests() {
+        let mut requests = FormatRequests::default();
+        let first = requests.start(PluginPid(1), 3, None);
+        let second = requests.start(PluginPid(1), 4, None);
+        assert!(requests.respond(first, PluginPid(1)).is_none());
+        assert!(requests.respond(second, PluginPid(2)).is_none());
+        assert_eq!(requests.respond(second, PluginPid(1)).map(|p| p.rev), Some(4));
+        assert!(requests.respond(second, PluginPid(1)).is_none());
+        let third = requests.start(PluginPid(1), 5, None);
+        requests.abandon_plugin(PluginPid(1));
+        assert!(requests.respond(third, PluginPid(1)).is_none());
+    }
+
+    #[test]
+    fn save_requests() {
+        let mut requests = FormatRequests::default();
+        let now = Instant::now();
+        let deadline = now + Duration::from_millis(100);
+        requests.start(PluginPid(1), 3, Some(("a.rs".into(), deadline)));
+        // a save outlives a superseded request, and a stopped plugin
+        let second = requests.start(PluginPid(1), 4, None);
+        requests.abandon_plugin(PluginPid(1));
+        assert_eq!(requests.expire(now), None);
+        assert_eq!(requests.expire(deadline), Some(PathBuf::from("a.rs")));
+        assert!(requests.respond(second, PluginPid(1)).is_none());
+
+        let third = requests.start(PluginPid(1), 5, Some(("b.rs".into(), deadline)));
+        let pending = requests.respond(third, PluginPid(1)).unwrap();
+        assert_eq!(pending.save.map(|(path, _)| path), Some(PathBuf::from("b.rs")));
+        assert_eq!(requests.expire(deadline), None);
+    }
+}
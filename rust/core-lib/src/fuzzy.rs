@@ -0,0 +1,71 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fuzzy matching, for finding symbols and files from a few characters
+//! of their names.
+
+/// Scores how well `candidate` matches `query`, whose characters it must
+/// contain in order, ignoring case. Runs of consecutive matches, matches at
+/// the start of words, and matches of the same case score higher.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<u32> {
+    let mut query = query.chars().peekable();
+    let mut score = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    for c in candidate.chars() {
+        let q = match query.peek() {
+            Some(&q) => q,
+            None => break,
+        };
+        let matched = c == q || c.to_lowercase().eq(q.to_lowercase());
+        if matched {
+            query.next();
+            score += 1;
+            if c == q {
+                score += 1;
+            }
+            if prev_matched {
+                score += 3;
+            }
+            let word_start = match prev {
+                None => true,
+                Some(p) => !p.is_alphanumeric() || (p.is_lowercase() && c.is_uppercase()),
+            };
+            if word_start {
+                score += 3;
+            }
+        }
+        prev_matched = matched;
+        prev = Some(c);
+    }
+    if query.peek().is_some() {
+        None
+    } else {
+        Some(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert_eq!(fuzzy_score("xyz", "x_y"), None);
+        assert!(fuzzy_score("ws", "WorkspaceSymbol") > fuzzy_score("ws", "answer"));
+        assert!(fuzzy_score("new", "new_view") > fuzzy_score("new", "renew"));
+        assert!(fuzzy_score("view", "view") > fuzzy_score("view", "VIEW"));
+    }
+}
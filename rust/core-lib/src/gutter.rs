@@ -0,0 +1,173 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Annotations shown in the gutter beside each line, such as line numbers,
+//! blame information, breakpoints and error markers.
+//!
+//! Annotations come from any number of providers. Plugins, which run out
+//! of process, provide theirs by sending them to the core, where they are
+//! kept per plugin and moved along with the lines they belong to.
+
+use std::collections::BTreeMap;
+
+use xi_rope::delta::Delta;
+use xi_rope::rope::{Rope, RopeInfo};
+
+use plugins::PluginPid;
+
+/// A single item to be drawn in the gutter of a line.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type", content = "value")]
+pub enum GutterAnnotation {
+    /// A line number from a plugin that numbers lines its own way; the
+    /// core leaves ordinary numbering to the frontend.
+    LineNumber(usize),
+    GitBlame(String),
+    Breakpoint,
+    ErrorIndicator,
+}
+
+/// A source of gutter annotations. Providers belong to a buffer's editor,
+/// which moves between threads, so they must be `Send`.
+pub trait GutterAnnotationProvider: Send {
+    /// Returns the annotations for the (logical) line `line` of `text`.
+    fn annotations(&self, text: &Rope, line: usize) -> Vec<GutterAnnotation>;
+}
+
+/// Annotations sent by a plugin, keyed by line.
+#[derive(Default)]
+pub struct PluginGutterAnnotations {
+    lines: BTreeMap<usize, Vec<GutterAnnotation>>,
+}
+
+impl PluginGutterAnnotations {
+    /// Updates line numbers after the lines `start..=end` have been
+    /// replaced by the lines `start..=new_end`. Annotations on the first
+    /// line are kept; those on the other replaced lines are dropped.
+    fn edit(&mut self, start: usize, end: usize, new_end: usize) {
+        let mut replaced = self.lines.split_off(&(start + 1));
+        let after = replaced.split_off(&(end + 1));
+        self.lines.extend(after.into_iter().map(|(line, annotations)| {
+            (line + new_end - end, annotations)
+        }));
+    }
+}
+
+impl GutterAnnotationProvider for PluginGutterAnnotations {
+    fn annotations(&self, _text: &Rope, line: usize) -> Vec<GutterAnnotation> {
+        self.lines.get(&line).cloned().unwrap_or_default()
+    }
+}
+
+/// The gutter annotation providers for a buffer.
+///
+/// Line numbers are not annotated by the core: annotations are part of the
+/// lines kept in the frontend's line cache, which are copied as lines move,
+/// so frontends number lines from the `ln` of each update op instead.
+#[derive(Default)]
+pub struct Gutter {
+    providers: Vec<Box<dyn GutterAnnotationProvider>>,
+    plugins: BTreeMap<PluginPid, PluginGutterAnnotations>,
+}
+
+impl Gutter {
+    /// Registers an additional provider.
+    pub fn add_provider(&mut self, provider: Box<dyn GutterAnnotationProvider>) {
+        self.providers.push(provider);
+    }
+
+    /// Replaces all of a plugin's annotations.
+    pub fn set_plugin_annotations(&mut self, plugin: PluginPid,
+                                  lines: BTreeMap<usize, Vec<GutterAnnotation>>) {
+        self.plugins.insert(plugin, PluginGutterAnnotations { lines });
+    }
+
+    pub fn remove_plugin(&mut self, plugin: PluginPid) {
+        self.plugins.remove(&plugin);
+    }
+
+    /// Moves plugin annotations to follow the lines changed by `delta`,
+    /// which turned `last_text` into `text`.
+    pub fn update(&mut self, delta: &Delta<RopeInfo>, last_text: &Rope, text: &Rope) {
+        let (iv, new_len) = delta.summary();
+        let start = last_text.line_of_offset(iv.start());
+        let end = last_text.line_of_offset(iv.end());
+        let new_end = text.line_of_offset(iv.start() + new_len);
+        for annotations in self.plugins.values_mut() {
+            annotations.edit(start, end, new_end);
+        }
+    }
+
+    /// Collects the annotations for `line` from every provider, built-in
+    /// providers first and then plugins in order of their ids.
+    pub fn annotations(&self, text: &Rope, line: usize) -> Vec<GutterAnnotation> {
+        let plugins = self.plugins.values().map(|p| p as &dyn GutterAnnotationProvider);
+        self.providers.iter().map(|p| p.as_ref()).chain(plugins)
+            .flat_map(|p| p.annotations(text, line))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::interval::Interval;
+
+    #[test]
+    fn merge_annotations() {
+        let text = Rope::from("a\nb\nc");
+        let mut gutter = Gutter::default();
+        let mut lines = BTreeMap::new();
+        lines.insert(1, vec![GutterAnnotation::Breakpoint]);
+        gutter.set_plugin_annotations(PluginPid(2), lines);
+        let mut lines = BTreeMap::new();
+        lines.insert(1, vec![GutterAnnotation::GitBlame("me".into())]);
+        gutter.set_plugin_annotations(PluginPid(1), lines);
+        assert_eq!(gutter.annotations(&text, 0), vec![]);
+        assert_eq!(gutter.annotations(&text, 1),
+                   vec![GutterAnnotation::GitBlame("me".into()),
+                        GutterAnnotation::Breakpoint]);
+        gutter.remove_plugin(PluginPid(2));
+        assert_eq!(gutter.annotations(&text, 1).len(), 1);
+    }
+
+    #[test]
+    fn annotations_follow_edits() {
+        let text = Rope::from("a\nb\nc\nd");
+        let mut gutter = Gutter::default();
+        let mut lines = BTreeMap::new();
+        lines.insert(1, vec![GutterAnnotation::ErrorIndicator]);
+        lines.insert(2, vec![GutterAnnotation::Breakpoint]);
+        lines.insert(3, vec![GutterAnnotation::Breakpoint]);
+        gutter.set_plugin_annotations(PluginPid(1), lines);
+
+        // join lines 1 and 2
+        let delta = Delta::simple_edit(Interval::new_closed_open(3, 4), "".into(), text.len());
+        let new_text = delta.apply(&text);
+        gutter.update(&delta, &text, &new_text);
+        assert_eq!(gutter.annotations(&new_text, 1)[0], GutterAnnotation::ErrorIndicator);
+        assert_eq!(gutter.annotations(&new_text, 2)[0], GutterAnnotation::Breakpoint);
+        assert_eq!(gutter.annotations(&new_text, 3).len(), 0);
+
+        // insert a line at the start
+        let text = new_text;
+        let delta = Delta::simple_edit(Interval::new_closed_open(0, 0), "z\n".into(), text.len());
+        let new_text = delta.apply(&text);
+        gutter.update(&delta, &text, &new_text);
+        assert_eq!(gutter.annotations(&new_text, 1).len(), 0);
+        assert_eq!(gutter.annotations(&new_text, 2)[0], GutterAnnotation::ErrorIndicator);
+        assert_eq!(gutter.annotations(&new_text, 3)[0], GutterAnnotation::Breakpoint);
+    }
+}
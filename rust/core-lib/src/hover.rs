@@ -0,0 +1,200 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Documentation shown on hover, fetched from the plugins that provide it.
+//!
+//! Requests are identified by the frontend's `request_id`, so several can be
+//! in flight at once. A request is answered by the first provider to respond
+//! with a result, or with nothing if every provider fails or none responds
+//! before the request's deadline.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+
+use xi_rpc::RemoteError;
+
+use plugins::PluginPid;
+use plugins::rpc::Hover;
+
+/// Hover information as sent to the frontend.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HoverInfo {
+    pub contents: String,
+    /// The region of the buffer the information applies to, as utf-8 offsets.
+    pub range: Option<(usize, usize)>,
+}
+
+impl From<Hover> for HoverInfo {
+    fn from(hover: Hover) -> HoverInfo {
+        HoverInfo {
+            contents: hover.content,
+            range: hover.range.map(|range| (range.start, range.end)),
+        }
+    }
+}
+
+struct PendingHover {
+    position: usize,
+    deadline: Instant,
+    /// The providers yet to respond.
+    waiting: BTreeSet<PluginPid>,
+}
+
+/// The hover providers registered for a view, and the requests waiting on them.
+#[derive(Default)]
+pub struct HoverRequests {
+    providers: BTreeSet<PluginPid>,
+    pending: BTreeMap<usize, PendingHover>,
+}
+
+impl HoverRequests {
+    pub fn register_provider(&mut self, plugin: PluginPid) {
+        self.providers.insert(plugin);
+    }
+
+    pub fn remove_provider(&mut self, plugin: PluginPid) {
+        self.providers.remove(&plugin);
+    }
+
+    pub fn is_provider(&self, plugin: PluginPid) -> bool {
+        self.providers.contains(&plugin)
+    }
+
+    /// Records a request for `position` sent to `providers`, which must be
+    /// answered by `deadline`.
+    pub fn start(&mut self, request_id: usize, position: usize, providers: &[PluginPid],
+                 deadline: Instant) {
+        let waiting = providers.iter().cloned().collect();
+        self.pending.insert(request_id, PendingHover { position, deadline, waiting });
+    }
+
+    /// Handles a provider's response. Returns the result to send to the
+    /// frontend if this response settles the request, or `None` if the
+    /// request is still waiting on other providers or is no longer pending.
+    pub fn respond(&mut self, request_id: usize, plugin: PluginPid,
+                   result: Result<Hover, RemoteError>) -> Option<Option<HoverInfo>> {
+        let result = match result {
+            Ok(hover) => Some(hover.into()),
+            Err(err) => {
+                warn!("hover provider error for request {}: {:?}", request_id, err);
+                None
+            }
+        };
+        {
+            let pending = self.pending.get_mut(&request_id)?;
+            if !pending.waiting.remove(&plugin) {
+                return None;
+            }
+            if result.is_none() && !pending.waiting.is_empty() {
+                return None;
+            }
+        }
+        self.pending.remove(&request_id);
+        Some(result)
+    }
+
+    /// Moves the requests waiting on `old`, a plugin that has crashed, to
+    /// `new`, its restarted instance. Returns the requests to send again, as
+    /// `(request_id, position)`.
+    pub fn replace_plugin(&mut self, old: PluginPid, new: PluginPid) -> Vec<(usize, usize)> {
+        self.pending.iter_mut()
+            .filter(|(_, pending)| pending.waiting.contains(&old))
+            .map(|(&request_id, pending)| {
+                pending.waiting.remove(&old);
+                pending.waiting.insert(new);
+                (request_id, pending.position)
+            })
+            .collect()
+    }
+
+    /// Stops waiting on `plugin`, which will not respond. Returns the
+    /// requests that no provider is left to answer.
+    pub fn abandon_plugin(&mut self, plugin: PluginPid) -> Vec<usize> {
+        let mut abandoned = Vec::new();
+        for (&request_id, pending) in &mut self.pending {
+            if pending.waiting.remove(&plugin) && pending.waiting.is_empty() {
+                abandoned.push(request_id);
+            }
+        }
+        for request_id in &abandoned {
+            self.pending.remove(request_id);
+        }
+        abandoned
+    }
+
+    /// Removes and returns the requests whose deadline has passed.
+    pub fn expire(&mut self, now: Instant) -> Vec<usize> {
+        let expired: Vec<usize> = self.pending.iter()
+            .filter(|&(_, pending)| pending.deadline <= now)
+            .map(|(&request_id, _)| request_id)
+            .collect();
+        for request_id in &expired {
+            self.pending.remove(request_id);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use plugins::rpc::Range;
+
+    fn hover(content: &str) -> Hover {
+        Hover { content: content.into(), range: Some(Range { start: 1, end: 4 }) }
+    }
+
+    #[test]
+    fn concurrent_requests() {
+        let now = Instant::now();
+        let mut requests = HoverRequests::default();
+        let providers = [PluginPid(1), PluginPid(2)];
+        requests.start(1, 0, &providers, now + Duration::from_millis(10));
+        requests.start(2, 0, &providers, now + Duration::from_millis(20));
+
+        let err = RemoteError::custom(1, "no hover", None);
+        assert_eq!(requests.respond(2, PluginPid(1), Err(err.clone())), None);
+        assert_eq!(requests.respond(1, PluginPid(2), Ok(hover("one"))),
+                   Some(Some(HoverInfo { contents: "one".into(), range: Some((1, 4)) })));
+        // later responses to a settled request are ignored
+        assert_eq!(requests.respond(1, PluginPid(1), Ok(hover("late"))), None);
+        // the request fails once every provider has failed
+        assert_eq!(requests.respond(2, PluginPid(2), Err(err)), Some(None));
+
+        requests.start(3, 0, &providers[..1], now + Duration::from_millis(10));
+        requests.start(4, 0, &providers[..1], now + Duration::from_millis(20));
+        assert_eq!(requests.expire(now + Duration::from_millis(15)), vec![3]);
+        assert_eq!(requests.respond(3, PluginPid(1), Ok(hover("late"))), None);
+        assert!(requests.respond(4, PluginPid(1), Ok(hover("four"))).is_some());
+    }
+
+    #[test]
+    fn crashed_providers() {
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let mut requests = HoverRequests::default();
+        requests.start(1, 5, &[PluginPid(1), PluginPid(2)], deadline);
+        requests.start(2, 7, &[PluginPid(1)], deadline);
+        requests.start(3, 9, &[PluginPid(2)], deadline);
+
+        assert_eq!(requests.replace_plugin(PluginPid(1), PluginPid(3)), vec![(1, 5), (2, 7)]);
+        // responses from the crashed instance are no longer expected
+        assert_eq!(requests.respond(2, PluginPid(1), Ok(hover("old"))), None);
+        assert!(requests.respond(2, PluginPid(3), Ok(hover("new"))).is_some());
+
+        assert_eq!(requests.abandon_plugin(PluginPid(2)), vec![3]);
+        assert!(requests.respond(1, PluginPid(3), Ok(hover("one"))).is_some());
+    }
+}
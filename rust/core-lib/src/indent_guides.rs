@@ -0,0 +1,127 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Indent guides: the display columns, within a line's leading whitespace,
+//! where a vertical guide should be drawn for each level of indentation.
+
+use std::cmp::min;
+
+use xi_rope::rope::Rope;
+
+/// Computes indent guides for lines of a buffer, caching the indent depth
+/// of each line until it is edited.
+pub struct IndentGuideAnnotation {
+    tab_size: usize,
+    /// The width of the leading whitespace of each line, in display
+    /// columns, or `None` if not yet computed.
+    depths: Vec<Option<usize>>,
+}
+
+impl IndentGuideAnnotation {
+    pub fn new(tab_size: usize) -> IndentGuideAnnotation {
+        IndentGuideAnnotation { tab_size: tab_size.max(1), depths: Vec::new() }
+    }
+
+    /// Sets the tab width, invalidating every cached depth. Returns `true`
+    /// if guides already computed for some line may have changed.
+    pub fn set_tab_size(&mut self, tab_size: usize) -> bool {
+        let tab_size = tab_size.max(1);
+        if tab_size == self.tab_size {
+            return false;
+        }
+        self.tab_size = tab_size;
+        let had_guides = self.depths.iter().filter_map(|&d| d).any(|d| d > 0);
+        self.depths.clear();
+        had_guides
+    }
+
+    /// Updates the cache after the lines `start..end` have been replaced
+    /// by `new_count` lines.
+    pub fn edit(&mut self, start: usize, end: usize, new_count: usize) {
+        if start >= self.depths.len() {
+            return;
+        }
+        let end = min(end, self.depths.len());
+        self.depths.splice(start..end, vec![None; new_count]);
+    }
+
+    /// Returns the columns at which guides should be drawn for `line`: one
+    /// at the start of each indent level that the line's leading whitespace
+    /// reaches into.
+    pub fn guides_for_line(&mut self, text: &Rope, line: usize) -> Vec<usize> {
+        let depth = self.depth(text, line);
+        (0..depth).step_by(self.tab_size).collect()
+    }
+
+    fn depth(&mut self, text: &Rope, line: usize) -> usize {
+        if line >= self.depths.len() {
+            self.depths.resize(line + 1, None);
+        }
+        if let Some(depth) = self.depths[line] {
+            return depth;
+        }
+        let depth = self.compute_depth(text, line);
+        self.depths[line] = Some(depth);
+        depth
+    }
+
+    /// Measures leading whitespace in display columns, so that tabs and
+    /// spaces can be mixed.
+    fn compute_depth(&self, text: &Rope, line: usize) -> usize {
+        let start = text.offset_of_line(line);
+        let end = text.offset_of_line(line + 1);
+        let mut width = 0;
+        for c in text.slice_to_cow(start..end).chars() {
+            match c {
+                ' ' => width += 1,
+                '\t' => width += self.tab_size - width % self.tab_size,
+                _ => break,
+            }
+        }
+        width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_indentation() {
+        let text = Rope::from("a\n    b\n\t  c\n  \td\n \n");
+        let mut guides = IndentGuideAnnotation::new(4);
+        assert_eq!(guides.guides_for_line(&text, 0), Vec::<usize>::new());
+        assert_eq!(guides.guides_for_line(&text, 1), vec![0]);
+        assert_eq!(guides.guides_for_line(&text, 2), vec![0, 4]);
+        assert_eq!(guides.guides_for_line(&text, 3), vec![0]);
+        assert_eq!(guides.guides_for_line(&text, 4), vec![0]);
+        assert_eq!(guides.guides_for_line(&text, 5), Vec::<usize>::new());
+        assert!(guides.set_tab_size(2));
+        assert_eq!(guides.guides_for_line(&text, 2), vec![0, 2]);
+    }
+
+    #[test]
+    fn invalidation() {
+        let mut text = Rope::from("a\n    b\nc\n");
+        let mut guides = IndentGuideAnnotation::new(4);
+        assert_eq!(guides.guides_for_line(&text, 1), vec![0]);
+        assert_eq!(guides.guides_for_line(&text, 2), Vec::<usize>::new());
+        // line 1 is replaced by two lines
+        text = Rope::from("a\n        b\nb\nc\n");
+        guides.edit(1, 2, 2);
+        assert_eq!(guides.guides_for_line(&text, 1), vec![0, 4]);
+        assert_eq!(guides.guides_for_line(&text, 2), Vec::<usize>::new());
+        assert_eq!(guides.guides_for_line(&text, 3), Vec::<usize>::new());
+    }
+}
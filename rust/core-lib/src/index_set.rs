@@ -19,6 +19,10 @@
 // crate. Maybe we don't need both.
 
 use std::cmp::{min, max};
+use std::iter::FromIterator;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
 use xi_rope::delta::{Delta, Transformer};
 use xi_rope::rope::RopeInfo;
 
@@ -26,16 +30,13 @@ pub struct IndexSet {
     ranges: Vec<(usize, usize)>,
 }
 
-pub fn remove_n_at<T: Clone>(v: &mut Vec<T>, index: usize, n: usize) {
-    if n == 1 {
-        v.remove(index);
-    } else if n > 1 {
-        let new_len = v.len() - n;
-        for i in index..new_len {
-            v[i] = v[i + n].clone();
-        }
-        v.truncate(new_len);
-    }
+/// Removes `n` elements from `v`, starting at `index`.
+///
+/// # Panics
+///
+/// Panics if `index + n > v.len()`.
+pub fn remove_n_at<T>(v: &mut Vec<T>, index: usize, n: usize) {
+    v.drain(index..index + n);
 }
 
 impl IndexSet {
@@ -51,6 +52,34 @@ impl IndexSet {
         self.ranges.clear();
     }
 
+    /// Returns the total number of indices covered by the set.
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|&(start, end)| end - start).sum()
+    }
+
+    /// Returns `true` if the set contains no indices.
+    pub fn is_empty(&self) -> bool {
+        // `union_one_range` accepts empty ranges, so we can't just check
+        // whether `ranges` is empty.
+        self.ranges.iter().all(|&(start, end)| start == end)
+    }
+
+    /// Returns the number of disjoint ranges stored in the set.
+    pub fn range_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Determines whether the given index is in the set.
+    ///
+    /// Performance note: O(log n).
+    pub fn contains(&self, index: usize) -> bool {
+        let ix = match self.ranges.binary_search_by(|r| r.1.cmp(&index)) {
+            Ok(ix) => ix + 1,
+            Err(ix) => ix,
+        };
+        ix < self.ranges.len() && self.ranges[ix].0 <= index && index < self.ranges[ix].1
+    }
+
     /// Add the range start..end to the set.
     pub fn union_one_range(&mut self, start: usize, end: usize) {
         for i in 0..self.ranges.len() {
@@ -74,6 +103,33 @@ impl IndexSet {
         self.ranges.push((start, end));
     }
 
+    /// Adds all indices covered by `other` to the set.
+    ///
+    /// Performance note: O(n + m), as both sets are merged in a single pass.
+    pub fn union(&mut self, other: &IndexSet) {
+        let mut ranges: Vec<(usize, usize)> =
+            Vec::with_capacity(self.ranges.len() + other.ranges.len());
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() || j < other.ranges.len() {
+            let next = if j == other.ranges.len()
+                || (i < self.ranges.len() && self.ranges[i].0 <= other.ranges[j].0) {
+                i += 1;
+                self.ranges[i - 1]
+            } else {
+                j += 1;
+                other.ranges[j - 1]
+            };
+            if let Some(last) = ranges.last_mut() {
+                if next.0 <= last.1 {
+                    last.1 = max(last.1, next.1);
+                    continue;
+                }
+            }
+            ranges.push(next);
+        }
+        self.ranges = ranges;
+    }
+
     /// Deletes the given range from the set.
     pub fn delete_range(&mut self, start: usize, end: usize) {
         let mut ix = match self.ranges.binary_search_by(|r| r.1.cmp(&start)) {
@@ -111,6 +167,39 @@ impl IndexSet {
         }
     }
 
+    /// Deletes all indices covered by `other` from the set.
+    ///
+    /// Performance note: O(n + m), as both sets are walked in a single pass.
+    pub fn subtract(&mut self, other: &IndexSet) {
+        let mut ranges = Vec::with_capacity(self.ranges.len());
+        // index of the first range in `other` that may overlap the current range
+        let mut ix = 0;
+        for &(start, end) in &self.ranges {
+            let mut start = start;
+            while ix < other.ranges.len() && other.ranges[ix].1 <= start {
+                ix += 1;
+            }
+            while start < end && ix < other.ranges.len() && other.ranges[ix].0 < end {
+                let (other_start, other_end) = other.ranges[ix];
+                if other_start < other_end {
+                    if other_start > start {
+                        ranges.push((start, other_start));
+                    }
+                    start = max(start, other_end);
+                }
+                if other_end > end {
+                    // this range may also cover the next range in self
+                    break;
+                }
+                ix += 1;
+            }
+            if start < end {
+                ranges.push((start, end));
+            }
+        }
+        self.ranges = ranges;
+    }
+
     /// Return an iterator that yields start..end minus the coverage in this set.
     pub fn minus_one_range(&self, start: usize, end: usize) -> MinusIter {
         let mut ranges = &self.ranges[..];
@@ -127,12 +216,22 @@ impl IndexSet {
     /// Computes a new set based on applying a delta to the old set. Collapsed regions are removed
     /// and contiguous regions are combined.
     pub fn apply_delta(&self, delta: &Delta<RopeInfo>) -> IndexSet {
+        self.apply_delta_impl(delta, false)
+    }
+
+    /// Like `apply_delta`, but insertions at the end of a range are absorbed
+    /// into it, so that the range grows rather than being pushed away.
+    pub fn apply_delta_expand(&self, delta: &Delta<RopeInfo>) -> IndexSet {
+        self.apply_delta_impl(delta, true)
+    }
+
+    fn apply_delta_impl(&self, delta: &Delta<RopeInfo>, expand: bool) -> IndexSet {
         let mut ranges: Vec<(usize, usize)> = Vec::new();
         let mut transformer = Transformer::new(delta);
         for &(start, end) in &self.ranges {
             let new_range = (
                 transformer.transform(start, false),
-                transformer.transform(end, false)
+                transformer.transform(end, expand)
             );
             if new_range.0 == new_range.1 {
                 continue; // remove collapsed regions
@@ -149,12 +248,65 @@ impl IndexSet {
         IndexSet { ranges }
     }
 
+    /// Return an iterator over the ranges in this set, in ascending order.
+    pub fn iter_ranges(&self) -> impl Iterator<Item=(usize, usize)> + '_ {
+        self.ranges.iter().cloned()
+    }
+
     #[cfg(test)]
     fn get_ranges(&self) -> &[(usize, usize)] {
         &self.ranges
     }
 }
 
+impl FromIterator<usize> for IndexSet {
+    /// Builds a set from individual indices, merging runs of consecutive
+    /// indices into ranges. This is O(n log n), as it sorts the input first.
+    fn from_iter<I: IntoIterator<Item=usize>>(iter: I) -> IndexSet {
+        let mut indices = iter.into_iter().collect::<Vec<_>>();
+        indices.sort_unstable();
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for ix in indices {
+            if let Some(last) = ranges.last_mut() {
+                if ix <= last.1 {
+                    last.1 = max(last.1, ix + 1);
+                    continue;
+                }
+            }
+            ranges.push((ix, ix + 1));
+        }
+        IndexSet { ranges }
+    }
+}
+
+/// Serializes as an array of `[start, end]` pairs.
+impl Serialize for IndexSet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        self.ranges.serialize(serializer)
+    }
+}
+
+/// Deserializes from an array of `[start, end]` pairs, which must be sorted
+/// and non-overlapping.
+impl<'de> Deserialize<'de> for IndexSet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let ranges: Vec<(usize, usize)> = Vec::deserialize(deserializer)?;
+        let mut prev_end = 0;
+        for &(start, end) in &ranges {
+            if start < prev_end || end < start {
+                return Err(de::Error::custom(
+                    "IndexSet ranges must be sorted and non-overlapping"));
+            }
+            prev_end = end;
+        }
+        Ok(IndexSet { ranges })
+    }
+}
+
 /// The iterator generated by `minus_one_range`.
 pub struct MinusIter<'a> {
     ranges: &'a [(usize, usize)],
@@ -297,6 +449,71 @@ mod tests {
         assert_eq!(e.get_ranges(), &[(2, 10), (11, 12)]);
     }
 
+    #[test]
+    fn contains() {
+        let mut e = IndexSet::new();
+        assert!(!e.contains(0));
+        e.union_one_range(3, 5);
+        e.union_one_range(7, 9);
+        let members = (0..11).filter(|&i| e.contains(i)).collect::<Vec<_>>();
+        assert_eq!(members, vec![3, 4, 7, 8]);
+    }
+
+    #[test]
+    fn from_iter() {
+        let e = vec![].into_iter().collect::<IndexSet>();
+        assert_eq!(e.get_ranges(), &[]);
+        let e = vec![9, 3, 4, 8, 4, 12, 5, 10].into_iter().collect::<IndexSet>();
+        assert_eq!(e.get_ranges(), &[(3, 6), (8, 11), (12, 13)]);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        use serde_json;
+
+        let mut e = IndexSet::new();
+        e.union_one_range(7, 9);
+        e.union_one_range(1, 3);
+        let json = serde_json::to_string(&e).unwrap();
+        assert_eq!(json, "[[1,3],[7,9]]");
+        let e2: IndexSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(e2.get_ranges(), &[(1, 3), (7, 9)]);
+
+        assert!(serde_json::from_str::<IndexSet>("[[7,9],[1,3]]").is_err());
+        assert!(serde_json::from_str::<IndexSet>("[[1,5],[3,9]]").is_err());
+        assert!(serde_json::from_str::<IndexSet>("[[5,1]]").is_err());
+    }
+
+    #[test]
+    fn iter_ranges() {
+        let mut e = IndexSet::new();
+        assert_eq!(e.iter_ranges().collect::<Vec<_>>(), vec![]);
+        e.union_one_range(7, 9);
+        e.union_one_range(3, 5);
+        assert_eq!(e.iter_ranges().collect::<Vec<_>>(), vec![(3, 5), (7, 9)]);
+    }
+
+    #[test]
+    fn len_and_is_empty() {
+        let mut e = IndexSet::new();
+        assert!(e.is_empty());
+        assert_eq!(e.len(), 0);
+        assert_eq!(e.range_count(), 0);
+        e.union_one_range(3, 5);
+        e.union_one_range(7, 10);
+        assert!(!e.is_empty());
+        assert_eq!(e.len(), 5);
+        assert_eq!(e.range_count(), 2);
+        e.union_one_range(5, 7);
+        assert_eq!(e.len(), 7);
+        assert_eq!(e.range_count(), 1);
+
+        let mut e = IndexSet::new();
+        e.union_one_range(8, 8);
+        assert!(e.is_empty());
+        assert_eq!(e.len(), 0);
+    }
+
     #[test]
     fn delete_range() {
         let mut e = IndexSet::new();
@@ -322,6 +539,65 @@ mod tests {
         assert_eq!(e.get_ranges(), &[(0, 4), (6, 10)]);
     }
 
+    #[test]
+    fn union() {
+        let mut e = IndexSet::new();
+        e.union_one_range(1, 2);
+        e.union_one_range(4, 7);
+        e.union_one_range(10, 12);
+        e.union_one_range(20, 22);
+
+        let mut other = IndexSet::new();
+        other.union_one_range(0, 1);
+        other.union_one_range(5, 6);
+        other.union_one_range(11, 15);
+        other.union_one_range(16, 18);
+        other.union_one_range(19, 25);
+
+        let mut expected = IndexSet::new();
+        for &(start, end) in e.get_ranges().iter().chain(other.get_ranges()) {
+            expected.union_one_range(start, end);
+        }
+
+        e.union(&other);
+        assert_eq!(e.get_ranges(), &[(0, 2), (4, 7), (10, 15), (16, 18), (19, 25)]);
+        assert_eq!(e.get_ranges(), expected.get_ranges());
+
+        e.union(&IndexSet::new());
+        assert_eq!(e.get_ranges(), expected.get_ranges());
+
+        let mut empty = IndexSet::new();
+        empty.union(&e);
+        assert_eq!(empty.get_ranges(), expected.get_ranges());
+    }
+
+    #[test]
+    fn subtract() {
+        let mut e = IndexSet::new();
+        e.union_one_range(1, 2);
+        e.union_one_range(4, 7);
+        e.union_one_range(10, 12);
+        e.union_one_range(13, 20);
+
+        let mut other = IndexSet::new();
+        other.union_one_range(0, 1);
+        other.union_one_range(5, 6);
+        other.union_one_range(11, 14);
+        other.union_one_range(16, 16);
+        other.union_one_range(18, 25);
+
+        e.subtract(&other);
+        assert_eq!(e.get_ranges(), &[(1, 2), (4, 5), (6, 7), (10, 11), (14, 18)]);
+
+        e.subtract(&IndexSet::new());
+        assert_eq!(e.get_ranges(), &[(1, 2), (4, 5), (6, 7), (10, 11), (14, 18)]);
+
+        let mut all = IndexSet::new();
+        all.union_one_range(0, 100);
+        e.subtract(&all);
+        assert!(e.is_empty());
+    }
+
     #[test]
     fn apply_delta() {
         use xi_rope::delta::Delta;
@@ -344,4 +620,29 @@ mod tests {
         let s = e.apply_delta(&d);
         assert_eq!(s.get_ranges(), &[(1, 5)]);
     }
+
+    #[test]
+    fn apply_delta_expand() {
+        use xi_rope::delta::Delta;
+        use xi_rope::interval::Interval;
+        use xi_rope::rope::Rope;
+
+        let mut e = IndexSet::new();
+        e.union_one_range(1, 3);
+        e.union_one_range(5, 9);
+
+        let d = Delta::simple_edit(Interval::new_closed_open(3, 3), Rope::from("..."), 10);
+        assert_eq!(e.apply_delta(&d).get_ranges(), &[(1, 3), (8, 12)]);
+        assert_eq!(e.apply_delta_expand(&d).get_ranges(), &[(1, 6), (8, 12)]);
+
+        let d = Delta::simple_edit(Interval::new_closed_open(9, 9), Rope::from("..."), 10);
+        assert_eq!(e.apply_delta(&d).get_ranges(), &[(1, 3), (5, 9)]);
+        assert_eq!(e.apply_delta_expand(&d).get_ranges(), &[(1, 3), (5, 12)]);
+
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 1), Rope::from("..."), 10);
+        assert_eq!(e.apply_delta_expand(&d).get_ranges(), &[(1, 6), (8, 12)]);
+
+        let d = Delta::simple_edit(Interval::new_closed_open(2, 6), Rope::from(""), 10);
+        assert_eq!(e.apply_delta_expand(&d).get_ranges(), &[(1, 5)]);
+    }
 }
@@ -0,0 +1,198 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Inlay hints: labels such as inferred types or parameter names, which the
+//! frontend draws between characters without them being part of the text.
+//!
+//! Hints are fetched from plugins for the lines the frontend asks for. Only
+//! one round of requests is in flight per view; lines asked for meanwhile
+//! are requested once it completes, so a frontend scrolling quickly does not
+//! pile up work in the plugins.
+
+use std::collections::BTreeMap;
+
+use xi_rope::delta::{Delta, DeltaElement, Transformer};
+use xi_rope::rope::RopeInfo;
+
+use plugins::PluginPid;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InlayHintKind {
+    Type,
+    Parameter,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InlayHint {
+    /// The utf-8 offset the hint is drawn before.
+    pub offset: usize,
+    pub label: String,
+    pub kind: InlayHintKind,
+}
+
+/// The inlay hints of a view, and the state of requests for more.
+#[derive(Default)]
+pub struct InlayHints {
+    /// Each plugin's hints, sorted by offset.
+    hints: BTreeMap<PluginPid, Vec<InlayHint>>,
+    /// The number of plugins yet to answer the requests in flight.
+    in_flight: usize,
+    /// The lines last asked for while requests were in flight.
+    queued: Option<(usize, usize)>,
+    /// The lines last requested.
+    requested: Option<(usize, usize)>,
+}
+
+impl InlayHints {
+    /// Asks for hints for the lines `first..=last` from `plugins` plugins.
+    /// Returns `true` if the requests should be sent now, or `false` if they
+    /// were queued behind those already in flight.
+    pub fn request(&mut self, first: usize, last: usize, plugins: usize) -> bool {
+        if self.in_flight > 0 {
+            self.queued = Some((first, last));
+            return false;
+        }
+        self.in_flight = plugins;
+        self.requested = Some((first, last));
+        plugins > 0
+    }
+
+    /// Records that the lines last requested are being asked for again from
+    /// one more plugin, such as one restarted after a crash, and returns them.
+    pub fn retry(&mut self) -> Option<(usize, usize)> {
+        let requested = self.requested?;
+        self.in_flight += 1;
+        Some(requested)
+    }
+
+    /// Handles a plugin's answer, which replaces its previous hints; `None`
+    /// means the request failed. Returns the queued lines to request next,
+    /// once every plugin has answered.
+    pub fn respond(&mut self, plugin: PluginPid, hints: Option<Vec<InlayHint>>)
+                   -> Option<(usize, usize)> {
+        if let Some(mut hints) = hints {
+            hints.sort_by_key(|hint| hint.offset);
+            self.hints.insert(plugin, hints);
+        }
+        self.in_flight = self.in_flight.saturating_sub(1);
+        if self.in_flight == 0 {
+            self.queued.take()
+        } else {
+            None
+        }
+    }
+
+    pub fn remove_plugin(&mut self, plugin: PluginPid) {
+        self.hints.remove(&plugin);
+    }
+
+    /// Returns the hints in `start..end`, in order of offset.
+    pub fn hints_in_range(&self, start: usize, end: usize) -> Vec<&InlayHint> {
+        let mut result: Vec<&InlayHint> = self.hints.values()
+            .flat_map(|hints| hints.iter())
+            .filter(|hint| hint.offset >= start && hint.offset < end)
+            .collect();
+        result.sort_by_key(|hint| hint.offset);
+        result
+    }
+
+    /// Moves hints to follow `delta`, dropping those inside deleted text.
+    /// Text inserted where a hint is goes after it.
+    pub fn apply_delta(&mut self, delta: &Delta<RopeInfo>) {
+        let mut deleted = Vec::new();
+        let mut last_end = 0;
+        for el in &delta.els {
+            if let DeltaElement::Copy(start, end) = *el {
+                if start > last_end {
+                    deleted.push((last_end, start));
+                }
+                last_end = end;
+            }
+        }
+        if last_end < delta.base_len {
+            deleted.push((last_end, delta.base_len));
+        }
+        let mut transformer = Transformer::new(delta);
+        for hints in self.hints.values_mut() {
+            hints.retain(|hint| !deleted.iter()
+                         .any(|&(start, end)| start < hint.offset && hint.offset < end));
+            for hint in hints.iter_mut() {
+                hint.offset = transformer.transform(hint.offset, false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::delta::Builder;
+    use xi_rope::interval::Interval;
+    use xi_rope::rope::Rope;
+
+    fn hint(offset: usize, label: &str) -> InlayHint {
+        InlayHint { offset, label: label.into(), kind: InlayHintKind::Type }
+    }
+
+    #[test]
+    fn one_request_in_flight() {
+        let mut hints = InlayHints::default();
+        assert!(hints.request(0, 10, 2));
+        assert!(!hints.request(5, 15, 2));
+        assert!(!hints.request(10, 20, 2));
+        assert_eq!(hints.respond(PluginPid(1), Some(vec![hint(4, "i32")])), None);
+        assert_eq!(hints.respond(PluginPid(2), None), Some((10, 20)));
+        assert!(hints.request(10, 20, 2));
+        assert_eq!(hints.hints_in_range(0, 10), vec![&hint(4, "i32")]);
+        assert!(!hints.request(0, 0, 0));
+    }
+
+    #[test]
+    fn retry() {
+        let mut hints = InlayHints::default();
+        assert_eq!(hints.retry(), None);
+        hints.request(0, 10, 1);
+        assert_eq!(hints.retry(), Some((0, 10)));
+        assert!(!hints.request(5, 15, 1));
+        assert_eq!(hints.respond(PluginPid(1), None), None);
+        assert_eq!(hints.respond(PluginPid(2), None), Some((5, 15)));
+    }
+
+    #[test]
+    fn hints_follow_edits() {
+        let mut hints = InlayHints::default();
+        hints.request(0, 10, 2);
+        hints.respond(PluginPid(2), Some(vec![hint(20, "b"), hint(5, "a")]));
+        hints.respond(PluginPid(1), Some(vec![hint(10, "c")]));
+        assert_eq!(hints.hints_in_range(0, 30), vec![&hint(5, "a"), &hint(10, "c"), &hint(20, "b")]);
+
+        // replace 8..12 with two characters
+        let delta = Delta::simple_edit(Interval::new_closed_open(8, 12), "xy".into(), 30);
+        hints.apply_delta(&delta);
+        assert_eq!(hints.hints_in_range(0, 30), vec![&hint(5, "a"), &hint(18, "b")]);
+
+        // edits on either side of a hint, in one delta, leave it
+        let mut builder = Builder::new(28);
+        builder.delete(Interval::new_closed_open(2, 4));
+        builder.replace(Interval::new_closed_open(5, 5), Rope::from("xyz"));
+        builder.replace(Interval::new_closed_open(20, 20), Rope::from("w"));
+        hints.apply_delta(&builder.build());
+        assert_eq!(hints.hints_in_range(0, 30), vec![&hint(3, "a"), &hint(19, "b")]);
+        hints.remove_plugin(PluginPid(2));
+        assert!(hints.hints_in_range(0, 30).is_empty());
+    }
+}
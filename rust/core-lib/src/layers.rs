@@ -18,6 +18,7 @@
 //! Scope information originating from any number of plugins can be resolved
 //! into styles using a theme, augmented with additional style definitions.
 
+use std::cmp::{max, min};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use syntect::parsing::Scope;
 use syntect::highlighting::StyleModifier;
@@ -28,7 +29,7 @@ use xi_rope::rope::RopeInfo;
 use xi_rope::spans::{Spans, SpansBuilder};
 use xi_trace::trace_block;
 
-use styles::{Style, ThemeStyleMap};
+use styles::{Style, ThemeStyleMap, SYNTAX_PRIORITY_DEFAULT};
 use plugins::PluginPid;
 
 /// A collection of layers containing scope information.
@@ -37,11 +38,15 @@ pub struct Layers {
     layers: BTreeMap<PluginPid, ScopeLayer>,
     deleted: HashSet<PluginPid>,
     merged: Spans<Style>,
+    /// Advanced whenever the merged styles change.
+    revision: u64,
 }
 
 /// A collection of scope spans from a single source.
 pub struct ScopeLayer {
     stack_lookup: Vec<Vec<Scope>>,
+    /// Whether each stack in `stack_lookup` is in a string or a comment.
+    string_or_comment: Vec<bool>,
     style_lookup: Vec<Style>,
     // TODO: this might be efficient (in memory at least) if we use
     // a prefix tree.
@@ -51,6 +56,9 @@ pub struct ScopeLayer {
     /// Human readable scope names, for debugging
     scope_spans: Spans<u32>,
     style_spans: Spans<Style>,
+    /// The priority of this layer's styles, which decides which layer's
+    /// style wins where several have a span.
+    priority: u16,
 }
 
 impl Layers {
@@ -59,6 +67,12 @@ impl Layers {
         &self.merged
     }
 
+    /// The revision of the merged styles, which is advanced whenever they
+    /// change, so that what is derived from them knows to be computed again.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
     /// Adds the provided scopes to the layer's lookup table.
     pub fn add_scopes(&mut self, layer: PluginPid, scopes: Vec<Vec<String>>,
                                 style_map: &ThemeStyleMap) {
@@ -67,6 +81,24 @@ impl Layers {
         self.layers.get_mut(&layer).unwrap().add_scopes(scopes, style_map);
     }
 
+    /// Sets the priority of the layer's styles. Layers with a higher
+    /// priority, such as one of semantic tokens, override the others where
+    /// their spans overlap.
+    pub fn set_priority(&mut self, layer: PluginPid, priority: u16,
+                        style_map: &ThemeStyleMap) {
+        if self.create_if_missing(layer).is_err() { return }
+        {
+            let layer = self.layers.get_mut(&layer).unwrap();
+            if layer.priority == priority {
+                return;
+            }
+            layer.priority = priority;
+            layer.theme_changed(style_map);
+        }
+        let iv_all = Interval::new_closed_closed(0, self.merged.len());
+        self.resolve_styles(iv_all);
+    }
+
     /// Applies the delta to all layers, inserting empty intervals
     /// for any regions inserted in the delta.
     ///
@@ -115,6 +147,7 @@ impl Layers {
     /// Resolves styles from all layers for the given interval, updating
     /// the master style spans.
     fn resolve_styles(&mut self, iv: Interval) {
+        self.revision += 1;
         if self.layers.is_empty() {
             return
         }
@@ -134,6 +167,55 @@ impl Layers {
         self.merged.edit(iv, resolved);
     }
 
+    /// Returns the scopes of the character before `offset` (or at it, at
+    /// the start of the buffer), outermost first, from every layer in turn.
+    pub fn scopes_at(&self, offset: usize) -> Vec<String> {
+        let end = min(max(offset, 1), self.merged.len());
+        if end == 0 {
+            return Vec::new();
+        }
+        let iv = Interval::new_closed_open(end - 1, end);
+        self.layers.values()
+            .flat_map(|layer| {
+                let spans = layer.scope_spans.subseq(iv);
+                let stack = spans.iter().next()
+                    .and_then(|(_, &id)| layer.stack_lookup.get(id as usize));
+                stack.cloned().unwrap_or_default()
+            })
+            .map(|scope| scope.build_string())
+            .collect()
+    }
+
+    /// Returns `true` if some layer scopes the character at `offset` as
+    /// part of a string or a comment.
+    pub fn is_string_or_comment(&self, offset: usize) -> bool {
+        self.layers.values().any(|layer| {
+            layer.scope_spans.data_at(offset)
+                .and_then(|&id| layer.string_or_comment.get(id as usize))
+                .cloned().unwrap_or(false)
+        })
+    }
+
+    /// Returns the interval of the first text in `iv` that some layer scopes
+    /// as the name of something being defined, such as a function or a
+    /// class.
+    pub fn definition_name(&self, iv: Interval) -> Option<Interval> {
+        const KINDS: &[&str] = &["function", "class", "struct", "enum", "union", "trait",
+                                 "impl", "interface", "module", "namespace"];
+        self.layers.values().filter_map(|layer| {
+            layer.scope_spans.subseq(iv).iter().find(|&(_, &id)| {
+                layer.stack_lookup.get(id as usize).map_or(false, |stack| {
+                    stack.iter().any(|scope| {
+                        let scope = scope.build_string();
+                        let mut parts = scope.split('.');
+                        parts.next() == Some("entity") && parts.next() == Some("name")
+                            && parts.next().map_or(false, |kind| KINDS.contains(&kind))
+                    })
+                })
+            }).map(|(name, _)| name.translate(iv.start()))
+        }).min_by_key(|name| name.start())
+    }
+
     /// Prints scopes and style information for the given `Interval`.
     pub fn debug_print_spans(&self, iv: Interval) {
         for (id, layer) in &self.layers {
@@ -167,10 +249,12 @@ impl Default for ScopeLayer {
     fn default() -> Self {
         ScopeLayer {
             stack_lookup: Vec::new(),
+            string_or_comment: Vec::new(),
             style_lookup: Vec::new(),
             style_cache: HashMap::new(),
             scope_spans: Spans::default(),
             style_spans: Spans::default(),
+            priority: SYNTAX_PRIORITY_DEFAULT,
         }
     }
 }
@@ -180,10 +264,12 @@ impl ScopeLayer {
     pub fn new(len: usize) -> Self {
         ScopeLayer {
             stack_lookup: Vec::new(),
+            string_or_comment: Vec::new(),
             style_lookup: Vec::new(),
             style_cache: HashMap::new(),
             scope_spans: SpansBuilder::new(len).build(),
             style_spans: SpansBuilder::new(len).build(),
+            priority: SYNTAX_PRIORITY_DEFAULT,
         }
     }
 
@@ -218,6 +304,11 @@ impl ScopeLayer {
             stacks.push(scopes);
         }
 
+        let string = Scope::new("string").unwrap();
+        let comment = Scope::new("comment").unwrap();
+        self.string_or_comment.extend(stacks.iter().map(|stack| {
+            stack.iter().any(|scope| string.is_prefix_of(*scope) || comment.is_prefix_of(*scope))
+        }));
         let mut new_styles = self.styles_for_stacks(stacks.as_slice(), style_map);
         self.stack_lookup.append(&mut stacks);
         self.style_lookup.append(&mut new_styles);
@@ -231,11 +322,12 @@ impl ScopeLayer {
 
         for stack in stacks {
             let mut last_style: Option<StyleModifier> = None;
-            let mut upper_bound_of_last = stack.len() as usize;
+            // without a cached parent, the whole stack is applied
+            let mut upper_bound_of_last = 0;
 
             // walk backwards through stack to see if we have an existing
             // style for any child stacks.
-            for i in 0..stack.len()-1 {
+            for i in 0..stack.len().saturating_sub(1) {
                 let prev_range = 0..stack.len() - (i + 1);
                 if let Some(s) = self.style_cache.get(&stack[prev_range]) {
                     last_style = Some(*s);
@@ -251,7 +343,8 @@ impl ScopeLayer {
                 base_style_mod = base_style_mod.apply(style_mod);
             }
 
-            let style = Style::from_syntect_style_mod(&base_style_mod);
+            let mut style = Style::from_syntect_style_mod(&base_style_mod);
+            style.priority = self.priority;
             self.style_cache.insert(stack.clone(), base_style_mod);
 
             new_styles.push(style);
@@ -305,3 +398,48 @@ impl ScopeLayer {
         self.style_spans.edit(iv, sb.build());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::rope::Rope;
+
+    /// Layers for a buffer of `len` bytes.
+    fn layers_for(len: usize) -> Layers {
+        let mut layers = Layers::default();
+        let text = Rope::from("a".repeat(len));
+        layers.update_all(&Delta::simple_edit(Interval::new_closed_open(0, 0), text, 0));
+        layers
+    }
+
+    fn set_scope(layers: &mut Layers, layer: PluginPid, scope: &str, len: usize) {
+        let style_map = ThemeStyleMap::new(None);
+        layers.add_scopes(layer, vec![vec![scope.to_string()]], &style_map);
+        let mut sb = SpansBuilder::new(len);
+        sb.add_span(Interval::new_closed_open(0, len), 0);
+        layers.update_layer(layer, Interval::new_closed_closed(0, len), sb.build());
+    }
+
+    fn fg_color(layers: &Layers) -> Option<u32> {
+        layers.get_merged().iter().next().and_then(|(_, style)| style.fg_color)
+    }
+
+    #[test]
+    fn layer_priority() {
+        let style_map = ThemeStyleMap::new(None);
+        let mut comment = layers_for(4);
+        set_scope(&mut comment, PluginPid(1), "comment", 4);
+        let mut string = layers_for(4);
+        set_scope(&mut string, PluginPid(1), "string", 4);
+        assert_ne!(fg_color(&comment), fg_color(&string));
+
+        // with equal priorities, the later layer wins
+        let mut layers = layers_for(4);
+        set_scope(&mut layers, PluginPid(1), "comment", 4);
+        set_scope(&mut layers, PluginPid(2), "string", 4);
+        assert_eq!(fg_color(&layers), fg_color(&string));
+
+        layers.set_priority(PluginPid(1), SYNTAX_PRIORITY_DEFAULT + 100, &style_map);
+        assert_eq!(fg_color(&layers), fg_color(&comment));
+    }
+}
@@ -42,8 +42,15 @@ extern crate serde_derive;
 extern crate time;
 extern crate syntect;
 extern crate toml;
+extern crate rand;
+extern crate languageserver_types;
+extern crate url;
 #[cfg(feature = "notify")]
 extern crate notify;
+#[cfg(feature = "fancy-regex")]
+extern crate fancy_regex;
+#[cfg(unix)]
+extern crate libc;
 
 extern crate xi_rope;
 extern crate xi_rpc;
@@ -69,26 +76,54 @@ pub mod core;
 pub mod tabs;
 pub mod editor;
 pub mod edit_types;
+pub mod code_actions;
+pub mod commands;
+pub mod definition;
+pub mod diagnostics;
+pub mod document_highlight;
+pub mod editorconfig;
 pub mod event_context;
 pub mod file;
+pub mod file_index;
 pub mod find;
+pub mod fuzzy;
+pub mod formatting;
 pub mod view;
 pub mod linewrap;
+pub mod macros;
+pub mod minimap;
 pub mod plugins;
+pub mod plugin_requests;
+pub mod rename;
 #[cfg(feature = "ledger")]
 pub mod fuchsia;
 pub mod styles;
 pub mod word_boundaries;
+pub mod bracket_matching;
+pub mod breadcrumbs;
+pub mod clipboard_history;
+pub mod gutter;
+pub mod hover;
 pub mod index_set;
+pub mod inlay_hints;
+pub mod indent_guides;
+pub mod undo_tree;
 pub mod selection;
+pub mod session;
+pub mod snippet;
+pub mod sticky_header;
+pub mod streaming;
 pub mod movement;
 pub mod syntax;
+pub mod syntax_context;
 pub mod layers;
+pub mod lsp_server;
 pub mod config;
 #[cfg(feature = "notify")]
 pub mod watcher;
 pub mod line_cache_shadow;
 pub mod width_cache;
+pub mod workspace_symbols;
 
 pub mod rpc;
 
@@ -0,0 +1,431 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A mode in which xi-core speaks the Language Server Protocol, rather than
+//! the xi protocol, to whatever is on the other end of stdio.
+//!
+//! Only document synchronization is served for now: the client opens,
+//! edits, saves and closes documents, and the core keeps their text in
+//! ropes. Messages are framed with `Content-Length` headers, as LSP
+//! requires, instead of the newline-delimited JSON used by `xi_rpc`.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use languageserver_types::{DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+                           DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+                           InitializeResult, Position, SaveOptions, ServerCapabilities,
+                           TextDocumentContentChangeEvent, TextDocumentSyncCapability,
+                           TextDocumentSyncKind, TextDocumentSyncOptions};
+use url::Url;
+
+use xi_rope::rope::Rope;
+
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+/// The largest message body accepted, so that a bad `Content-Length`
+/// can't make us allocate without bound.
+const MAX_CONTENT_LENGTH: usize = 256 << 20;
+
+/// A document opened by the client.
+#[derive(Debug)]
+pub struct Document {
+    pub language_id: String,
+    pub text: Rope,
+    pub version: u64,
+    /// The version last saved by the client.
+    pub saved_version: Option<u64>,
+}
+
+/// The documents opened by the client, kept in step with its edits.
+#[derive(Debug, Default)]
+pub struct TextDocumentSync {
+    documents: HashMap<Url, Document>,
+}
+
+impl TextDocumentSync {
+    pub fn get(&self, uri: &Url) -> Option<&Document> {
+        self.documents.get(uri)
+    }
+
+    pub fn did_open(&mut self, params: DidOpenTextDocumentParams) {
+        let item = params.text_document;
+        let document = Document {
+            language_id: item.language_id,
+            text: Rope::from(item.text),
+            version: item.version,
+            saved_version: None,
+        };
+        self.documents.insert(item.uri, document);
+    }
+
+    /// Applies the client's changes, in order. Returns `false` if the
+    /// document isn't open.
+    pub fn did_change(&mut self, params: DidChangeTextDocumentParams) -> bool {
+        let document = match self.documents.get_mut(&params.text_document.uri) {
+            Some(document) => document,
+            None => return false,
+        };
+        for change in &params.content_changes {
+            apply_change(&mut document.text, change);
+        }
+        if let Some(version) = params.text_document.version {
+            document.version = version;
+        }
+        true
+    }
+
+    pub fn did_close(&mut self, params: DidCloseTextDocumentParams) -> bool {
+        self.documents.remove(&params.text_document.uri).is_some()
+    }
+
+    pub fn did_save(&mut self, params: DidSaveTextDocumentParams) -> bool {
+        match self.documents.get_mut(&params.text_document.uri) {
+            Some(document) => {
+                document.saved_version = Some(document.version);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Applies a single content change: a replacement of the whole document
+/// if it has no range, or else of that range.
+fn apply_change(text: &mut Rope, change: &TextDocumentContentChangeEvent) {
+    match change.range {
+        Some(range) => {
+            let start = offset_of_position(text, range.start);
+            let end = offset_of_position(text, range.end).max(start);
+            text.edit_str(start..end, &change.text);
+        }
+        None => *text = Rope::from(change.text.as_str()),
+    }
+}
+
+/// Converts an LSP position, whose `character` counts UTF-16 code units,
+/// to a byte offset. Positions past the end of a line are clamped to it,
+/// and lines past the end of the document to its end.
+pub fn offset_of_position(text: &Rope, position: Position) -> usize {
+    let line = position.line as usize;
+    if line > text.line_of_offset(text.len()) {
+        return text.len();
+    }
+    let line_start = text.offset_of_line(line);
+    let line_end = text.offset_of_line(line + 1);
+    let mut utf16_len = 0;
+    let mut offset = line_start;
+    for c in text.slice_to_cow(line_start..line_end).chars() {
+        if utf16_len >= position.character as usize || c == '\n' || c == '\r' {
+            break;
+        }
+        utf16_len += c.len_utf16();
+        offset += c.len_utf8();
+    }
+    offset
+}
+
+/// Reads one message, returning `None` at the end of the input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or_default();
+        let value = parts.next().unwrap_or_default().trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse::<usize>().ok();
+        }
+    }
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    if content_length > MAX_CONTENT_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("Content-Length {} is too large", content_length)));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = serde_json::to_string(message)?;
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// The state of a connection to an LSP client.
+#[derive(Debug, Default)]
+pub struct LspServer {
+    pub documents: TextDocumentSync,
+    initialized: bool,
+    shutdown: bool,
+    exited: bool,
+}
+
+impl LspServer {
+    pub fn new() -> LspServer {
+        LspServer::default()
+    }
+
+    /// Returns `true` once the client has sent `exit`.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// The process exit code LSP asks for: success only if `shutdown` was
+    /// requested before `exit`.
+    pub fn exit_code(&self) -> i32 {
+        if self.shutdown { 0 } else { 1 }
+    }
+
+    /// Handles a message from the client, returning the response if it was
+    /// a request.
+    pub fn handle_message(&mut self, message: Value) -> Option<Value> {
+        let method = message.get("method").and_then(Value::as_str).map(String::from);
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let id = message.get("id").cloned();
+        match (id, method) {
+            (Some(id), Some(method)) => {
+                let result = self.handle_request(&method, params);
+                Some(response(id, result))
+            }
+            (None, Some(method)) => {
+                self.handle_notification(&method, params);
+                None
+            }
+            // responses to requests we never send
+            (_, None) => None,
+        }
+    }
+
+    fn handle_request(&mut self, method: &str, _params: Value)
+                      -> Result<Value, (i64, String)> {
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "server is shutting down".into()));
+        }
+        if !self.initialized && method != "initialize" {
+            return Err((SERVER_NOT_INITIALIZED, "server is not initialized".into()));
+        }
+        match method {
+            "initialize" => {
+                self.initialized = true;
+                Ok(serde_json::to_value(server_capabilities()).unwrap())
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: Value) {
+        if method == "exit" {
+            self.exited = true;
+            return;
+        }
+        if !self.initialized {
+            warn!("ignoring {} before initialize", method);
+            return;
+        }
+        let found = match method {
+            "initialized" => true,
+            "textDocument/didOpen" => {
+                if let Some(params) = parse(method, params) {
+                    self.documents.did_open(params);
+                }
+                true
+            }
+            "textDocument/didChange" => match parse(method, params) {
+                Some(params) => self.documents.did_change(params),
+                None => true,
+            },
+            "textDocument/didClose" => match parse(method, params) {
+                Some(params) => self.documents.did_close(params),
+                None => true,
+            },
+            "textDocument/didSave" => match parse(method, params) {
+                Some(params) => self.documents.did_save(params),
+                None => true,
+            },
+            // `$/` notifications are optional, and may be ignored
+            _ if method.starts_with("$/") => true,
+            _ => {
+                warn!("unknown notification {}", method);
+                true
+            }
+        };
+        if !found {
+            warn!("{} for a document that isn't open", method);
+        }
+    }
+}
+
+fn server_capabilities() -> InitializeResult {
+    let sync = TextDocumentSyncOptions {
+        open_close: Some(true),
+        change: Some(TextDocumentSyncKind::Incremental),
+        will_save: None,
+        will_save_wait_until: None,
+        save: Some(SaveOptions { include_text: Some(false) }),
+    };
+    InitializeResult {
+        capabilities: ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Options(sync)),
+            ..Default::default()
+        },
+    }
+}
+
+fn parse<T: DeserializeOwned>(method: &str, params: Value) -> Option<T> {
+    match serde_json::from_value(params) {
+        Ok(params) => Some(params),
+        Err(e) => {
+            warn!("invalid params for {}: {}", method, e);
+            None
+        }
+    }
+}
+
+fn response(id: Value, result: Result<Value, (i64, String)>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": code, "message": message},
+        }),
+    }
+}
+
+/// Serves LSP over `reader` and `writer` until the client exits, returning
+/// the exit code the process should use.
+pub fn run<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> io::Result<i32> {
+    let mut server = LspServer::new();
+    while let Some(message) = read_message(&mut reader)? {
+        if let Some(response) = server.handle_message(message) {
+            write_message(&mut writer, &response)?;
+        }
+        if server.has_exited() {
+            break;
+        }
+    }
+    Ok(server.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[test]
+    fn framing() {
+        let input = message(r#"{"jsonrpc":"2.0","method":"exit"}"#)
+            + "Content-Type: application/vscode-jsonrpc; charset=utf-8\r\n"
+            + &message(r#"{"id":1}"#);
+        let mut reader = input.as_bytes();
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({"jsonrpc": "2.0", "method": "exit"})));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({"id": 1})));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let input = format!("Content-Length: {}\r\n\r\n", MAX_CONTENT_LENGTH + 1);
+        let err = read_message(&mut input.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut out = Vec::new();
+        write_message(&mut out, &json!({"id": 1})).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), message(r#"{"id":1}"#));
+    }
+
+    #[test]
+    fn positions() {
+        let text = Rope::from("a😀b\r\nxy\n");
+        let pos = |line, character| offset_of_position(&text, Position { line, character });
+        assert_eq!(pos(0, 1), 1);
+        // the emoji is two UTF-16 code units, and four bytes
+        assert_eq!(pos(0, 3), 5);
+        assert_eq!(pos(0, 9), 6);
+        assert_eq!(pos(1, 1), 9);
+        assert_eq!(pos(2, 0), 11);
+        assert_eq!(pos(7, 0), 11);
+    }
+
+    #[test]
+    fn document_sync() {
+        let mut server = LspServer::new();
+        let uri = Url::parse("file:///tmp/a.rs").unwrap();
+        let before_init = server.handle_message(json!({"id": 0, "method": "shutdown"}));
+        assert_eq!(before_init.unwrap()["error"]["code"], SERVER_NOT_INITIALIZED);
+
+        let init = server.handle_message(json!({"id": 1, "method": "initialize",
+                                                "params": {"capabilities": {}}}));
+        assert_eq!(init.unwrap()["result"]["capabilities"]["textDocumentSync"]["change"], 2);
+        server.handle_message(json!({"method": "initialized", "params": {}}));
+
+        server.handle_message(json!({"method": "textDocument/didOpen", "params": {
+            "textDocument": {"uri": uri.as_str(), "languageId": "rust", "version": 1,
+                             "text": "fn main() {\n}\n"}}}));
+        server.handle_message(json!({"method": "textDocument/didChange", "params": {
+            "textDocument": {"uri": uri.as_str(), "version": 2},
+            "contentChanges": [
+                {"range": {"start": {"line": 0, "character": 3},
+                           "end": {"line": 0, "character": 7}}, "text": "start"},
+                {"range": {"start": {"line": 1, "character": 0},
+                           "end": {"line": 1, "character": 0}}, "text": "    go();\n"},
+            ]}}));
+        {
+            let doc = server.documents.get(&uri).unwrap();
+            assert_eq!(String::from(&doc.text), "fn start() {\n    go();\n}\n");
+            assert_eq!(doc.version, 2);
+        }
+
+        server.handle_message(json!({"method": "textDocument/didChange", "params": {
+            "textDocument": {"uri": uri.as_str(), "version": 3},
+            "contentChanges": [{"text": "fn main() {}"}]}}));
+        server.handle_message(json!({"method": "textDocument/didSave", "params": {
+            "textDocument": {"uri": uri.as_str()}}}));
+        assert_eq!(String::from(&server.documents.get(&uri).unwrap().text), "fn main() {}");
+        assert_eq!(server.documents.get(&uri).unwrap().saved_version, Some(3));
+
+        server.handle_message(json!({"method": "textDocument/didClose", "params": {
+            "textDocument": {"uri": uri.as_str()}}}));
+        assert!(server.documents.get(&uri).is_none());
+
+        let unknown = server.handle_message(json!({"id": 2, "method": "textDocument/hover"}));
+        assert_eq!(unknown.unwrap()["error"]["code"], METHOD_NOT_FOUND);
+        server.handle_message(json!({"id": 3, "method": "shutdown"}));
+        server.handle_message(json!({"method": "exit"}));
+        assert!(server.has_exited());
+        assert_eq!(server.exit_code(), 0);
+    }
+}
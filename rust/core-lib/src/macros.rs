@@ -0,0 +1,210 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Macros: the edit commands sent to a view, recorded into a register
+//! named by a character, to be played back in any view.
+
+use std::collections::BTreeMap;
+
+use rpc::EditNotification;
+
+/// The recorded macros, by register.
+pub type Registers = BTreeMap<char, Vec<EditNotification>>;
+
+/// The most times a macro is played for one `PlayMacro`.
+pub const MAX_PLAY_COUNT: usize = 1000;
+
+/// The most commands played for one `PlayMacro`, including those of the
+/// macros it plays in turn.
+const MAX_PLAYED_COMMANDS: usize = 100_000;
+
+#[derive(Debug, Default)]
+pub struct Macros {
+    registers: Registers,
+    /// The register being recorded into, and the commands recorded so far.
+    recording: Option<(char, Vec<EditNotification>)>,
+    /// The registers of the macros being played, each played by the one
+    /// before it. Their commands are not recorded.
+    playing: Vec<char>,
+    /// The number of commands that may still be played before the
+    /// outermost macro being played is stopped.
+    budget: usize,
+}
+
+impl Macros {
+    /// Starts recording into `register`, finishing any recording already
+    /// under way.
+    pub fn start_recording(&mut self, register: char) {
+        self.stop_recording();
+        self.recording = Some((register, Vec::new()));
+    }
+
+    /// Finishes the recording under way, if any, replacing the macro in
+    /// its register.
+    pub fn stop_recording(&mut self) {
+        if let Some((register, commands)) = self.recording.take() {
+            self.registers.insert(register, commands);
+        }
+    }
+
+    /// Records `cmd`, if a recording is under way and `cmd` is an edit.
+    /// A played macro is recorded as the `PlayMacro` playing it, rather
+    /// than as its commands.
+    pub fn record(&mut self, cmd: &EditNotification) {
+        if !self.playing.is_empty() || !is_recorded(cmd) {
+            return;
+        }
+        if let Some((_, ref mut commands)) = self.recording {
+            commands.push(cmd.clone());
+        }
+    }
+
+    /// Returns the commands of the macro in `register`, unless it is
+    /// already being played; a macro may play others, but not itself.
+    /// Each call returning commands is paired with a `stop_playing`.
+    pub fn start_playing(&mut self, register: char) -> Option<Vec<EditNotification>> {
+        if self.playing.contains(&register) {
+            return None;
+        }
+        let commands = self.registers.get(&register).cloned()?;
+        if self.playing.is_empty() {
+            self.budget = MAX_PLAYED_COMMANDS;
+        }
+        self.playing.push(register);
+        Some(commands)
+    }
+
+    /// Counts a command about to be played. Returns `false` once too many
+    /// have been, in which case the command should not be.
+    pub fn play_command(&mut self) -> bool {
+        if self.budget == 0 {
+            return false;
+        }
+        self.budget -= 1;
+        true
+    }
+
+    /// Returns `true` if the macro being played is played by another.
+    pub fn is_nested(&self) -> bool {
+        self.playing.len() > 1
+    }
+
+    pub fn stop_playing(&mut self) {
+        self.playing.pop();
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Replaces the recorded macros, as with ones saved by the frontend.
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.registers = registers;
+    }
+}
+
+/// Returns `true` for the commands that edit the buffer, move through it
+/// or change its selections, or search it. Commands reporting the state of
+/// the frontend's window, asking for information, made with the mouse,
+/// whose positions depend on what was on the screen, or that are new and
+/// not listed here, are not recorded.
+fn is_recorded(cmd: &EditNotification) -> bool {
+    use self::EditNotification::*;
+    match *cmd {
+        Insert { .. } | Paste { .. } | PasteFromHistory { .. }
+        | DeleteForward | DeleteBackward | DeleteWordForward | DeleteWordBackward
+        | DeleteToEndOfParagraph | DeleteToBeginningOfLine
+        | InsertNewline | InsertTab
+        | MoveUp | MoveUpAndModifySelection | MoveDown | MoveDownAndModifySelection
+        | MoveLeft | MoveBackward | MoveLeftAndModifySelection
+        | MoveRight | MoveForward | MoveRightAndModifySelection
+        | MoveWordLeft | MoveWordLeftAndModifySelection
+        | MoveWordRight | MoveWordRightAndModifySelection
+        | MoveToBeginningOfParagraph | MoveToEndOfParagraph
+        | MoveToLeftEndOfLine | MoveToLeftEndOfLineAndModifySelection
+        | MoveToRightEndOfLine | MoveToRightEndOfLineAndModifySelection
+        | MoveToBeginningOfDocument | MoveToBeginningOfDocumentAndModifySelection
+        | MoveToEndOfDocument | MoveToEndOfDocumentAndModifySelection
+        | ScrollPageUp | PageUpAndModifySelection | ScrollPageDown | PageDownAndModifySelection
+        | SelectAll | Deselect | ExpandSelection | ShrinkSelection
+        | AddSelectionAbove | AddSelectionBelow
+        | FoldRange { .. } | UnfoldRange { .. }
+        | GotoLine { .. } | SetMark { .. } | JumpToMark { .. }
+        | Yank | Transpose | Undo | Redo | UndoAll | RedoAll
+        | Find { .. } | FindNext { .. } | FindPrevious { .. } | FindAll
+        | CancelOperation | Uppercase | Lowercase | Capitalize | Indent | Outdent
+        | HighlightFind { .. } | SelectionForFind { .. }
+        | Replace { .. } | ReplaceNext | ReplaceAll | SelectionForReplace
+        | SelectionIntoLines | DuplicateLine | MoveLineUp | MoveLineDown
+        | MoveUpAndModifyRectSelection | MoveDownAndModifyRectSelection
+        | MoveLeftAndModifyRectSelection | MoveRightAndModifyRectSelection
+        | JoinLines | SortLines | SortLinesCaseInsensitive | ToggleComment
+        | SelectWord | SelectParagraph
+        | AcceptCompletion { .. } | ExpandSnippet { .. } | PlayMacro { .. } => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn record_and_play() {
+        let mut macros = Macros::default();
+        let insert = EditNotification::Insert { chars: "a".into() };
+        macros.record(&insert);
+        macros.start_recording('q');
+        macros.record(&insert);
+        macros.record(&EditNotification::RequestLines(::rpc::LineRange { first: 0, last: 1 }));
+        macros.record(&EditNotification::MoveLeft);
+        assert!(macros.registers().is_empty());
+        macros.stop_recording();
+        assert_eq!(macros.registers()[&'q'], vec![insert.clone(), EditNotification::MoveLeft]);
+
+        assert_eq!(macros.start_playing('w'), None);
+        assert_eq!(macros.start_playing('q').map(|commands| commands.len()), Some(2));
+        assert_eq!(macros.start_playing('q'), None);
+        macros.stop_playing();
+
+        // a macro may play another, but not itself, and only the playing
+        // is recorded
+        macros.start_recording('w');
+        let play = EditNotification::PlayMacro { register: 'q', count: 1 };
+        macros.record(&play);
+        assert!(macros.start_playing('q').is_some());
+        macros.record(&insert);
+        macros.stop_recording();
+        assert_eq!(macros.registers()[&'w'], vec![play]);
+        assert!(macros.start_playing('w').is_some());
+        assert!(macros.is_nested());
+        assert_eq!(macros.start_playing('q'), None);
+        macros.stop_playing();
+        macros.stop_playing();
+        assert!(!macros.is_nested());
+
+        // playing stops after too many commands
+        macros.start_playing('q');
+        assert!((0..MAX_PLAYED_COMMANDS).all(|_| macros.play_command()));
+        assert!(!macros.play_command());
+        macros.stop_playing();
+
+        // macros survive being saved and restored
+        let saved = serde_json::to_value(macros.registers()).unwrap();
+        let mut restored = Macros::default();
+        restored.set_registers(serde_json::from_value(saved).unwrap());
+        assert_eq!(restored.registers(), macros.registers());
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimap: the whole document shrunk to a grid of colored cells, each
+//! the color of most of the text in the region of the document it covers.
+//! It is computed on a background thread, and cached until the styles or
+//! the length of the document change.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use xi_rope::Rope;
+use xi_rope::spans::Spans;
+
+use styles::Style;
+
+/// A document, downsampled.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MinimapData {
+    /// The number of cells in a row.
+    pub width: usize,
+    /// The number of rows.
+    pub height: usize,
+    /// The number of lines of the document in each row.
+    pub lines_per_row: usize,
+    /// The number of columns of the document in each cell.
+    pub columns_per_cell: usize,
+    /// The ARGB color of each cell, row by row; 0 where there is no text.
+    pub cells: Vec<u32>,
+}
+
+/// What a minimap is computed for; a cached one is only used for the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MinimapKey {
+    pub target_width: usize,
+    pub target_height: usize,
+    /// The length of the document.
+    pub len: usize,
+    /// The revision of the document's styles; see `Layers::revision`.
+    pub styles_revision: u64,
+    pub default_color: u32,
+}
+
+/// The minimap of a view.
+#[derive(Default)]
+pub struct Minimap {
+    /// The minimap last computed, and what it was computed for.
+    cached: Arc<Mutex<Option<(MinimapKey, MinimapData)>>>,
+    /// What the minimap being computed is for, if one is.
+    pending: Arc<Mutex<Option<MinimapKey>>>,
+}
+
+impl Minimap {
+    /// Returns the cached minimap, if it was computed for `key`.
+    pub fn get(&self, key: &MinimapKey) -> Option<MinimapData> {
+        match *self.cached.lock().unwrap() {
+            Some((ref cached_key, ref data)) if cached_key == key => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    /// Computes the minimap for `key` on a background thread, unless it is
+    /// already being computed. The minimap is cached, then passed to
+    /// `done`, on that thread.
+    pub fn compute_in_background<F>(&self, key: MinimapKey, text: Rope,
+                                    styles: Spans<Style>, tab_size: usize, done: F)
+        where F: FnOnce(&MinimapData) + Send + 'static
+    {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if *pending == Some(key) {
+                return;
+            }
+            *pending = Some(key);
+        }
+        let (cached, pending) = (self.cached.clone(), self.pending.clone());
+        thread::spawn(move || {
+            let data = compute_minimap(&text, &styles, key.default_color, tab_size,
+                                       key.target_width, key.target_height);
+            *cached.lock().unwrap() = Some((key, data.clone()));
+            {
+                let mut pending = pending.lock().unwrap();
+                if *pending == Some(key) {
+                    *pending = None;
+                }
+            }
+            done(&data);
+        });
+    }
+}
+
+/// Shrinks `text` to at most `target_width` cells by `target_height` rows.
+/// Each row covers as many lines, and each cell as many columns, as it
+/// takes for the whole document to fit; a document that already fits is
+/// not enlarged. A cell has the color most of the visible characters it
+/// covers are drawn in, `default_color` for those with no style of their
+/// own.
+pub fn compute_minimap(text: &Rope, styles: &Spans<Style>, default_color: u32,
+                       tab_size: usize, target_width: usize, target_height: usize)
+                       -> MinimapData
+{
+    let tab_size = tab_size.max(1);
+    let n_lines = text.line_of_offset(text.len()) + 1;
+    let max_cols = text.lines(..).map(|line| display_width(&line, tab_size)).max()
+        .unwrap_or(0);
+    let lines_per_row = div_ceil(n_lines, target_height.max(1)).max(1);
+    let columns_per_cell = div_ceil(max_cols, target_width.max(1)).max(1);
+    let width = div_ceil(max_cols, columns_per_cell);
+    let height = div_ceil(n_lines, lines_per_row);
+
+    let mut cells = Vec::with_capacity(width * height);
+    let mut spans = styles.iter().peekable();
+    // the number of characters of each color in each cell of the row
+    let mut counts: Vec<Vec<(u32, usize)>> = vec![Vec::new(); width];
+    let mut offset = 0;
+    for (line_num, line) in text.lines_raw(..).enumerate() {
+        let mut col = 0;
+        for (i, c) in line.char_indices() {
+            let char_offset = offset + i;
+            let char_col = col;
+            col = if c == '\t' { (col / tab_size + 1) * tab_size } else { col + 1 };
+            if c.is_whitespace() {
+                continue;
+            }
+            while spans.peek().map(|&(iv, _)| iv.end() <= char_offset).unwrap_or(false) {
+                spans.next();
+            }
+            let color = match spans.peek() {
+                Some(&(iv, style)) if iv.start() <= char_offset =>
+                    style.fg_color.unwrap_or(default_color),
+                _ => default_color,
+            };
+            let cell = &mut counts[char_col / columns_per_cell];
+            match cell.iter().position(|&(cell_color, _)| cell_color == color) {
+                Some(ix) => cell[ix].1 += 1,
+                None => cell.push((color, 1)),
+            }
+        }
+        offset += line.len();
+        if (line_num + 1) % lines_per_row == 0 {
+            finish_row(&mut counts, &mut cells);
+        }
+    }
+    while cells.len() < width * height {
+        finish_row(&mut counts, &mut cells);
+    }
+    MinimapData { width, height, lines_per_row, columns_per_cell, cells }
+}
+
+/// Returns `n / d`, rounded up.
+fn div_ceil(n: usize, d: usize) -> usize {
+    (n + d - 1) / d
+}
+
+/// Adds the dominant color of each cell of a row, and empties the counts.
+fn finish_row(counts: &mut [Vec<(u32, usize)>], cells: &mut Vec<u32>) {
+    for cell in counts.iter_mut() {
+        // the first color seen wins ties
+        let color = cell.iter().fold(None, |best: Option<(u32, usize)>, &(color, n)| {
+            match best {
+                Some((_, best_n)) if best_n >= n => best,
+                _ => Some((color, n)),
+            }
+        });
+        cells.push(color.map(|(color, _)| color).unwrap_or(0));
+        cell.clear();
+    }
+}
+
+fn display_width(line: &str, tab_size: usize) -> usize {
+    line.chars().fold(0, |col, c| {
+        if c == '\t' { (col / tab_size + 1) * tab_size } else { col + 1 }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::interval::Interval;
+    use xi_rope::spans::SpansBuilder;
+
+    const DEFAULT: u32 = 0xff00_0000;
+    const RED: u32 = 0xffff_0000;
+
+    fn styles(len: usize, spans: &[(usize, usize, u32)]) -> Spans<Style> {
+        let mut sb = SpansBuilder::new(len);
+        for &(start, end, color) in spans {
+            sb.add_span(Interval::new_closed_open(start, end),
+                        Style::new(0, Some(color), None, None, None, None));
+        }
+        sb.build()
+    }
+
+    #[test]
+    fn small_documents_are_not_enlarged() {
+        let text = Rope::from("ab\n\tc\n");
+        let spans = styles(text.len(), &[(1, 2, RED)]);
+        let minimap = compute_minimap(&text, &spans, DEFAULT, 4, 80, 40);
+        assert_eq!((minimap.width, minimap.height), (5, 3));
+        assert_eq!((minimap.lines_per_row, minimap.columns_per_cell), (1, 1));
+        assert_eq!(minimap.cells, vec![DEFAULT, RED, 0, 0, 0,
+                                       0, 0, 0, 0, DEFAULT,
+                                       0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn cells_take_the_dominant_color() {
+        let text = Rope::from("aaaa\nbb b\ncc\nd");
+        let spans = styles(text.len(), &[(0, 3, RED), (5, 6, RED)]);
+        let minimap = compute_minimap(&text, &spans, DEFAULT, 4, 2, 2);
+        assert_eq!((minimap.width, minimap.height), (2, 2));
+        assert_eq!((minimap.lines_per_row, minimap.columns_per_cell), (2, 2));
+        // "aaaa" is mostly red, as is the start of "bb b"
+        assert_eq!(minimap.cells, vec![RED, DEFAULT,
+                                       DEFAULT, 0]);
+    }
+}
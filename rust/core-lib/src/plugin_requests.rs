@@ -0,0 +1,102 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The hover, definition and format requests sent to plugins on behalf of
+//! a view.
+//!
+//! Plugins see a buffer through only one of its views, so a request made
+//! in any other view would be answered to the wrong one. Each request is
+//! instead sent with an id of its own, by which its response is routed
+//! back to the view that made it.
+
+use std::collections::HashMap;
+
+use tabs::ViewId;
+
+/// The kinds of request routed back to the view that made them. Each kind
+/// has its own ids in a view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Hover,
+    Definition,
+    Format,
+}
+
+#[derive(Debug, Default)]
+pub struct PluginRequests {
+    next_id: usize,
+    /// The view that made each request in flight, and its id there.
+    senders: HashMap<usize, (ViewId, RequestKind, usize)>,
+}
+
+impl PluginRequests {
+    /// Returns the id to send `view_id`'s request `request_id` to plugins
+    /// with. A request that is sent again, as to a restarted plugin, keeps
+    /// its id.
+    pub fn start(&mut self, view_id: ViewId, kind: RequestKind, request_id: usize) -> usize {
+        let sender = (view_id, kind, request_id);
+        if let Some((&id, _)) = self.senders.iter().find(|&(_, s)| *s == sender) {
+            return id;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.senders.insert(id, sender);
+        id
+    }
+
+    /// Returns the view that made the request of `kind` sent as `id`, and
+    /// its id there.
+    pub fn sender(&self, kind: RequestKind, id: usize) -> Option<(ViewId, usize)> {
+        match self.senders.get(&id) {
+            Some(&(view_id, k, request_id)) if k == kind => Some((view_id, request_id)),
+            _ => None,
+        }
+    }
+
+    /// Forgets `view_id`'s request `request_id`, once it is settled.
+    pub fn finish(&mut self, view_id: ViewId, kind: RequestKind, request_id: usize) {
+        let sender = (view_id, kind, request_id);
+        self.senders.retain(|_, s| *s != sender);
+    }
+
+    /// Forgets the requests of `view_id`, which is closed.
+    pub fn close_view(&mut self, view_id: ViewId) {
+        self.senders.retain(|_, s| s.0 != view_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routing() {
+        let mut requests = PluginRequests::default();
+        let first = requests.start(ViewId(1), RequestKind::Hover, 0);
+        let second = requests.start(ViewId(3), RequestKind::Hover, 0);
+        let definition = requests.start(ViewId(3), RequestKind::Definition, 0);
+        assert_ne!(first, second);
+        assert_ne!(second, definition);
+        assert_eq!(requests.start(ViewId(1), RequestKind::Hover, 0), first);
+        assert_eq!(requests.sender(RequestKind::Hover, first), Some((ViewId(1), 0)));
+        assert_eq!(requests.sender(RequestKind::Hover, second), Some((ViewId(3), 0)));
+        assert_eq!(requests.sender(RequestKind::Format, definition), None);
+
+        requests.finish(ViewId(1), RequestKind::Hover, 0);
+        assert_eq!(requests.sender(RequestKind::Hover, first), None);
+        requests.close_view(ViewId(3));
+        assert_eq!(requests.sender(RequestKind::Hover, second), None);
+        assert_eq!(requests.sender(RequestKind::Definition, definition), None);
+    }
+}
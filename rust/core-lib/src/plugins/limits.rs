@@ -0,0 +1,217 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Enforcement of the resource limits in plugin manifests.
+//!
+//! Memory is limited when the process is spawned, with `setrlimit`. CPU
+//! time is budgeted per request rather than for the life of the process, so
+//! it can't be left to `RLIMIT_CPU`; instead a watchdog thread measures the
+//! CPU time the plugin spends while a request is outstanding, and kills it
+//! if a request goes over budget. The exit is then handled like any other
+//! crash.
+//!
+//! CPU time is only measured on Linux, from `/proc`, so CPU budgets are
+//! ignored elsewhere. Windows has no `setrlimit`, and limits there would
+//! need a Job Object, which is not implemented; memory limits are ignored
+//! too. Each ignored limit is logged when the plugin is spawned.
+
+use std::process::Command as ProcCommand;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use libc;
+
+use super::manifest::ResourceLimits;
+
+/// How often the watchdog checks a plugin's CPU time.
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Arranges for `limits.max_memory_mb` to apply to the process `cmd` spawns.
+#[cfg(unix)]
+pub(crate) fn limit_memory(cmd: &mut ProcCommand, limits: &ResourceLimits) {
+    use std::io;
+    use std::os::unix::process::CommandExt;
+
+    if limits.max_memory_mb == 0 {
+        return;
+    }
+    let bytes = (limits.max_memory_mb as libc::rlim_t).saturating_mul(1024 * 1024);
+    let limit = libc::rlimit { rlim_cur: bytes, rlim_max: bytes };
+    // only async-signal-safe calls are allowed between fork and exec.
+    // `pre_exec`, which replaces this, is newer than our minimum Rust.
+    #[allow(deprecated)]
+    cmd.before_exec(move || {
+        if unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    });
+}
+
+#[cfg(not(unix))]
+pub(crate) fn limit_memory(_cmd: &mut ProcCommand, limits: &ResourceLimits) {
+    if limits.max_memory_mb != 0 {
+        warn!("plugin memory limits are not supported on this platform");
+    }
+}
+
+/// Returns the CPU time, user and system, used so far by the process `pid`.
+#[cfg(target_os = "linux")]
+fn process_cpu_time(pid: u32) -> Option<Duration> {
+    use std::fs;
+
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let ticks = parse_cpu_ticks(&stat)?;
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        return None;
+    }
+    Some(Duration::from_millis(ticks * 1000 / ticks_per_sec as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_cpu_time(_pid: u32) -> Option<Duration> {
+    None
+}
+
+/// Returns `utime + stime` from the contents of `/proc/<pid>/stat`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    // the command name is parenthesized, and may contain spaces
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) {
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL); }
+}
+
+#[cfg(not(unix))]
+fn kill(_pid: u32) { }
+
+#[derive(Default)]
+struct BudgetState {
+    /// The number of requests the plugin has yet to answer.
+    outstanding: usize,
+    /// The plugin's CPU time when the current request started.
+    start: Option<Duration>,
+}
+
+/// The CPU time a plugin may spend on each request.
+pub(crate) struct CpuBudget {
+    pid: u32,
+    limit: Duration,
+    state: Mutex<BudgetState>,
+}
+
+impl CpuBudget {
+    /// Starts watching the process `pid`, if `limits` has a CPU budget and
+    /// its CPU time can be measured on this platform. The watchdog stops
+    /// when the returned budget is dropped.
+    pub(crate) fn watch(pid: u32, limits: &ResourceLimits) -> Option<Arc<CpuBudget>> {
+        if limits.cpu_time_ms == 0 {
+            return None;
+        }
+        if process_cpu_time(pid).is_none() {
+            warn!("plugin cpu time limits are not supported on this platform");
+            return None;
+        }
+        let budget = Arc::new(CpuBudget {
+            pid,
+            limit: Duration::from_millis(limits.cpu_time_ms as u64),
+            state: Mutex::new(BudgetState::default()),
+        });
+        let weak = Arc::downgrade(&budget);
+        let spawn_result = thread::Builder::new()
+            .name(format!("plugin {} watchdog", pid))
+            .spawn(move || loop {
+                thread::sleep(WATCHDOG_INTERVAL);
+                let budget = match weak.upgrade() {
+                    Some(budget) => budget,
+                    None => break,
+                };
+                if budget.exceeded() {
+                    warn!("plugin process {} exceeded its cpu budget; killing it", pid);
+                    kill(pid);
+                    break;
+                }
+            });
+        if let Err(err) = spawn_result {
+            error!("watchdog spawn failed for plugin process {}, {:?}", pid, err);
+        }
+        Some(budget)
+    }
+
+    /// Records that a request has been sent to the plugin.
+    pub(crate) fn request_started(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.outstanding == 0 {
+            state.start = process_cpu_time(self.pid);
+        }
+        state.outstanding += 1;
+    }
+
+    /// Records that the plugin has answered a request. Any requests still
+    /// outstanding are measured from now.
+    pub(crate) fn request_finished(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.outstanding = state.outstanding.saturating_sub(1);
+        state.start = if state.outstanding > 0 { process_cpu_time(self.pid) } else { None };
+    }
+
+    fn exceeded(&self) -> bool {
+        let start = match self.state.lock().unwrap().start {
+            Some(start) => start,
+            None => return false,
+        };
+        match process_cpu_time(self.pid) {
+            Some(now) => now > start + self.limit,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stat() {
+        let stat = "1234 (my (odd) plugin) S 1 1234 1234 0 -1 4194560 1087 0 0 0 \
+                    17 5 0 0 20 0 1 0 98765 12345678 456 18446744073709551615";
+        assert_eq!(parse_cpu_ticks(stat), Some(22));
+        assert_eq!(parse_cpu_ticks("1234 (plugin) S 1"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn budget_follows_requests() {
+        let limits = ResourceLimits { max_memory_mb: 0, cpu_time_ms: 60_000 };
+        let budget = CpuBudget::watch(::std::process::id(), &limits).unwrap();
+        assert!(!budget.exceeded());
+        budget.request_started();
+        budget.request_started();
+        assert!(budget.state.lock().unwrap().start.is_some());
+        budget.request_finished();
+        assert!(budget.state.lock().unwrap().start.is_some());
+        budget.request_finished();
+        assert!(budget.state.lock().unwrap().start.is_none());
+    }
+}
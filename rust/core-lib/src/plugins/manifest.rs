@@ -41,6 +41,52 @@ pub struct PluginDescription {
     pub commands: Vec<Command>,
     #[serde(default)]
     pub languages: Vec<LanguageDefinition>,
+    /// Limits on the resources the plugin process may use.
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    /// How the plugin is restarted when it crashes.
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+}
+
+/// Limits on the resources a plugin process may use; zero means no limit.
+///
+/// Both limits are enforced only on Linux. On other Unix platforms the CPU
+/// budget is ignored, and on Windows both are; an ignored limit is logged
+/// as a warning when the plugin starts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ResourceLimits {
+    /// The address space the process may use, in megabytes.
+    #[serde(default)]
+    pub max_memory_mb: usize,
+    /// The CPU time the process may spend on a single request, in
+    /// milliseconds. A plugin that goes over is killed.
+    #[serde(default)]
+    pub cpu_time_ms: usize,
+}
+
+/// How a plugin is restarted when it crashes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "snake_case")]
+pub struct RestartPolicy {
+    /// The delay before each consecutive restart, in milliseconds; the
+    /// last is repeated.
+    pub backoff_ms: Vec<u64>,
+    /// The number of crashes within `crash_window_ms` after which the
+    /// plugin is disabled.
+    pub max_crashes: usize,
+    pub crash_window_ms: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> RestartPolicy {
+        RestartPolicy {
+            backoff_ms: vec![1000, 2000, 4000],
+            max_crashes: 4,
+            crash_window_ms: 60_000,
+        }
+    }
 }
 
 /// `PluginActivation`s represent events that trigger running a plugin.
@@ -257,4 +303,43 @@ mod tests {
         assert_eq!(command.rpc_cmd.params_ref()["non_arg"], "plugin supplied value");
         assert_eq!(command.args[1].options.clone().unwrap()[1].value, json!(10));
     }
+
+    #[test]
+    fn test_resource_limits() {
+        let manifest = r#"
+name = "limited"
+version = "0.1"
+exec_path = "./bin/limited"
+
+[resource_limits]
+max_memory_mb = 512
+"#;
+        let desc: PluginDescription = ::toml::from_str(manifest).unwrap();
+        assert_eq!(desc.resource_limits, ResourceLimits { max_memory_mb: 512, cpu_time_ms: 0 });
+        let desc: PluginDescription = ::toml::from_str(&manifest[..manifest.find('[').unwrap()])
+            .unwrap();
+        assert_eq!(desc.resource_limits, ResourceLimits::default());
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let manifest = r#"
+name = "flaky"
+version = "0.1"
+exec_path = "./bin/flaky"
+
+[restart_policy]
+backoff_ms = [100, 500]
+max_crashes = 10
+"#;
+        let desc: PluginDescription = ::toml::from_str(manifest).unwrap();
+        assert_eq!(desc.restart_policy, RestartPolicy {
+            backoff_ms: vec![100, 500],
+            max_crashes: 10,
+            crash_window_ms: 60_000,
+        });
+        let desc: PluginDescription = ::toml::from_str(&manifest[..manifest.find('[').unwrap()])
+            .unwrap();
+        assert_eq!(desc.restart_policy, RestartPolicy::default());
+    }
 }
@@ -17,6 +17,8 @@
 pub mod rpc;
 pub mod manifest;
 mod catalog;
+mod limits;
+mod supervisor;
 
 use std::fmt;
 use std::io::BufReader;
@@ -34,13 +36,34 @@ use WeakXiCore;
 use config::Table;
 use tabs::ViewId;
 
-use self::rpc::{PluginUpdate, PluginBufferInfo};
+use self::limits::CpuBudget;
+use self::rpc::{DefinitionKind, PluginCapability, PluginUpdate, PluginBufferInfo};
 
-pub use self::manifest::{PluginDescription, Command, PlaceholderRpc};
+pub use self::manifest::{PluginDescription, Command, PlaceholderRpc, ResourceLimits,
+                         RestartPolicy};
 pub(crate) use self::catalog::PluginCatalog;
+pub(crate) use self::supervisor::{CrashAction, PluginSupervisor};
 
 pub type PluginName = String;
 
+/// The state of a plugin, as reported to the frontend with `plugin_status`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type", content = "value")]
+pub enum PluginStatus {
+    /// The process is being launched.
+    Starting,
+    Running,
+    /// The plugin is working on the named task.
+    Busy(String),
+    /// The plugin has finished the task it reported.
+    Idle,
+    /// The process exited unexpectedly, for the given reason.
+    Crashed(String),
+    /// The plugin has been stopped, and will not be restarted on its own.
+    Disabled,
+}
+
 /// A process-unique identifier for a running plugin.
 ///
 /// Note: two instances of the same executable will have different identifiers.
@@ -61,6 +84,11 @@ pub struct Plugin {
     peer: RpcPeer,
     pub(crate) id: PluginId,
     pub(crate) name: String,
+    /// The capabilities the plugin claimed at startup; empty until it
+    /// has answered.
+    pub(crate) capabilities: Vec<PluginCapability>,
+    /// The CPU time the plugin may spend on each request, if limited.
+    cpu_budget: Option<Arc<CpuBudget>>,
     #[allow(dead_code)]
     process: Child,
 }
@@ -77,6 +105,17 @@ impl Plugin {
                                         }))
     }
 
+    /// Asks the plugin which optional features it provides.
+    pub fn request_capabilities<F>(&self, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.send_request("capabilities", &json!({}), callback)
+    }
+
+    pub fn has_capability(&self, capability: PluginCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
     pub fn shutdown(&self) {
         self.peer.send_rpc_notification("shutdown", &json!({}));
     }
@@ -108,10 +147,82 @@ impl Plugin {
     pub fn update<F>(&self, update: &PluginUpdate, callback: F)
         where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
     {
-        self.peer.send_rpc_request_async("update", &json!(update),
-                                         Box::new(callback))
+        self.send_request("update", &json!(update), callback)
+    }
+
+    /// Asks for the inlay hints of the lines `first_line..=last_line`.
+    pub fn get_inlay_hints<F>(&self, view_id: ViewId, first_line: usize,
+                              last_line: usize, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.send_request("get_inlay_hints",
+                          &json!({
+                              "view_id": view_id,
+                              "first_line": first_line,
+                              "last_line": last_line,
+                          }),
+                          callback)
+    }
+
+    /// Asks for the symbols defined in the buffer.
+    pub fn get_document_symbols<F>(&self, view_id: ViewId, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.send_request("get_document_symbols", &json!({"view_id": view_id}), callback)
+    }
+
+    /// Asks for the code actions offered for the range from `start` to `end`.
+    pub fn get_code_actions<F>(&self, view_id: ViewId, start: usize, end: usize, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.send_request("get_code_actions",
+                          &json!({
+                              "view_id": view_id,
+                              "start": start,
+                              "end": end,
+                          }),
+                          callback)
+    }
+
+    /// Asks for the references to the symbol at `position`.
+    pub fn get_document_highlights<F>(&self, view_id: ViewId, position: usize, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.send_request("get_document_highlights",
+                          &json!({"view_id": view_id, "position": position}),
+                          callback)
+    }
+
+    /// Asks what a rename at `position` would change.
+    pub fn prepare_rename<F>(&self, view_id: ViewId, position: usize, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.send_request("prepare_rename",
+                          &json!({"view_id": view_id, "position": position}),
+                          callback)
     }
 
+    /// Asks for the edits that rename the symbol at `position`.
+    pub fn rename<F>(&self, view_id: ViewId, position: usize, new_name: &str, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.send_request("rename",
+                          &json!({
+                              "view_id": view_id,
+                              "position": position,
+                              "new_name": new_name,
+                          }),
+                          callback)
+    }
+
+    /// Runs the command of a code action that this plugin offered.
+    pub fn execute_command(&self, view_id: ViewId, command: &rpc::Command) {
+        self.peer.send_rpc_notification("execute_command",
+                                        &json!({
+                                            "view_id": view_id,
+                                            "command": command,
+                                        }))
+    }
 
     pub fn toggle_tracing(&self, enabled: bool) {
         self.peer.send_rpc_notification("tracing_config",
@@ -135,6 +246,53 @@ impl Plugin {
                                                 "position": position}))
     }
 
+    pub fn get_definition(&self, view_id: ViewId, request_id: usize, position: usize,
+                          kind: DefinitionKind) {
+        self.peer.send_rpc_notification("get_definition",
+                                        &json!({
+                                            "view_id": view_id,
+                                            "request_id": request_id,
+                                            "position": position,
+                                            "kind": kind,
+                                        }))
+    }
+
+    pub fn format_document(&self, view_id: ViewId, request_id: usize) {
+        self.peer.send_rpc_notification("format_document",
+                                        &json!({
+                                            "view_id": view_id,
+                                            "request_id": request_id,
+                                        }))
+    }
+
+    /// Sends a request, counting the time until it is answered against the
+    /// plugin's CPU budget.
+    fn send_request<F>(&self, method: &str, params: &Value, callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        let budget = self.cpu_budget.clone();
+        if let Some(ref budget) = budget {
+            budget.request_started();
+        }
+        self.peer.send_rpc_request_async(method, params, Box::new(move |response| {
+            if let Some(budget) = budget {
+                budget.request_finished();
+            }
+            callback(response)
+        }))
+    }
+
+    pub fn streaming_request(&self, view_id: ViewId, token: &str, method: &str,
+                             params: &Value) {
+        self.peer.send_rpc_notification("streaming_request",
+                                        &json!({
+                                            "view_id": view_id,
+                                            "token": token,
+                                            "method": method,
+                                            "params": params,
+                                        }))
+    }
+
     pub fn dispatch_command(&self, view_id: ViewId, method: &str, params: &Value) {
         self.peer.send_rpc_notification("custom_command", 
                                         &json!({"view_id": view_id,
@@ -150,10 +308,10 @@ pub(crate) fn start_plugin_process(plugin_desc: Arc<PluginDescription>,
         .name(format!("<{}> core host thread", &plugin_desc.name))
         .spawn(move || {
             info!("starting plugin {}", &plugin_desc.name);
-            let child = ProcCommand::new(&plugin_desc.exec_path)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn();
+            let mut command = ProcCommand::new(&plugin_desc.exec_path);
+            command.stdin(Stdio::piped()).stdout(Stdio::piped());
+            limits::limit_memory(&mut command, &plugin_desc.resource_limits);
+            let child = command.spawn();
 
             match child {
                 Ok(mut child) => {
@@ -163,7 +321,9 @@ pub(crate) fn start_plugin_process(plugin_desc: Arc<PluginDescription>,
                     let peer: RpcPeer = Box::new(looper.get_raw_peer());
                     let name = plugin_desc.name.clone();
                     peer.send_rpc_notification("ping", &Value::Array(Vec::new()));
-                    let plugin = Plugin { peer, process: child, name, id };
+                    let cpu_budget = CpuBudget::watch(child.id(), &plugin_desc.resource_limits);
+                    let plugin = Plugin { peer, process: child, name, id,
+                                          capabilities: Vec::new(), cpu_budget };
 
                     // set tracing immediately
                     if xi_trace::is_enabled() {
@@ -14,6 +14,7 @@
 
 //! RPC types, corresponding to protocol requests, notifications & responses.
 
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::borrow::Borrow;
 
@@ -24,6 +25,16 @@ use serde_json::{self, Value};
 use xi_rope::rope::{RopeDelta, Rope, LinesMetric};
 use xi_rpc::RemoteError;
 use super::PluginPid;
+pub use code_actions::{CodeAction, CodeActionKind, Command, WorkspaceEdit};
+pub use definition::{DefinitionKind, Location};
+pub use diagnostics::{DiagnosticSeverity, DiagnosticSpan};
+pub use document_highlight::{DocumentHighlight, HighlightKind};
+pub use formatting::{EditPosition, TextEdit};
+pub use gutter::GutterAnnotation;
+pub use inlay_hints::{InlayHint, InlayHintKind};
+pub use rename::RenameRange;
+pub use syntax_context::SyntaxContext;
+pub use workspace_symbols::{SymbolInfo, SymbolKind};
 use syntax::LanguageId;
 use tabs::{BufferIdentifier, ViewId};
 use config::Table;
@@ -96,6 +107,38 @@ pub struct EmptyStruct {}
 pub enum HostRequest {
     Update(PluginUpdate),
     CollectTrace(EmptyStruct),
+    GetInlayHints { view_id: ViewId, first_line: usize, last_line: usize },
+    /// Asks a symbol provider for the symbols defined in the buffer.
+    GetDocumentSymbols { view_id: ViewId },
+    /// Asks a code action provider for the actions it offers for the range
+    /// from `start` to `end`.
+    GetCodeActions { view_id: ViewId, start: usize, end: usize },
+    /// Asks a document highlight provider for the references to the symbol
+    /// at `position`.
+    GetDocumentHighlights { view_id: ViewId, position: usize },
+    /// Asks a rename provider what a rename at `position` would change.
+    PrepareRename { view_id: ViewId, position: usize },
+    /// Asks a rename provider for the edits that rename the symbol at
+    /// `position` to `new_name`, in every file.
+    Rename { view_id: ViewId, position: usize, new_name: String },
+    /// Sent once at startup; the plugin answers with its `PluginCapability`s.
+    Capabilities(EmptyStruct),
+}
+
+/// The optional features a plugin provides. The core only sends the requests
+/// for a feature to the plugins that have claimed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginCapability {
+    HoverProvider,
+    CompletionProvider,
+    DefinitionProvider,
+    InlayHintProvider,
+    FormattingProvider,
+    DocumentSymbolProvider,
+    CodeActionProvider,
+    RenameProvider,
+    DocumentHighlightProvider,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -110,6 +153,15 @@ pub enum HostNotification {
     NewBuffer { buffer_info: Vec<PluginBufferInfo> },
     DidClose { view_id: ViewId },
     GetHover { view_id: ViewId, request_id: usize, position: usize },
+    GetDefinition { view_id: ViewId, request_id: usize, position: usize, kind: DefinitionKind },
+    /// A request answered by `partial_result` notifications with the same
+    /// `token`, followed by `complete`.
+    StreamingRequest { view_id: ViewId, token: String, method: String, params: Value },
+    /// Asks a formatting provider to reformat the buffer; it answers with
+    /// `format_edits`.
+    FormatDocument { view_id: ViewId, request_id: usize },
+    /// Runs the command of a code action that the plugin offered.
+    ExecuteCommand { view_id: ViewId, command: Command },
     Shutdown(EmptyStruct),
     TracingConfig {enabled: bool},
 }
@@ -171,6 +223,8 @@ pub enum PluginRequest {
     GetData { start: usize, unit: TextUnit, max_size: usize, rev: u64 },
     LineCount,
     GetSelections,
+    /// Returns the `SyntaxContext` at `offset` in the current revision.
+    GetSyntaxContext { offset: usize },
 }
 
 
@@ -179,14 +233,40 @@ pub enum PluginRequest {
 #[serde(tag = "method", content = "params")]
 /// RPC commands sent from plugins.
 pub enum PluginNotification {
-    AddScopes { scopes: Vec<Vec<String>> },
+    /// Adds scope stacks to the plugin's layer, optionally setting the
+    /// priority of its styles.
+    AddScopes {
+        scopes: Vec<Vec<String>>,
+        #[serde(default)]
+        priority: Option<u16>,
+    },
     UpdateSpans { start: usize, len: usize, spans: Vec<ScopeSpan>, rev: u64 },
     Edit { edit: PluginEdit },
     Alert { msg: String },
     AddStatusItem { key: String, value: String, alignment: String },
     UpdateStatusItem { key: String, value: String  },
     RemoveStatusItem { key: String },
+    /// Registers the plugin as a hover provider for the view; `get_hover`
+    /// is only sent to registered providers.
+    RegisterHoverProvider,
     ShowHover { request_id: usize, result: Result<Hover, RemoteError> },
+    /// Registers the plugin as a definition provider for the view.
+    RegisterDefinitionProvider,
+    ShowDefinition { request_id: usize, result: Result<Location, RemoteError> },
+    /// Replaces the plugin's gutter annotations, keyed by line.
+    SetGutterAnnotations { lines: BTreeMap<usize, Vec<GutterAnnotation>> },
+    AddDiagnostics { spans: Vec<DiagnosticSpan> },
+    /// Removes the diagnostics the plugin has added from `source`.
+    ClearDiagnostics { source: String },
+    ShowCompletions { list: CompletionList },
+    HideCompletions,
+    /// Reports that the plugin is working on `task`, or, if it is `None`,
+    /// that it has finished.
+    ReportProgress { task: Option<String>, message: Option<String> },
+    PartialResult { token: String, value: Value },
+    Complete { token: String, result: Result<Value, RemoteError> },
+    /// The edits that format the document, answering `format_document`.
+    FormatEdits { request_id: usize, result: Result<Vec<TextEdit>, RemoteError> },
 }
 
 /// Range expressed in terms of PluginPosition. Meant to be sent from
@@ -206,6 +286,40 @@ pub struct Hover {
     pub range: Option<Range>
 }
 
+/// The kind of thing a completion inserts, for choosing an icon.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionKind {
+    Text,
+    Keyword,
+    Function,
+    Method,
+    Variable,
+    Field,
+    Type,
+    Module,
+    Snippet,
+}
+
+/// A single completion offered to the user.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct CompletionItem {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub insert_text: String,
+    pub detail: Option<String>,
+}
+
+/// Completions sent from plugin to core, and forwarded to the frontend.
+/// Accepting an item replaces the text from `trigger_offset` to the caret.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct CompletionList {
+    pub items: Vec<CompletionItem>,
+    pub trigger_offset: usize,
+}
+
 /// Common wrapper for plugin-originating RPCs.
 pub struct PluginCommand<T> {
     pub view_id: ViewId,
@@ -349,6 +463,36 @@ mod tests {
         assert_eq!(val.syntax, "toml".into());
     }
 
+    #[test]
+    fn test_capabilities() {
+        let json = r#"{"method": "capabilities", "params": {}}"#;
+        let de: HostRequest = serde_json::from_str(json).unwrap();
+        match de {
+            HostRequest::Capabilities(..) => (),
+            other => panic!("{:?}", other),
+        }
+        let json = r#"["hover_provider", "inlay_hint_provider"]"#;
+        let de: Vec<PluginCapability> = serde_json::from_str(json).unwrap();
+        assert_eq!(de, vec![PluginCapability::HoverProvider, PluginCapability::InlayHintProvider]);
+    }
+
+    #[test]
+    fn test_report_progress() {
+        let json = r#"{"method": "report_progress", "params": {"view_id": "view-id-1",
+            "plugin_id": 42, "task": "indexing", "message": null}}"#;
+        let de: PluginCommand<PluginNotification> = serde_json::from_str(json).unwrap();
+        match de.cmd {
+            PluginNotification::ReportProgress { task: Some(ref task), message: None }
+                if task == "indexing" => (),
+            _ => panic!("{:?}", de.cmd),
+        }
+        let status = ::plugins::PluginStatus::Busy("indexing".into());
+        assert_eq!(serde_json::to_value(&status).unwrap(),
+                   json!({"type": "busy", "value": "indexing"}));
+        assert_eq!(serde_json::to_value(&::plugins::PluginStatus::Idle).unwrap(),
+                   json!({"type": "idle"}));
+    }
+
     #[test]
     fn test_de_plugin_rpc() {
         let json = r#"{"method": "alert", "params": {"view_id": "view-id-1", "plugin_id": 42, "msg": "ahhh!"}}"#;
@@ -0,0 +1,155 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Recovery from plugin crashes.
+//!
+//! A plugin that exits without being asked to is restarted after a delay
+//! that grows with each consecutive crash. A plugin that keeps crashing is
+//! disabled until it is started again explicitly. The delays, and what
+//! counts as crashing too often, are set by each plugin's `RestartPolicy`.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use super::{PluginName, PluginPid, RestartPolicy};
+
+/// What to do about a crashed plugin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrashAction {
+    /// Restart it once this time has passed.
+    Restart(Instant),
+    Disable,
+}
+
+struct PendingRestart {
+    due: Instant,
+    name: PluginName,
+    /// The instance that crashed.
+    crashed: PluginPid,
+}
+
+/// Tracks plugin crashes, and the restarts they call for.
+#[derive(Default)]
+pub struct PluginSupervisor {
+    /// The times of each plugin's recent crashes.
+    crashes: HashMap<PluginName, Vec<Instant>>,
+    pending: Vec<PendingRestart>,
+    /// The crashed instances of plugins that are being restarted, whose
+    /// requests should be sent again to the new instance once it connects.
+    restarting: HashMap<PluginName, PluginPid>,
+    disabled: HashSet<PluginName>,
+}
+
+impl PluginSupervisor {
+    /// Records that the instance `id` of `name`, restarted according to
+    /// `policy`, exited unexpectedly at `now`.
+    pub fn plugin_crashed(&mut self, name: &str, id: PluginPid, policy: &RestartPolicy,
+                          now: Instant) -> CrashAction {
+        self.pending.retain(|r| r.name != name);
+        let window = Duration::from_millis(policy.crash_window_ms);
+        let n_crashes = {
+            let crashes = self.crashes.entry(name.to_owned()).or_default();
+            crashes.retain(|&time| now.duration_since(time) < window);
+            crashes.push(now);
+            crashes.len()
+        };
+        if n_crashes >= policy.max_crashes {
+            self.crashes.remove(name);
+            self.disabled.insert(name.to_owned());
+            return CrashAction::Disable;
+        }
+        let due = match policy.backoff_ms.get(n_crashes - 1)
+            .or_else(|| policy.backoff_ms.last()) {
+            Some(&delay) => now + Duration::from_millis(delay),
+            None => now,
+        };
+        self.pending.push(PendingRestart { due, name: name.to_owned(), crashed: id });
+        CrashAction::Restart(due)
+    }
+
+    /// Returns the names of the plugins whose restart is due at `now`.
+    pub fn take_due_restarts(&mut self, now: Instant) -> Vec<PluginName> {
+        let (due, pending): (Vec<_>, Vec<_>) = self.pending.drain(..).partition(|r| r.due <= now);
+        self.pending = pending;
+        due.into_iter().map(|PendingRestart { name, crashed, .. }| {
+            self.restarting.insert(name.clone(), crashed);
+            name
+        }).collect()
+    }
+
+    /// Returns the crashed instance that a newly connected instance of
+    /// `name` replaces, if it was restarted.
+    pub fn take_restarted(&mut self, name: &str) -> Option<PluginPid> {
+        self.restarting.remove(name)
+    }
+
+    pub fn is_disabled(&self, name: &str) -> bool {
+        self.disabled.contains(name)
+    }
+
+    /// Forgets the crashes of `name`, which has been started or stopped
+    /// explicitly, enabling it again. Returns the crashed instance whose
+    /// restart this cancels, if any.
+    pub fn reset(&mut self, name: &str) -> Option<PluginPid> {
+        self.crashes.remove(name);
+        self.disabled.remove(name);
+        let cancelled = self.pending.iter().position(|r| r.name == name)
+            .map(|ix| self.pending.remove(ix).crashed);
+        cancelled.or_else(|| self.restarting.remove(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_then_disable() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        let policy = RestartPolicy::default();
+        let mut supervisor = PluginSupervisor::default();
+        assert_eq!(supervisor.plugin_crashed("p", PluginPid(1), &policy, start),
+                   CrashAction::Restart(secs(1)));
+        assert!(supervisor.take_due_restarts(start).is_empty());
+        assert_eq!(supervisor.take_due_restarts(secs(1)), vec!["p".to_string()]);
+        assert_eq!(supervisor.take_restarted("p"), Some(PluginPid(1)));
+        assert_eq!(supervisor.take_restarted("p"), None);
+
+        assert_eq!(supervisor.plugin_crashed("p", PluginPid(2), &policy, secs(2)),
+                   CrashAction::Restart(secs(4)));
+        assert_eq!(supervisor.plugin_crashed("p", PluginPid(3), &policy, secs(5)),
+                   CrashAction::Restart(secs(9)));
+        assert_eq!(supervisor.plugin_crashed("p", PluginPid(4), &policy, secs(10)),
+                   CrashAction::Disable);
+        assert!(supervisor.is_disabled("p"));
+        assert_eq!(supervisor.reset("p"), None);
+        assert!(!supervisor.is_disabled("p"));
+    }
+
+    #[test]
+    fn crashes_outside_window_are_forgotten() {
+        let start = Instant::now();
+        let policy = RestartPolicy { max_crashes: 2, ..Default::default() };
+        let mut supervisor = PluginSupervisor::default();
+        supervisor.plugin_crashed("p", PluginPid(1), &policy, start);
+        let later = start + Duration::from_secs(61);
+        assert_eq!(supervisor.plugin_crashed("p", PluginPid(2), &policy, later),
+                   CrashAction::Restart(later + Duration::from_secs(1)));
+        // an explicit start cancels the pending restart
+        assert_eq!(supervisor.reset("p"), Some(PluginPid(2)));
+        assert!(supervisor.take_due_restarts(start + Duration::from_secs(100)).is_empty());
+    }
+}
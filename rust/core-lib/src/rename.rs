@@ -0,0 +1,89 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Renaming a symbol everywhere it is used, with the edits found by a
+//! rename provider, such as a plugin backed by a language server.
+
+use std::fmt;
+
+use xi_rpc::RemoteError;
+
+use code_actions::CodeActionError;
+use formatting::EditPosition;
+
+/// The symbol that a rename at a position would change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RenameRange {
+    pub start: EditPosition,
+    pub end: EditPosition,
+    /// The name to suggest replacing, if it is not the text of the range.
+    pub placeholder: Option<String>,
+}
+
+// The codes of the errors sent to the frontend, after those of code actions.
+pub const NO_PROVIDER: i64 = 16;
+pub const INVALID_NAME: i64 = 17;
+pub const PROVIDER_FAILED: i64 = 18;
+pub const READ_ONLY: i64 = 19;
+pub const INVALID_EDIT: i64 = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenameError {
+    /// No plugin for the buffer provides renaming.
+    NoProvider,
+    /// The new name is empty.
+    InvalidName,
+    /// The provider failed to find the edits.
+    Provider(String),
+    /// A buffer to edit is read-only.
+    ReadOnly,
+    /// An edit was outside its buffer or overlapped another, or its file
+    /// could not be opened.
+    InvalidEdit(String),
+}
+
+impl From<CodeActionError> for RenameError {
+    fn from(src: CodeActionError) -> RenameError {
+        match src {
+            CodeActionError::ReadOnly => RenameError::ReadOnly,
+            CodeActionError::InvalidEdit(msg) => RenameError::InvalidEdit(msg),
+            other => RenameError::InvalidEdit(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenameError::NoProvider => write!(f, "No rename provider for this buffer"),
+            RenameError::InvalidName => write!(f, "The new name is empty"),
+            RenameError::Provider(ref msg) => write!(f, "Rename failed: {}", msg),
+            RenameError::ReadOnly => write!(f, "Buffer is read-only"),
+            RenameError::InvalidEdit(ref msg) => write!(f, "Invalid rename edit: {}", msg),
+        }
+    }
+}
+
+impl From<RenameError> for RemoteError {
+    fn from(src: RenameError) -> RemoteError {
+        let code = match src {
+            RenameError::NoProvider => NO_PROVIDER,
+            RenameError::InvalidName => INVALID_NAME,
+            RenameError::Provider(_) => PROVIDER_FAILED,
+            RenameError::ReadOnly => READ_ONLY,
+            RenameError::InvalidEdit(_) => INVALID_EDIT,
+        };
+        RemoteError::custom(code, src.to_string(), None)
+    }
+}
@@ -26,6 +26,8 @@ use serde::de::{self, Deserialize, Deserializer};
 use serde::ser::{self, Serialize, Serializer};
 
 use config::{Table, ConfigDomainExternal};
+use find::{FindOptions, SearchScope};
+use macros::Registers;
 use plugins::PlaceholderRpc;
 use tabs::ViewId;
 use view::Size;
@@ -174,11 +176,21 @@ pub enum CoreNotification {
     /// # }
     /// ```
     Plugin(PluginNotification),
-    /// Tells `xi-core` to close the specified view.
-    CloseView { view_id: ViewId },
+    /// Tells `xi-core` to close the specified view. If the client handles
+    /// `confirm_close`, the last view of a buffer with unsaved changes is
+    /// not closed unless `force`d; the client is sent `confirm_close`
+    /// instead.
+    CloseView {
+        view_id: ViewId,
+        #[serde(default)]
+        force: bool,
+    },
     /// Tells `xi-core` to save the contents of the specified view's
     /// buffer to the specified path.
     Save { view_id: ViewId, file_path: String },
+    /// Tells `xi-core` to replace the contents of the specified view's
+    /// buffer with the contents of its file on disk.
+    Reload { view_id: ViewId },
     /// Tells `xi-core` to set the theme.
     SetTheme { theme_name: String },
     /// Notifies `xi-core` that the client has started.
@@ -188,6 +200,10 @@ pub enum CoreNotification {
         /// Path to additional plugins, included by the client.
         #[serde(default)]
         client_extras_dir: Option<PathBuf>,
+        /// Whether the client handles `confirm_close`. If it does not,
+        /// `close_view` always closes the view.
+        #[serde(default)]
+        confirm_close: bool,
     },
     /// Updates the user's config for the given domain. Where keys in
     /// `changes` are `null`, those keys are cleared in the user config
@@ -199,6 +215,9 @@ pub enum CoreNotification {
     /// represents non-persistent view-specific settings, such as when
     /// a user manually changes whitespace settings for a given view.
     ModifyUserConfig { domain: ConfigDomainExternal, changes: Table },
+    /// Binds `key` to `preferred_command` alone, overriding the other
+    /// bindings of the key in the user's general config.
+    ResolveConflict { key: String, preferred_command: String },
     /// Control whether the tracing infrastructure is enabled.
     /// This propagates to all peers that should respond by toggling its own
     /// infrastructure on/off.
@@ -207,7 +226,27 @@ pub enum CoreNotification {
     /// CoreRequest::CollectTrace to all peers to collect the samples.
     SaveTrace { destination: PathBuf, frontend_samples: Value },
     /// Tells `xi-core` to set the language id for the view.
-    SetLanguage { view_id: ViewId, language_id: LanguageId }
+    SetLanguage { view_id: ViewId, language_id: LanguageId },
+    /// Sets the directory up to which directory config files are looked
+    /// for, or clears it.
+    SetWorkspaceRoot { path: Option<PathBuf> },
+    /// Searches the symbols of the files in the workspace; the results are
+    /// sent with `workspace_symbols`.
+    SearchWorkspaceSymbols { request_id: usize, query: String },
+    /// Searches the paths of the files under `root`, or the working
+    /// directory, for a file to open; the results are sent with
+    /// `fuzzy_file_results`.
+    OpenFileFuzzy {
+        request_id: usize,
+        query: String,
+        #[serde(default)]
+        root: Option<PathBuf>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Replaces the recorded macros, as with those returned by `get_macros`
+    /// and saved by the frontend.
+    SetMacros { macros: Registers },
 }
 
 /// The requests which make up the base of the protocol.
@@ -249,12 +288,49 @@ pub enum CoreRequest {
     /// Returns the view identifier that should be used to interact
     /// with the newly created view.
     NewView { file_path: Option<String> },
+    /// Tells `xi-core` to create another view of the buffer shown by
+    /// `view_id`, with its own selections and scroll position.
+    ///
+    /// Returns the view identifier of the new view.
+    SplitView { view_id: ViewId },
     /// Returns the current collated config object for the given view.
     GetConfig { view_id: ViewId },
     /// Returns the contents of the buffer for a given `ViewId`.
     /// In the future this might also be used to return structured data (such
     /// as for printing).
     DebugGetContents { view_id: ViewId },
+    /// Returns the names of the available themes.
+    ListThemes {},
+    /// Returns the entries of the clipboard history, most recent first.
+    GetClipboardHistory {},
+    /// Returns the recorded macros, by register.
+    GetMacros {},
+    /// Returns `true` if any open buffer has unsaved changes.
+    HasUnsavedChanges {},
+    /// Saves the files open in the workspace, and the selections, folds and
+    /// scroll position of each of their views, to the file at `path`.
+    SessionSave { path: PathBuf },
+    /// Opens the views saved to the session file at `path`, as they were.
+    ///
+    /// Returns the identifier and file of each view opened.
+    SessionRestore { path: PathBuf },
+    /// Returns the commands of the command palette; with a `query`, those
+    /// matching it, best match first.
+    GetAllCommands {
+        #[serde(default)]
+        query: Option<String>,
+    },
+    /// Runs the palette command `name` in `view_id`, with `args` as the
+    /// params of its edit method.
+    ExecuteCommandByName {
+        view_id: ViewId,
+        name: String,
+        #[serde(default)]
+        args: Value,
+    },
+    /// Returns the problems with the settings in a TOML config, such as
+    /// the contents of a config file being edited.
+    ValidateConfig { toml: String },
 }
 
 /// A helper type, which extracts the `view_id` field from edit
@@ -293,7 +369,7 @@ pub struct EditCommand<T> {
 }
 
 /// An enum representing touch and mouse gestures applied to the text.
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum GestureType {
     PointSelect,
@@ -303,6 +379,8 @@ pub enum GestureType {
     WordSelect,
     MultiLineSelect,
     MultiWordSelect,
+    /// Starts a rectangular selection, which is extended by dragging.
+    RectSelect,
 }
 
 /// An inclusive range.
@@ -312,7 +390,7 @@ pub enum GestureType {
 /// Several core protocol commands use a params array to pass arguments
 /// which are named, internally. this type use custom Serialize /
 /// Deserialize impls to accommodate this.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct LineRange {
     pub first: i64,
     pub last: i64,
@@ -321,7 +399,7 @@ pub struct LineRange {
 /// A mouse event. See the note for [`LineRange`].
 ///
 /// [`LineRange`]: enum.LineRange.html
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct MouseAction {
     pub line: u64,
     pub column: u64,
@@ -329,15 +407,22 @@ pub struct MouseAction {
     pub click_count: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct Position {
     pub line: usize,
     pub column: usize
 }
 
+/// The text from `start` to `end`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct PositionRange {
+    pub start: Position,
+    pub end: Position,
+}
+
 /// Represents how the current selection is modified (used by find
 /// operations).
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum SelectionModifier {
     None,
@@ -354,12 +439,15 @@ impl Default for SelectionModifier {
 ///
 /// Alongside the [`EditRequest`] members, these commands constitute
 /// the API for interacting with a particular window and document.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "method", content = "params")]
 pub enum EditNotification {
     Insert { chars: String },
     Paste { chars: String },
+    /// Pastes the `index`-th most recent entry of the clipboard history,
+    /// 0 being the most recent.
+    PasteFromHistory { index: usize },
     DeleteForward,
     DeleteBackward,
     DeleteWordForward,
@@ -399,11 +487,40 @@ pub enum EditNotification {
     ScrollPageDown,
     PageDownAndModifySelection,
     SelectAll,
+    /// Turns each selection into a caret at its end, keeping every cursor.
+    Deselect,
+    /// Grows each selection to the next enclosing pair of brackets.
+    ExpandSelection,
+    /// Undoes the last `ExpandSelection`.
+    ShrinkSelection,
     AddSelectionAbove,
     AddSelectionBelow,
     Scroll(LineRange),
+    /// Sets the number of columns and lines the view shows, keeping its
+    /// scroll position.
+    SetViewport { width: usize, height: usize },
+    /// Shows the lines between `start_line` and `end_line` as a single
+    /// placeholder line.
+    FoldRange { start_line: usize, end_line: usize },
+    /// Unfolds the folds overlapping the lines from `start_line` to
+    /// `end_line`.
+    UnfoldRange { start_line: usize, end_line: usize },
     Resize(Size),
-    GotoLine { line: u64 },
+    GotoLine {
+        line: u64,
+        #[serde(default)]
+        col: u64,
+    },
+    /// Sets the mark `name` at the caret. Marks follow the text they are
+    /// set at as the buffer is edited.
+    SetMark { name: char },
+    /// Moves the caret to the mark `name`; with `line`, to the first
+    /// non-blank character of its line instead.
+    JumpToMark {
+        name: char,
+        #[serde(default)]
+        line: bool,
+    },
     RequestLines(LineRange),
     Yank,
     Transpose,
@@ -412,13 +529,17 @@ pub enum EditNotification {
     Gesture { line: u64, col: u64, ty: GestureType},
     Undo,
     Redo,
+    UndoAll,
+    RedoAll,
     Find {
         chars: String,
         case_sensitive: bool,
         #[serde(default)]
         regex: bool,
         #[serde(default)]
-        whole_words: bool
+        whole_words: bool,
+        #[serde(default)]
+        scope: SearchScope,
     },
     FindNext {
         #[serde(default)]
@@ -462,8 +583,47 @@ pub enum EditNotification {
     ReplaceAll,
     SelectionForReplace,
     RequestHover { request_id: usize, position: Option<Position> },
+    GotoDefinition { request_id: usize, position: Option<Position> },
+    GotoTypeDefinition { request_id: usize, position: Option<Position> },
+    PeekDefinition { request_id: usize, position: Option<Position> },
+    /// Highlights the references to the symbol at `position`, until the
+    /// selection changes.
+    DocumentHighlight { position: Option<Position> },
+    /// Asks what a rename at `position` would change; answered with
+    /// `prepare_rename_result`.
+    PrepareRename { request_id: usize, position: Option<Position> },
+    /// Renames the symbol at `position` everywhere it is used; answered
+    /// with `rename_result`.
+    RenameSymbol { request_id: usize, position: Option<Position>, new_name: String },
     SelectionIntoLines,
     DuplicateLine,
+    MoveLineUp,
+    MoveLineDown,
+    MoveUpAndModifyRectSelection,
+    MoveDownAndModifyRectSelection,
+    MoveLeftAndModifyRectSelection,
+    MoveRightAndModifyRectSelection,
+    JoinLines,
+    SortLines,
+    SortLinesCaseInsensitive,
+    ToggleComment,
+    SelectWord,
+    SelectParagraph,
+    JumpToUndoBranch { id: usize },
+    /// Sets whether the buffer refuses edits.
+    SetReadOnly { read_only: bool },
+    /// Inserts the item at `index` of the completions last shown.
+    AcceptCompletion { index: usize },
+    /// Inserts a snippet and selects its first tab stop.
+    ExpandSnippet { snippet: String },
+    /// Starts recording the edit commands sent to any view into `register`,
+    /// finishing any recording under way.
+    StartMacroRecord { register: char },
+    /// Finishes recording, replacing the macro in the register.
+    StopMacroRecord,
+    /// Plays the macro in `register` `count` times, at most
+    /// `MAX_PLAY_COUNT`; the edits it makes each time are undone together.
+    PlayMacro { register: char, count: usize },
 }
 
 /// The edit related requests.
@@ -477,6 +637,39 @@ pub enum EditRequest {
     /// Copies the active selection, returning their contents or
     /// or `Null` if the selection was empty.
     Copy,
+    /// Returns the current undo group, and every live undo group with
+    /// its parent and children.
+    ListUndoBranches,
+    /// Returns the number of words and characters in the buffer.
+    DocumentStats,
+    /// Returns the number of non-overlapping matches of `pattern` in the
+    /// buffer, without changing the selections or the search.
+    CountOccurrences {
+        pattern: String,
+        #[serde(default)]
+        options: FindOptions,
+    },
+    /// Returns the known inlay hints for the lines `first_line..=last_line`,
+    /// and asks plugins for up-to-date ones, which arrive with line updates.
+    RequestInlayHints { first_line: usize, last_line: usize },
+    /// Asks the formatting provider to reformat the buffer. Fails straight
+    /// away if there is none; the edits are applied when they arrive.
+    FormatDocument,
+    /// Returns the code actions for `range`, or for the last selection
+    /// region, if every plugin has offered its actions. If not, returns
+    /// `Null`, and the actions are sent with `code_actions` once they have.
+    GetCodeActions { range: Option<PositionRange> },
+    /// Applies the action at `action_index` of those last returned by
+    /// `GetCodeActions`, and returns the views opened for its edits.
+    ApplyCodeAction { action_index: usize },
+    /// Returns the minimap of the document, at most `target_width` cells
+    /// by `target_height` rows, if it is cached. If not, returns `Null`, and
+    /// the minimap is sent with `minimap` once computed.
+    GetMinimap { target_width: usize, target_height: usize },
+    /// Returns the lines defining what encloses the first visible line, or
+    /// `Null` if nothing does; from then on, they are sent again with
+    /// `sticky_header` whenever the first visible line changes.
+    GetStickyHeader,
 }
 
 
@@ -488,6 +681,10 @@ pub enum PluginNotification {
     Start { view_id: ViewId, plugin_name: String },
     Stop { view_id: ViewId, plugin_name: String },
     PluginRpc { view_id: ViewId, receiver: String, rpc: PlaceholderRpc },
+    /// Sends a streaming request to the named plugin; its results are sent
+    /// back with `plugin_partial_result` and `plugin_request_complete`.
+    PluginStreamingRpc { view_id: ViewId, receiver: String, request_id: usize,
+                         method: String, params: Value },
 }
 
 // Serialize / Deserialize
@@ -28,6 +28,36 @@ use xi_rope::rope::RopeInfo;
 /// 1 each. It will change.
 pub type HorizPos = usize;
 
+/// A rectangular (column) selection, spanning the same columns on each of a
+/// range of lines. The start is the anchor and the end is the active corner,
+/// so either may be the smaller. Columns are display columns, with tabs
+/// expanded and wide characters counted as two, so that the rectangle is
+/// straight on screen; they may extend past the end of short lines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RectSel {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub start_col: usize,
+    pub end_col: usize,
+}
+
+impl RectSel {
+    /// Returns a rectangle with no width or height at the given position.
+    pub fn caret(line: usize, col: usize) -> Self {
+        RectSel { start_line: line, end_line: line, start_col: col, end_col: col }
+    }
+
+    /// The first line of the rectangle.
+    pub fn min_line(&self) -> usize {
+        min(self.start_line, self.end_line)
+    }
+
+    /// The last line of the rectangle (inclusive).
+    pub fn max_line(&self) -> usize {
+        max(self.start_line, self.end_line)
+    }
+}
+
 /// A set of zero or more selection regions, representing a selection state.
 #[derive(Default, Debug, Clone)]
 pub struct Selection {
@@ -60,6 +90,15 @@ impl Selection {
         self.regions[0].start = self.regions[0].end;
     }
 
+    /// Reduce the selection to its primary (last) region, leaving that
+    /// region itself unchanged.
+    pub fn collapse_to_primary(&mut self) {
+        if let Some(primary) = self.regions.pop() {
+            self.regions.clear();
+            self.regions.push(primary);
+        }
+    }
+
     // The smallest index so that offset > region.max() for all preceding
     // regions.
     pub fn search(&self, offset: usize) -> usize {
@@ -109,6 +148,34 @@ impl Selection {
         }
     }
 
+    /// Removes the given region from the selection, if it is present.
+    /// Returns `true` if a region was removed.
+    pub fn remove_region(&mut self, region: SelRegion) -> bool {
+        let ix = self.search(region.min());
+        let found = self.regions[ix..].iter()
+            .take_while(|r| r.min() <= region.max())
+            .position(|r| *r == region);
+        match found {
+            Some(offset) => {
+                self.regions.remove(ix + offset);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Computes a new selection by applying `f` to each region. Regions that
+    /// overlap after the mapping are merged, as with `add_region`.
+    pub fn map_regions<F>(&self, f: F) -> Selection
+        where F: Fn(SelRegion) -> SelRegion
+    {
+        let mut result = Selection::new();
+        for &region in &self.regions {
+            result.add_region(f(region));
+        }
+        result
+    }
+
     /// Gets a slice of regions that intersect the given range. Regions that
     /// merely touch the range at the edges are also included, so it is the
     /// caller's responsibility to further trim them, in particular to only
@@ -515,4 +582,42 @@ mod tests {
         s.add_region(r(8, 2));
         assert_eq!(s.deref(), &[r(9, 1)]);
     }
+
+    #[test]
+    fn remove_region() {
+        let mut s = Selection::new();
+        s.add_region(r(1, 1));
+        s.add_region(r(3, 5));
+        s.add_region(r(5, 5));
+        assert_eq!(s.deref(), &[r(1, 1), r(3, 5)]);
+        assert!(!s.remove_region(r(5, 5)));
+        assert!(s.remove_region(r(3, 5)));
+        assert_eq!(s.deref(), &[r(1, 1)]);
+        assert!(s.remove_region(r(1, 1)));
+        assert!(s.is_empty());
+        assert!(!s.remove_region(r(1, 1)));
+    }
+
+    #[test]
+    fn collapse_to_primary() {
+        let mut s = Selection::new();
+        s.collapse_to_primary();
+        assert!(s.is_empty());
+        s.add_region(r(1, 2));
+        s.add_region(r(7, 4));
+        s.collapse_to_primary();
+        assert_eq!(s.deref(), &[r(7, 4)]);
+    }
+
+    #[test]
+    fn map_regions() {
+        let mut s = Selection::new();
+        s.add_region(r(1, 1));
+        s.add_region(r(3, 4));
+        s.add_region(r(8, 8));
+        let shifted = s.map_regions(|region| r(region.start + 2, region.end + 2));
+        assert_eq!(shifted.deref(), &[r(3, 3), r(5, 6), r(10, 10)]);
+        let extended = s.map_regions(|region| r(region.start, region.end + 4));
+        assert_eq!(extended.deref(), &[r(1, 8), r(8, 12)]);
+    }
 }
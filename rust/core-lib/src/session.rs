@@ -0,0 +1,123 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sessions: the files open in the workspace, and the state of each of
+//! their views, saved to a JSON file so that they can be opened again as
+//! they were.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use xi_rpc::RemoteError;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Session {
+    /// The views of files, in the order they were opened.
+    pub views: Vec<ViewSession>,
+}
+
+/// The state of a view, as saved with a session.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ViewSession {
+    /// The file the view shows.
+    pub path: PathBuf,
+    /// The selections, as the offsets of their start and end, in order.
+    pub selections: Vec<(usize, usize)>,
+    /// The folded ranges of logical lines; see `View::folds`.
+    #[serde(default)]
+    pub folds: Vec<(usize, usize)>,
+    /// The first visible line.
+    #[serde(default)]
+    pub first_line: usize,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error, PathBuf),
+    /// The session file is not valid.
+    Format(serde_json::Error, PathBuf),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SessionError::Io(ref e, ref path) =>
+                write!(f, "Session file {}: {}", path.display(), e),
+            SessionError::Format(ref e, ref path) =>
+                write!(f, "Invalid session file {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl From<SessionError> for RemoteError {
+    fn from(src: SessionError) -> RemoteError {
+        let code = match src {
+            SessionError::Io(..) => 23,
+            SessionError::Format(..) => 24,
+        };
+        RemoteError::custom(code, src.to_string(), None)
+    }
+}
+
+impl Session {
+    /// Writes the session to `path`, replacing any file there.
+    pub fn save(&self, path: &Path) -> Result<(), SessionError> {
+        let file = File::create(path).map_err(|e| SessionError::Io(e, path.to_owned()))?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|e| SessionError::Format(e, path.to_owned()))
+    }
+
+    pub fn load(path: &Path) -> Result<Session, SessionError> {
+        let file = File::open(path).map_err(|e| SessionError::Io(e, path.to_owned()))?;
+        serde_json::from_reader(file).map_err(|e| SessionError::Format(e, path.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn save_and_load() {
+        let tmp = tempdir::TempDir::new("xi-test-session").unwrap();
+        let path = tmp.path().join("session.json");
+        let session = Session {
+            views: vec![ViewSession {
+                path: "src/main.rs".into(),
+                selections: vec![(4, 4), (10, 12)],
+                folds: vec![(1, 5)],
+                first_line: 3,
+            }],
+        };
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path).unwrap(), session);
+
+        File::create(&path).unwrap().write_all(b"{\"views\": 1}").unwrap();
+        match Session::load(&path) {
+            Err(SessionError::Format(..)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match Session::load(&tmp.path().join("missing.json")) {
+            Err(SessionError::Io(..)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snippets with tab stops, in the syntax used by VS Code and TextMate.
+//!
+//! `$1` or `${1}` is a tab stop, `${1:text}` a tab stop with placeholder
+//! text (which may itself contain tab stops), and `$0` the final caret
+//! position. Tab stops sharing a number are edited together. `\$`, `\}`
+//! and `\\` are literal characters.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use xi_rope::delta::{Delta, DeltaElement, Transformer};
+use xi_rope::rope::RopeInfo;
+
+/// A parsed snippet: the text to insert, and its tab stops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    /// Tab stops as `(number, start, end)`, with offsets into `text`.
+    pub tab_stops: Vec<(usize, usize, usize)>,
+}
+
+impl Snippet {
+    pub fn parse(source: &str) -> Snippet {
+        let mut snippet = Snippet { text: String::new(), tab_stops: Vec::new() };
+        snippet.parse_until(&mut source.chars().peekable(), None);
+        snippet
+    }
+
+    /// Appends text up to `end` (or the end of input) to the snippet.
+    fn parse_until(&mut self, chars: &mut Peekable<Chars>, end: Option<char>) {
+        while let Some(c) = chars.next() {
+            match c {
+                _ if Some(c) == end => return,
+                '\\' => match chars.peek().cloned() {
+                    Some(next @ '$') | Some(next @ '}') | Some(next @ '\\') => {
+                        chars.next();
+                        self.text.push(next);
+                    }
+                    _ => self.text.push('\\'),
+                },
+                '$' => self.parse_tab_stop(chars),
+                _ => self.text.push(c),
+            }
+        }
+    }
+
+    /// Parses what follows a `$`. Anything that isn't a tab stop is kept as
+    /// literal text.
+    fn parse_tab_stop(&mut self, chars: &mut Peekable<Chars>) {
+        let braced = chars.peek() == Some(&'{');
+        let mut lookahead = chars.clone();
+        if braced {
+            lookahead.next();
+        }
+        let digits: String = lookahead.clone().take_while(|c| c.is_ascii_digit()).collect();
+        // a number too large for a tab stop is literal text too
+        let number = match digits.parse() {
+            Ok(number) => number,
+            Err(_) => {
+                self.text.push('$');
+                return;
+            }
+        };
+        for _ in 0..digits.len() {
+            lookahead.next();
+        }
+        let start = self.text.len();
+        if braced {
+            match lookahead.next() {
+                Some('}') => (),
+                Some(':') => {
+                    *chars = lookahead;
+                    self.parse_until(chars, Some('}'));
+                    self.tab_stops.push((number, start, self.text.len()));
+                    return;
+                }
+                _ => {
+                    self.text.push('$');
+                    return;
+                }
+            }
+        }
+        *chars = lookahead;
+        self.tab_stops.push((number, start, start));
+    }
+}
+
+/// The tab stops of an expanded snippet, which follow edits to the buffer.
+pub struct SnippetSession {
+    /// The regions of each tab stop, in the order they are visited;
+    /// `$0`, or the end of the snippet, is last.
+    stops: Vec<Vec<(usize, usize)>>,
+    current: usize,
+}
+
+impl SnippetSession {
+    /// Starts a session for `snippet`, inserted at `offset`.
+    pub fn new(snippet: &Snippet, offset: usize) -> SnippetSession {
+        let mut numbers: Vec<usize> = snippet.tab_stops.iter()
+            .map(|&(number, _, _)| number)
+            .filter(|&number| number != 0)
+            .collect();
+        numbers.sort();
+        numbers.dedup();
+        numbers.push(0);
+        let mut stops: Vec<Vec<(usize, usize)>> = numbers.iter().map(|&number| {
+            snippet.tab_stops.iter()
+                .filter(|&&(n, _, _)| n == number)
+                .map(|&(_, start, end)| (offset + start, offset + end))
+                .collect()
+        }).collect();
+        if stops.last().unwrap().is_empty() {
+            let end = offset + snippet.text.len();
+            stops.last_mut().unwrap().push((end, end));
+        }
+        SnippetSession { stops, current: 0 }
+    }
+
+    /// The regions of the current tab stop.
+    pub fn current(&self) -> &[(usize, usize)] {
+        &self.stops[self.current]
+    }
+
+    /// Returns `true` if the current tab stop is the final one.
+    pub fn is_finished(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    /// Moves to the next tab stop, or back to the previous one.
+    pub fn advance(&mut self, forward: bool) {
+        if forward {
+            self.current = (self.current + 1).min(self.stops.len() - 1);
+        } else {
+            self.current = self.current.saturating_sub(1);
+        }
+    }
+
+    /// Moves the tab stops to follow `delta`. Returns `false`, meaning the
+    /// session should end, if any part of the edit was outside the tab stops.
+    pub fn apply_delta(&mut self, delta: &Delta<RopeInfo>) -> bool {
+        let inside = changed_regions(delta).iter().all(|&(changed_start, changed_end)| {
+            self.stops.iter().flat_map(|regions| regions.iter())
+                .any(|&(start, end)| start <= changed_start && changed_end <= end)
+        });
+        let mut transformer = Transformer::new(delta);
+        for regions in &mut self.stops {
+            for region in regions.iter_mut() {
+                // a tab stop grows when text is typed at either end
+                *region = (transformer.transform(region.0, false),
+                           transformer.transform(region.1, true));
+            }
+        }
+        inside
+    }
+}
+
+/// Returns the regions of the old text that `delta` deletes or inserts into.
+fn changed_regions(delta: &Delta<RopeInfo>) -> Vec<(usize, usize)> {
+    let mut regions = Vec::new();
+    let mut last_end = 0;
+    let mut inserted = false;
+    for el in &delta.els {
+        match *el {
+            DeltaElement::Copy(start, end) => {
+                if inserted || start > last_end {
+                    regions.push((last_end, start));
+                }
+                last_end = end;
+                inserted = false;
+            }
+            DeltaElement::Insert(_) => inserted = true,
+        }
+    }
+    if inserted || last_end < delta.base_len {
+        regions.push((last_end, delta.base_len));
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::interval::Interval;
+
+    #[test]
+    fn parse() {
+        let snippet = Snippet::parse("for ${1:i} in ${2:0..${3:n}} {\n\t$0\n}");
+        assert_eq!(snippet.text, "for i in 0..n {\n\t\n}");
+        assert_eq!(snippet.tab_stops, vec![(1, 4, 5), (3, 12, 13), (2, 9, 13), (0, 17, 17)]);
+
+        let snippet = Snippet::parse("\\$1 costs $5, ${x} \\} $");
+        assert_eq!(snippet.text, "$1 costs , ${x} } $");
+        assert_eq!(snippet.tab_stops, vec![(5, 9, 9)]);
+
+        let snippet = Snippet::parse("$99999999999999999999 ${99999999999999999999:x}");
+        assert_eq!(snippet.text, "$99999999999999999999 ${99999999999999999999:x}");
+        assert!(snippet.tab_stops.is_empty());
+    }
+
+    #[test]
+    fn session() {
+        let snippet = Snippet::parse("f(${1:a}, $2, $1)");
+        let mut session = SnippetSession::new(&snippet, 10);
+        assert_eq!(session.current(), &[(12, 13), (17, 17)]);
+        session.advance(true);
+        assert_eq!(session.current(), &[(15, 15)]);
+        session.advance(false);
+        session.advance(false);
+        assert_eq!(session.current(), &[(12, 13), (17, 17)]);
+
+        // typing in a tab stop keeps the session going
+        let delta = Delta::simple_edit(Interval::new_closed_open(12, 13), "xy".into(), 30);
+        assert!(session.apply_delta(&delta));
+        assert_eq!(session.current(), &[(12, 14), (18, 18)]);
+        session.advance(true);
+        session.advance(true);
+        assert!(session.is_finished());
+        // the end of the snippet is the final stop
+        assert_eq!(session.current(), &[(19, 19)]);
+
+        // so does typing in several regions of one
+        let mut builder = ::xi_rope::delta::Builder::new(31);
+        builder.replace(Interval::new_closed_open(12, 12), "z".into());
+        builder.replace(Interval::new_closed_open(18, 18), "z".into());
+        assert!(session.apply_delta(&builder.build()));
+
+        let delta = Delta::simple_edit(Interval::new_closed_open(0, 1), "".into(), 33);
+        assert!(!session.apply_delta(&delta));
+    }
+}
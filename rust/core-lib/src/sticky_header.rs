@@ -0,0 +1,197 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The sticky header: the lines defining the functions, classes and such
+//! that enclose the top of the view, once they have been scrolled past, so
+//! that the frontend can show them stuck to the top of the viewport.
+
+use xi_rope::Rope;
+use xi_rope::interval::Interval;
+
+/// A line of the sticky header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StickyLine {
+    /// The number of the line, 0-based.
+    pub line_number: usize,
+    /// The text of the line, without its line ending.
+    pub text: String,
+    /// How many of the header's definitions enclose this one.
+    pub scope_depth: usize,
+}
+
+/// Returns the definitions enclosing the logical line `first_line` and
+/// above it, outermost first, of those lines that `definition_name` finds
+/// the name of something being defined in; it is passed the interval of a
+/// line, and returns that of the name.
+pub fn sticky_lines<F>(text: &Rope, first_line: usize, tab_size: usize,
+                       definition_name: F) -> Vec<StickyLine>
+    where F: Fn(Interval) -> Option<Interval>
+{
+    enclosing_lines(text, first_line, tab_size).into_iter()
+        .filter(|&line| definition_name(line_interval(text, line)).is_some())
+        .enumerate()
+        .map(|(scope_depth, line_number)| {
+            let iv = line_interval(text, line_number);
+            let text = text.slice_to_cow(iv.start()..iv.end()).into_owned();
+            StickyLine { line_number, text, scope_depth }
+        })
+        .collect()
+}
+
+/// Returns the lines above `line` that enclose it, outermost first: those
+/// less indented than the lines below them, as far as the first line that
+/// is not indented.
+pub(crate) fn enclosing_lines(text: &Rope, line: usize, tab_size: usize) -> Vec<usize> {
+    let n_lines = text.line_of_offset(text.len()) + 1;
+    // a blank line belongs with the lines after it
+    let indent = (line..n_lines).filter_map(|line| line_indent(text, line, tab_size)).next();
+    let mut indent = match indent {
+        Some(indent) => indent,
+        None => return Vec::new(),
+    };
+    let mut lines = Vec::new();
+    for line in (0..line.min(n_lines)).rev() {
+        if indent == 0 {
+            break;
+        }
+        match line_indent(text, line, tab_size) {
+            Some(line_indent) if line_indent < indent => {
+                indent = line_indent;
+                lines.push(line);
+            }
+            _ => (),
+        }
+    }
+    lines.reverse();
+    lines
+}
+
+/// Returns the interval of the innermost definition enclosing `line`, or
+/// made on it, of those lines that `definition_name` finds the name of
+/// something being defined in. It runs to the next line that is not more
+/// indented, which is included if it closes a bracket, as with `}`.
+pub(crate) fn enclosing_definition<F>(text: &Rope, line: usize, tab_size: usize,
+                                      definition_name: F) -> Option<Interval>
+    where F: Fn(Interval) -> Option<Interval>
+{
+    let mut lines = enclosing_lines(text, line, tab_size);
+    lines.push(line);
+    let start_line = lines.into_iter().rev()
+        .find(|&line| definition_name(line_interval(text, line)).is_some())?;
+    let indent = line_indent(text, start_line, tab_size).unwrap_or(0);
+    let n_lines = text.line_of_offset(text.len()) + 1;
+    let end_line = (start_line + 1..n_lines)
+        .find(|&line| line_indent(text, line, tab_size).map_or(false, |i| i <= indent));
+    let end = match end_line {
+        Some(line) => {
+            let iv = line_interval(text, line);
+            let closes = text.slice_to_cow(iv.start()..iv.end()).trim_left()
+                .starts_with(&['}', ')', ']'][..]);
+            if closes { iv.end() } else { text.offset_of_line(line) }
+        }
+        None => text.len(),
+    };
+    Some(Interval::new_closed_open(text.offset_of_line(start_line), end))
+}
+
+/// The interval of `line`, without its line ending.
+pub(crate) fn line_interval(text: &Rope, line: usize) -> Interval {
+    Interval::new_closed_open(text.offset_of_line(line), line_end(text, line))
+}
+
+/// The display width of the leading whitespace of `line`, or `None` if the
+/// line is blank.
+fn line_indent(text: &Rope, line: usize, tab_size: usize) -> Option<usize> {
+    let start = text.offset_of_line(line);
+    let line = text.slice_to_cow(start..line_end(text, line));
+    let mut indent = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => indent += 1,
+            '\t' => indent = (indent / tab_size.max(1) + 1) * tab_size.max(1),
+            c if c.is_whitespace() => (),
+            _ => return Some(indent),
+        }
+    }
+    None
+}
+
+/// The offset of the end of `line`, before its line ending.
+fn line_end(text: &Rope, line: usize) -> usize {
+    let start = text.offset_of_line(line);
+    let end = text.offset_of_line(line + 1);
+    let line = text.slice_to_cow(start..end);
+    start + line.trim_right_matches(&['\n', '\r'][..]).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "\
+struct A;
+
+impl A {
+    fn f(&self) {
+        let x = 1;
+
+        if x > 0 {
+            g();
+        }
+    }
+}
+";
+
+    /// Lines starting with `impl` or `fn`, after their indentation; the
+    /// whole line is taken as the name.
+    fn definition_name(text: &Rope) -> impl Fn(Interval) -> Option<Interval> + '_ {
+        move |iv: Interval| {
+            let line = text.slice_to_cow(iv.start()..iv.end());
+            let line = line.trim_left();
+            if line.starts_with("impl") || line.starts_with("fn") { Some(iv) } else { None }
+        }
+    }
+
+    fn header(text: &Rope, first_line: usize) -> Vec<(usize, String, usize)> {
+        sticky_lines(text, first_line, 4, definition_name(text)).into_iter()
+            .map(|line| (line.line_number, line.text, line.scope_depth))
+            .collect()
+    }
+
+    #[test]
+    fn enclosing_definitions() {
+        let text = Rope::from(TEXT);
+        assert_eq!(header(&text, 0), vec![]);
+        assert_eq!(header(&text, 3), vec![(2, "impl A {".to_string(), 0)]);
+        // the `if` is not a definition, and the blank line goes with the next
+        assert_eq!(header(&text, 5), vec![(2, "impl A {".to_string(), 0),
+                                          (3, "    fn f(&self) {".to_string(), 1)]);
+        assert_eq!(header(&text, 7), header(&text, 5));
+        assert_eq!(header(&text, 10), vec![]);
+    }
+
+    #[test]
+    fn enclosing_definition_interval() {
+        let text = Rope::from(TEXT);
+        let definition = |line| {
+            enclosing_definition(&text, line, 4, definition_name(&text))
+                .map(|iv| text.slice_to_cow(iv.start()..iv.end()).into_owned())
+        };
+        assert_eq!(definition(0), None);
+        let f = "    fn f(&self) {\n        let x = 1;\n\n        if x > 0 {\n            g();\n        }\n    }";
+        assert_eq!(definition(7), Some(f.to_owned()));
+        assert_eq!(definition(3), definition(7));
+        assert_eq!(definition(2).map(|def| def.lines().count()), Some(9));
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Streaming requests to plugins, for long-running operations such as
+//! incremental search, whose results are worth showing as they arrive.
+//!
+//! Modeled on partial results in the Language Server Protocol: the core
+//! sends `streaming_request` with a fresh `token`, and the plugin answers
+//! with any number of `partial_result` notifications carrying that token,
+//! followed by a single `complete`.
+
+use std::collections::HashMap;
+
+use rand::{thread_rng, Rng};
+use serde_json::Value;
+
+use xi_rpc::RemoteError;
+
+use event_context::EventContext;
+use plugins::PluginPid;
+
+/// Receives the results of a streaming request.
+pub trait StreamHandler: Send {
+    fn partial_result(&mut self, ctx: &mut EventContext, value: Value);
+    /// Called once, with the final result; a plugin that stops before it
+    /// completes the request is reported as an error.
+    fn complete(self: Box<Self>, ctx: &mut EventContext, result: Result<Value, RemoteError>);
+}
+
+/// Forwards results to the frontend, which identifies the request by its
+/// own `request_id`.
+pub struct ClientStream {
+    pub request_id: usize,
+}
+
+impl StreamHandler for ClientStream {
+    fn partial_result(&mut self, ctx: &mut EventContext, value: Value) {
+        ctx.client.plugin_partial_result(ctx.view_id, self.request_id, &value);
+    }
+
+    fn complete(self: Box<Self>, ctx: &mut EventContext, result: Result<Value, RemoteError>) {
+        ctx.client.plugin_request_complete(ctx.view_id, self.request_id, &result);
+    }
+}
+
+struct Stream {
+    plugin: PluginPid,
+    handler: Box<dyn StreamHandler>,
+}
+
+/// The streaming requests of a view that have yet to complete.
+#[derive(Default)]
+pub struct StreamingRequests {
+    streams: HashMap<String, Stream>,
+}
+
+impl StreamingRequests {
+    /// Records a request to `plugin`, returning the token to send with it.
+    pub fn start(&mut self, plugin: PluginPid, handler: Box<dyn StreamHandler>) -> String {
+        let token = new_token();
+        self.streams.insert(token.clone(), Stream { plugin, handler });
+        token
+    }
+
+    /// Removes the handler for `token`, if `plugin` made the request. It
+    /// should be given back with `resume` unless the request is complete.
+    pub fn take(&mut self, plugin: PluginPid, token: &str) -> Option<Box<dyn StreamHandler>> {
+        match self.streams.get(token) {
+            Some(stream) if stream.plugin == plugin => (),
+            _ => return None,
+        }
+        self.streams.remove(token).map(|stream| stream.handler)
+    }
+
+    pub fn resume(&mut self, plugin: PluginPid, token: String, handler: Box<dyn StreamHandler>) {
+        self.streams.insert(token, Stream { plugin, handler });
+    }
+
+    /// Removes and returns the handlers of the requests made to `plugin`.
+    pub fn remove_plugin(&mut self, plugin: PluginPid) -> Vec<Box<dyn StreamHandler>> {
+        let tokens: Vec<String> = self.streams.iter()
+            .filter(|(_, stream)| stream.plugin == plugin)
+            .map(|(token, _)| token.clone())
+            .collect();
+        tokens.iter().flat_map(|token| self.streams.remove(token))
+            .map(|stream| stream.handler)
+            .collect()
+    }
+}
+
+/// Returns a random (version 4) UUID.
+fn new_token() -> String {
+    let mut rng = thread_rng();
+    let (high, low): (u64, u64) = (rng.gen(), rng.gen());
+    format!("{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+            high >> 32, (high >> 16) & 0xffff, high & 0xfff,
+            0x8000 | ((low >> 48) & 0x3fff), low & 0xffff_ffff_ffff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ignore;
+
+    impl StreamHandler for Ignore {
+        fn partial_result(&mut self, _ctx: &mut EventContext, _value: Value) { }
+        fn complete(self: Box<Self>, _ctx: &mut EventContext,
+                    _result: Result<Value, RemoteError>) { }
+    }
+
+    #[test]
+    fn tokens() {
+        let token = new_token();
+        assert_eq!(token.len(), 36);
+        assert_eq!(token.as_bytes()[14], b'4');
+        assert!(token.split('-').map(str::len).eq(vec![8, 4, 4, 4, 12]));
+        assert_ne!(token, new_token());
+    }
+
+    #[test]
+    fn streams_belong_to_plugins() {
+        let mut requests = StreamingRequests::default();
+        let one = requests.start(PluginPid(1), Box::new(Ignore));
+        requests.start(PluginPid(2), Box::new(Ignore));
+        assert!(requests.take(PluginPid(2), &one).is_none());
+        let handler = requests.take(PluginPid(1), &one).unwrap();
+        assert!(requests.take(PluginPid(1), &one).is_none());
+        requests.resume(PluginPid(1), one.clone(), handler);
+        assert_eq!(requests.remove_plugin(PluginPid(2)).len(), 1);
+        assert!(requests.take(PluginPid(1), &one).is_some());
+    }
+}
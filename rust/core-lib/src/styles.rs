@@ -16,21 +16,26 @@
 
 use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 
 use serde_json::{self, Value};
 use syntect::dumps::{dump_to_file, from_dump_file};
 use syntect::highlighting::StyleModifier as SynStyleModifier;
-use syntect::highlighting::{Color, Highlighter, Theme, ThemeSet};
+use syntect::highlighting::{Color, Highlighter, ParseThemeError, Theme, ThemeSet};
 use syntect::LoadingError;
 
+use document_highlight::HighlightKind;
+
 pub use syntect::highlighting::ThemeSettings;
 
 const N_RESERVED_STYLES: usize = 2;
-const SYNTAX_PRIORITY_DEFAULT: u16 = 200;
+pub(crate) const SYNTAX_PRIORITY_DEFAULT: u16 = 200;
 const SYNTAX_PRIORITY_LOWEST: u16 = 0;
+/// The priority of the styles the core draws over the syntax styles.
+const OVERLAY_PRIORITY: u16 = 900;
 pub const DEFAULT_THEME: &str = "InspiredGitHub";
 
 #[derive(Clone, PartialEq, Eq, Default, Hash, Debug, Serialize, Deserialize)]
@@ -124,8 +129,7 @@ impl Style {
         Style::new(
             p1.priority,
             p1.fg_color.or(p2.fg_color),
-            //TODO: stop ignoring background color
-            None,
+            p1.bg_color.or(p2.bg_color),
             p1.weight.or(p2.weight),
             p1.underline.or(p2.underline),
             p1.italic.or(p2.italic),
@@ -205,6 +209,34 @@ impl ThemeStyleMap {
         &self.theme.settings
     }
 
+    /// The style of the bracket at a caret and of the bracket matching it:
+    /// the theme's bracket colors, underlined.
+    pub fn get_bracket_style(&self) -> Style {
+        let settings = &self.theme.settings;
+        Style::new(
+            OVERLAY_PRIORITY,
+            settings.brackets_foreground.map(|c| Style::rgba_from_syntect_color(&c)),
+            settings.brackets_background.map(|c| Style::rgba_from_syntect_color(&c)),
+            None,
+            Some(true),
+            None)
+    }
+
+    /// The style of the references found with `document_highlight`: the
+    /// theme's highlight color as background, with writes underlined. If the
+    /// theme has no such color, they are all underlined.
+    pub fn get_document_highlight_style(&self, kind: HighlightKind) -> Style {
+        let settings = &self.theme.settings;
+        let bg = settings.highlight.or(settings.line_highlight)
+            .map(|c| Style::rgba_from_syntect_color(&c));
+        let underline = if kind == HighlightKind::Write || bg.is_none() {
+            Some(true)
+        } else {
+            None
+        };
+        Style::new(OVERLAY_PRIORITY, None, bg, None, underline, None)
+    }
+
     pub fn get_theme_names(&self) -> Vec<String>  {
         self.themes.themes.keys().cloned().collect()
     }
@@ -257,10 +289,11 @@ impl ThemeStyleMap {
         Some(theme_name.to_string())
     }
 
-    /// Load all themes inside the given directory.
+    /// Load all themes inside the given directory, both `.tmTheme` files
+    /// and TextMate themes in JSON.
     pub(crate) fn load_theme_dir(&mut self) {
         if let Some(themes_dir) = self.themes_dir.clone() {
-            match ThemeSet::discover_theme_paths(themes_dir) {
+            match discover_theme_paths(&themes_dir) {
                 Ok(themes) => {
                     self.caching_enabled = self.caching_enabled && self.init_cache_dir();
 
@@ -318,7 +351,11 @@ impl ThemeStyleMap {
     /// caching is enabled.
     pub(crate) fn load_theme(&mut self, theme_p: &Path) -> Result<String, LoadingError> {
         validate_theme_file(theme_p)?;
-        let theme = ThemeSet::get_theme(theme_p)?;
+        let theme = if theme_p.extension() == Some(OsStr::new("json")) {
+            load_json_theme(theme_p)?
+        } else {
+            ThemeSet::get_theme(theme_p)?
+        };
         let theme_name = theme_p
             .file_stem()
             .and_then(OsStr::to_str)
@@ -351,7 +388,7 @@ impl ThemeStyleMap {
     /// to the present ones.
     pub(crate) fn sync_dir(&mut self, dir: Option<&Path>) {
         if let Some(themes_dir) = dir {
-            if let Ok(paths) = ThemeSet::discover_theme_paths(themes_dir) {
+            if let Ok(paths) = discover_theme_paths(themes_dir) {
                 let current_state = HashSet::from_iter(paths.into_iter());
                 let maintained_state = self.state.clone();
 
@@ -385,9 +422,164 @@ impl ThemeStyleMap {
     }
 }
 
-/// Used to remove files with extension other than `tmTheme`.
-fn validate_theme_file(path: &Path) -> Result<bool, LoadingError> {
-    path.extension()
-        .map(|e| e != "tmTheme")
-        .ok_or(LoadingError::BadPath)
+/// Used to skip files with extension other than `tmTheme` or `json`.
+fn validate_theme_file(path: &Path) -> Result<(), LoadingError> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("tmTheme") | Some("json") => Ok(()),
+        _ => Err(LoadingError::BadPath),
+    }
+}
+
+/// Finds the theme files in `dir` and its subdirectories. Entries that
+/// can't be read are logged and skipped, and directories reached again
+/// through symlinks are only searched once.
+fn discover_theme_paths(dir: &Path) -> Result<Vec<PathBuf>, LoadingError> {
+    let mut paths = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(dir.canonicalize()?);
+    visit_theme_dir(dir, fs::read_dir(dir)?, &mut visited, &mut paths);
+    Ok(paths)
+}
+
+fn visit_theme_dir(dir: &Path, entries: fs::ReadDir, visited: &mut HashSet<PathBuf>,
+                   paths: &mut Vec<PathBuf>) {
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                warn!("skipping unreadable entry in {:?}: {}", dir, e);
+                continue;
+            }
+        };
+        if path.is_dir() {
+            let entries = path.canonicalize()
+                .and_then(|canonical| {
+                    if visited.insert(canonical) { fs::read_dir(&path).map(Some) } else { Ok(None) }
+                });
+            match entries {
+                Ok(Some(entries)) => visit_theme_dir(&path, entries, visited, paths),
+                Ok(None) => (),
+                Err(e) => warn!("skipping theme dir {:?}: {}", path, e),
+            }
+        } else if validate_theme_file(&path).is_ok() {
+            paths.push(path);
+        }
+    }
+}
+
+/// Loads a TextMate theme written in JSON, which has the structure of a
+/// `.tmTheme` plist. Syntect only parses plists, so it is converted to one.
+fn load_json_theme(path: &Path) -> Result<Theme, LoadingError> {
+    let reader = BufReader::new(File::open(path)?);
+    let json = serde_json::from_reader(reader)
+        .map_err(|_| ParseThemeError::IncorrectSyntax)?;
+    theme_from_json(&json)
+}
+
+fn theme_from_json(json: &Value) -> Result<Theme, LoadingError> {
+    let mut plist = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\">");
+    write_plist(json, &mut plist);
+    plist.push_str("</plist>");
+    ThemeSet::load_from_reader(&mut io::Cursor::new(plist))
+}
+
+/// Appends `value` to `out` as a plist element. Plists have no null, so
+/// null values are left out.
+fn write_plist(value: &Value, out: &mut String) {
+    match *value {
+        Value::Null => (),
+        Value::Bool(b) => out.push_str(if b { "<true/>" } else { "<false/>" }),
+        Value::Number(ref n) if n.is_f64() => out.push_str(&format!("<real>{}</real>", n)),
+        Value::Number(ref n) => out.push_str(&format!("<integer>{}</integer>", n)),
+        Value::String(ref s) => {
+            out.push_str("<string>");
+            push_escaped(s, out);
+            out.push_str("</string>");
+        }
+        Value::Array(ref items) => {
+            out.push_str("<array>");
+            items.iter().for_each(|item| write_plist(item, out));
+            out.push_str("</array>");
+        }
+        Value::Object(ref map) => {
+            out.push_str("<dict>");
+            for (key, value) in map.iter().filter(|&(_, v)| !v.is_null()) {
+                out.push_str("<key>");
+                push_escaped(key, out);
+                out.push_str("</key>");
+                write_plist(value, out);
+            }
+            out.push_str("</dict>");
+        }
+    }
+}
+
+fn push_escaped(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_theme() {
+        let json = json!({
+            "name": "Test <JSON>",
+            "settings": [
+                { "settings": { "foreground": "#112233", "background": "#FFFFFF" } },
+                { "name": "Comment", "scope": "comment", "settings": { "foreground": "#808080" } },
+                { "scope": "string", "settings": { "fontStyle": "italic" }, "unused": null },
+            ],
+        });
+        let theme = theme_from_json(&json).unwrap();
+        assert_eq!(theme.name, Some("Test <JSON>".into()));
+        assert_eq!(theme.settings.foreground, Some(Color { r: 0x11, g: 0x22, b: 0x33, a: 0xFF }));
+        assert_eq!(theme.scopes.len(), 2);
+        assert!(theme_from_json(&json!({ "name": "no settings" })).is_err());
+    }
+
+    #[test]
+    fn bracket_style() {
+        let mut style_map = ThemeStyleMap::new(None);
+        let style = style_map.get_bracket_style();
+        assert_eq!(style.underline, Some(true));
+        // the first style defined follows the reserved ones
+        assert_eq!(style_map.add(&style), N_RESERVED_STYLES);
+        assert_eq!(style_map.lookup(&style), Some(2));
+    }
+
+    #[test]
+    fn theme_files() {
+        assert!(validate_theme_file(Path::new("a/b.tmTheme")).is_ok());
+        assert!(validate_theme_file(Path::new("b.json")).is_ok());
+        assert!(validate_theme_file(Path::new("cache/b.tmdump")).is_err());
+        assert!(validate_theme_file(Path::new("b")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn theme_dir_cycles() {
+        extern crate tempdir;
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempdir::TempDir::new("xi-test-themes").unwrap();
+        let nested = tmp.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        File::create(tmp.path().join("a.tmTheme")).unwrap();
+        File::create(nested.join("b.json")).unwrap();
+        symlink(tmp.path(), nested.join("loop")).unwrap();
+        symlink(tmp.path().join("missing"), nested.join("dangling")).unwrap();
+
+        let mut paths = discover_theme_paths(tmp.path()).unwrap();
+        paths.sort();
+        assert_eq!(paths, vec![tmp.path().join("a.tmTheme"), nested.join("b.json")]);
+    }
 }
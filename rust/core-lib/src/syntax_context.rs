@@ -0,0 +1,146 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A summary of the syntax around an offset, built from the scopes that
+//! syntax plugins have already reported, so that completion plugins can
+//! filter candidates without parsing the buffer themselves.
+
+use xi_rope::rope::{Rope, RopeInfo};
+use xi_rope::tree::Cursor;
+
+use layers::Layers;
+use syntax::LanguageId;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyntaxContext {
+    pub language: LanguageId,
+    /// The scopes of the character before the offset, outermost first.
+    pub scopes: Vec<String>,
+    /// The innermost of `scopes`, which names the kind of token.
+    pub token_type: Option<String>,
+    /// The part of the identifier at the offset that precedes it.
+    pub prefix: String,
+    /// The nearest identifier before the one at the offset, on the same line.
+    pub previous_identifier: Option<String>,
+    /// The nearest identifier after the one at the offset, on the same line.
+    pub next_identifier: Option<String>,
+}
+
+impl SyntaxContext {
+    /// Summarizes the syntax at `offset`, which must be a character boundary
+    /// in `text`.
+    pub fn new(text: &Rope, layers: &Layers, language: LanguageId,
+               offset: usize) -> SyntaxContext {
+        let scopes = layers.scopes_at(offset);
+        let token_type = scopes.last().cloned();
+
+        let mut cursor = Cursor::new(text, offset);
+        let start = skip_back(&mut cursor, is_identifier_char);
+        let prefix = text.slice_to_cow(start..offset).into_owned();
+        skip_back(&mut cursor, |c| !is_identifier_char(c) && c != '\n');
+        let prev_end = cursor.pos();
+        let prev_start = skip_back(&mut cursor, is_identifier_char);
+
+        let mut cursor = Cursor::new(text, offset);
+        skip_forward(&mut cursor, is_identifier_char);
+        skip_forward(&mut cursor, |c| !is_identifier_char(c) && c != '\n');
+        let next_start = cursor.pos();
+        let next_end = skip_forward(&mut cursor, is_identifier_char);
+
+        let identifier = |start: usize, end: usize| if start < end {
+            Some(text.slice_to_cow(start..end).into_owned())
+        } else {
+            None
+        };
+
+        SyntaxContext {
+            language,
+            scopes,
+            token_type,
+            prefix,
+            previous_identifier: identifier(prev_start, prev_end),
+            next_identifier: identifier(next_start, next_end),
+        }
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Moves the cursor back over characters matching `pred`, returning the
+/// new position.
+fn skip_back<F: Fn(char) -> bool>(cursor: &mut Cursor<RopeInfo>, pred: F) -> usize {
+    let mut pos = cursor.pos();
+    while let Some(c) = cursor.prev_codepoint() {
+        if !pred(c) {
+            break;
+        }
+        pos = cursor.pos();
+    }
+    cursor.set(pos);
+    pos
+}
+
+/// Moves the cursor forward over characters matching `pred`, returning the
+/// new position.
+fn skip_forward<F: Fn(char) -> bool>(cursor: &mut Cursor<RopeInfo>, pred: F) -> usize {
+    let mut pos = cursor.pos();
+    while let Some(c) = cursor.next_codepoint() {
+        if !pred(c) {
+            break;
+        }
+        pos = cursor.pos();
+    }
+    cursor.set(pos);
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::interval::Interval;
+    use xi_rope::spans::SpansBuilder;
+    use plugins::PluginPid;
+    use styles::ThemeStyleMap;
+
+    #[test]
+    fn identifiers_and_scopes() {
+        let text = Rope::from("let x = foo.bar_baz(y);\nz");
+        let mut layers = Layers::default();
+        let style_map = ThemeStyleMap::new(None);
+        let scopes = vec![vec!["source.rust".to_string()],
+                          vec!["source.rust".to_string(), "meta.function-call.rust".to_string()]];
+        layers.add_scopes(PluginPid(1), scopes, &style_map);
+        let mut sb = SpansBuilder::new(text.len());
+        sb.add_span(Interval::new_closed_open(0, 12), 0);
+        sb.add_span(Interval::new_closed_open(12, 19), 1);
+        layers.update_layer(PluginPid(1), Interval::new_closed_closed(0, text.len()),
+                            sb.build());
+
+        let ctx = SyntaxContext::new(&text, &layers, LanguageId::from("Rust"), 15);
+        assert_eq!(ctx.scopes, vec!["source.rust", "meta.function-call.rust"]);
+        assert_eq!(ctx.token_type, Some("meta.function-call.rust".into()));
+        assert_eq!(ctx.prefix, "bar");
+        assert_eq!(ctx.previous_identifier, Some("foo".into()));
+        assert_eq!(ctx.next_identifier, Some("y".into()));
+
+        // identifiers are only looked for on the same line
+        let ctx = SyntaxContext::new(&text, &layers, LanguageId::from("Rust"), 25);
+        assert_eq!(ctx.scopes, Vec::<String>::new());
+        assert_eq!(ctx.prefix, "z");
+        assert_eq!(ctx.previous_identifier, None);
+        assert_eq!(ctx.next_identifier, None);
+    }
+}
@@ -20,16 +20,18 @@
 //! be renamed.
 
 use std::collections::{BTreeMap, HashSet};
+use std::env;
 use std::cell::{Cell, RefCell};
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use serde::de::{self, Deserialize, Deserializer, Unexpected};
 use serde::ser::{Serialize, Serializer};
-use serde_json::Value;
+use serde_json::{self, Value};
 
 use xi_rpc::{self, RpcPeer, RpcCtx, RemoteError, ReadError};
 use xi_rope::Rope;
@@ -37,17 +39,31 @@ use xi_trace::{self, trace_block};
 
 use WeakXiCore;
 use client::Client;
-use config::{self, ConfigManager, ConfigDomain, ConfigDomainExternal, Table};
+use code_actions::{self, CodeActionError, WorkspaceEdit};
+use clipboard_history::ClipboardHistory;
+use commands::{self, CommandError};
+use macros::Macros;
+use config::{self, ConfigDomain, ConfigDomainExternal, ConfigError, ConfigManager, Table};
+use definition::{DefinitionResult, Location};
 use editor::Editor;
 use event_context::EventContext;
-use file::FileManager;
-use plugins::{PluginCatalog, PluginPid, Plugin, start_plugin_process};
-use plugin_rpc::{PluginNotification, PluginRequest};
+use file::{self, BackgroundSave, CharacterEncoding, FileError, FileManager, LineEnding,
+           SaveWorker};
+use formatting::{delta_from_edits, TextEdit};
+use plugins::{CrashAction, PluginCatalog, PluginPid, PluginStatus, PluginSupervisor, Plugin,
+              start_plugin_process};
+use plugin_requests::{PluginRequests, RequestKind};
+use plugin_rpc::{PluginCapability, PluginNotification, PluginRequest};
+use rename::{RenameError, RenameRange};
+use session::{Session, SessionError, ViewSession};
 use rpc::{CoreNotification, CoreRequest, EditNotification, EditRequest,
           PluginNotification as CorePluginNotification};
+use streaming::ClientStream;
 use styles::{ThemeStyleMap, DEFAULT_THEME};
 use view::View;
 use width_cache::WidthCache;
+use file_index::{FileFinder, FileSearch, DEFAULT_SEARCH_LIMIT};
+use workspace_symbols::{SymbolIndex, SymbolInfo, SymbolSearch, SEARCH_TIMEOUT};
 use syntax::LanguageId;
 
 #[cfg(feature = "notify")]
@@ -75,11 +91,39 @@ pub type BufferIdentifier = BufferId;
 /// Totally arbitrary; we reserve this space for `ViewId`s
 pub(crate) const RENDER_VIEW_IDLE_MASK: usize = 1 << 25;
 
+/// ViewIds are also used as timer tokens for expiring hover requests.
+pub(crate) const HOVER_TIMEOUT_MASK: usize = 1 << 24;
+
 const NEW_VIEW_IDLE_TOKEN: usize = 1001;
 
 /// xi_rpc idle Token for watcher related idle scheduling.
 pub(crate) const WATCH_IDLE_TOKEN: usize = 1002;
 
+/// ViewIds are also used as timer tokens for saving buffers whose
+/// formatting, before the save, takes too long.
+pub(crate) const FORMAT_TIMEOUT_MASK: usize = 1 << 23;
+
+/// ViewIds are also used as timer tokens for finding the breadcrumbs of a
+/// view again, when its caret moved too soon after they were last found.
+pub(crate) const BREADCRUMBS_TIMER_MASK: usize = 1 << 22;
+
+/// BufferIds are also used as timer tokens for auto-saving buffers, once
+/// no edit has been made to them for a while.
+pub(crate) const AUTO_SAVE_TIMER_MASK: usize = 1 << 21;
+
+/// Timer token for restarting crashed plugins.
+const PLUGIN_RESTART_TOKEN: usize = 1003;
+
+/// Timer token for answering a workspace symbol search that plugins are
+/// slow to help with.
+const SYMBOL_SEARCH_TOKEN: usize = 1004;
+
+/// Idle token for updating file searches as the file index grows.
+const FILE_INDEX_TOKEN: usize = 1005;
+
+/// Idle token for finishing the auto-saves written in the background.
+const AUTO_SAVE_TOKEN: usize = 1006;
+
 #[cfg(feature = "notify")]
 const CONFIG_EVENT_TOKEN: WatchToken = WatchToken(1);
 
@@ -90,6 +134,18 @@ pub const OPEN_FILE_EVENT_TOKEN: WatchToken = WatchToken(2);
 #[cfg(feature = "notify")]
 const THEME_FILE_EVENT_TOKEN: WatchToken = WatchToken(3);
 
+/// Token for file-change events in the workspace indexed for file search.
+#[cfg(feature = "notify")]
+const FILE_INDEX_EVENT_TOKEN: WatchToken = WatchToken(4);
+
+/// Token for file-change events in the directory config files of open files.
+#[cfg(feature = "notify")]
+const DIRECTORY_CONFIG_EVENT_TOKEN: WatchToken = WatchToken(5);
+
+/// The root of the workspace: every open buffer and its views, the config
+/// and the plugins they share. A plugin process is shared by every buffer
+/// it is interested in, and operations that span buffers, such as renaming
+/// a symbol or searching the workspace's symbols, are made here.
 #[allow(dead_code)]
 pub struct CoreState {
     editors: BTreeMap<BufferId, RefCell<Editor>>,
@@ -97,6 +153,12 @@ pub struct CoreState {
     file_manager: FileManager,
     /// A local pasteboard.
     kill_ring: RefCell<Rope>,
+    /// The text copied or cut in any buffer, for pasting again.
+    clipboard_history: RefCell<ClipboardHistory>,
+    /// The macros recorded in any view, by register.
+    macros: RefCell<Macros>,
+    /// The views that hover, definition and format requests were made in.
+    plugin_requests: RefCell<PluginRequests>,
     /// Theme and style state.
     style_map: RefCell<ThemeStyleMap>,
     width_cache: RefCell<WidthCache>,
@@ -107,11 +169,30 @@ pub struct CoreState {
     self_ref: Option<WeakXiCore>,
     /// Views which need to have setup finished.
     pending_views: Vec<(ViewId, Table)>,
+    /// The saved state of views opened from a session, restored once they
+    /// are set up.
+    pending_restores: Vec<(ViewId, ViewSession)>,
+    /// The edits of workspace edits to files that were opened for them,
+    /// made once their views are set up, and whether to save them after.
+    pending_edits: Vec<(ViewId, Vec<TextEdit>, bool)>,
     peer: Client,
     id_counter: Counter,
     plugins: PluginCatalog,
     // for the time being we auto-start all plugins we find on launch.
     running_plugins: Vec<Plugin>,
+    /// Restarts plugins that crash.
+    supervisor: PluginSupervisor,
+    /// The symbols of the files in the workspace, for searching.
+    symbol_index: SymbolIndex,
+    /// The workspace symbol search waiting on plugins, if any.
+    symbol_search: Option<SymbolSearch>,
+    /// The index of the workspace's files, once a file search is made.
+    file_finder: Option<FileFinder>,
+    /// Writes auto-saved buffers, once one is.
+    save_worker: Option<SaveWorker>,
+    /// Whether the client asks to confirm closing a buffer with unsaved
+    /// changes, rather than its views always closing.
+    confirm_close: bool,
 }
 
 /// Initial setup and bookkeeping
@@ -138,6 +219,7 @@ impl CoreState {
         }
 
         let config_manager = ConfigManager::new(config_dir, extras_dir);
+        let clipboard_history = ClipboardHistory::new(config_manager.clipboard_history_size());
 
         let themes_dir = config_manager.get_themes_dir();
         if let Some(p) = themes_dir.as_ref() {
@@ -145,7 +227,8 @@ impl CoreState {
             watcher.watch_filtered(p, true, THEME_FILE_EVENT_TOKEN,
                                    |p| p.extension()
                                    .and_then(OsStr::to_str)
-                                   .unwrap_or("") == "tmTheme");
+                                   .map(|ext| ext == "tmTheme" || ext == "json")
+                                   .unwrap_or(false));
         }
 
         CoreState {
@@ -156,15 +239,26 @@ impl CoreState {
             #[cfg(not(feature = "notify"))]
             file_manager: FileManager::new(),
             kill_ring: RefCell::new(Rope::from("")),
+            clipboard_history: RefCell::new(clipboard_history),
+            macros: RefCell::new(Macros::default()),
+            plugin_requests: RefCell::new(PluginRequests::default()),
             style_map: RefCell::new(ThemeStyleMap::new(themes_dir)),
             width_cache: RefCell::new(WidthCache::new()),
             config_manager,
             self_ref: None,
             pending_views: Vec::new(),
+            pending_restores: Vec::new(),
+            pending_edits: Vec::new(),
             peer: Client::new(peer.clone()),
             id_counter: Counter::default(),
             plugins: PluginCatalog::default(),
             running_plugins: Vec::new(),
+            supervisor: PluginSupervisor::default(),
+            symbol_index: SymbolIndex::default(),
+            symbol_search: None,
+            file_finder: None,
+            save_worker: None,
+            confirm_close: false,
         }
     }
 
@@ -185,6 +279,8 @@ impl CoreState {
 
         if let Some(path) = self.config_manager.base_config_file_path() {
             self.load_file_based_config(&path);
+        } else {
+            self.report_keybinding_conflicts();
         }
 
         // Load the custom theme files.
@@ -212,7 +308,7 @@ impl CoreState {
         let _t = trace_block("CoreState::load_config_file", &["core"]);
         if let Some(domain) = self.config_manager.domain_for_path(path) {
             match config::try_load_from_file(&path) {
-                Ok(table) => self.set_config(domain, table),
+                Ok(table) => self.set_config(domain, table, Some(path)),
                 Err(e) => self.peer.alert(e.to_string()),
             }
         } else {
@@ -220,23 +316,52 @@ impl CoreState {
         }
     }
 
-    /// Sets (overwriting) the config for a given domain.
-    fn set_config(&mut self, domain: ConfigDomain, table: Table) {
+    /// Sets (overwriting) the config for a given domain. `path` is that of
+    /// the file the config was loaded from, if any.
+    fn set_config(&mut self, domain: ConfigDomain, table: Table, path: Option<&Path>) {
+        let general = domain == ConfigDomain::General;
         match self.config_manager.set_user_config(domain, table) {
-            Err(e) => self.peer.alert(format!("{}", &e)),
-            Ok(changes) => self.handle_config_changes(changes),
+            Err(e) => self.report_config_error(e, path),
+            Ok(changes) => {
+                self.handle_config_changes(changes);
+                if general {
+                    let size = self.config_manager.clipboard_history_size();
+                    self.clipboard_history.borrow_mut().set_capacity(size);
+                    self.report_keybinding_conflicts();
+                }
+            }
+        }
+    }
+
+    /// Tells the client of the keys bound to more than one command, if any.
+    fn report_keybinding_conflicts(&self) {
+        let conflicts = commands::keybinding_conflicts(&self.config_manager.keybindings());
+        if !conflicts.is_empty() {
+            self.peer.keybinding_conflicts(&conflicts);
+        }
+    }
+
+    /// Tells the client why a config was rejected: invalid settings are
+    /// listed with `config_error`, and other problems are alerts.
+    fn report_config_error(&self, err: ConfigError, path: Option<&Path>) {
+        match err {
+            ConfigError::Invalid(errors) => self.peer.config_error(path, &errors),
+            other => self.peer.alert(other.to_string()),
         }
     }
 
-    /// Notify editors/views/plugins of config changes.
+    /// Notify editors/views/plugins of config changes, for every view of
+    /// each buffer whose config has changed.
     fn handle_config_changes(&self, changes: Vec<(BufferId, Table)>) {
         for (id, table) in changes {
-            let view_id = self.views.values()
-                .find(|v| v.borrow().get_buffer_id() == id)
+            let view_ids = self.views.values()
+                .filter(|v| v.borrow().get_buffer_id() == id)
                 .map(|v| v.borrow().get_view_id())
-                .unwrap();
+                .collect::<Vec<_>>();
 
-            self.make_context(view_id).unwrap().config_changed(&table)
+            for view_id in view_ids {
+                self.make_context(view_id).unwrap().config_changed(&table)
+            }
         }
     }
 }
@@ -255,6 +380,10 @@ impl CoreState {
             let buffer_id = view.borrow().get_buffer_id();
 
             let editor = self.editors.get(&buffer_id).unwrap();
+            let siblings = self.views.iter()
+                .filter(|&(&id, v)| id != view_id && v.borrow().get_buffer_id() == buffer_id)
+                .map(|(_, v)| v)
+                .collect();
             let info = self.file_manager.get_info(buffer_id);
             let plugins = self.running_plugins.iter().collect::<Vec<_>>();
             let config = self.config_manager.get_buffer_config(buffer_id);
@@ -268,18 +397,26 @@ impl CoreState {
                 config: &config.items,
                 language,
                 info: info,
-                siblings: Vec::new(),
+                siblings,
                 plugins: plugins,
                 client: &self.peer,
                 style_map: &self.style_map,
                 width_cache: &self.width_cache,
                 kill_ring: &self.kill_ring,
+                clipboard_history: &self.clipboard_history,
+                macros: &self.macros,
+                plugin_requests: &self.plugin_requests,
                 weak_core: self.self_ref.as_ref().unwrap(),
             }
         })
     }
 
-    /// Produces an iterator over all event contexts, with each view appearing
+    /// Produces an iterator over the event contexts of every view.
+    fn iter_views<'a>(&'a self) -> impl Iterator<Item=EventContext<'a>> + 'a {
+        self.views.keys().map(move |&view_id| self.make_context(view_id).unwrap())
+    }
+
+    /// Produces an iterator over all event contexts, with each buffer appearing
     /// exactly once.
     fn iter_groups<'a>(&'a self) -> Iter<'a, Box<Iterator<Item=&ViewId> + 'a>>
     {
@@ -298,10 +435,14 @@ impl CoreState {
                 self.do_edit(view_id, cmd),
             Save { view_id, file_path } =>
                 self.do_save(view_id, file_path),
-            CloseView { view_id } =>
-                self.do_close_view(view_id),
+            Reload { view_id } =>
+                self.do_reload(view_id),
+            CloseView { view_id, force } =>
+                self.do_close_view(view_id, force),
             ModifyUserConfig { domain, changes } =>
                 self.do_modify_user_config(domain, changes),
+            ResolveConflict { key, preferred_command } =>
+                self.do_resolve_conflict(key, preferred_command),
             SetTheme { theme_name } =>
                 self.do_set_theme(&theme_name),
             SaveTrace { destination, frontend_samples } =>
@@ -314,12 +455,21 @@ impl CoreState {
                         self.do_stop_plugin(view_id, &plugin_name),
                     PN::PluginRpc { view_id, receiver, rpc } =>
                         self.do_plugin_rpc(view_id, &receiver, &rpc.method, &rpc.params),
+                    PN::PluginStreamingRpc { view_id, receiver, request_id, method, params } =>
+                        self.do_plugin_streaming_rpc(view_id, &receiver, request_id,
+                                                     &method, &params),
                 }
             TracingConfig { enabled } =>
                 self.toggle_tracing(enabled),
-            // handled at the top level
-            ClientStarted { .. } => (),
+            // the rest is handled at the top level
+            ClientStarted { confirm_close, .. } => self.confirm_close = confirm_close,
             SetLanguage { view_id, language_id } => self.do_set_language(view_id, language_id),
+            SetWorkspaceRoot { path } => self.do_set_workspace_root(path),
+            SearchWorkspaceSymbols { request_id, query } =>
+                self.do_search_workspace_symbols(request_id, query),
+            OpenFileFuzzy { request_id, query, root, limit } =>
+                self.do_open_file_fuzzy(request_id, query, root, limit),
+            SetMacros { macros } => self.macros.borrow_mut().set_registers(macros),
         }
     }
 
@@ -332,6 +482,12 @@ impl CoreState {
             //TODO: make this a notification
             NewView { file_path } =>
                 self.do_new_view(file_path.map(PathBuf::from)),
+            SplitView { view_id } =>
+                self.do_split_view(view_id),
+            Edit(::rpc::EditCommand { view_id, cmd: EditRequest::ApplyCodeAction { action_index } }) =>
+                self.do_apply_code_action(view_id, action_index)
+                    .map(|opened| json!({"opened": opened}))
+                    .map_err(|e| e.into()),
             Edit(::rpc::EditCommand { view_id, cmd }) =>
                 self.do_edit_sync(view_id, cmd),
             //TODO: why is this a request?? make a notification?
@@ -339,6 +495,27 @@ impl CoreState {
                 self.do_get_config(view_id).map(|c| json!(c)),
             DebugGetContents { view_id } =>
                 self.do_get_contents(view_id).map(|c| json!(c)),
+            ListThemes {} =>
+                Ok(json!(self.style_map.borrow().get_theme_names())),
+            GetClipboardHistory {} =>
+                Ok(json!(self.clipboard_history.borrow().entries())),
+            GetMacros {} =>
+                Ok(json!(self.macros.borrow().registers())),
+            HasUnsavedChanges {} =>
+                Ok(json!(self.editors.values().any(|ed| !ed.borrow().is_pristine()))),
+            GetAllCommands { query } => {
+                let keybindings = self.config_manager.keybindings();
+                Ok(json!(commands::all_commands(query.as_ref().map(String::as_str), &keybindings)))
+            }
+            SessionSave { path } =>
+                self.do_session_save(&path).map(|()| Value::Null).map_err(|e| e.into()),
+            SessionRestore { path } =>
+                self.do_session_restore(&path).map_err(|e| e.into()),
+            ExecuteCommandByName { view_id, name, args } =>
+                self.execute_command_by_name(view_id, &name, args).map(|()| Value::Null)
+                    .map_err(|e| e.into()),
+            ValidateConfig { toml } =>
+                Ok(json!(self.config_manager.validate_config(&toml))),
         }
     }
 
@@ -348,6 +525,17 @@ impl CoreState {
         }
     }
 
+    fn execute_command_by_name(&mut self, view_id: ViewId, name: &str, args: Value)
+        -> Result<(), CommandError>
+    {
+        let cmd = commands::parse_command(name, args)?;
+        if !self.views.contains_key(&view_id) {
+            return Err(CommandError::MissingView(view_id));
+        }
+        self.do_edit(view_id, cmd);
+        Ok(())
+    }
+
     fn do_edit_sync(&mut self, view_id: ViewId,
                     cmd: EditRequest) -> Result<Value, RemoteError> {
         if let Some(mut edit_ctx) = self.make_context(view_id) {
@@ -363,6 +551,23 @@ impl CoreState {
     fn do_new_view(&mut self, path: Option<PathBuf>)
         -> Result<Value, RemoteError>
     {
+        self.new_view(path).map(|view_id| json!(view_id))
+    }
+
+    /// Opens another view of the buffer shown by `view_id`. It has its own
+    /// selections, scroll position and folds, and shows the edits made in
+    /// any view of the buffer.
+    fn do_split_view(&mut self, view_id: ViewId) -> Result<Value, RemoteError> {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return Err(RemoteError::custom(404,
+                                                   format!("missing view {:?}", view_id),
+                                                   None)),
+        };
+        Ok(json!(self.new_sibling_view(buffer_id)))
+    }
+
+    fn new_view(&mut self, path: Option<PathBuf>) -> Result<ViewId, RemoteError> {
         let view_id = self.next_view_id();
         let buffer_id = self.next_buffer_id();
 
@@ -380,6 +585,7 @@ impl CoreState {
         let config = self.config_manager.add_buffer(
             buffer_id,
             path.as_ref().map(|p| p.as_path()));
+        self.watch_directory_configs(buffer_id, true);
 
         //NOTE: because this is a synchronous call, we have to return the
         //view_id before we can send any events to this view. We use mark the
@@ -388,7 +594,190 @@ impl CoreState {
         self.pending_views.push((view_id, config));
         self.peer.schedule_idle(NEW_VIEW_IDLE_TOKEN);
 
-        Ok(json!(view_id))
+        Ok(view_id)
+    }
+
+    /// Adds a view of an open buffer; like `new_view`, it is set up on the
+    /// next runloop pass.
+    fn new_sibling_view(&mut self, buffer_id: BufferId) -> ViewId {
+        let view_id = self.next_view_id();
+        self.views.insert(view_id, RefCell::new(View::new(view_id, buffer_id)));
+        let config = self.config_manager.get_buffer_config(buffer_id).to_table();
+        self.pending_views.push((view_id, config));
+        self.peer.schedule_idle(NEW_VIEW_IDLE_TOKEN);
+        view_id
+    }
+
+    /// Handles a definition found by a plugin for a request from `view_id`.
+    /// Unless peeking, a view already showing the file is reused; otherwise
+    /// a new view is opened. Its caret is moved to the definition.
+    fn do_show_definition(&mut self, view_id: ViewId, plugin_id: PluginId, request_id: usize,
+                          result: Result<Location, RemoteError>) {
+        let settled = match self.views.get(&view_id) {
+            Some(view) => view.borrow_mut().definition_requests_mut()
+                .respond(request_id, plugin_id, result),
+            None => return,
+        };
+        let (peek, location) = match settled {
+            Some(settled) => settled,
+            None => return,
+        };
+        self.plugin_requests.borrow_mut().finish(view_id, RequestKind::Definition, request_id);
+        let result = location.and_then(|location| {
+            let buffer_id = self.file_manager.get_editor(&location.path);
+            let source_buffer = self.views[&view_id].borrow().get_buffer_id();
+            let existing = match buffer_id {
+                Some(id) if !peek && id == source_buffer => Some(view_id),
+                Some(id) if !peek => self.views.values()
+                    .map(|v| v.borrow())
+                    .find(|v| v.get_buffer_id() == id)
+                    .map(|v| v.get_view_id()),
+                _ => None,
+            };
+            let target = match (existing, buffer_id) {
+                (Some(target), _) => target,
+                (None, Some(id)) => self.new_sibling_view(id),
+                (None, None) => match self.new_view(Some(location.path.clone())) {
+                    Ok(target) => target,
+                    Err(err) => {
+                        warn!("opening definition failed: {:?}", err);
+                        self.peer.alert(format!("could not open {}",
+                                                location.path.display()));
+                        return None;
+                    }
+                },
+            };
+            let mut ctx = self.make_context(target).unwrap();
+            ctx.goto_line_col(location.line, location.col);
+            if existing.is_some() {
+                ctx.render_if_needed();
+            }
+            Some(DefinitionResult { location, view_id: target, peek })
+        });
+        self.peer.show_definition(view_id, request_id, result.as_ref());
+    }
+
+    /// Applies a code action that the frontend was sent for `view_id`: makes
+    /// its edit, then has the plugin that offered it run its command.
+    /// Returns the views opened for files that its edit changed, by path.
+    fn do_apply_code_action(&mut self, view_id: ViewId, index: usize)
+        -> Result<BTreeMap<PathBuf, ViewId>, CodeActionError>
+    {
+        let shown = self.views.get(&view_id)
+            .and_then(|view| view.borrow().code_actions().get_shown(index));
+        let (plugin_id, rev, action) = shown.ok_or(CodeActionError::NoSuchAction(index))?;
+        let opened = match action.edit {
+            Some(edit) => self.apply_workspace_edit(view_id, rev, edit, false)?,
+            None => BTreeMap::new(),
+        };
+        if let Some(command) = action.command {
+            match self.running_plugins.iter().find(|p| p.id == plugin_id) {
+                Some(plugin) => plugin.execute_command(view_id, &command),
+                None => warn!("plugin {} stopped before running {}", plugin_id, command.command),
+            }
+        }
+        Ok(opened)
+    }
+
+    /// Makes the edits of a code action or a rename found for revision `rev`
+    /// of the buffer of `view_id`. Edits to the files of other open buffers
+    /// apply to their current text; files that are not open are opened in
+    /// new views, and edited once those are set up. Every file's edits are
+    /// checked before any file is opened or edited, and each buffer is
+    /// edited as one undo group. If `save`, each buffer is saved once it has
+    /// been edited. Returns the views opened, by path.
+    fn apply_workspace_edit(&mut self, view_id: ViewId, rev: u64, edit: WorkspaceEdit,
+                            save: bool)
+        -> Result<BTreeMap<PathBuf, ViewId>, CodeActionError>
+    {
+        let source_buffer = self.views[&view_id].borrow().get_buffer_id();
+        let mut targets = Vec::new();
+        let mut unopened = Vec::new();
+        for (path, edits) in edit.changes {
+            let buffer_id = self.file_manager.get_editor(&path);
+            let target = match buffer_id {
+                Some(id) if id == source_buffer => Some((view_id, Some(rev))),
+                Some(id) => self.views.values()
+                    .map(|v| v.borrow())
+                    .find(|v| v.get_buffer_id() == id)
+                    .map(|v| (v.get_view_id(), None)),
+                None => None,
+            };
+            let (target, rev) = match target {
+                Some(target) => target,
+                None => {
+                    let (text, _) = file::load_unopened(&path).map_err(|err| {
+                        warn!("loading {} for a code action failed: {:?}", path.display(), err);
+                        CodeActionError::InvalidEdit(format!("could not open {}", path.display()))
+                    })?;
+                    delta_from_edits(&text, &edits)?;
+                    unopened.push((path, edits));
+                    continue;
+                }
+            };
+            let buffer_id = self.views[&target].borrow().get_buffer_id();
+            let ed = self.editors[&buffer_id].borrow();
+            let rev = rev.unwrap_or_else(|| ed.get_head_rev_token());
+            ed.check_text_edits(rev, &edits)?;
+            targets.push((target, rev, edits, path));
+        }
+
+        let mut opened = BTreeMap::new();
+        for (path, edits) in unopened {
+            match self.new_view(Some(path.clone())) {
+                Ok(target) => {
+                    self.pending_edits.push((target, edits, save));
+                    opened.insert(path, target);
+                }
+                Err(err) => {
+                    warn!("opening {} for a code action failed: {:?}", path.display(), err);
+                    let opened = opened.values().cloned().collect::<Vec<_>>();
+                    self.pending_edits.retain(|&(id, _, _)| !opened.contains(&id));
+                    for target in opened {
+                        self.do_close_view(target, true);
+                    }
+                    return Err(CodeActionError::InvalidEdit(
+                        format!("could not open {}", path.display())));
+                }
+            }
+        }
+        for (target, rev, edits, path) in targets {
+            self.make_context(target).unwrap().apply_text_edits(Some(rev), &edits)?;
+            if save {
+                let buffer_id = self.views[&target].borrow().get_buffer_id();
+                if let Err(err) = self.write_buffer(buffer_id, &path) {
+                    self.peer.alert(err.to_string());
+                }
+            }
+        }
+        Ok(opened)
+    }
+
+    /// Makes the edits of workspace edits to the files that were opened
+    /// for them, now that their views are set up.
+    fn apply_pending_edits(&mut self) {
+        let pending = mem::replace(&mut self.pending_edits, Vec::new());
+        for (view_id, edits, save) in pending {
+            let result = match self.make_context(view_id) {
+                Some(mut ctx) => ctx.apply_text_edits(None, &edits),
+                None => continue,
+            };
+            if let Err(err) = result {
+                // the file changed since the edits were checked
+                self.peer.alert(format!("could not edit a file: {}", err));
+                continue;
+            }
+            if !save {
+                continue;
+            }
+            let buffer_id = self.views[&view_id].borrow().get_buffer_id();
+            let path = self.file_manager.get_info(buffer_id).map(|info| info.path.clone());
+            if let Some(path) = path {
+                if let Err(err) = self.write_buffer(buffer_id, &path) {
+                    self.peer.alert(err.to_string());
+                }
+            }
+        }
     }
 
     fn do_save<P>(&mut self, view_id: ViewId, path: P)
@@ -402,40 +791,150 @@ impl CoreState {
             None => return,
         };
 
-        let ed = self.editors.get(&buffer_id).unwrap();
+        // with format on save, the buffer is saved once the formatting
+        // plugin's edits have been applied
+        if self.config_manager.get_buffer_config(buffer_id).items.format_on_save
+            && self.make_context(view_id).unwrap().format_before_save(path) {
+            return;
+        }
+        self.save_buffer(view_id, path);
+    }
 
-        if let Err(e) = self.file_manager.save(path, ed.borrow().get_buffer(),
-                                               buffer_id) {
+    /// Writes the buffer shown in `view_id` to `path`.
+    fn save_buffer(&mut self, view_id: ViewId, path: &Path) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+
+        self.make_context(view_id).unwrap().clean_up_before_save();
+        if let Err(e) = self.write_buffer(buffer_id, path) {
             self.peer.alert(e.to_string());
-            return;
         }
+    }
+
+    /// Writes the buffer to `path`, and tells its views that it was saved.
+    fn write_buffer(&mut self, buffer_id: BufferId, path: &Path) -> Result<(), FileError> {
+        // an auto-save still being written must not overwrite this one
+        let done = self.save_worker.as_mut().map(|w| w.wait()).unwrap_or_default();
+        self.finish_auto_saves(done);
 
-        self.make_context(view_id).unwrap().after_save(path);
+        let (encoding, line_ending) = {
+            let items = &self.config_manager.get_buffer_config(buffer_id).items;
+            (CharacterEncoding::from_charset(&items.charset),
+             LineEnding::from_name(&items.end_of_line))
+        };
 
-        // update the config _after_ sending save related events
+        let path_changed = self.file_manager.get_info(buffer_id)
+            .map(|info| info.path != path)
+            .unwrap_or(true);
+
+        let rev = {
+            let ed = self.editors.get(&buffer_id).unwrap().borrow();
+            self.file_manager.save(path, ed.get_buffer(), buffer_id, encoding, line_ending)?;
+            ed.get_head_rev_id()
+        };
+
+        // every view of the buffer needs to know that it is now pristine,
+        // and if it was saved to a new path, what that path is.
+        let view_ids = self.views.values()
+            .filter(|v| v.borrow().get_buffer_id() == buffer_id)
+            .map(|v| v.borrow().get_view_id())
+            .collect::<Vec<_>>();
+        for &id in &view_ids {
+            self.make_context(id).unwrap().after_save(path, rev);
+            if path_changed {
+                self.peer.file_path_changed(id, path);
+            }
+        }
+
+        // update the config _after_ sending save related events; the new
+        // path may have a different language, and directory configs.
+        self.watch_directory_configs(buffer_id, false);
         let changes = self.config_manager.update_buffer_path(buffer_id, path);
+        self.watch_directory_configs(buffer_id, true);
         if let Some(changes) = changes {
-            self.make_context(view_id).unwrap().config_changed(&changes);
+            for &id in &view_ids {
+                self.make_context(id).unwrap().config_changed(&changes);
+            }
         }
+        Ok(())
     }
 
-    fn do_close_view(&mut self, view_id: ViewId) {
-        let close_buffer = self.make_context(view_id)
-            .map(|ctx| ctx.close_view())
-            .unwrap_or(true);
+    fn do_reload(&mut self, view_id: ViewId) {
+        let buffer_id = self.views.get(&view_id).map(|v| v.borrow().get_buffer_id());
+        let buffer_id = match buffer_id {
+            Some(id) => id,
+            None => return,
+        };
+        let path = match self.file_manager.get_info(buffer_id) {
+            Some(info) => info.path.clone(),
+            None => return,
+        };
+
+        match self.file_manager.open(&path, buffer_id) {
+            Ok(text) => self.make_context(view_id).unwrap().reload(text),
+            Err(e) => self.peer.alert(e.to_string()),
+        }
+    }
+
+    /// Closes `view_id`. If the client handles `confirm_close`, the last
+    /// view of a buffer with unsaved changes stays open unless `force`d,
+    /// and the client is asked to confirm.
+    fn do_close_view(&mut self, view_id: ViewId, force: bool) {
+        if self.confirm_close && !force && self.is_dirty_last_view(view_id) {
+            let buffer_id = self.views[&view_id].borrow().get_buffer_id();
+            let path = self.file_manager.get_info(buffer_id).map(|info| info.path.as_path());
+            self.peer.confirm_close(view_id, buffer_id, path);
+            return;
+        }
+
+        let (close_buffer, plugin_view_closed) = self.make_context(view_id)
+            .map(|ctx| (ctx.close_view(), ctx.plugin_view_id() == view_id))
+            .unwrap_or((true, false));
 
         let buffer_id = self.views.remove(&view_id)
             .map(|v| v.borrow().get_buffer_id());
+        self.plugin_requests.borrow_mut().close_view(view_id);
 
         if let Some(buffer_id) = buffer_id {
+            if !close_buffer && plugin_view_closed {
+                // plugins see the buffer through another of its views now
+                let next = self.views.values()
+                    .map(|v| v.borrow())
+                    .find(|v| v.get_buffer_id() == buffer_id)
+                    .map(|v| v.get_view_id());
+                if let Some(next) = next {
+                    self.make_context(next).unwrap().open_in_plugins();
+                }
+            }
             if close_buffer {
-                self.editors.remove(&buffer_id);
+                let editor = self.editors.remove(&buffer_id);
+                if let Some(info) = self.file_manager.get_info(buffer_id) {
+                    let pristine = editor.map(|ed| ed.borrow().is_pristine()).unwrap_or(false);
+                    let mod_time = if pristine { info.mod_time } else { None };
+                    self.symbol_index.buffer_closed(&info.path, mod_time);
+                }
                 self.file_manager.close(buffer_id);
+                self.watch_directory_configs(buffer_id, false);
                 self.config_manager.remove_buffer(buffer_id);
             }
         }
     }
 
+    /// Returns `true` if `view_id` is the only view of a buffer that has
+    /// unsaved changes.
+    fn is_dirty_last_view(&self, view_id: ViewId) -> bool {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return false,
+        };
+        let n_views = self.views.values()
+            .filter(|view| view.borrow().get_buffer_id() == buffer_id)
+            .count();
+        n_views == 1 && !self.editors[&buffer_id].borrow().is_pristine()
+    }
+
     fn do_set_theme(&self, theme_name: &str) {
         //Set only if requested theme is different from the
         //current one.
@@ -455,7 +954,7 @@ impl CoreState {
                                     style_map.get_theme_settings());
         }
 
-        self.iter_groups().for_each(|mut edit_ctx| {
+        self.iter_views().for_each(|mut edit_ctx| {
             edit_ctx.with_editor(|ed, view, _, _| {
                 ed.theme_changed(&self.style_map.borrow());
                 view.set_dirty(ed.get_buffer());
@@ -480,7 +979,65 @@ impl CoreState {
         };
         let new_config = self.config_manager.table_for_update(domain.clone(),
                                                               changes);
-        self.set_config(domain, new_config);
+        self.set_config(domain, new_config, None);
+    }
+
+    /// Binds `key` to `preferred_command` alone, unbinding it from the
+    /// other commands in the user's general config.
+    fn do_resolve_conflict(&mut self, key: String, preferred_command: String) {
+        let mut keybindings = self.config_manager.user_keybindings();
+        for (command, bound) in self.config_manager.keybindings() {
+            if bound == key && command != preferred_command {
+                keybindings.insert(command, String::new());
+            }
+        }
+        keybindings.insert(preferred_command, key);
+        let mut changes = Table::new();
+        changes.insert("keybindings".into(), json!(keybindings));
+        let new_config = self.config_manager.table_for_update(ConfigDomain::General, changes);
+        self.set_config(ConfigDomain::General, new_config, None);
+    }
+
+    /// Saves the files open in the workspace, and the state of their views,
+    /// to `path`. Views of untitled buffers are left out.
+    fn do_session_save(&self, path: &Path) -> Result<(), SessionError> {
+        let views = self.views.values()
+            .filter_map(|view| {
+                let view = view.borrow();
+                let info = self.file_manager.get_info(view.get_buffer_id())?;
+                Some(view.session(info.path.clone()))
+            })
+            .collect();
+        Session { views }.save(path)
+    }
+
+    /// Opens the views of the session saved to `path`, returning their ids
+    /// and files. Files that no longer exist are skipped with an alert;
+    /// those already open get another view.
+    fn do_session_restore(&mut self, path: &Path) -> Result<Value, SessionError> {
+        let session = Session::load(path)?;
+        let mut opened = Vec::new();
+        for view in session.views {
+            if !view.path.exists() {
+                self.peer.alert(format!("{} no longer exists, and was not restored",
+                                        view.path.display()));
+                continue;
+            }
+            let view_id = match self.file_manager.get_editor(&view.path) {
+                Some(buffer_id) => self.new_sibling_view(buffer_id),
+                None => match self.new_view(Some(view.path.clone())) {
+                    Ok(view_id) => view_id,
+                    Err(err) => {
+                        warn!("restoring {:?} failed: {:?}", view.path, err);
+                        self.peer.alert(format!("could not open {}", view.path.display()));
+                        continue;
+                    }
+                },
+            };
+            opened.push(json!({"view_id": view_id, "path": view.path}));
+            self.pending_restores.push((view_id, view));
+        }
+        Ok(json!(opened))
     }
 
     fn do_get_config(&self, view_id: ViewId) -> Result<Table, RemoteError> {
@@ -503,7 +1060,18 @@ impl CoreState {
             return;
         }
 
+        if self.supervisor.is_disabled(plugin) {
+            info!("enabling plugin {} again", plugin);
+        }
+        if let Some(crashed) = self.supervisor.reset(plugin) {
+            self.abandon_plugin_requests(crashed);
+        }
+        self.launch_plugin(plugin);
+    }
+
+    fn launch_plugin(&mut self, plugin: &str) {
         if let Some(manifest) = self.plugins.get_named(plugin) {
+            self.peer.plugin_status(plugin, &PluginStatus::Starting, None);
             //TODO: lots of races possible here, we need to keep track of
             //pending launches.
             start_plugin_process(manifest.clone(),
@@ -515,12 +1083,16 @@ impl CoreState {
     }
 
     fn do_stop_plugin(&mut self, _view_id: ViewId, plugin: &str) {
+        if let Some(crashed) = self.supervisor.reset(plugin) {
+            self.abandon_plugin_requests(crashed);
+        }
         if let Some(p) = self.running_plugins.iter()
             .position(|p| p.name == plugin)
             .map(|ix| self.running_plugins.remove(ix)) {
                 //TODO: verify shutdown; kill if necessary
                 p.shutdown();
                 self.after_stop_plugin(&p);
+                self.peer.plugin_status(&p.name, &PluginStatus::Disabled, None);
             }
     }
 
@@ -530,8 +1102,180 @@ impl CoreState {
             .for_each(|p| p.dispatch_command(view_id, method, params))
     }
 
+    fn do_plugin_streaming_rpc(&self, view_id: ViewId, receiver: &str, request_id: usize,
+                               method: &str, params: &Value) {
+        let plugin = match self.running_plugins.iter().find(|p| p.name == receiver) {
+            Some(plugin) => plugin,
+            None => {
+                let err = RemoteError::custom(404, format!("no plugin {} running", receiver),
+                                              None);
+                return self.peer.plugin_request_complete(view_id, request_id, &Err(err));
+            }
+        };
+        if let Some(mut ctx) = self.make_context(view_id) {
+            ctx.start_streaming_request(plugin, method, params,
+                                        Box::new(ClientStream { request_id }));
+        }
+    }
+
     fn after_stop_plugin(&mut self, plugin: &Plugin) {
-        self.iter_groups().for_each(|mut cx| cx.plugin_stopped(plugin));
+        self.iter_views().for_each(|mut cx| cx.plugin_stopped(plugin));
+    }
+
+    fn abandon_plugin_requests(&mut self, plugin: PluginId) {
+        self.iter_views().for_each(|mut cx| cx.abandon_plugin_requests(plugin));
+    }
+
+    /// Searches the symbols of every file in the workspace for `query`.
+    /// Plugins are first asked for the symbols of the buffers edited since
+    /// their symbols were last listed.
+    fn do_search_workspace_symbols(&mut self, request_id: usize, query: String) {
+        // a new search supersedes the one in progress
+        self.answer_symbol_search();
+
+        let waiting = {
+            let providers: Vec<_> = self.running_plugins.iter()
+                .filter(|p| p.has_capability(PluginCapability::DocumentSymbolProvider))
+                .collect();
+            let mut waiting = Vec::new();
+            for (buffer_id, editor) in &self.editors {
+                let path = match self.file_manager.get_info(*buffer_id) {
+                    Some(info) => &info.path,
+                    None => continue,
+                };
+                let view_id = match self.views.values()
+                    .find(|v| v.borrow().get_buffer_id() == *buffer_id) {
+                    Some(view) => view.borrow().get_view_id(),
+                    None => continue,
+                };
+                let rev = editor.borrow().get_head_rev_token();
+                if self.symbol_index.is_current(path, rev) {
+                    continue;
+                }
+                for plugin in &providers {
+                    let weak_core = self.self_ref.clone().unwrap();
+                    let plugin_id = plugin.id;
+                    plugin.get_document_symbols(view_id, move |resp| {
+                        weak_core.handle_document_symbols(plugin_id, view_id, rev, resp);
+                    });
+                    waiting.push((plugin_id, view_id));
+                }
+            }
+            waiting
+        };
+
+        let deadline = Instant::now() + SEARCH_TIMEOUT;
+        let no_wait = waiting.is_empty();
+        self.symbol_search = Some(SymbolSearch::new(request_id, query, waiting, deadline));
+        if no_wait {
+            self.answer_symbol_search();
+        } else {
+            self.peer.schedule_timer(deadline, SYMBOL_SEARCH_TOKEN);
+        }
+    }
+
+    pub(crate) fn plugin_document_symbols(&mut self, plugin_id: PluginId, view_id: ViewId,
+                                          rev: u64, response: Result<Value, xi_rpc::Error>) {
+        let symbols = match response.map(serde_json::from_value::<Vec<SymbolInfo>>) {
+            Ok(Ok(symbols)) => Some(symbols),
+            Ok(Err(err)) => {
+                warn!("bad document symbols from plugin {}: {:?}", plugin_id, err);
+                None
+            }
+            Err(err) => {
+                warn!("document symbol request to plugin {} failed: {:?}", plugin_id, err);
+                None
+            }
+        };
+        let buffer_id = self.views.get(&view_id).map(|v| v.borrow().get_buffer_id());
+        let path = buffer_id.and_then(|id| self.file_manager.get_info(id))
+            .map(|info| info.path.clone());
+        if let (Some(symbols), Some(path)) = (symbols, path) {
+            self.symbol_index.update(path, rev, plugin_id, symbols);
+        }
+        let settled = self.symbol_search.as_mut()
+            .map(|search| search.respond(plugin_id, view_id))
+            .unwrap_or(false);
+        if settled {
+            self.answer_symbol_search();
+        }
+    }
+
+    fn handle_symbol_search_timer(&mut self) {
+        let expired = self.symbol_search.as_ref()
+            .map(|search| search.is_expired(Instant::now()))
+            .unwrap_or(false);
+        if expired {
+            self.answer_symbol_search();
+        }
+    }
+
+    /// Sends the results of the search in progress, with the symbols
+    /// listed so far.
+    fn answer_symbol_search(&mut self) {
+        if let Some(search) = self.symbol_search.take() {
+            let symbols = self.symbol_index.search(&search.query);
+            self.peer.workspace_symbols(search.request_id, &symbols);
+        }
+    }
+
+    /// Searches the files under `root`, or the working directory, for
+    /// `query`. The results are sent straight away, and again as more
+    /// files are indexed, until the workspace has been scanned.
+    fn do_open_file_fuzzy(&mut self, request_id: usize, query: String,
+                          root: Option<PathBuf>, limit: Option<usize>) {
+        let root = match root.or_else(|| env::current_dir().ok()) {
+            Some(root) => root,
+            None => return,
+        };
+        let same_root = self.file_finder.as_ref().map(|f| f.root() == root).unwrap_or(false);
+        if !same_root {
+            self.start_file_index(root);
+        }
+        let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        let finder = self.file_finder.as_mut().unwrap();
+        let results = finder.search(&query, limit);
+        let done = finder.is_scanned();
+        self.peer.fuzzy_file_results(request_id, &results, done);
+        if !done {
+            finder.searches.push(FileSearch { request_id, query, limit, results });
+        }
+    }
+
+    /// Replaces the file index with one of `root`, built in the background.
+    fn start_file_index(&mut self, root: PathBuf) {
+        #[cfg(feature = "notify")]
+        {
+            if let Some(old_root) = self.file_finder.as_ref().map(|f| f.root()) {
+                self.file_manager.watcher().unwatch(&old_root, FILE_INDEX_EVENT_TOKEN);
+            }
+            self.file_manager.watcher().watch(&root, true, FILE_INDEX_EVENT_TOKEN);
+        }
+        let peer = self.peer.clone();
+        self.file_finder = Some(FileFinder::spawn(root, move || {
+            peer.schedule_idle(FILE_INDEX_TOKEN);
+        }));
+    }
+
+    /// Sends the file searches whose results have changed as the index has
+    /// grown, finishing them once the scan is done.
+    fn update_file_searches(&mut self) {
+        let finder = match self.file_finder.as_mut() {
+            Some(finder) => finder,
+            None => return,
+        };
+        let done = finder.is_scanned();
+        let mut searches = mem::replace(&mut finder.searches, Vec::new());
+        for search in &mut searches {
+            let results = finder.search(&search.query, search.limit);
+            if done || results != search.results {
+                self.peer.fuzzy_file_results(search.request_id, &results, done);
+                search.results = results;
+            }
+        }
+        if !done {
+            finder.searches = searches;
+        }
     }
 
     fn do_set_language(&mut self, view_id: ViewId, language_id: LanguageId) {
@@ -548,18 +1292,42 @@ impl CoreState {
         match token {
             NEW_VIEW_IDLE_TOKEN => self.finalize_new_views(),
             WATCH_IDLE_TOKEN => self.handle_fs_events(),
+            PLUGIN_RESTART_TOKEN => self.restart_plugins(),
+            SYMBOL_SEARCH_TOKEN => self.handle_symbol_search_timer(),
+            FILE_INDEX_TOKEN => self.update_file_searches(),
+            AUTO_SAVE_TOKEN => {
+                let done = self.save_worker.as_mut().map(|w| w.finished()).unwrap_or_default();
+                self.finish_auto_saves(done);
+            }
             other if (other & RENDER_VIEW_IDLE_MASK) != 0 =>
                 self.handle_render_timer(other ^ RENDER_VIEW_IDLE_MASK),
+            other if (other & HOVER_TIMEOUT_MASK) != 0 =>
+                self.handle_hover_timer(other ^ HOVER_TIMEOUT_MASK),
+            other if (other & FORMAT_TIMEOUT_MASK) != 0 =>
+                self.handle_format_timer(other ^ FORMAT_TIMEOUT_MASK),
+            other if (other & BREADCRUMBS_TIMER_MASK) != 0 =>
+                self.handle_breadcrumbs_timer(other ^ BREADCRUMBS_TIMER_MASK),
+            other if (other & AUTO_SAVE_TIMER_MASK) != 0 =>
+                self.handle_auto_save_timer(other ^ AUTO_SAVE_TIMER_MASK),
             other => panic!("unexpected idle token {}", other),
         };
     }
 
     fn finalize_new_views(&mut self) {
         let to_start = mem::replace(&mut self.pending_views, Vec::new());
+        // a view may be closed before it is set up
         to_start.iter().for_each(|(id, config)| {
-            let mut edit_ctx = self.make_context(*id).unwrap();
-            edit_ctx.finish_init(config);
+            if let Some(mut edit_ctx) = self.make_context(*id) {
+                edit_ctx.finish_init(config);
+            }
         });
+        let to_restore = mem::replace(&mut self.pending_restores, Vec::new());
+        for (id, session) in to_restore {
+            if let Some(mut edit_ctx) = self.make_context(id) {
+                edit_ctx.restore_session(&session);
+            }
+        }
+        self.apply_pending_edits();
     }
 
     fn handle_render_timer(&mut self, token: usize) {
@@ -569,6 +1337,105 @@ impl CoreState {
         }
     }
 
+    fn restart_plugins(&mut self) {
+        for name in self.supervisor.take_due_restarts(Instant::now()) {
+            info!("restarting plugin {}", name);
+            self.launch_plugin(&name);
+        }
+    }
+
+    fn handle_hover_timer(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        if let Some(mut ctx) = self.make_context(id) {
+            ctx.expire_hover_requests();
+        }
+    }
+
+    fn handle_breadcrumbs_timer(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        if let Some(mut ctx) = self.make_context(id) {
+            ctx.breadcrumbs_timer_fired();
+        }
+    }
+
+    fn handle_format_timer(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        let path = self.make_context(id).and_then(|mut ctx| ctx.expire_format_request());
+        if let Some(path) = path {
+            self.save_buffer(id, &path);
+        }
+    }
+
+    /// Saves the buffer in the background, unless it was edited again since
+    /// the timer was set, in which case it is set again. The buffer is saved
+    /// as it is, without being cleaned up or formatted.
+    fn handle_auto_save_timer(&mut self, token: usize) {
+        let buffer_id = BufferId(token);
+        let (due, pristine, text, rev) = match self.editors.get(&buffer_id) {
+            Some(ed) => {
+                let ed = ed.borrow();
+                (ed.auto_save_due(), ed.is_pristine(), ed.get_buffer().clone(),
+                 ed.get_head_rev_id())
+            }
+            None => return,
+        };
+        match due {
+            Some(due) if due > Instant::now() => {
+                self.peer.schedule_timer(due, AUTO_SAVE_TIMER_MASK | token);
+                return;
+            }
+            Some(_) => self.editors[&buffer_id].borrow_mut().clear_auto_save(),
+            None => return,
+        }
+        let path = match self.file_manager.get_info(buffer_id) {
+            Some(info) => info.path.clone(),
+            None => return,
+        };
+        if pristine {
+            return;
+        }
+        let (encoding, line_ending) = {
+            let items = &self.config_manager.get_buffer_config(buffer_id).items;
+            (CharacterEncoding::from_charset(&items.charset),
+             LineEnding::from_name(&items.end_of_line))
+        };
+        let save = self.file_manager
+            .start_background_save(buffer_id, &text, rev, encoding, line_ending);
+        match save {
+            Ok(save) => {
+                let peer = self.peer.clone();
+                self.save_worker
+                    .get_or_insert_with(|| SaveWorker::new(move || {
+                        peer.schedule_idle(AUTO_SAVE_TOKEN)
+                    }))
+                    .save(save);
+            }
+            Err(e) => self.peer.auto_save_failed(&path, &e.to_string()),
+        }
+    }
+
+    /// Tells the views of the buffers auto-saved in the background that
+    /// they were saved, as of the revision that was written.
+    fn finish_auto_saves(&mut self, done: Vec<(BackgroundSave, io::Result<()>)>) {
+        for (save, result) in done {
+            if !self.file_manager.finish_background_save(&save, result.is_ok()) {
+                continue;
+            }
+            if let Err(e) = result {
+                self.peer.auto_save_failed(&save.path, &e.to_string());
+                continue;
+            }
+            let view_ids = self.views.values()
+                .filter(|v| v.borrow().get_buffer_id() == save.buffer_id)
+                .map(|v| v.borrow().get_view_id())
+                .collect::<Vec<_>>();
+            for id in view_ids {
+                self.make_context(id).unwrap().after_save(&save.path, save.rev);
+            }
+            self.peer.auto_saved(&save.path, save.rev.token());
+        }
+    }
+
     #[cfg(feature = "notify")]
     fn handle_fs_events(&mut self) {
         let _t = trace_block("CoreState::handle_fs_events", &["core"]);
@@ -579,6 +1446,8 @@ impl CoreState {
                 OPEN_FILE_EVENT_TOKEN => self.handle_open_file_fs_event(event),
                 CONFIG_EVENT_TOKEN => self.handle_config_fs_event(event),
                 THEME_FILE_EVENT_TOKEN => self.handle_themes_fs_event(event),
+                FILE_INDEX_EVENT_TOKEN => self.handle_file_index_fs_event(event),
+                DIRECTORY_CONFIG_EVENT_TOKEN => self.handle_directory_config_fs_event(event),
                 _ => warn!("unexpected fs event token {:?}", token),
             }
         }
@@ -587,6 +1456,25 @@ impl CoreState {
     #[cfg(not(feature = "notify"))]
     fn handle_fs_events(&mut self) { }
 
+    /// Keeps the file index up to date with the files in the workspace.
+    #[cfg(feature = "notify")]
+    fn handle_file_index_fs_event(&mut self, event: DebouncedEvent) {
+        use notify::DebouncedEvent::*;
+        let finder = match self.file_finder.as_ref() {
+            Some(finder) => finder,
+            None => return,
+        };
+        match event {
+            Create(ref path) => finder.add(path),
+            Remove(ref path) => finder.remove(path),
+            Rename(ref from, ref to) => {
+                finder.remove(from);
+                finder.add(to);
+            }
+            _ => (),
+        }
+    }
+
     /// Handles a file system event related to a currently open file
     #[cfg(feature = "notify")]
     fn handle_open_file_fs_event(&mut self, event: DebouncedEvent) {
@@ -613,15 +1501,24 @@ impl CoreState {
         // determining if a file has been changed by another process.
         // A more robust solution would also hash the file's contents.
 
-        if has_changes && is_pristine {
+        if !has_changes {
+            return;
+        }
+        // this is ugly; we don't map buffer_id -> view_id anywhere
+        // but we know we must have a view.
+        let view_ids = self.views.values()
+            .filter(|v| v.borrow().get_buffer_id() == buffer_id)
+            .map(|v| v.borrow().get_view_id())
+            .collect::<Vec<_>>();
+
+        if is_pristine {
             if let Ok(text) = self.file_manager.open(path, buffer_id) {
-                // this is ugly; we don't map buffer_id -> view_id anywhere
-                // but we know we must have a view.
-                let view_id = self.views.values()
-                    .find(|v| v.borrow().get_buffer_id() == buffer_id)
-                    .map(|v| v.borrow().get_view_id())
-                    .unwrap();
-                self.make_context(view_id).unwrap().reload(text);
+                self.make_context(view_ids[0]).unwrap().reload(text);
+            }
+        } else {
+            // the frontend can ask the user, and send `reload` if they agree
+            for view_id in view_ids {
+                self.peer.file_changed_externally(view_id);
             }
         }
     }
@@ -643,9 +1540,73 @@ impl CoreState {
         }
     }
 
+    /// Handles a change to a directory config file of an open file.
+    #[cfg(feature = "notify")]
+    fn handle_directory_config_fs_event(&mut self, event: DebouncedEvent) {
+        use self::DebouncedEvent::*;
+        match event {
+            Create(ref path) | Write(ref path) | Remove(ref path) =>
+                self.reload_directory_config(path),
+            Rename(ref old, ref new) => {
+                self.reload_directory_config(old);
+                self.reload_directory_config(new);
+            }
+            _ => (),
+        }
+    }
+
+    fn reload_directory_config(&mut self, path: &Path) {
+        if path.file_name().and_then(OsStr::to_str) != Some(config::DIRECTORY_CONFIG_FILE_NAME) {
+            return;
+        }
+        match self.config_manager.reload_directory_config(path) {
+            Err(e) => self.report_config_error(e, Some(path)),
+            Ok(changes) => self.handle_config_changes(changes),
+        }
+    }
+
+    /// Sets the directory up to which directory config files are looked
+    /// for, and finds those of the open files again.
+    fn do_set_workspace_root(&mut self, root: Option<PathBuf>) {
+        self.symbol_index.set_root(root.clone());
+        self.config_manager.set_workspace_root(root);
+        let buffers = self.editors.keys()
+            .filter_map(|&id| self.file_manager.get_info(id).map(|info| (id, info.path.clone())))
+            .collect::<Vec<_>>();
+        let mut changes = Vec::new();
+        for (buffer_id, path) in buffers {
+            self.watch_directory_configs(buffer_id, false);
+            if let Some(table) = self.config_manager.update_buffer_path(buffer_id, &path) {
+                changes.push((buffer_id, table));
+            }
+            self.watch_directory_configs(buffer_id, true);
+        }
+        self.handle_config_changes(changes);
+    }
+
+    /// Starts or stops watching the directories searched for the directory
+    /// config files of a buffer.
+    #[cfg(feature = "notify")]
+    fn watch_directory_configs(&mut self, buffer_id: BufferId, watch: bool) {
+        let dirs = self.config_manager.get_buffer_dirs(buffer_id).to_owned();
+        let watcher = self.file_manager.watcher();
+        for dir in &dirs {
+            if watch {
+                watcher.watch_filtered(dir, false, DIRECTORY_CONFIG_EVENT_TOKEN,
+                                       |p| p.file_name().and_then(OsStr::to_str)
+                                       == Some(config::DIRECTORY_CONFIG_FILE_NAME));
+            } else {
+                watcher.unwatch(dir, DIRECTORY_CONFIG_EVENT_TOKEN);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn watch_directory_configs(&mut self, _buffer_id: BufferId, _watch: bool) { }
+
     fn remove_config_at_path(&mut self, path: &Path) {
         if let Some(domain) = self.config_manager.domain_for_path(path) {
-            self.set_config(domain, Table::default());
+            self.set_config(domain, Table::default(), Some(path));
         }
     }
 
@@ -755,7 +1716,13 @@ impl CoreState {
                     .map(|mut ctx| ctx.plugin_info())
                     .collect::<Vec<_>>();
                 plugin.initialize(init_info);
-                self.iter_groups().for_each(|mut cx| cx.plugin_started(&plugin));
+                let weak_core = self.self_ref.clone().unwrap();
+                let id = plugin.id;
+                plugin.request_capabilities(move |resp| {
+                    weak_core.handle_plugin_capabilities(id, resp);
+                });
+                self.iter_views().for_each(|mut cx| cx.plugin_started(&plugin));
+                self.peer.plugin_status(&plugin.name, &PluginStatus::Running, None);
                 self.running_plugins.push(plugin);
             }
             Err(e) => error!("failed to start plugin {:?}", e),
@@ -767,9 +1734,62 @@ impl CoreState {
         warn!("plugin {:?} exited with result {:?}", id, error);
         let running_idx = self.running_plugins.iter()
             .position(|p| p.id == id);
+        // plugins that were asked to stop are no longer running
         if let Some(idx) = running_idx {
             let plugin = self.running_plugins.remove(idx);
             self.after_stop_plugin(&plugin);
+            let reason = match error {
+                Ok(()) => "exited unexpectedly".to_owned(),
+                Err(err) => err.to_string(),
+            };
+            let policy = self.plugins.get_named(&plugin.name)
+                .map(|desc| desc.restart_policy.clone())
+                .unwrap_or_default();
+            let now = Instant::now();
+            match self.supervisor.plugin_crashed(&plugin.name, plugin.id, &policy, now) {
+                CrashAction::Restart(due) => {
+                    let message = format!("restarting in {}s", (due - now).as_secs());
+                    self.peer.plugin_status(&plugin.name, &PluginStatus::Crashed(reason),
+                                            Some(&message));
+                    self.peer.schedule_timer(due, PLUGIN_RESTART_TOKEN);
+                }
+                CrashAction::Disable => {
+                    self.abandon_plugin_requests(plugin.id);
+                    self.peer.plugin_status(&plugin.name, &PluginStatus::Crashed(reason), None);
+                    self.peer.plugin_status(&plugin.name, &PluginStatus::Disabled,
+                                            Some("crashed too many times"));
+                    self.peer.alert(format!("The plugin {} has been disabled because it \
+                                             keeps crashing.", plugin.name));
+                }
+            }
+        }
+    }
+
+    pub(crate) fn plugin_capabilities(&mut self, plugin_id: PluginId,
+                                      response: Result<Value, xi_rpc::Error>) {
+        let capabilities = match response.map(serde_json::from_value::<Vec<PluginCapability>>) {
+            Ok(Ok(capabilities)) => capabilities,
+            Ok(Err(err)) => {
+                warn!("bad capabilities from {}: {:?}", plugin_id, err);
+                Vec::new()
+            }
+            // plugins that predate the handshake have no optional capabilities
+            Err(err) => {
+                info!("no capabilities from {}: {:?}", plugin_id, err);
+                Vec::new()
+            }
+        };
+        let idx = match self.running_plugins.iter().position(|p| p.id == plugin_id) {
+            Some(idx) => idx,
+            None => return,
+        };
+        self.running_plugins[idx].capabilities = capabilities;
+        // once its capabilities are known, a restarted plugin is sent the
+        // requests its crashed instance did not answer
+        let crashed = self.supervisor.take_restarted(&self.running_plugins[idx].name);
+        if let Some(crashed) = crashed {
+            let plugin = &self.running_plugins[idx];
+            self.iter_views().for_each(|mut cx| cx.resend_plugin_requests(crashed, plugin));
         }
     }
 
@@ -782,20 +1802,133 @@ impl CoreState {
         }
     }
 
+    pub(crate) fn plugin_inlay_hints(&mut self, plugin_id: PluginId, view_id: ViewId,
+                                     response: Result<Value, xi_rpc::Error>) {
+        if let Some(mut edit_ctx) = self.make_context(view_id) {
+            edit_ctx.do_inlay_hints(plugin_id, response);
+        }
+    }
+
+    pub(crate) fn plugin_document_highlights(&mut self, plugin_id: PluginId, view_id: ViewId,
+                                             generation: u64,
+                                             response: Result<Value, xi_rpc::Error>) {
+        if let Some(mut edit_ctx) = self.make_context(view_id) {
+            edit_ctx.do_document_highlights(plugin_id, generation, response);
+        }
+    }
+
+    pub(crate) fn plugin_prepare_rename(&mut self, plugin_id: PluginId, view_id: ViewId,
+                                        request_id: usize,
+                                        response: Result<Value, xi_rpc::Error>) {
+        let range = match code_actions::parse_response::<Option<RenameRange>>(response) {
+            Ok(range) => range,
+            Err(msg) => {
+                warn!("prepare rename request to plugin {} failed: {}", plugin_id, msg);
+                None
+            }
+        };
+        self.peer.prepare_rename_result(view_id, request_id, range.as_ref());
+    }
+
+    /// Makes the edits of a rename in every file, opening the files that
+    /// are not open, and saves each buffer edited.
+    pub(crate) fn plugin_rename(&mut self, plugin_id: PluginId, view_id: ViewId,
+                                request_id: usize, rev: u64,
+                                response: Result<Value, xi_rpc::Error>) {
+        if !self.views.contains_key(&view_id) {
+            return;
+        }
+        let result = code_actions::parse_response::<WorkspaceEdit>(response).map_err(|msg| {
+            warn!("rename request to plugin {} failed: {}", plugin_id, msg);
+            RenameError::Provider(msg)
+        });
+        let result = result.and_then(|edit| {
+            let opened = self.apply_workspace_edit(view_id, rev, edit.clone(), true)?;
+            Ok((edit, opened))
+        });
+        self.peer.rename_result(view_id, request_id, &result.map_err(|err| err.into()));
+    }
+
+    pub(crate) fn plugin_code_actions(&mut self, plugin_id: PluginId, view_id: ViewId,
+                                      start: usize, end: usize, rev: u64,
+                                      response: Result<Value, xi_rpc::Error>) {
+        if let Some(mut edit_ctx) = self.make_context(view_id) {
+            edit_ctx.do_code_actions(plugin_id, start, end, rev, response);
+        }
+    }
+
     pub(crate) fn plugin_notification(&mut self, _ctx: &RpcCtx,
                                        view_id: ViewId, plugin_id: PluginId,
                                        cmd: PluginNotification) {
+        // responses are sent to the view plugins know the buffer by, and
+        // routed here to the view that made the request
+        match cmd {
+            PluginNotification::ShowHover { request_id, result } => {
+                let sender = self.request_sender(RequestKind::Hover, request_id);
+                if let Some((view_id, request_id)) = sender {
+                    if let Some(mut ctx) = self.make_context(view_id) {
+                        ctx.do_show_hover(plugin_id, request_id, result);
+                    }
+                }
+                return;
+            }
+            PluginNotification::ShowDefinition { request_id, result } => {
+                let sender = self.request_sender(RequestKind::Definition, request_id);
+                if let Some((view_id, request_id)) = sender {
+                    self.do_show_definition(view_id, plugin_id, request_id, result);
+                }
+                return;
+            }
+            PluginNotification::ReportProgress { task, message } =>
+                return self.do_report_progress(plugin_id, task, message),
+            PluginNotification::FormatEdits { request_id, result } => {
+                let sender = self.request_sender(RequestKind::Format, request_id);
+                if let Some((view_id, request_id)) = sender {
+                    self.do_format_edits(view_id, plugin_id, request_id, result);
+                }
+                return;
+            }
+            _ => (),
+        }
         if let Some(mut edit_ctx) = self.make_context(view_id) {
             edit_ctx.do_plugin_cmd(plugin_id, cmd)
         }
     }
 
+    /// Returns the view and view-local id of the request a plugin is
+    /// responding to, if it is still outstanding.
+    fn request_sender(&self, kind: RequestKind, id: usize) -> Option<(ViewId, usize)> {
+        self.plugin_requests.borrow().sender(kind, id)
+    }
+
+    fn do_format_edits(&mut self, view_id: ViewId, plugin_id: PluginId, request_id: usize,
+                       result: Result<Vec<TextEdit>, RemoteError>) {
+        let path = self.make_context(view_id)
+            .and_then(|mut ctx| ctx.do_format_edits(plugin_id, request_id, result));
+        if let Some(path) = path {
+            self.save_buffer(view_id, &path);
+        }
+    }
+
+    fn do_report_progress(&self, plugin_id: PluginId, task: Option<String>,
+                          message: Option<String>) {
+        let plugin = match self.running_plugins.iter().find(|p| p.id == plugin_id) {
+            Some(plugin) => plugin,
+            None => return,
+        };
+        let status = match task {
+            Some(task) => PluginStatus::Busy(task),
+            None => PluginStatus::Idle,
+        };
+        self.peer.plugin_status(&plugin.name, &status, message.as_ref().map(String::as_str));
+    }
+
     pub(crate) fn plugin_request(&mut self, _ctx: &RpcCtx, view_id: ViewId,
                                   plugin_id: PluginId, cmd: PluginRequest
                                   ) -> Result<Value, RemoteError>
     {
         if let Some(mut edit_ctx) = self.make_context(view_id) {
-            Ok(edit_ctx.do_plugin_cmd_sync(plugin_id, cmd))
+            edit_ctx.do_plugin_cmd_sync(plugin_id, cmd)
         } else {
             Err(RemoteError::custom(404, "missing view", None))
         }
@@ -919,9 +2052,21 @@ impl BufferId {
 
 #[cfg(test)]
 mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::collections::BTreeMap;
+
     use serde::Deserialize;
+    use xi_rpc::test_utils::DummyPeer;
+    use xi_rpc::Peer;
 
-    use super::ViewId;
+    use super::{CoreState, ViewId};
+    use code_actions::WorkspaceEdit;
+    use core::dummy_weak_core;
+    use formatting::{EditPosition, TextEdit};
+    use plugins::PluginPid;
+    use rpc::EditNotification;
 
     #[test]
     fn test_deserialize_view_id() {
@@ -931,4 +2076,93 @@ mod tests {
         let de = json!("not-a-view-id");
         assert!(ViewId::deserialize(&de).unwrap_err().is_data());
     }
+
+    #[test]
+    fn workspace_edit_of_unopened_files() {
+        let tmp = tempdir::TempDir::new("xi-test-workspace-edit").unwrap();
+        let (open, unopened) = (tmp.path().join("open.txt"), tmp.path().join("unopened.txt"));
+        fs::write(&open, "one\n").unwrap();
+        fs::write(&unopened, "two\r\nthree\r\n").unwrap();
+        let mut state = CoreState::new(&DummyPeer.box_clone(), None, None);
+        state.self_ref = Some(dummy_weak_core());
+        let view_id = state.new_view(Some(open.clone())).unwrap();
+        let rev = state.editors.values().next().unwrap().borrow().get_head_rev_token();
+
+        let replace_first = |new_text: &str, line: usize| vec![TextEdit {
+            start: EditPosition { line, col: 0 },
+            end: EditPosition { line, col: 3 },
+            new_text: new_text.into(),
+        }];
+        let mut changes = BTreeMap::new();
+        changes.insert(open.clone(), replace_first("1", 0));
+        changes.insert(unopened.clone(), replace_first("2", 0));
+        let opened = state.apply_workspace_edit(view_id, rev, WorkspaceEdit { changes }, false)
+            .unwrap();
+        // the unopened file is opened, and edited once its view is set up
+        assert_eq!(opened.keys().collect::<Vec<_>>(), vec![&unopened]);
+        let opened_id = opened[&unopened];
+        state.finalize_new_views();
+        let buffer_id = state.views[&opened_id].borrow().get_buffer_id();
+        let text = String::from(state.editors[&buffer_id].borrow().get_buffer());
+        assert_eq!(text, "2\nthree\n");
+        assert!(!state.editors[&buffer_id].borrow().is_pristine());
+        assert_eq!(fs::read_to_string(&unopened).unwrap(), "two\r\nthree\r\n");
+        state.do_close_view(opened_id, true);
+
+        // nothing is edited, or left open, if an edit is invalid
+        let short = tmp.path().join("short.txt");
+        fs::write(&short, "four").unwrap();
+        let mut changes = BTreeMap::new();
+        changes.insert(open.clone(), replace_first("5", 0));
+        changes.insert(unopened.clone(), replace_first("3", 1));
+        changes.insert(short.clone(), replace_first("4", 5));
+        let rev = state.editors.values().next().unwrap().borrow().get_head_rev_token();
+        assert!(state.apply_workspace_edit(view_id, rev, WorkspaceEdit { changes }, true)
+                .is_err());
+        let text = String::from(state.editors.values().next().unwrap().borrow().get_buffer());
+        assert_eq!(text, "1\n");
+        assert_eq!(fs::read_to_string(&open).unwrap(), "one\n");
+        assert_eq!(fs::read_to_string(&unopened).unwrap(), "two\r\nthree\r\n");
+        assert_eq!(fs::read_to_string(&short).unwrap(), "four");
+        assert_eq!(state._test_open_views(), vec![view_id]);
+        assert_eq!(state.editors.len(), 1);
+    }
+
+    #[test]
+    fn rename_is_one_undo_group_per_buffer() {
+        let tmp = tempdir::TempDir::new("xi-test-rename").unwrap();
+        let (open, unopened) = (tmp.path().join("open.txt"), tmp.path().join("unopened.txt"));
+        fs::write(&open, "one one\n").unwrap();
+        fs::write(&unopened, "one\n").unwrap();
+        let mut state = CoreState::new(&DummyPeer.box_clone(), None, None);
+        state.self_ref = Some(dummy_weak_core());
+        let view_id = state.new_view(Some(open.clone())).unwrap();
+        state.finalize_new_views();
+        let rev = state.editors.values().next().unwrap().borrow().get_head_rev_token();
+
+        let rename = |line, col| json!({
+            "start": {"line": line, "col": col},
+            "end": {"line": line, "col": col + 3},
+            "new_text": "two",
+        });
+        let edit = json!({"changes": {
+            open.to_str().unwrap(): [rename(0, 0), rename(0, 4)],
+            unopened.to_str().unwrap(): [rename(0, 0)],
+        }});
+        state.plugin_rename(PluginPid(1), view_id, 0, rev, Ok(edit));
+        state.finalize_new_views();
+        assert_eq!(fs::read_to_string(&open).unwrap(), "two two\n");
+        assert_eq!(fs::read_to_string(&unopened).unwrap(), "two\n");
+        assert_eq!(state.views.len(), 2);
+
+        // one undo in each buffer reverts the rename there
+        for view_id in state._test_open_views() {
+            state.make_context(view_id).unwrap().do_edit(EditNotification::Undo);
+        }
+        let mut texts = state.editors.values()
+            .map(|ed| String::from(ed.borrow().get_buffer()))
+            .collect::<Vec<_>>();
+        texts.sort();
+        assert_eq!(texts, vec!["one\n", "one one\n"]);
+    }
 }
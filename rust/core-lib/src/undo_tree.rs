@@ -0,0 +1,235 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The history of undo groups, kept as a tree so that undone edits are not
+//! lost when a new edit is made.
+//!
+//! Each node is an undo group, and its parent is the group that was current
+//! when it was created. The buffer state at a node is given by applying the
+//! groups on the path from the root to it; every other group is undone.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Identifies a node of the tree. This is the node's undo group.
+pub type BranchId = usize;
+
+struct Node {
+    parent: Option<BranchId>,
+    /// Children, oldest first.
+    children: Vec<BranchId>,
+    /// The child that `redo` moves to: the one most recently created or
+    /// moved away from.
+    redo_child: Option<BranchId>,
+}
+
+/// A node of the tree, as reported to the frontend.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct UndoBranch {
+    pub id: BranchId,
+    pub parent: Option<BranchId>,
+    pub children: Vec<BranchId>,
+}
+
+pub struct UndoTree {
+    nodes: BTreeMap<BranchId, Node>,
+    root: BranchId,
+    current: BranchId,
+}
+
+impl UndoTree {
+    /// Creates a tree containing only `root`, which can't be undone.
+    pub fn new(root: BranchId) -> UndoTree {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(root, Node { parent: None, children: Vec::new(), redo_child: None });
+        UndoTree { nodes, root, current: root }
+    }
+
+    /// The undo group whose state the buffer is in.
+    pub fn current(&self) -> BranchId {
+        self.current
+    }
+
+    /// Adds `group` as a new child of the current node, and makes it current.
+    pub fn push(&mut self, group: BranchId) {
+        let current = self.current;
+        {
+            let node = self.nodes.get_mut(&current).unwrap();
+            node.children.push(group);
+            node.redo_child = Some(group);
+        }
+        self.nodes.insert(group, Node {
+            parent: Some(current),
+            children: Vec::new(),
+            redo_child: None,
+        });
+        self.current = group;
+    }
+
+    /// Moves to the parent of the current node, returning the group that is
+    /// now undone, or `None` at the root.
+    pub fn undo(&mut self) -> Option<BranchId> {
+        let undone = self.current;
+        let parent = self.nodes[&undone].parent?;
+        self.nodes.get_mut(&parent).unwrap().redo_child = Some(undone);
+        self.current = parent;
+        Some(undone)
+    }
+
+    /// Moves to the most recently visited child of the current node,
+    /// returning the group that is now redone, or `None` at a leaf.
+    pub fn redo(&mut self) -> Option<BranchId> {
+        let child = self.nodes[&self.current].redo_child?;
+        self.current = child;
+        Some(child)
+    }
+
+    /// Makes `id` the current node. Returns the groups that must be undone
+    /// and the groups that must be redone to get there, or `None` if `id`
+    /// is not in the tree.
+    pub fn jump_to_branch(&mut self, id: BranchId) -> Option<(Vec<BranchId>, Vec<BranchId>)> {
+        if !self.nodes.contains_key(&id) {
+            return None;
+        }
+        let target_path = self.path_from_root(id);
+        let on_target_path: BTreeSet<_> = target_path.iter().cloned().collect();
+
+        let mut undone = Vec::new();
+        let mut ancestor = self.current;
+        while !on_target_path.contains(&ancestor) {
+            undone.push(ancestor);
+            ancestor = self.nodes[&ancestor].parent.unwrap();
+        }
+
+        let ancestor_ix = target_path.iter().position(|&n| n == ancestor).unwrap();
+        let redone = target_path[ancestor_ix + 1..].to_vec();
+        for pair in target_path[ancestor_ix..].windows(2) {
+            self.nodes.get_mut(&pair[0]).unwrap().redo_child = Some(pair[1]);
+        }
+        self.current = id;
+        Some((undone, redone))
+    }
+
+    /// Returns the nodes of the tree, in the order they were created.
+    pub fn branches(&self) -> Vec<UndoBranch> {
+        self.nodes.iter().map(|(&id, node)| UndoBranch {
+            id,
+            parent: node.parent,
+            children: node.children.clone(),
+        }).collect()
+    }
+
+    /// Removes nodes until there are at most `max` left, returning the
+    /// removed groups. The oldest undone leaves are removed first; when the
+    /// tree is a single path, the root is removed instead and its applied
+    /// edit becomes permanent.
+    pub fn prune(&mut self, max: usize) -> Vec<BranchId> {
+        let mut removed = Vec::new();
+        while self.nodes.len() > max.max(1) {
+            let current_path: BTreeSet<_> =
+                self.path_from_root(self.current).into_iter().collect();
+            let leaf = self.nodes.iter()
+                .find(|&(id, node)| node.children.is_empty() && !current_path.contains(id))
+                .map(|(&id, _)| id);
+            let victim = if let Some(leaf) = leaf {
+                let parent = self.nodes[&leaf].parent.unwrap();
+                let parent = self.nodes.get_mut(&parent).unwrap();
+                parent.children.retain(|&child| child != leaf);
+                if parent.redo_child == Some(leaf) {
+                    parent.redo_child = parent.children.last().cloned();
+                }
+                leaf
+            } else if self.current != self.root {
+                // with no undone leaves, the tree is a single path
+                let old_root = self.root;
+                self.root = self.nodes[&old_root].children[0];
+                self.nodes.get_mut(&self.root).unwrap().parent = None;
+                old_root
+            } else {
+                break;
+            };
+            self.nodes.remove(&victim);
+            removed.push(victim);
+        }
+        removed
+    }
+
+    fn path_from_root(&self, id: BranchId) -> Vec<BranchId> {
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[path.last().unwrap()].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_redo() {
+        let mut tree = UndoTree::new(0);
+        assert_eq!(tree.undo(), None);
+        tree.push(1);
+        tree.push(2);
+        assert_eq!(tree.undo(), Some(2));
+        assert_eq!(tree.undo(), Some(1));
+        assert_eq!(tree.undo(), None);
+        assert_eq!(tree.redo(), Some(1));
+        assert_eq!(tree.redo(), Some(2));
+        assert_eq!(tree.redo(), None);
+        assert_eq!(tree.current(), 2);
+    }
+
+    #[test]
+    fn branches() {
+        let mut tree = UndoTree::new(0);
+        tree.push(1);
+        tree.push(2);
+        tree.undo();
+        // a new edit starts a branch, and keeps the old one
+        tree.push(3);
+        tree.push(4);
+        assert_eq!(tree.branches()[1],
+                   UndoBranch { id: 1, parent: Some(0), children: vec![2, 3] });
+
+        assert_eq!(tree.jump_to_branch(2), Some((vec![4, 3], vec![2])));
+        assert_eq!(tree.current(), 2);
+        assert_eq!(tree.undo(), Some(2));
+        // redo follows the branch that was visited last
+        assert_eq!(tree.redo(), Some(2));
+        assert_eq!(tree.jump_to_branch(0), Some((vec![2, 1], vec![])));
+        assert_eq!(tree.redo(), Some(1));
+        assert_eq!(tree.redo(), Some(2));
+        assert_eq!(tree.jump_to_branch(4), Some((vec![2], vec![3, 4])));
+        assert_eq!(tree.jump_to_branch(5), None);
+    }
+
+    #[test]
+    fn prune() {
+        let mut tree = UndoTree::new(0);
+        tree.push(1);
+        tree.push(2);
+        tree.undo();
+        tree.push(3);
+        // the undone leaf goes first, then the root
+        assert_eq!(tree.prune(2), vec![2, 0]);
+        assert_eq!(tree.branches(),
+                   vec![UndoBranch { id: 1, parent: None, children: vec![3] },
+                        UndoBranch { id: 3, parent: Some(1), children: vec![] }]);
+        assert_eq!(tree.undo(), Some(3));
+        assert_eq!(tree.undo(), None);
+    }
+}
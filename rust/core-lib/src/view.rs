@@ -14,28 +14,51 @@
 
 use std::cmp::{min,max};
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::mem;
 use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Instant;
 
 use serde_json::Value;
 
 use xi_rope::rope::{Rope, LinesMetric, RopeInfo};
-use xi_rope::delta::Delta;
+use xi_rope::delta::{Delta, Transformer};
 use xi_rope::tree::Cursor;
 use xi_rope::breaks::{Breaks, BreaksInfo, BreaksMetric, BreaksBaseMetric};
 use xi_rope::interval::Interval;
 use xi_rope::spans::Spans;
 use xi_trace::trace_block;
+use breadcrumbs::{BreadcrumbItem, Breadcrumbs, BreadcrumbsKey};
+use bracket_matching::{scope_aware_bracket_match, scope_aware_enclosing_brackets};
 use client::Client;
+use code_actions::CodeActions;
+use minimap::Minimap;
+use config::BufferItems;
 use edit_types::ViewEvent;
+use layers::Layers;
 use line_cache_shadow::{self, LineCacheShadow, RenderPlan, RenderTactic};
-use movement::{Movement, region_movement, selection_movement};
+use movement::{Movement, selection_movement};
+use plugins::rpc::CompletionList;
 use rpc::{GestureType, MouseAction, SelectionModifier};
 use styles::{Style, ThemeStyleMap};
-use selection::{Affinity, Selection, SelRegion};
+use selection::{Affinity, RectSel, Selection, SelRegion};
+use session::ViewSession;
+use snippet::SnippetSession;
 use tabs::{ViewId, BufferId};
 use width_cache::WidthCache;
 use word_boundaries::WordCursor;
-use find::{Find, FindStatus};
+use find::{Find, FindStatus, SearchScope};
+use definition::DefinitionRequests;
+use formatting::FormatRequests;
+use diagnostics::{DiagnosticSpan, Diagnostics};
+use document_highlight::{DocumentHighlight, DocumentHighlights};
+use gutter::Gutter;
+use hover::HoverRequests;
+use indent_guides::IndentGuideAnnotation;
+use inlay_hints::{InlayHint, InlayHints};
+use streaming::StreamingRequests;
+use index_set::IndexSet;
 use linewrap;
 
 type StyleMap = RefCell<ThemeStyleMap>;
@@ -58,10 +81,25 @@ pub struct View {
 
     drag_state: Option<DragState>,
 
+    /// The rectangle that the selection was built from, if the user is
+    /// making a rectangular selection. Cleared whenever the selection is
+    /// changed by other means.
+    rect_sel: Option<RectSel>,
+
+    /// The brackets at the carets, and the brackets matching them.
+    bracket_highlights: IndexSet,
+
+    /// Indent guide columns, cached per line. Filled in while rendering.
+    indent_guides: RefCell<IndentGuideAnnotation>,
+
     /// vertical scroll position
     first_line: usize,
     /// height of visible portion
     height: usize,
+    /// The folded ranges of logical lines, in order and not overlapping.
+    /// The lines after the first of a range, up to the last, exclusive, are
+    /// shown as a single placeholder line.
+    folds: Vec<(usize, usize)>,
     breaks: Option<Breaks>,
     wrap_col: WrapWidth,
 
@@ -72,6 +110,10 @@ pub struct View {
     /// New offset to be scrolled into position after an edit.
     scroll_to: Option<usize>,
 
+    /// The first visible line, if an edit above the viewport has moved it.
+    /// Sent with the next update so the visible text stays in place.
+    scroll_anchor: Option<usize>,
+
     /// The state for finding text for this view.
     /// Each instance represents a separate search query.
     find: Vec<Find>,
@@ -87,6 +129,57 @@ pub struct View {
     /// The state for replacing matches for this view.
     replace: Option<Replace>,
 
+    /// The completions last shown, which `accept_completion` picks from.
+    completions: Option<CompletionList>,
+
+    /// The tab stops of the snippet being filled in, if any.
+    snippet: Option<SnippetSession>,
+
+    /// The plugins that provide hover information, and unanswered requests.
+    hover: HoverRequests,
+
+    inlay_hints: InlayHints,
+
+    /// Streaming requests to plugins that have yet to complete.
+    streams: StreamingRequests,
+
+    /// The plugins that find definitions, and unanswered requests.
+    definitions: DefinitionRequests,
+
+    /// The format request in flight, if any.
+    formatting: FormatRequests,
+
+    /// The code actions for the last selection region, or for the range
+    /// the frontend last asked about.
+    code_actions: CodeActions,
+
+    /// Whether the frontend was told, with the last update, that there are
+    /// code actions for the last selection region.
+    lightbulb: bool,
+
+    /// The references to the symbol at the caret, until the selection
+    /// changes.
+    highlights: DocumentHighlights,
+
+    /// The minimap last computed, and the one being computed.
+    minimap: Minimap,
+
+    /// The symbols enclosing the caret, sent with each update.
+    breadcrumbs: Breadcrumbs,
+
+    /// The first visible line the sticky header was last computed for;
+    /// `None` until the frontend asks for it.
+    sticky_header_line: Option<usize>,
+
+    /// The selections that `expand_selection` grew from, latest last, so
+    /// that `shrink_selection` can restore them. Any other change to the
+    /// selection clears it.
+    expansions: Vec<Selection>,
+
+    /// The offsets of the marks set with `set_mark`, by name. They follow
+    /// the text they were set at as the buffer is edited.
+    marks: BTreeMap<char, usize>,
+
     /// Tracks whether the replacement string or replace parameters changed.
     replace_changed: bool,
 }
@@ -113,7 +206,7 @@ pub struct Replace {
 }
 
 /// A size, in pixel units (not display pixels).
-#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Size {
     pub width: f64,
     pub height: f64,
@@ -169,10 +262,15 @@ impl View {
             pending_render: false,
             selection: SelRegion::caret(0).into(),
             scroll_to: Some(0),
+            scroll_anchor: None,
             size: Size::default(),
             drag_state: None,
+            rect_sel: None,
+            bracket_highlights: IndexSet::new(),
+            indent_guides: RefCell::new(IndentGuideAnnotation::new(4)),
             first_line: 0,
             height: 10,
+            folds: Vec::new(),
             breaks: None,
             wrap_col: WrapWidth::None,
             lc_shadow: LineCacheShadow::default(),
@@ -181,6 +279,21 @@ impl View {
             highlight_find: false,
             replace: None,
             replace_changed: false,
+            completions: None,
+            snippet: None,
+            hover: HoverRequests::default(),
+            inlay_hints: InlayHints::default(),
+            streams: StreamingRequests::default(),
+            definitions: DefinitionRequests::default(),
+            formatting: FormatRequests::default(),
+            code_actions: CodeActions::default(),
+            minimap: Minimap::default(),
+            breadcrumbs: Breadcrumbs::default(),
+            sticky_header_line: None,
+            lightbulb: false,
+            highlights: DocumentHighlights::default(),
+            expansions: Vec::new(),
+            marks: BTreeMap::new(),
         }
     }
 
@@ -196,6 +309,16 @@ impl View {
         self.replace.clone()
     }
 
+    /// Returns a delta replacing every match of the active search query with
+    /// `replacement`, or `None` if there is no query.
+    pub(crate) fn replace_all_delta(&self, text: &Rope, replacement: &str,
+                                    preserve_case: bool) -> Option<Delta<RopeInfo>>
+    {
+        self.find.iter()
+            .find(|find| find.is_active())
+            .map(|find| find.replace_all(text, replacement, preserve_case))
+    }
+
     pub(crate) fn set_has_pending_render(&mut self, pending: bool) {
         self.pending_render = pending
     }
@@ -204,22 +327,32 @@ impl View {
         self.pending_render
     }
 
-    pub(crate) fn do_edit(&mut self, text: &Rope, cmd: ViewEvent) {
+    pub(crate) fn do_edit(&mut self, text: &Rope, config: &BufferItems, cmd: ViewEvent) {
         use self::ViewEvent::*;
         match cmd {
             Move(movement) => self.do_move(text, movement, false),
             ModifySelection(movement) => self.do_move(text, movement, true),
+            ModifyRectSelection(movement) =>
+                self.modify_rect_selection(text, movement, config.tab_size),
             SelectAll => self.select_all(text),
+            Deselect => self.deselect(text),
+            ShrinkSelection => self.shrink_selection(text),
             Scroll(range) => self.set_scroll(range.first, range.last),
-            AddSelectionAbove =>
-                self.add_selection_by_movement(text, Movement::Up),
-            AddSelectionBelow =>
-                self.add_selection_by_movement(text, Movement::Down),
+            SetViewport { height } => self.set_viewport(text, height),
+            FoldRange { start_line, end_line } => self.fold_range(text, start_line, end_line),
+            UnfoldRange { start_line, end_line } =>
+                self.unfold_range(text, start_line, end_line),
+            AddSelectionAbove => self.add_selection_above(text, config.tab_size),
+            AddSelectionBelow => self.add_selection_below(text, config.tab_size),
             Gesture { line, col, ty } =>
-                self.do_gesture(text, line, col, ty),
-            GotoLine { line } => self.goto_line(text, line),
-            Find { chars, case_sensitive, regex, whole_words } =>
-                self.do_find(text, chars, case_sensitive, regex, whole_words),
+                self.do_gesture(text, line, col, ty, config.tab_size),
+            GotoLine { line, col } => self.goto_line(text, line, col),
+            SetMark { name } => self.set_mark(name),
+            JumpToMark { name, line } => self.jump_to_mark(text, name, line),
+            Find { chars, case_sensitive, regex, whole_words, scope } => {
+                let scope = self.selection_scope(scope);
+                self.do_find(text, chars, case_sensitive, regex, whole_words, scope)
+            }
             FindNext { wrap_around, allow_same, modify_selection } =>
                 self.do_find_next(text, false, wrap_around, allow_same, &modify_selection),
             FindPrevious { wrap_around, allow_same, modify_selection } =>
@@ -230,17 +363,17 @@ impl View {
                 // should be removed in favor of do_gesture
                 warn!("Usage of click is deprecated; use do_gesture");
                 if (flags & FLAG_SELECT) != 0 {
-                    self.do_gesture(text, line, column, GestureType::RangeSelect)
+                    self.do_gesture(text, line, column, GestureType::RangeSelect, config.tab_size)
                 } else if click_count == Some(2) {
-                    self.do_gesture(text, line, column, GestureType::WordSelect)
+                    self.do_gesture(text, line, column, GestureType::WordSelect, config.tab_size)
                 } else if click_count == Some(3) {
-                    self.do_gesture(text, line, column, GestureType::LineSelect)
+                    self.do_gesture(text, line, column, GestureType::LineSelect, config.tab_size)
                 } else {
-                    self.do_gesture(text, line, column, GestureType::PointSelect)
+                    self.do_gesture(text, line, column, GestureType::PointSelect, config.tab_size)
                 }
             }
             Drag(MouseAction { line, column, .. }) =>
-                self.do_drag(text, line, column, Affinity::default()),
+                self.do_drag(text, line, column, Affinity::default(), config.tab_size),
             Cancel => self.do_cancel(text),
             HighlightFind { visible } => {
                 self.highlight_find = visible;
@@ -253,10 +386,13 @@ impl View {
                 self.do_set_replace(chars, preserve_case),
             SelectionForReplace => self.do_selection_for_replace(text),
             SelectionIntoLines => self.do_split_selection_into_lines(text),
+            SelectWord => self.do_select_word(text),
+            SelectParagraph => self.do_select_paragraph(text),
         }
     }
 
-    fn do_gesture(&mut self, text: &Rope, line: u64, col: u64, ty: GestureType) {
+    fn do_gesture(&mut self, text: &Rope, line: u64, col: u64, ty: GestureType,
+                  tab_size: usize) {
         let line = line as usize;
         let col = col as usize;
         let offset = self.line_col_to_offset(text, line, col);
@@ -274,7 +410,12 @@ impl View {
             GestureType::MultiLineSelect =>
                 self.select_line(text, offset, line, true),
             GestureType::MultiWordSelect =>
-                self.select_word(text, offset, true)
+                self.select_word(text, offset, true),
+            GestureType::RectSelect => {
+                self.drag_state = None;
+                let col = self.offset_to_display_col(text, offset, tab_size);
+                self.set_rect_selection(text, RectSel::caret(line, col), tab_size);
+            }
         }
     }
 
@@ -294,8 +435,41 @@ impl View {
         self.find.clear();
     }
 
-    fn goto_line(&mut self, text: &Rope, line: u64) {
-        let offset = self.line_col_to_offset(text, line as usize, 0);
+    /// Moves to a caret at `line` and `col`, which are clamped to the end
+    /// of the text and of the line, and centers that line vertically.
+    fn goto_line(&mut self, text: &Rope, line: u64, col: u64) {
+        let offset = self.line_col_to_offset(text, line as usize, col as usize);
+        self.set_selection(text, SelRegion::caret(offset));
+        // the frontend owns the viewport, so it is sent `scroll_to` for the
+        // target; centering here only renders the lines it scrolls to
+        let line = self.line_of_offset(text, offset);
+        self.first_line = line.saturating_sub(self.height / 2);
+        self.scroll_to = Some(offset);
+    }
+
+    /// Sets the mark `name` at the caret, replacing any mark of that name.
+    fn set_mark(&mut self, name: char) {
+        if let Some(region) = self.selection.last() {
+            self.marks.insert(name, region.end);
+        }
+    }
+
+    /// Moves the caret to the mark `name`, if it is set. With `line`, as
+    /// for vim's `'`, the caret goes to the first non-blank character of
+    /// the mark's line rather than to the mark itself.
+    fn jump_to_mark(&mut self, text: &Rope, name: char, line: bool) {
+        let mut offset = match self.marks.get(&name) {
+            Some(&offset) => offset,
+            None => return,
+        };
+        if line {
+            let start = text.offset_of_line(text.line_of_offset(offset));
+            let end = text.offset_of_line(text.line_of_offset(offset) + 1);
+            let indent = text.slice_to_cow(start..end).chars()
+                .take_while(|&c| c == ' ' || c == '\t')
+                .count();
+            offset = start + indent;
+        }
         self.set_selection(text, SelRegion::caret(offset));
     }
 
@@ -308,12 +482,160 @@ impl View {
         let last = max(last, 0) as usize;
         self.first_line = first;
         self.height = last - first;
+        self.scroll_anchor = None;
+    }
+
+    /// Sets the number of lines the frontend shows, as when its window is
+    /// resized, keeping the text at the top of the view in place. Only the
+    /// lines it newly reveals are invalidated, so that the next update
+    /// renders them and copies the ones still visible. The width is not
+    /// needed: lines are wrapped to the `wrap_width` setting or to the
+    /// pixel width given with `resize`.
+    fn set_viewport(&mut self, text: &Rope, height: usize) {
+        let anchor = self.offset_of_line(text, self.first_line);
+        let old_end = self.first_line + self.height;
+        self.keep_first_line_at(text, anchor);
+        self.height = height;
+        let new_end = self.first_line + height;
+        if new_end > old_end {
+            let start = max(old_end, self.first_line);
+            self.lc_shadow.partial_invalidate(start, new_end, line_cache_shadow::ALL_VALID);
+        }
     }
 
     pub fn scroll_height(&self) -> usize {
         self.height
     }
 
+    pub fn first_line(&self) -> usize {
+        self.first_line
+    }
+
+    pub(crate) fn set_completions(&mut self, completions: Option<CompletionList>) {
+        self.completions = completions;
+    }
+
+    pub(crate) fn take_completions(&mut self) -> Option<CompletionList> {
+        self.completions.take()
+    }
+
+    pub(crate) fn hover_requests(&self) -> &HoverRequests {
+        &self.hover
+    }
+
+    pub(crate) fn hover_requests_mut(&mut self) -> &mut HoverRequests {
+        &mut self.hover
+    }
+
+    pub(crate) fn streaming_requests_mut(&mut self) -> &mut StreamingRequests {
+        &mut self.streams
+    }
+
+    pub(crate) fn definition_requests(&self) -> &DefinitionRequests {
+        &self.definitions
+    }
+
+    pub(crate) fn definition_requests_mut(&mut self) -> &mut DefinitionRequests {
+        &mut self.definitions
+    }
+
+    pub(crate) fn format_requests(&self) -> &FormatRequests {
+        &self.formatting
+    }
+
+    pub(crate) fn format_requests_mut(&mut self) -> &mut FormatRequests {
+        &mut self.formatting
+    }
+
+    pub(crate) fn code_actions(&self) -> &CodeActions {
+        &self.code_actions
+    }
+
+    pub(crate) fn code_actions_mut(&mut self) -> &mut CodeActions {
+        &mut self.code_actions
+    }
+
+    pub(crate) fn minimap(&self) -> &Minimap {
+        &self.minimap
+    }
+
+    pub(crate) fn breadcrumbs(&self) -> &Breadcrumbs {
+        &self.breadcrumbs
+    }
+
+    pub(crate) fn breadcrumbs_mut(&mut self) -> &mut Breadcrumbs {
+        &mut self.breadcrumbs
+    }
+
+    /// Sets the breadcrumbs found for `key`, and makes sure that the next
+    /// update is sent if they changed.
+    pub(crate) fn set_breadcrumbs(&mut self, text: &Rope, items: Vec<BreadcrumbItem>,
+                                  key: BreadcrumbsKey, now: Instant) {
+        if self.breadcrumbs.set(items, key, now) {
+            let line = self.line_of_offset(text, self.sel_regions().last().unwrap().end);
+            self.lc_shadow.partial_invalidate(line, line + 1, line_cache_shadow::CURSOR_VALID);
+        }
+    }
+
+    pub(crate) fn sticky_header_line(&self) -> Option<usize> {
+        self.sticky_header_line
+    }
+
+    pub(crate) fn set_sticky_header_line(&mut self, line: usize) {
+        self.sticky_header_line = Some(line);
+    }
+
+    pub(crate) fn inlay_hints(&self) -> &InlayHints {
+        &self.inlay_hints
+    }
+
+    pub(crate) fn inlay_hints_mut(&mut self) -> &mut InlayHints {
+        &mut self.inlay_hints
+    }
+
+    pub(crate) fn has_snippet(&self) -> bool {
+        self.snippet.is_some()
+    }
+
+    /// Starts filling in a snippet, selecting its first tab stop.
+    pub(crate) fn start_snippet(&mut self, text: &Rope, session: SnippetSession) {
+        self.snippet = Some(session);
+        self.select_tab_stop(text);
+    }
+
+    /// Selects the next tab stop of the snippet, or the previous one.
+    pub(crate) fn move_to_tab_stop(&mut self, text: &Rope, forward: bool) {
+        if let Some(session) = self.snippet.as_mut() {
+            session.advance(forward);
+        }
+        self.select_tab_stop(text);
+    }
+
+    /// Selects the current tab stop, ending the snippet if it's the last.
+    fn select_tab_stop(&mut self, text: &Rope) {
+        let (selection, finished) = match self.snippet {
+            Some(ref session) => {
+                let mut selection = Selection::new();
+                for &(start, end) in session.current() {
+                    selection.add_region(SelRegion::new(start, end));
+                }
+                (selection, session.is_finished())
+            }
+            None => return,
+        };
+        self.set_selection(text, selection);
+        if finished {
+            self.snippet = None;
+        }
+    }
+
+    /// Sets the tab width used to measure indentation for indent guides.
+    pub fn set_tab_size(&mut self, text: &Rope, tab_size: usize) {
+        if self.indent_guides.borrow_mut().set_tab_size(tab_size) {
+            self.set_dirty(text);
+        }
+    }
+
     fn scroll_to_cursor(&mut self, text: &Rope) {
         let end = self.sel_regions().last().unwrap().end;
         let line = self.line_of_offset(text, end);
@@ -366,6 +688,9 @@ impl View {
 
     /// Sets the selection to a new value, without invalidating.
     fn set_selection_for_edit(&mut self, text: &Rope, sel: Selection) {
+        self.rect_sel = None;
+        self.expansions.clear();
+        self.clear_document_highlights(text);
         self.selection = sel;
         self.scroll_to_cursor(text);
     }
@@ -373,11 +698,64 @@ impl View {
     /// Sets the selection to a new value, invalidating the line cache as needed.
     /// This function does not perform any scrolling.
     fn set_selection_raw(&mut self, text: &Rope, sel: Selection) {
+        self.rect_sel = None;
+        self.expansions.clear();
+        self.clear_document_highlights(text);
         self.invalidate_selection(text);
         self.selection = sel;
         self.invalidate_selection(text);
     }
 
+    /// Removes the document highlights, and drops those being asked for.
+    /// Returns the generation that the highlights asked for next belong to.
+    pub(crate) fn clear_document_highlights(&mut self, text: &Rope) -> u64 {
+        let cleared = self.highlights.clear();
+        self.invalidate_highlights(text, &cleared);
+        self.highlights.generation()
+    }
+
+    /// Shows the highlights found by a request made at `generation`, unless
+    /// the selection has changed since.
+    pub(crate) fn add_document_highlights(&mut self, text: &Rope, generation: u64,
+                                          highlights: Vec<DocumentHighlight>) {
+        if let Some(added) = self.highlights.add(generation, highlights, text.len()) {
+            self.invalidate_highlights(text, &added);
+        }
+    }
+
+    /// Invalidates the lines of `highlights`, so that they are rendered
+    /// again with or without them.
+    fn invalidate_highlights(&mut self, text: &Rope, highlights: &[DocumentHighlight]) {
+        for highlight in highlights {
+            let (start, end) = highlight.range;
+            let first_line = self.line_of_offset(text, min(start, text.len()));
+            let last_line = self.line_of_offset(text, min(end, text.len())) + 1;
+            self.lc_shadow.partial_invalidate(first_line, last_line,
+                                              line_cache_shadow::STYLES_VALID);
+        }
+    }
+
+    /// Recomputes the bracket highlights from the carets, invalidating the
+    /// lines where they change. Brackets in strings and comments, as scoped
+    /// by `layers`, are not matched.
+    pub(crate) fn update_bracket_highlights(&mut self, text: &Rope, layers: &Layers) {
+        let mut brackets = IndexSet::new();
+        for region in self.selection.iter().filter(|region| region.is_caret()) {
+            if let Some((open, close)) = scope_aware_bracket_match(text, layers, region.end) {
+                brackets.union_one_range(open, open + 1);
+                brackets.union_one_range(close, close + 1);
+            }
+        }
+        if brackets.iter_ranges().eq(self.bracket_highlights.iter_ranges()) {
+            return;
+        }
+        for (start, _) in self.bracket_highlights.iter_ranges().chain(brackets.iter_ranges()) {
+            let line = self.line_of_offset(text, start);
+            self.lc_shadow.partial_invalidate(line, line + 1, line_cache_shadow::STYLES_VALID);
+        }
+        self.bracket_highlights = brackets;
+    }
+
     /// Invalidate the current selection. Note that we could be even more
     /// fine-grained in the case of multiple cursors, but we also want this
     /// method to be fast even when the selection is large.
@@ -394,13 +772,38 @@ impl View {
         self.lc_shadow.partial_invalidate(first_line, last_line, invalid);
     }
 
-    fn add_selection_by_movement(&mut self, text: &Rope, movement: Movement) {
+    /// Adds a caret on the line above each selection region, at the same
+    /// display column.
+    pub(crate) fn add_selection_above(&mut self, text: &Rope, tab_size: usize) {
+        self.add_selection_by_line(text, -1, tab_size);
+    }
+
+    /// Adds a caret on the line below each selection region, at the same
+    /// display column.
+    pub(crate) fn add_selection_below(&mut self, text: &Rope, tab_size: usize) {
+        self.add_selection_by_line(text, 1, tab_size);
+    }
+
+    fn add_selection_by_line(&mut self, text: &Rope, line_delta: isize, tab_size: usize) {
+        let n_lines = self.line_of_offset(text, text.len());
         let mut sel = Selection::new();
         for &region in self.sel_regions() {
             sel.add_region(region);
-            let new_region = region_movement(movement, region, self,
-                                             &text, false);
-            sel.add_region(new_region);
+            let active = if line_delta < 0 { region.min() } else { region.max() };
+            let line = self.line_of_offset(text, active);
+            let target = if line_delta < 0 {
+                match line.checked_sub(1) {
+                    Some(target) => target,
+                    None => continue,
+                }
+            } else if line < n_lines {
+                line + 1
+            } else {
+                continue;
+            };
+            let col = self.offset_to_display_col(text, active, tab_size);
+            let offset = self.display_col_to_offset(text, target, col, tab_size);
+            sel.add_region(SelRegion::caret(offset));
         }
         self.set_selection(text, sel);
     }
@@ -423,6 +826,49 @@ impl View {
         self.set_selection_raw(text, selection);
     }
 
+    /// Collapses each selection to a caret at its focus, so that there are
+    /// as many cursors as before, in the same places.
+    pub fn deselect(&mut self, text: &Rope) {
+        let mut selection = Selection::new();
+        for region in self.sel_regions() {
+            selection.add_region(SelRegion::caret(region.end).with_horiz(region.horiz));
+        }
+        self.set_selection_raw(text, selection);
+    }
+
+    /// Grows each selection region to the smallest enclosing syntactic
+    /// range: the inside of the innermost pair of brackets around it, then
+    /// the pair itself, and finally the whole buffer. There is no syntax
+    /// tree in the core, so the boundaries are those of bracket matching,
+    /// which skips the brackets in strings and comments.
+    ///
+    /// Note: like `select_all`, this does not scroll.
+    pub fn expand_selection(&mut self, text: &Rope, layers: &Layers) {
+        let mut selection = Selection::new();
+        let mut grew = false;
+        for region in self.sel_regions() {
+            let (start, end) = expand_range(text, layers, region.min(), region.max());
+            grew |= (start, end) != (region.min(), region.max());
+            selection.add_region(SelRegion::new(start, end));
+        }
+        if !grew {
+            return;
+        }
+        let mut expansions = mem::replace(&mut self.expansions, Vec::new());
+        expansions.push(self.selection.clone());
+        self.set_selection_raw(text, selection);
+        self.expansions = expansions;
+    }
+
+    /// Restores the selection from before the last `expand_selection`.
+    pub fn shrink_selection(&mut self, text: &Rope) {
+        let mut expansions = mem::replace(&mut self.expansions, Vec::new());
+        if let Some(selection) = expansions.pop() {
+            self.set_selection_raw(text, selection);
+        }
+        self.expansions = expansions;
+    }
+
     /// Selects a specific range (eg. when the user performs SHIFT + click).
     pub fn select_range(&mut self, text: &Rope, offset: usize) {
         let sel = {
@@ -500,6 +946,49 @@ impl View {
         self.set_selection_raw(text, selection);
     }
 
+    /// Expands each caret to the word containing it. Other regions are
+    /// left as they are.
+    fn do_select_word(&mut self, text: &Rope) {
+        let mut selection = Selection::new();
+        for &region in self.selection.iter() {
+            if region.is_caret() {
+                let (start, end) = WordCursor::new(text, region.end).select_word();
+                selection.add_region(SelRegion::new(start, end));
+            } else {
+                selection.add_region(region);
+            }
+        }
+        self.set_selection_raw(text, selection);
+    }
+
+    /// Expands each region to the paragraph containing it, that is, to the
+    /// surrounding lines up to the nearest blank lines. On a blank line, the
+    /// run of blank lines around it is selected instead.
+    fn do_select_paragraph(&mut self, text: &Rope) {
+        let is_blank = |line: usize| {
+            let start = text.offset_of_line(line);
+            let end = text.offset_of_line(line + 1);
+            text.slice_to_cow(start..end).trim().is_empty()
+        };
+        let last_line = text.line_of_offset(text.len());
+        let mut selection = Selection::new();
+        for region in self.selection.iter() {
+            let mut first = text.line_of_offset(region.min());
+            let mut last = text.line_of_offset(region.max());
+            let blank = is_blank(first);
+            while first > 0 && is_blank(first - 1) == blank {
+                first -= 1;
+            }
+            while last < last_line && is_blank(last + 1) == blank {
+                last += 1;
+            }
+            let start = text.offset_of_line(first);
+            let end = text.offset_of_line(last + 1);
+            selection.add_region(SelRegion::new(start, end));
+        }
+        self.set_selection_raw(text, selection);
+    }
+
     /// Starts a drag operation.
     pub fn start_drag(&mut self, offset: usize, min: usize, max: usize, granularity: SelectionGranularity, multi_select: bool) {
         let base_sel = match multi_select {
@@ -511,8 +1000,15 @@ impl View {
 
     /// Does a drag gesture, setting the selection from a combination of the drag
     /// state and new offset.
-    fn do_drag(&mut self, text: &Rope, line: u64, col: u64, affinity: Affinity) {
+    fn do_drag(&mut self, text: &Rope, line: u64, col: u64, affinity: Affinity,
+               tab_size: usize) {
         let offset = self.line_col_to_offset(text, line as usize, col as usize);
+        if let Some(rect) = self.rect_sel {
+            let end_col = self.offset_to_display_col(text, offset, tab_size);
+            let rect = RectSel { end_line: line as usize, end_col, ..rect };
+            self.set_rect_selection(text, rect, tab_size);
+            return;
+        }
         let new_sel = self.drag_state.as_ref().map(|drag_state| {
             let mut sel = drag_state.base_sel.clone();
             // Determine which word or line the cursor is in
@@ -546,6 +1042,87 @@ impl View {
         }
     }
 
+    /// Sets the selection to one region per line of `rect`, so that edits
+    /// apply at the same columns on every line.
+    fn set_rect_selection(&mut self, text: &Rope, rect: RectSel, tab_size: usize) {
+        let sel = self.rect_to_selection(text, rect, tab_size);
+        self.set_selection(text, sel);
+        self.rect_sel = Some(rect);
+    }
+
+    /// Grows or shrinks the rectangular selection by moving its active
+    /// corner. If there is no rectangular selection, one is started from the
+    /// current selection, or from the last caret.
+    fn modify_rect_selection(&mut self, text: &Rope, movement: Movement, tab_size: usize) {
+        self.drag_state = None;
+        let mut rect = match self.rect_sel.or_else(|| self.selection_to_rect(text, tab_size)) {
+            Some(rect) => rect,
+            None => {
+                let end = self.sel_regions().last().unwrap().end;
+                let line = self.line_of_offset(text, end);
+                RectSel::caret(line, self.offset_to_display_col(text, end, tab_size))
+            }
+        };
+        let n_lines = self.line_of_offset(text, text.len());
+        match movement {
+            Movement::Up => rect.end_line = rect.end_line.saturating_sub(1),
+            Movement::Down => rect.end_line = min(rect.end_line + 1, n_lines),
+            Movement::Left => rect.end_col = rect.end_col.saturating_sub(1),
+            Movement::Right => {
+                // don't grow past the end of the longest line in the rectangle
+                let longest = (rect.min_line()..rect.max_line() + 1)
+                    .map(|line| {
+                        let end = self.line_col_to_offset(text, line, usize::max_value());
+                        self.offset_to_display_col(text, end, tab_size)
+                    })
+                    .max()
+                    .unwrap_or(0);
+                if rect.end_col < longest {
+                    rect.end_col += 1;
+                }
+            }
+            _ => return,
+        }
+        self.set_rect_selection(text, rect, tab_size);
+    }
+
+    /// Converts a rectangle to a selection with one region per line. On
+    /// lines shorter than the rectangle, regions are clamped to the end of
+    /// the line.
+    pub(crate) fn rect_to_selection(&self, text: &Rope, rect: RectSel,
+                                    tab_size: usize) -> Selection {
+        let mut sel = Selection::new();
+        for line in rect.min_line()..rect.max_line() + 1 {
+            let start = self.display_col_to_offset(text, line, rect.start_col, tab_size);
+            let end = self.display_col_to_offset(text, line, rect.end_col, tab_size);
+            sel.add_region(SelRegion::new(start, end));
+        }
+        sel
+    }
+
+    /// Returns the rectangle described by the current selection, if it has
+    /// one region on each of a range of lines, all with the same columns.
+    pub(crate) fn selection_to_rect(&self, text: &Rope, tab_size: usize) -> Option<RectSel> {
+        let line_col = |offset| (self.line_of_offset(text, offset),
+                                 self.offset_to_display_col(text, offset, tab_size));
+        let regions = self.sel_regions();
+        let first = regions.first()?;
+        let (first_line, start_col) = line_col(first.start);
+        let (end_line, end_col) = line_col(first.end);
+        if regions.len() < 2 || end_line != first_line {
+            return None;
+        }
+        for (i, region) in regions.iter().enumerate() {
+            let line = first_line + i;
+            if line_col(region.start) != (line, start_col)
+                || line_col(region.end) != (line, end_col) {
+                return None;
+            }
+        }
+        let last_line = first_line + regions.len() - 1;
+        Some(RectSel { start_line: first_line, end_line: last_line, start_col, end_col })
+    }
+
     /// Returns the regions of the current selection.
     pub fn sel_regions(&self) -> &[SelRegion] {
         &self.selection
@@ -568,7 +1145,8 @@ impl View {
     fn render_line(&self, client: &Client, styles: &StyleMap,
                    text: &Rope, start_of_line: &mut Cursor<RopeInfo>,
                    soft_breaks: Option<&mut Cursor<BreaksInfo>>,
-                   style_spans: &Spans<Style>, line_num: usize) -> Value
+                   style_spans: &Spans<Style>, gutter: &Gutter,
+                   diagnostics: &Diagnostics, line_num: usize) -> Value
     {
         let start_pos = start_of_line.pos();
         let pos = soft_breaks.map_or(start_of_line.next::<LinesMetric>(), |bc| {
@@ -614,38 +1192,116 @@ impl View {
             }
         }
 
+        let mut overlays = Vec::new();
+        let brackets = self.bracket_highlights.iter_ranges()
+            .filter(|&(start, end)| start < pos && end > start_pos);
+        for (start, end) in brackets {
+            let style = styles.borrow().get_bracket_style();
+            overlays.push((max(start, start_pos) - start_pos, min(end, pos) - start_pos, style));
+        }
+        let highlights = self.highlights.ranges().iter()
+            .filter(|h| h.range.0 < pos && h.range.1 > start_pos);
+        for highlight in highlights {
+            let (start, end) = highlight.range;
+            let style = styles.borrow().get_document_highlight_style(highlight.kind);
+            overlays.push((max(start, start_pos) - start_pos, min(end, pos) - start_pos, style));
+        }
+
         let styles = self.render_styles(client, styles, start_pos, pos,
-                                        &selections, &hls, style_spans);
+                                        &selections, &hls, &overlays, style_spans);
 
+        // soft wrapping breaks a logical line into several visual lines
+        let logical_line = text.line_of_offset(start_pos);
+        let logical_start = text.offset_of_line(logical_line);
         let mut result = json!({
             "text": &l_str,
             "styles": styles,
         });
+        if start_pos > logical_start {
+            result["wrap_offset"] = json!(start_pos - logical_start);
+        }
 
         if !cursors.is_empty() {
             result["cursor"] = json!(cursors);
         }
+
+        let diagnostics = diagnostics.spans_in_range(start_pos, pos).into_iter()
+            .map(|span| DiagnosticSpan {
+                start: span.start.saturating_sub(start_pos),
+                end: min(span.end, pos).saturating_sub(start_pos),
+                ..span.clone()
+            })
+            .collect::<Vec<_>>();
+        if !diagnostics.is_empty() {
+            result["diagnostics"] = json!(diagnostics);
+        }
+
+        let hints = self.inlay_hints.hints_in_range(start_pos, pos).into_iter()
+            .map(|hint| InlayHint { offset: hint.offset - start_pos, ..hint.clone() })
+            .collect::<Vec<_>>();
+        if !hints.is_empty() {
+            result["inlay_hints"] = json!(hints);
+        }
+
+        // guides and gutter annotations belong to the first visual line of
+        // each logical line
+        if logical_start == start_pos {
+            let guides = self.indent_guides.borrow_mut().guides_for_line(text, logical_line);
+            if !guides.is_empty() {
+                result["indent_guides"] = json!(guides);
+            }
+            let annotations = gutter.annotations(text, logical_line);
+            if !annotations.is_empty() {
+                result["gutter"] = json!(annotations);
+            }
+        }
+        result
+    }
+
+    /// Renders the placeholder line of `fold`, which has a caret if a caret
+    /// is in the text it hides.
+    fn render_fold(&self, text: &Rope, fold: (usize, usize)) -> Value {
+        let start = text.offset_of_line(fold.0 + 1);
+        let end = text.offset_of_line(fold.1);
+        let mut result = json!({
+            "text": "\u{2026}\n",
+            "styles": [],
+            "fold": [fold.0, fold.1],
+        });
+        if self.selection.regions_in_range(start, end).iter()
+            .any(|region| region.end >= start && region.end < end) {
+            result["cursor"] = json!([0]);
+        }
         result
     }
 
     pub fn render_styles(&self, client: &Client, styles: &StyleMap,
                          start: usize, end: usize, sel: &[(usize, usize)],
-                         hls: &[(usize, usize)],
+                         hls: &[(usize, usize)], overlays: &[(usize, usize, Style)],
                          style_spans: &Spans<Style>) -> Vec<isize>
     {
         let mut rendered_styles = Vec::new();
         let style_spans = style_spans.subseq(Interval::new_closed_open(start, end));
 
         let mut ix = 0;
-        // we add the special find highlights (1) and selection (0) styles first.
-        // We add selection after find because we want it to be preferred if the
-        // same span exists in both sets (as when there is an active selection)
+        // we add the special find highlights (1), the styles the core draws
+        // over the text (matching brackets and document highlights) and
+        // selection (0) styles first. We add selection after find because we
+        // want it to be preferred if the same span exists in both sets (as when
+        // there is an active selection)
         for &(sel_start, sel_end) in hls {
             rendered_styles.push((sel_start as isize) - ix);
             rendered_styles.push(sel_end as isize - sel_start as isize);
             rendered_styles.push(1);
             ix = sel_end as isize;
         }
+        for &(ov_start, ov_end, ref style) in overlays {
+            let style_id = self.get_or_def_style_id(client, styles, style);
+            rendered_styles.push((ov_start as isize) - ix);
+            rendered_styles.push(ov_end as isize - ov_start as isize);
+            rendered_styles.push(style_id as isize);
+            ix = ov_end as isize;
+        }
         for &(sel_start, sel_end) in sel {
             rendered_styles.push((sel_start as isize) - ix);
             rendered_styles.push(sel_end as isize - sel_start as isize);
@@ -674,6 +1330,13 @@ impl View {
         ix
     }
 
+    /// The 1-based number of the logical line that the (visual) line `line`
+    /// belongs to; for the placeholder line of a fold, that of the first
+    /// line it hides.
+    fn logical_line_num(&self, text: &Rope, line: usize) -> usize {
+        text.line_of_offset(self.offset_of_line(text, line)) + 1
+    }
+
     fn build_update_op(&self, op: &str, lines: Option<Vec<Value>>, n: usize) -> Value {
         let mut update = json!({
             "op": op,
@@ -689,7 +1352,8 @@ impl View {
 
     fn send_update_for_plan(&mut self, text: &Rope, client: &Client,
                             styles: &StyleMap, style_spans: &Spans<Style>,
-                            plan: &RenderPlan, pristine: bool)
+                            gutter: &Gutter, diagnostics: &Diagnostics,
+                            plan: &RenderPlan, pristine: bool, read_only: bool)
     {
         if !self.lc_shadow.needs_render(plan) { return; }
 
@@ -724,7 +1388,9 @@ impl View {
                         if n_skip > 0 {
                             ops.push(self.build_update_op("skip", None, n_skip));
                         }
-                        ops.push(self.build_update_op("copy", None, seg.n));
+                        let mut op = self.build_update_op("copy", None, seg.n);
+                        op["ln"] = json!(self.logical_line_num(text, seg.our_line_num));
+                        ops.push(op);
                         b.add_span(seg.n, seg.our_line_num, line_cache_shadow::ALL_VALID);
                         line_num = seg.their_line_num + seg.n;
                     } else {
@@ -739,7 +1405,9 @@ impl View {
                         if n_skip > 0 {
                             ops.push(self.build_update_op("skip", None, n_skip));
                         }
-                        ops.push(self.build_update_op("copy", None, seg.n));
+                        let mut op = self.build_update_op("copy", None, seg.n);
+                        op["ln"] = json!(self.logical_line_num(text, seg.our_line_num));
+                        ops.push(op);
                         b.add_span(seg.n, seg.our_line_num, line_cache_shadow::ALL_VALID);
                         line_num = seg.their_line_num + seg.n;
                     } else {
@@ -752,22 +1420,45 @@ impl View {
                             Cursor::new(breaks, offset));
                         let mut rendered_lines = Vec::new();
                         for line_num in start_line..end_line {
+                            if !self.folds.is_empty() {
+                                // lines are not contiguous across a fold
+                                let offset = self.offset_of_line(text, line_num);
+                                if let Some(fold) = self.fold_hiding(text, offset) {
+                                    rendered_lines.push(self.render_fold(text, fold));
+                                    continue;
+                                }
+                                line_cursor.set(offset);
+                                if let Some(breaks) = soft_breaks.as_mut() {
+                                    breaks.set(offset);
+                                }
+                            }
                             let line = self.render_line(client, styles, text,
                                                         &mut line_cursor,
                                                         soft_breaks.as_mut(),
-                                                        style_spans, line_num);
+                                                        style_spans, gutter,
+                                                        diagnostics, line_num);
                             rendered_lines.push(line);
                         }
-                        ops.push(self.build_update_op("ins", Some(rendered_lines), seg.n));
+                        let mut op = self.build_update_op("ins", Some(rendered_lines), seg.n);
+                        op["ln"] = json!(self.logical_line_num(text, start_line));
+                        ops.push(op);
                         b.add_span(seg.n, seg.our_line_num, line_cache_shadow::ALL_VALID);
                     }
                 }
             }
         }
-        let params = json!({
+        self.lightbulb = self.code_actions.is_available();
+        let mut params = json!({
             "ops": ops,
             "pristine": pristine,
+            "read_only": read_only,
+            "lightbulb_available": self.lightbulb,
+            "breadcrumbs": self.breadcrumbs.items(),
+            "wrap_width": self.wrap_columns(),
         });
+        if let Some(first_line) = self.scroll_anchor.take() {
+            params["first_line"] = json!(first_line);
+        }
 
         client.update_view(self.view_id, &params);
         self.lc_shadow = b.build();
@@ -788,15 +1479,21 @@ impl View {
 
     /// Update front-end with any changes to view since the last time sent.
     /// The `pristine` argument indicates whether or not the buffer has
-    /// unsaved changes.
+    /// unsaved changes, and `read_only` whether it refuses edits.
     pub fn render_if_dirty(&mut self, text: &Rope, client: &Client,
                            styles: &StyleMap, style_spans: &Spans<Style>,
-                           pristine: bool)
+                           gutter: &Gutter, diagnostics: &Diagnostics,
+                           pristine: bool, read_only: bool)
     {
+        if self.code_actions.is_available() != self.lightbulb {
+            // the lightbulb is drawn by the caret
+            let line = self.line_of_offset(text, self.sel_regions().last().unwrap().end);
+            self.lc_shadow.partial_invalidate(line, line + 1, line_cache_shadow::CURSOR_VALID);
+        }
         let height = self.line_of_offset(text, text.len()) + 1;
         let plan = RenderPlan::create(height, self.first_line, self.height);
-        self.send_update_for_plan(text, client, styles,
-                                  style_spans, &plan, pristine);
+        self.send_update_for_plan(text, client, styles, style_spans, gutter,
+                                  diagnostics, &plan, pristine, read_only);
         if let Some(new_scroll_pos) = self.scroll_to.take() {
             let (line, col) = self.offset_to_line_col(text, new_scroll_pos);
             client.scroll_to(self.view_id, line, col);
@@ -806,12 +1503,14 @@ impl View {
     // Send the requested lines even if they're outside the current scroll region.
     pub fn request_lines(&mut self, text: &Rope, client: &Client,
                          styles: &StyleMap, style_spans: &Spans<Style>,
-                         first_line: usize, last_line: usize, pristine: bool) {
+                         gutter: &Gutter, diagnostics: &Diagnostics,
+                         first_line: usize, last_line: usize,
+                         pristine: bool, read_only: bool) {
         let height = self.line_of_offset(text, text.len()) + 1;
         let mut plan = RenderPlan::create(height, self.first_line, self.height);
         plan.request_lines(first_line, last_line);
-        self.send_update_for_plan(text, client, styles,
-                                  style_spans, &plan, pristine);
+        self.send_update_for_plan(text, client, styles, style_spans, gutter,
+                                  diagnostics, &plan, pristine, read_only);
     }
 
     /// Invalidates front-end's entire line cache, forcing a full render at the next
@@ -840,6 +1539,39 @@ impl View {
         (line, offset - self.offset_of_line(text, line))
     }
 
+    /// Returns the display column of `offset` within its line, expanding
+    /// tabs to `tab_size` and counting wide characters as two columns.
+    pub(crate) fn offset_to_display_col(&self, text: &Rope, offset: usize,
+                                        tab_size: usize) -> usize {
+        let line_start = self.offset_of_line(text, self.line_of_offset(text, offset));
+        text.slice_to_cow(line_start..offset).chars()
+            .fold(0, |col, c| col + char_display_width(c, col, tab_size))
+    }
+
+    /// Returns the offset in `line` at display column `col`. If the column
+    /// falls inside a tab or a wide character, the offset before it is
+    /// returned; if the line is too short, the end of the line is returned.
+    pub(crate) fn display_col_to_offset(&self, text: &Rope, line: usize, col: usize,
+                                        tab_size: usize) -> usize {
+        let line_start = self.offset_of_line(text, line);
+        let line_end = self.offset_of_line(text, line + 1);
+        let mut cur_col = 0;
+        for (ix, c) in text.slice_to_cow(line_start..line_end).char_indices() {
+            if cur_col >= col || c == '\n' || c == '\r' {
+                return line_start + ix;
+            }
+            cur_col += char_display_width(c, cur_col, tab_size);
+            if cur_col > col {
+                return line_start + ix;
+            }
+        }
+        // a soft-wrapped line: stay before the break
+        if line_end > line_start && self.line_of_offset(text, line_end) > line {
+            return text.prev_grapheme_offset(line_end).unwrap_or(line_start);
+        }
+        line_end
+    }
+
     pub(crate) fn line_col_to_offset(&self, text: &Rope, line: usize, col: usize) -> usize {
         let mut offset = self.offset_of_line(text, line).saturating_add(col);
         if offset >= text.len() {
@@ -862,10 +1594,155 @@ impl View {
         offset
     }
 
+    /// Returns the visible line number containing the given offset. The
+    /// offsets hidden by a fold are on its placeholder line.
+    pub fn line_of_offset(&self, text: &Rope, offset: usize) -> usize {
+        let mut hidden = 0;
+        for (start, end) in self.folded_regions(text) {
+            if offset < start {
+                break;
+            }
+            let placeholder = self.wrapped_line_of_offset(text, start);
+            if offset < end {
+                return placeholder - hidden;
+            }
+            hidden += self.wrapped_line_of_offset(text, end) - placeholder - 1;
+        }
+        self.wrapped_line_of_offset(text, offset) - hidden
+    }
+
+    /// Returns the byte offset corresponding to the line `line`; for the
+    /// placeholder line of a fold, the start of the text it hides.
+    pub fn offset_of_line(&self, text: &Rope, line: usize) -> usize {
+        let mut hidden = 0;
+        for (start, end) in self.folded_regions(text) {
+            let placeholder = self.wrapped_line_of_offset(text, start);
+            if line + hidden < placeholder {
+                break;
+            }
+            if line + hidden == placeholder {
+                return start;
+            }
+            hidden += self.wrapped_line_of_offset(text, end) - placeholder - 1;
+        }
+        self.offset_of_wrapped_line(text, line + hidden)
+    }
+
+    /// The start and end offsets of the text hidden by each fold, in order.
+    fn folded_regions<'a>(&'a self, text: &'a Rope) -> impl Iterator<Item=(usize, usize)> + 'a {
+        self.folds.iter()
+            .map(move |&(start, end)| (text.offset_of_line(start + 1), text.offset_of_line(end)))
+    }
+
+    /// Returns the fold whose hidden text starts at `offset`, which is the
+    /// start of its placeholder line, if there is one.
+    fn fold_hiding(&self, text: &Rope, offset: usize) -> Option<(usize, usize)> {
+        self.folds.iter().zip(self.folded_regions(text))
+            .find(|&(_, (start, _))| start == offset)
+            .map(|(&fold, _)| fold)
+    }
+
+    pub fn folds(&self) -> &[(usize, usize)] {
+        &self.folds
+    }
+
+    /// Folds the logical lines from `start_line` to `end_line`: the lines
+    /// between them are shown as a single placeholder line. Folds that
+    /// overlap the range are replaced.
+    pub(crate) fn fold_range(&mut self, text: &Rope, start_line: usize, end_line: usize) {
+        let end_line = min(end_line, text.measure::<LinesMetric>());
+        if end_line <= start_line + 1 {
+            return;
+        }
+        self.folds.retain(|&(start, end)| end <= start_line || start >= end_line);
+        let ix = self.folds.iter().position(|&(start, _)| start > start_line)
+            .unwrap_or(self.folds.len());
+        self.folds.insert(ix, (start_line, end_line));
+        self.set_dirty(text);
+    }
+
+    /// Returns the state of the view to save with a session, for the file
+    /// at `path`.
+    pub(crate) fn session(&self, path: PathBuf) -> ViewSession {
+        ViewSession {
+            path,
+            selections: self.selection.iter().map(|region| (region.start, region.end)).collect(),
+            folds: self.folds.clone(),
+            first_line: self.first_line,
+        }
+    }
+
+    /// Restores the state saved with a session. The file may have changed
+    /// since: offsets past the end of the text are clamped to it, and
+    /// folds that no longer fit are left out.
+    pub(crate) fn restore_session(&mut self, text: &Rope, session: &ViewSession) {
+        for &(start_line, end_line) in &session.folds {
+            self.fold_range(text, start_line, end_line);
+        }
+        let clamp = |offset| {
+            let offset = min(offset, text.len());
+            if text.is_codepoint_boundary(offset) {
+                offset
+            } else {
+                text.prev_codepoint_offset(offset).unwrap_or(0)
+            }
+        };
+        let mut selection = Selection::new();
+        for &(start, end) in &session.selections {
+            selection.add_region(SelRegion::new(clamp(start), clamp(end)));
+        }
+        if !selection.is_empty() {
+            self.set_selection_raw(text, selection);
+        }
+        let first_line = min(session.first_line, self.line_of_offset(text, text.len()));
+        self.first_line = first_line;
+        self.scroll_anchor = Some(first_line);
+        self.scroll_to = None;
+        self.set_dirty(text);
+    }
+
+    /// Unfolds the folds that overlap the logical lines from `start_line`
+    /// to `end_line`, inclusive.
+    pub(crate) fn unfold_range(&mut self, text: &Rope, start_line: usize, end_line: usize) {
+        let n_folds = self.folds.len();
+        self.folds.retain(|&(start, end)| end <= start_line || start > end_line);
+        if self.folds.len() != n_folds {
+            self.set_dirty(text);
+        }
+    }
+
+    /// Updates the folds for an edit, of `iv` in `last_text` to `new_len`
+    /// bytes: the folds after the edit move with their lines, and those
+    /// whose hidden lines it touches are unfolded. Returns `true` if any
+    /// fold was unfolded.
+    fn update_folds(&mut self, text: &Rope, last_text: &Rope, iv: Interval,
+                    new_len: usize) -> bool {
+        if self.folds.is_empty() {
+            return false;
+        }
+        let added_lines = text.line_of_offset(iv.start() + new_len) as isize
+            - last_text.line_of_offset(iv.end()) as isize;
+        let n_folds = self.folds.len();
+        self.folds = self.folds.iter().filter_map(|&(start_line, end_line)| {
+            let start = last_text.offset_of_line(start_line + 1);
+            let end = last_text.offset_of_line(end_line);
+            if iv.end() < start {
+                Some(((start_line as isize + added_lines) as usize,
+                      (end_line as isize + added_lines) as usize))
+            } else if iv.start() >= end {
+                Some((start_line, end_line))
+            } else {
+                None
+            }
+        }).collect();
+        self.folds.len() != n_folds
+    }
+
     // use own breaks if present, or text if not (no line wrapping)
 
-    /// Returns the visible line number containing the given offset.
-    pub fn line_of_offset(&self, text: &Rope, offset: usize) -> usize {
+    /// Returns the line number, counting each line soft wrapping makes,
+    /// containing the given offset, as if nothing were folded.
+    fn wrapped_line_of_offset(&self, text: &Rope, offset: usize) -> usize {
         match self.breaks {
             Some(ref breaks) => {
                 breaks.convert_metrics::<BreaksBaseMetric, BreaksMetric>(offset)
@@ -874,8 +1751,9 @@ impl View {
         }
     }
 
-    /// Returns the byte offset corresponding to the line `line`.
-    pub fn offset_of_line(&self, text: &Rope, line: usize) -> usize {
+    /// Returns the byte offset corresponding to the wrapped line `line`,
+    /// as if nothing were folded.
+    fn offset_of_wrapped_line(&self, text: &Rope, line: usize) -> usize {
         match self.breaks {
             Some(ref breaks) => {
                 breaks.convert_metrics::<BreaksMetric, BreaksBaseMetric>(line)
@@ -889,11 +1767,47 @@ impl View {
     }
 
     pub(crate) fn rewrap(&mut self, text: &Rope, wrap_col: usize) {
+        let anchor = self.offset_of_line(text, self.first_line);
         if wrap_col > 0 {
             self.breaks = Some(linewrap::linewrap(text, wrap_col));
             self.wrap_col = WrapWidth::Bytes(wrap_col);
         } else {
-            self.breaks = None
+            self.breaks = None;
+            self.wrap_col = WrapWidth::None;
+        }
+        self.keep_first_line_at(text, anchor);
+    }
+
+    /// Scrolls so that the line containing `offset` is the first visible
+    /// one, after the lines have been wrapped again: keeping the text at
+    /// the top of the view where it was.
+    fn keep_first_line_at(&mut self, text: &Rope, offset: usize) {
+        let first_line = self.line_of_offset(text, min(offset, text.len()));
+        if first_line != self.first_line {
+            self.first_line = first_line;
+            self.scroll_anchor = Some(first_line);
+        }
+    }
+
+    /// The column at which lines are soft wrapped, or 0 if they are not
+    /// wrapped at a column: either not at all, or to the width of the view.
+    pub(crate) fn wrap_columns(&self) -> usize {
+        match self.wrap_col {
+            WrapWidth::Bytes(col) if self.breaks.is_some() => col,
+            _ => 0,
+        }
+    }
+
+    /// Returns `true` if the lines are not wrapped as the `word_wrap` and
+    /// `wrap_width` settings would have them, and must be wrapped again.
+    pub(crate) fn needs_rewrap(&self, word_wrap: bool, wrap_width: usize) -> bool {
+        match self.wrap_col {
+            WrapWidth::Width(width) if word_wrap =>
+                (width - self.size.width).abs() > ::std::f64::EPSILON,
+            _ if word_wrap => true,
+            WrapWidth::None => wrap_width > 0,
+            WrapWidth::Bytes(col) => col != wrap_width,
+            WrapWidth::Width(_) => true,
         }
     }
 
@@ -903,10 +1817,12 @@ impl View {
                              client: &Client, style_spans: &Spans<Style>)
     {
         let _t = trace_block("View::wrap_width", &["core"]);
+        let anchor = self.offset_of_line(text, self.first_line);
         self.breaks = Some(linewrap::linewrap_width(text, width_cache,
                                                     style_spans, client,
                                                     self.size.width));
         self.wrap_col = WrapWidth::Width(self.size.width);
+        self.keep_first_line_at(text, anchor);
     }
 
     /// Updates the view after the text has been modified by the given `delta`.
@@ -914,9 +1830,18 @@ impl View {
     /// recomputing line wraps.
     pub fn after_edit(&mut self, text: &Rope, last_text: &Rope,
                       delta: &Delta<RopeInfo>, client: &Client,
-                      width_cache: &mut WidthCache, keep_selections: bool)
+                      width_cache: &mut WidthCache, keep_selections: bool,
+                      after: bool)
     {
         let (iv, new_len) = delta.summary();
+        {
+            let start = last_text.line_of_offset(iv.start());
+            let end = last_text.line_of_offset(iv.end()) + 1;
+            let new_end = text.line_of_offset(iv.start() + new_len) + 1;
+            self.indent_guides.borrow_mut().edit(start, end, new_end - start);
+        }
+        // the start of the first visible line, measured before rewrapping
+        let anchor = self.offset_of_line(last_text, self.first_line);
         if let Some(breaks) = self.breaks.as_mut() {
             match self.wrap_col {
                 WrapWidth::None => (),
@@ -928,14 +1853,19 @@ impl View {
             }
         }
         if self.breaks.is_some() {
+            self.update_folds(text, last_text, iv, new_len);
             // TODO: finer grain invalidation for the line wrapping, needs info
             // about what wrapped.
             self.set_dirty(text);
         } else {
             let start = self.line_of_offset(last_text, iv.start());
             let end = self.line_of_offset(last_text, iv.end()) + 1;
-            let new_end = self.line_of_offset(text, iv.start() + new_len) + 1;
-            self.lc_shadow.edit(start, end, new_end - start);
+            if self.update_folds(text, last_text, iv, new_len) {
+                self.set_dirty(text);
+            } else {
+                let new_end = self.line_of_offset(text, iv.start() + new_len) + 1;
+                self.lc_shadow.edit(start, end, new_end - start);
+            }
         }
         // Any edit cancels a drag. This is good behavior for edits initiated through
         // the front-end, but perhaps not for async edits.
@@ -947,11 +1877,46 @@ impl View {
         }
 
         self.find_changed = FindStatusChange::Matches;
+        self.bracket_highlights = self.bracket_highlights.apply_delta(delta);
+        // the highlights are cleared below; rebase them first so that the
+        // lines they are on now are invalidated
+        self.highlights.apply_delta(delta);
+        self.inlay_hints.apply_delta(delta);
+        if let Some(completions) = self.completions.as_mut() {
+            let offset = completions.trigger_offset;
+            completions.trigger_offset = Transformer::new(delta).transform(offset, false);
+        }
+        let mut transformer = Transformer::new(delta);
+        for offset in self.marks.values_mut() {
+            *offset = transformer.transform(*offset, false);
+        }
+        // typing outside the tab stops ends the snippet
+        let snippet_ended = match self.snippet {
+            Some(ref mut session) => !session.apply_delta(delta),
+            None => false,
+        };
+        if snippet_ended {
+            self.snippet = None;
+        }
 
         // Note: for committing plugin edits, we probably want to know the priority
         // of the delta so we can set the cursor before or after the edit, as needed.
-        let new_sel = self.selection.apply_delta(delta, true, keep_selections);
+        let new_sel = self.selection.apply_delta(delta, after, keep_selections);
+
+        // Keep the first visible line on the same text when the edit is
+        // entirely above it, so that the viewport doesn't jump.
+        if self.first_line > 0 && iv.end() <= anchor {
+            let new_first_line = self.line_of_offset(text, anchor + new_len - iv.size());
+            if new_first_line != self.first_line {
+                self.first_line = new_first_line;
+                self.scroll_anchor = Some(new_first_line);
+            }
+        }
         self.set_selection_for_edit(text, new_sel);
+        if self.scroll_anchor.is_some() {
+            // scrolling the caret into view may have moved the viewport again
+            self.scroll_anchor = Some(self.first_line);
+        }
     }
 
     fn do_selection_for_find(&mut self, text: &Rope, case_sensitive: bool) {
@@ -981,11 +1946,27 @@ impl View {
             self.find.push(Find::new());
         }
 
-        self.find.first_mut().unwrap().do_find(text, &search_query, case_sensitive, false, true);
+        self.find.first_mut().unwrap()
+            .do_find(text, &search_query, case_sensitive, false, true, Vec::new());
+    }
+
+    /// Returns the ranges that a search in `scope` is restricted to: those
+    /// of the selections that are not carets for `SearchScope::Selection`,
+    /// and none otherwise. The function enclosing the caret depends on the
+    /// syntax, so the `EventContext` finds it.
+    fn selection_scope(&self, scope: SearchScope) -> Vec<Interval> {
+        match scope {
+            SearchScope::Selection => self.selection.iter()
+                .filter(|region| !region.is_caret())
+                .map(|region| Interval::new_closed_open(region.min(), region.max()))
+                .collect(),
+            SearchScope::WholeFile | SearchScope::CurrentFunction => Vec::new(),
+        }
     }
 
+    /// Searches for `chars`, within the ranges of `scope` unless it is empty.
     pub fn do_find(&mut self, text: &Rope, chars: String, case_sensitive: bool, is_regex: bool,
-                   whole_words: bool) {
+                   whole_words: bool, scope: Vec<Interval>) {
         self.set_dirty(text);
         self.find_changed = FindStatusChange::Matches;
 
@@ -996,7 +1977,8 @@ impl View {
             self.find.push(Find::new());
         }
 
-        self.find.first_mut().unwrap().do_find(text, &chars, case_sensitive, is_regex, whole_words);
+        self.find.first_mut().unwrap()
+            .do_find(text, &chars, case_sensitive, is_regex, whole_words, scope);
     }
 
     /// Selects the next find match.
@@ -1008,8 +1990,14 @@ impl View {
         }
     }
 
-    /// Selects all find matches.
+    /// Selects all find matches. If there is no search query, the last
+    /// selection (or the word under the last caret) is searched for first.
     pub fn do_find_all(&mut self, text: &Rope) {
+        if !self.find.iter().any(|find| find.is_active()) {
+            self.do_selection_for_find(text, true);
+        }
+
+        // overlapping occurrences of different queries are merged
         let mut selection = Selection::new();
         for find in self.find.iter() {
             for &occurrence in find.occurrences().iter() {
@@ -1120,3 +2108,75 @@ fn clamp(x: usize, min: usize, max: usize) -> usize {
         max
     }
 }
+
+/// Returns the smallest bracket-delimited range strictly containing the
+/// range from `start` to `end`, or the whole of `text` if there is none.
+fn expand_range(text: &Rope, layers: &Layers, start: usize, end: usize) -> (usize, usize) {
+    match scope_aware_enclosing_brackets(text, layers, start, end) {
+        Some((open, close)) if (open + 1, close) != (start, end) => (open + 1, close),
+        Some((open, close)) => (open, close + 1),
+        None => (0, text.len()),
+    }
+}
+
+/// The number of columns `c` occupies when displayed starting at column `col`.
+fn char_display_width(c: char, col: usize, tab_size: usize) -> usize {
+    match c as u32 {
+        0x09 => {
+            let tab_size = max(tab_size, 1);
+            tab_size - col % tab_size
+        }
+        // combining marks, zero width spaces and joiners, variation selectors
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+        // East Asian wide and fullwidth ranges, and emoji
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the lines the next update renders, rather than copies from
+    /// the frontend's cache.
+    fn rendered_lines(view: &View, text: &Rope) -> Vec<usize> {
+        let height = view.line_of_offset(text, text.len()) + 1;
+        let plan = RenderPlan::create(height, view.first_line, view.height);
+        view.lc_shadow.iter_with_plan(&plan)
+            .filter(|seg| seg.tactic == RenderTactic::Render
+                    && seg.validity != line_cache_shadow::ALL_VALID)
+            .flat_map(|seg| seg.our_line_num..seg.our_line_num + seg.n)
+            .collect()
+    }
+
+    /// Marks every line as sent to the frontend.
+    fn all_cached(text: &Rope) -> LineCacheShadow {
+        let mut b = line_cache_shadow::Builder::new();
+        b.add_span(text.measure::<LinesMetric>() + 1, 0, line_cache_shadow::ALL_VALID);
+        b.build()
+    }
+
+    #[test]
+    fn set_viewport() {
+        let text = Rope::from((0..100).map(|i| format!("{}\n", i)).collect::<String>());
+        let mut view = View::new(ViewId(1), BufferId(2));
+        view.set_scroll(10, 30);
+        view.lc_shadow = all_cached(&text);
+
+        // growing renders only the lines it reveals
+        view.set_viewport(&text, 30);
+        assert_eq!(view.first_line(), 10);
+        assert_eq!(view.scroll_height(), 30);
+        assert_eq!(rendered_lines(&view, &text), (30..40).collect::<Vec<_>>());
+
+        // shrinking reveals nothing, so needs no update
+        view.lc_shadow = all_cached(&text);
+        view.set_viewport(&text, 10);
+        assert_eq!(view.first_line(), 10);
+        assert!(rendered_lines(&view, &text).is_empty());
+    }
+}
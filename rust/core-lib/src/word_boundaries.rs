@@ -13,7 +13,11 @@
 // limitations under the License.
 
 //! Segmentation of word boundaries. Note: this current implementation
-//! is intended to work for code. Future work is to make it Unicode aware.
+//! is intended to work for code. Outside of ASCII it approximates the
+//! Unicode word boundary rules (UAX #29) using character properties:
+//! whitespace separates words, and the general, CJK and fullwidth
+//! punctuation blocks are punctuation. Everything else, including
+//! ideographs and combining marks, counts as part of a word.
 
 use xi_rope::rope::{Rope, RopeInfo};
 use xi_rope::tree::Cursor;
@@ -188,6 +192,33 @@ fn classify_boundary_initial(prev: WordProperty, next: WordProperty) -> WordBoun
     }
 }
 
+/// Counts the words in `text`: runs of characters that are neither spaces
+/// nor punctuation. An apostrophe between two letters, as in "don't",
+/// doesn't end a word.
+pub fn count_words(text: &Rope) -> usize {
+    let mut count = 0;
+    let mut in_word = false;
+    let mut after_apostrophe = false;
+    for chunk in text.iter_chunks(..text.len()) {
+        for c in chunk.chars() {
+            match get_word_property(c) {
+                WordProperty::Other => {
+                    if !in_word && !after_apostrophe {
+                        count += 1;
+                    }
+                    in_word = true;
+                    after_apostrophe = false;
+                }
+                _ => {
+                    after_apostrophe = in_word && (c == '\'' || c == '\u{2019}');
+                    in_word = false;
+                }
+            }
+        }
+    }
+    count
+}
+
 #[derive(Copy, Clone)]
 enum WordProperty {
     Lf,
@@ -213,6 +244,27 @@ fn get_word_property(codepoint: char) -> WordProperty {
         if (0x7800000178000001u64 >> ((codepoint as u32) & 0x3f)) & 1 != 0 {
             return WordProperty::Punctuation;
         }
+    } else if codepoint.is_whitespace() {
+        return WordProperty::Space;
+    } else if is_unicode_punctuation(codepoint) {
+        return WordProperty::Punctuation;
     }
     WordProperty::Other
 }
+
+/// Checks whether a non-ASCII codepoint is in one of the punctuation blocks.
+fn is_unicode_punctuation(codepoint: char) -> bool {
+    match codepoint {
+        // Latin-1 punctuation and symbols, except letter-like ones
+        '\u{a1}'..='\u{bf}' => !codepoint.is_alphanumeric(),
+        '\u{d7}' | '\u{f7}' => true,
+        // General Punctuation, Supplemental Punctuation
+        '\u{2010}'..='\u{205e}' | '\u{2e00}'..='\u{2e7f}' => true,
+        // CJK Symbols and Punctuation, except the iteration marks
+        '\u{3001}'..='\u{3004}' | '\u{3008}'..='\u{3020}' => true,
+        // Fullwidth ASCII punctuation
+        '\u{ff01}'..='\u{ff0f}' | '\u{ff1a}'..='\u{ff20}'
+            | '\u{ff3b}'..='\u{ff40}' | '\u{ff5b}'..='\u{ff65}' => true,
+        _ => false,
+    }
+}
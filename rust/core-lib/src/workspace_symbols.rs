@@ -0,0 +1,562 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Searching the symbols of the files in the workspace. Symbols are listed
+//! by plugins, for open buffers, and kept in an index; the symbols of a
+//! file stay in the index after it is closed, for as long as it doesn't
+//! change on disk.
+//!
+//! Plugins only see open buffers, so the other files under the workspace
+//! root are indexed on a background thread, by looking for the keywords
+//! that introduce definitions in common languages, such as `fn`, `class`
+//! or `def`. Each search rescans the workspace in the background, so that
+//! the next one sees the files added, changed or removed since.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use definition::Location;
+use fuzzy::fuzzy_score;
+use plugins::PluginId;
+use tabs::ViewId;
+
+/// How long a search waits for plugins to list the symbols of the buffers
+/// edited since the last search.
+pub const SEARCH_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Files larger than this are not scanned for symbols.
+const MAX_SCANNED_FILE_SIZE: u64 = 1 << 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolKind {
+    Module,
+    Class,
+    Struct,
+    Enum,
+    Interface,
+    Function,
+    Method,
+    Field,
+    Variable,
+    Constant,
+    Type,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SymbolInfo {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub location: Location,
+    /// The name of the symbol that contains this one, such as its class.
+    pub container: Option<String>,
+}
+
+/// Which version of a file a list of symbols describes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Version {
+    /// A revision of the open buffer.
+    Rev(u64),
+    /// The file on disk, as of its modification time.
+    Disk(SystemTime),
+}
+
+#[derive(Debug)]
+struct FileSymbols {
+    version: Version,
+    by_plugin: BTreeMap<PluginId, Vec<SymbolInfo>>,
+}
+
+/// The symbols of each file, as last listed by plugins, and of the other
+/// files under the workspace root.
+#[derive(Default)]
+pub struct SymbolIndex {
+    files: HashMap<PathBuf, FileSymbols>,
+    workspace: Option<WorkspaceSymbols>,
+}
+
+impl SymbolIndex {
+    /// Starts indexing the files under `root`, or stops indexing files
+    /// that have not been opened if it is `None`.
+    pub fn set_root(&mut self, root: Option<PathBuf>) {
+        self.workspace = root.map(|root| {
+            WorkspaceSymbols::spawn(root.canonicalize().unwrap_or(root))
+        });
+    }
+
+    /// Returns `true` if the symbols of `path` were listed at revision `rev`
+    /// of its buffer; an edit to the buffer makes them stale.
+    pub fn is_current(&self, path: &Path, rev: u64) -> bool {
+        self.files.get(path).map(|f| f.version) == Some(Version::Rev(rev))
+    }
+
+    /// Records the symbols that `plugin` listed for revision `rev` of the
+    /// buffer for `path`, replacing those listed for other revisions.
+    pub fn update(&mut self, path: PathBuf, rev: u64, plugin: PluginId,
+                  symbols: Vec<SymbolInfo>) {
+        let version = Version::Rev(rev);
+        let file = self.files.entry(path)
+            .or_insert_with(|| FileSymbols { version, by_plugin: BTreeMap::new() });
+        if file.version != version {
+            file.version = version;
+            file.by_plugin.clear();
+        }
+        file.by_plugin.insert(plugin, symbols);
+    }
+
+    /// Keeps the symbols of a closed buffer for as long as its file is
+    /// modified at `mod_time`, or drops them if the buffer had unsaved
+    /// changes (in which case `mod_time` is `None`).
+    pub fn buffer_closed(&mut self, path: &Path, mod_time: Option<SystemTime>) {
+        match mod_time {
+            Some(mod_time) => if let Some(file) = self.files.get_mut(path) {
+                file.version = Version::Disk(mod_time);
+            },
+            None => { self.files.remove(path); }
+        }
+    }
+
+    /// Drops the symbols of closed files that have changed on disk.
+    fn prune_changed_files(&mut self) {
+        self.files.retain(|path, file| match file.version {
+            Version::Rev(_) => true,
+            Version::Disk(mod_time) => fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .map(|modified| modified == mod_time)
+                .unwrap_or(false),
+        });
+    }
+
+    /// Returns the symbols matching `query`, best match first. The symbols
+    /// plugins listed for a file are used rather than those scanned.
+    pub fn search(&mut self, query: &str) -> Vec<SymbolInfo> {
+        self.prune_changed_files();
+        let scanned = match self.workspace {
+            Some(ref workspace) => {
+                let listed: HashSet<PathBuf> = self.files.keys()
+                    .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+                    .collect();
+                workspace.rescan();
+                workspace.symbols(&listed)
+            }
+            None => Vec::new(),
+        };
+        let mut matches: Vec<_> = self.files.values()
+            .flat_map(|file| file.by_plugin.values())
+            .flat_map(|symbols| symbols.iter())
+            .chain(scanned.iter())
+            .filter_map(|symbol| fuzzy_score(query, &symbol.name).map(|score| (score, symbol)))
+            .collect();
+        matches.sort_by(|a, b| b.0.cmp(&a.0)
+                        .then_with(|| a.1.name.len().cmp(&b.1.name.len()))
+                        .then_with(|| a.1.name.cmp(&b.1.name)));
+        matches.into_iter().map(|(_, symbol)| symbol.clone()).collect()
+    }
+}
+
+type ScannedFiles = Arc<Mutex<HashMap<PathBuf, (SystemTime, Vec<SymbolInfo>)>>>;
+
+/// The symbols of the files under the workspace root, found by scanning
+/// them on a background thread.
+struct WorkspaceSymbols {
+    root: PathBuf,
+    files: ScannedFiles,
+    /// Set while a scan is running.
+    scanning: Arc<AtomicBool>,
+    /// Tells the scan to stop when the index is dropped.
+    stop: Arc<AtomicBool>,
+}
+
+impl WorkspaceSymbols {
+    fn spawn(root: PathBuf) -> WorkspaceSymbols {
+        let workspace = WorkspaceSymbols {
+            root,
+            files: Arc::new(Mutex::new(HashMap::new())),
+            scanning: Arc::new(AtomicBool::new(false)),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        workspace.rescan();
+        workspace
+    }
+
+    /// Scans the workspace again in the background, unless a scan is
+    /// running. Only the files modified since they were last scanned are
+    /// read again.
+    fn rescan(&self) {
+        if self.scanning.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let (root, files) = (self.root.clone(), self.files.clone());
+        let (scanning, stop) = (self.scanning.clone(), self.stop.clone());
+        thread::spawn(move || {
+            scan_workspace(&root, &files, &stop);
+            scanning.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Returns the symbols scanned so far, except for the files in `skip`.
+    fn symbols(&self, skip: &HashSet<PathBuf>) -> Vec<SymbolInfo> {
+        self.files.lock().unwrap().iter()
+            .filter(|&(path, _)| !skip.contains(path))
+            .flat_map(|(_, file)| file.1.iter().cloned())
+            .collect()
+    }
+}
+
+impl Drop for WorkspaceSymbols {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Finds the symbols of the files under `root`, skipping hidden ones, and
+/// those that are too large or not utf-8, until `stop` is set. The files
+/// no longer there are forgotten once the scan is complete.
+fn scan_workspace(root: &Path, files: &Mutex<HashMap<PathBuf, (SystemTime, Vec<SymbolInfo>)>>,
+                  stop: &AtomicBool) {
+    let mut seen = HashSet::new();
+    let mut dirs = vec![root.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("could not scan {:?} for symbols: {}", dir, err);
+                continue;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(ty) if ty.is_dir() => dirs.push(path),
+                Ok(ty) if ty.is_file() => {
+                    seen.insert(path.clone());
+                    let meta = match entry.metadata() {
+                        Ok(meta) => meta,
+                        Err(_) => continue,
+                    };
+                    let mod_time = match meta.modified() {
+                        Ok(mod_time) => mod_time,
+                        Err(_) => continue,
+                    };
+                    let is_current = files.lock().unwrap().get(&path)
+                        .map(|&(scanned, _)| scanned == mod_time)
+                        .unwrap_or(false);
+                    if is_current || meta.len() > MAX_SCANNED_FILE_SIZE {
+                        continue;
+                    }
+                    if let Ok(text) = fs::read_to_string(&path) {
+                        let symbols = scan_symbols(&path, &text);
+                        files.lock().unwrap().insert(path, (mod_time, symbols));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    files.lock().unwrap().retain(|path, _| seen.contains(path));
+}
+
+/// The keywords that introduce a definition, followed by its name.
+const DEFINITION_KEYWORDS: &[(&str, SymbolKind)] = &[
+    ("fn", SymbolKind::Function),
+    ("func", SymbolKind::Function),
+    ("function", SymbolKind::Function),
+    ("def", SymbolKind::Function),
+    ("struct", SymbolKind::Struct),
+    ("class", SymbolKind::Class),
+    ("enum", SymbolKind::Enum),
+    ("trait", SymbolKind::Interface),
+    ("interface", SymbolKind::Interface),
+    ("protocol", SymbolKind::Interface),
+    ("mod", SymbolKind::Module),
+    ("module", SymbolKind::Module),
+    ("namespace", SymbolKind::Module),
+    ("type", SymbolKind::Type),
+    ("typedef", SymbolKind::Type),
+    ("const", SymbolKind::Constant),
+    ("static", SymbolKind::Constant),
+];
+
+/// Finds the definitions in `text`, the contents of the file at `path`,
+/// from the keywords that introduce them. A definition is contained in the
+/// nearest less indented type or module definition, or Rust `impl`, above
+/// it, and functions in one are methods.
+pub fn scan_symbols(path: &Path, text: &str) -> Vec<SymbolInfo> {
+    let mut symbols = Vec::new();
+    let mut containers: Vec<(usize, String)> = Vec::new();
+    for (line_num, line) in text.lines().enumerate() {
+        let trimmed = line.trim_left();
+        let comment = ["//", "/*", "*", "#", "--", ";"].iter()
+            .any(|prefix| trimmed.starts_with(prefix));
+        if trimmed.is_empty() || comment {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        while containers.last().map(|&(i, _)| i >= indent).unwrap_or(false) {
+            containers.pop();
+        }
+        if let Some(name) = impl_target(trimmed) {
+            containers.push((indent, name.to_owned()));
+            continue;
+        }
+        let (col, name, kind) = match definition_in_line(line) {
+            Some(definition) => definition,
+            None => continue,
+        };
+        let container = containers.last().map(|container| container.1.clone());
+        let kind = match kind {
+            SymbolKind::Function if container.is_some() => SymbolKind::Method,
+            kind => kind,
+        };
+        match kind {
+            SymbolKind::Struct | SymbolKind::Class | SymbolKind::Enum
+                | SymbolKind::Interface | SymbolKind::Module =>
+                containers.push((indent, name.to_owned())),
+            _ => (),
+        }
+        symbols.push(SymbolInfo {
+            name: name.to_owned(),
+            kind,
+            location: Location { path: path.to_owned(), line: line_num, col },
+            container,
+        });
+    }
+    symbols
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the offset, name and kind of the first definition in `line`: a
+/// word that is a definition keyword, followed by a name that is not one.
+fn definition_in_line(line: &str) -> Option<(usize, &str, SymbolKind)> {
+    let mut words: Vec<(usize, usize)> = Vec::new();
+    for (ix, c) in line.match_indices(|c: char| is_identifier_char(c)) {
+        if words.last().map(|&(_, end)| end == ix).unwrap_or(false) {
+            words.last_mut().unwrap().1 += c.len();
+        } else {
+            words.push((ix, ix + c.len()));
+        }
+    }
+    let word = |ix: usize| words.get(ix).map(|&(start, end)| &line[start..end]);
+    for ix in 0..words.len() {
+        let kind = match DEFINITION_KEYWORDS.iter().find(|&&(k, _)| Some(k) == word(ix)) {
+            Some(&(_, kind)) => kind,
+            None => continue,
+        };
+        // `static mut NAME`
+        let name_ix = if word(ix + 1) == Some("mut") { ix + 2 } else { ix + 1 };
+        let name = match word(name_ix) {
+            Some(name) => name,
+            None => break,
+        };
+        let between = &line[words[ix].1..words[name_ix].0];
+        let is_name = between.starts_with(char::is_whitespace)
+            && (between.trim().is_empty() || between.trim() == "mut")
+            && !name.starts_with(|c: char| c.is_numeric())
+            && !DEFINITION_KEYWORDS.iter().any(|&(k, _)| k == name);
+        if is_name {
+            return Some((words[name_ix].0, name, kind));
+        }
+    }
+    None
+}
+
+/// Returns the type a Rust `impl` block at the start of `line` is for.
+fn impl_target(line: &str) -> Option<&str> {
+    let rest = line.trim_left_matches("pub ").trim_left_matches("unsafe ");
+    if !rest.starts_with("impl") {
+        return None;
+    }
+    let mut rest = &rest["impl".len()..];
+    if rest.starts_with('<') {
+        let mut depth = 0;
+        let end = rest.char_indices().find(|&(_, c)| {
+            depth += match c { '<' => 1, '>' => -1, _ => 0 };
+            depth == 0
+        })?.0;
+        rest = &rest[end + 1..];
+    } else if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    if let Some(ix) = rest.find(" for ") {
+        rest = &rest[ix + " for ".len()..];
+    }
+    let rest = rest.trim_left();
+    let end = rest.find(|c: char| !is_identifier_char(c)).unwrap_or(rest.len());
+    if end == 0 { None } else { Some(&rest[..end]) }
+}
+
+/// A search waiting on plugins to list the symbols of edited buffers.
+#[derive(Debug)]
+pub struct SymbolSearch {
+    pub request_id: usize,
+    pub query: String,
+    waiting: Vec<(PluginId, ViewId)>,
+    deadline: Instant,
+}
+
+impl SymbolSearch {
+    pub fn new(request_id: usize, query: String, waiting: Vec<(PluginId, ViewId)>,
+               deadline: Instant) -> SymbolSearch {
+        SymbolSearch { request_id, query, waiting, deadline }
+    }
+
+    /// Records that `plugin` has listed the symbols of `view`. Returns
+    /// `true` once no plugin is left to answer.
+    pub fn respond(&mut self, plugin: PluginId, view: ViewId) -> bool {
+        self.waiting.retain(|&waiting| waiting != (plugin, view));
+        self.waiting.is_empty()
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.deadline <= now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use plugins::PluginPid;
+
+    fn symbol(name: &str, path: &str) -> SymbolInfo {
+        SymbolInfo {
+            name: name.into(),
+            kind: SymbolKind::Function,
+            location: Location { path: path.into(), line: 0, col: 0 },
+            container: None,
+        }
+    }
+
+    #[test]
+    fn index() {
+        let mut index = SymbolIndex::default();
+        let path = PathBuf::from("/nonexistent/a.rs");
+        index.update(path.clone(), 1, PluginPid(1), vec![symbol("new_view", "a.rs")]);
+        index.update(path.clone(), 1, PluginPid(2), vec![symbol("renew", "a.rs")]);
+        assert!(index.is_current(&path, 1));
+        assert!(!index.is_current(&path, 2));
+        let names: Vec<_> = index.search("new").into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["new_view", "renew"]);
+
+        // a new revision replaces every plugin's symbols
+        index.update(path.clone(), 2, PluginPid(1), vec![symbol("do_edit", "a.rs")]);
+        assert_eq!(index.search("new"), vec![]);
+        assert_eq!(index.search("de").len(), 1);
+
+        // the file doesn't exist, so it has changed on disk since
+        index.buffer_closed(&path, Some(SystemTime::now()));
+        assert_eq!(index.search("de"), vec![]);
+    }
+
+    #[test]
+    fn scanned_symbols() {
+        let text = "\
+// fn not_this()
+pub struct Editor {
+    text: Rope,
+}
+
+impl<T: Clone> Default for Editor {
+    pub(crate) fn new() -> Editor {
+        let type_ = 1;
+    }
+}
+
+static mut COUNT: usize = 0;
+pub const fn zero() -> usize { 0 }
+class View:
+    def render(self):
+        pass
+";
+        let symbols = scan_symbols(Path::new("/ws/a.rs"), text);
+        let found: Vec<_> = symbols.iter()
+            .map(|s| (s.name.as_str(), s.kind, s.location.line, s.location.col,
+                      s.container.as_ref().map(String::as_str)))
+            .collect();
+        assert_eq!(found, vec![
+            ("Editor", SymbolKind::Struct, 1, 11, None),
+            ("new", SymbolKind::Method, 6, 18, Some("Editor")),
+            ("COUNT", SymbolKind::Constant, 11, 11, None),
+            ("zero", SymbolKind::Function, 12, 13, None),
+            ("View", SymbolKind::Class, 13, 6, None),
+            ("render", SymbolKind::Method, 14, 8, Some("View")),
+        ]);
+    }
+
+    #[test]
+    fn workspace_scan() {
+        let tmp = tempdir::TempDir::new("xi-workspace-symbols-test").unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("src/.hidden")).unwrap();
+        fs::write(root.join("src/lib.rs"), "fn open_file() {}\n").unwrap();
+        fs::write(root.join("src/.hidden/x.rs"), "fn open_hidden() {}\n").unwrap();
+        fs::write(root.join("main.py"), "def open_main():\n").unwrap();
+
+        let files = Mutex::new(HashMap::new());
+        scan_workspace(&root, &files, &AtomicBool::new(false));
+        let mut names: Vec<_> = files.lock().unwrap().values()
+            .flat_map(|&(_, ref symbols)| symbols.iter().map(|s| s.name.clone()))
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["open_file", "open_main"]);
+
+        // removed files are forgotten
+        fs::remove_file(root.join("main.py")).unwrap();
+        scan_workspace(&root, &files, &AtomicBool::new(false));
+        assert_eq!(files.lock().unwrap().len(), 1);
+
+        // plugins' symbols are used for the files they have listed
+        let mut index = SymbolIndex::default();
+        index.workspace = Some(WorkspaceSymbols {
+            root: root.clone(),
+            files: Arc::new(files),
+            scanning: Arc::new(AtomicBool::new(true)),
+            stop: Arc::new(AtomicBool::new(false)),
+        });
+        let names = |index: &mut SymbolIndex| index.search("open").into_iter()
+            .map(|s| s.name).collect::<Vec<_>>();
+        assert_eq!(names(&mut index), vec!["open_file"]);
+        index.update(root.join("src/lib.rs"), 1, PluginPid(1),
+                     vec![symbol("open_files", "src/lib.rs")]);
+        assert_eq!(names(&mut index), vec!["open_files"]);
+    }
+
+    #[test]
+    fn searches() {
+        let mut search = SymbolSearch::new(0, "x".into(),
+                                           vec![(PluginPid(1), ViewId(1)),
+                                                (PluginPid(1), ViewId(2))],
+                                           Instant::now());
+        assert!(!search.respond(PluginPid(2), ViewId(1)));
+        assert!(!search.respond(PluginPid(1), ViewId(1)));
+        assert!(search.respond(PluginPid(1), ViewId(2)));
+        assert!(search.is_expired(Instant::now()));
+    }
+}
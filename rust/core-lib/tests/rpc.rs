@@ -15,13 +15,16 @@
 #[macro_use]
 extern crate serde_json;
 
+extern crate tempdir;
 extern crate xi_rpc;
 extern crate xi_core_lib;
 
 use std::io;
 
+use serde_json::Value;
+
 use xi_rpc::{RpcLoop, ReadError};
-use xi_rpc::test_utils::{make_reader, test_channel};
+use xi_rpc::test_utils::{make_reader, test_channel, DummyReader};
 use xi_core_lib::XiCore;
 use xi_core_lib::test_helpers;
 
@@ -243,6 +246,8 @@ const MOVEMENT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1",
 {"method":"edit","params":{"view_id":"view-id-1","method":"page_up_and_modify_selection","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"page_down_and_modify_selection","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"select_all","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"select_word","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"select_paragraph","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"add_selection_above","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"add_selection_below","params":[]}}"#;
 
@@ -257,12 +262,21 @@ const TEXT_EDIT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1"
 {"method":"edit","params":{"view_id":"view-id-1","method":"yank","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"undo","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"redo","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"undo_all","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"redo_all","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"transpose","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"uppercase","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"lowercase","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"indent","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"outdent","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"duplicate_line","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"move_line_up","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"move_line_down","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"join_lines","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"sort_lines","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"sort_lines_case_insensitive","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"toggle_comment","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"jump_to_undo_branch","params":{"id":1}}}
 {"id":2,"method":"edit","params":{"view_id":"view-id-1","method":"cut","params":[]}}"#;
 
 const OTHER_EDIT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1","method":"scroll","params":[0,1]}}
@@ -276,9 +290,455 @@ const OTHER_EDIT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1
 {"method":"edit","params":{"view_id":"view-id-1","method":"gesture","params":{"line": 1, "col": 2, "ty": "word_select"}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"gesture","params":{"line": 1, "col": 2, "ty": "multi_line_select"}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"gesture","params":{"line": 1, "col": 2, "ty": "multi_word_select"}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"gesture","params":{"line": 1, "col": 2, "ty": "rect_select"}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"move_down_and_modify_rect_selection","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"move_right_and_modify_rect_selection","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"find","params":{"case_sensitive":false,"chars":"m"}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"find_next","params":{"wrap_around":true}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"find_previous","params":{"wrap_around":true}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"debug_rewrap","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"debug_print_spans","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"set_read_only","params":{"read_only":false}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"accept_completion","params":{"index":0}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"expand_snippet","params":{"snippet":"$1"}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"goto_definition","params":{"request_id":1}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"goto_type_definition","params":{"request_id":2,"position":{"line":0,"column":0}}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"peek_definition","params":{"request_id":3}}}
 {"id":3,"method":"edit","params":{"view_id":"view-id-1","method":"copy","params":[]}}"#;
+
+/// Reads messages up to and including the first one `is_last` accepts, so
+/// that a test waits only as long as the core takes to send it.
+fn read_until<F>(rx: &mut DummyReader, is_last: F) -> Vec<Value>
+    where F: Fn(&Value) -> bool
+{
+    let mut msgs = Vec::new();
+    loop {
+        let msg = rx.expect_object().0;
+        let done = is_last(&msg);
+        msgs.push(msg);
+        if done {
+            return msgs;
+        }
+    }
+}
+
+/// Returns the `update` param of the last update among `msgs`.
+fn last_update(msgs: &[Value]) -> Value {
+    msgs.iter().rev()
+        .find(|msg| msg["method"] == json!("update"))
+        .map(|msg| msg["params"]["update"].clone())
+        .expect("an update")
+}
+
+#[test]
+/// Tests that lines moved by an edit above them are copied, with the copy
+/// op giving their new line number.
+fn test_moved_line_numbers() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"method":"set_theme","params":{"theme_name":"InspiredGitHub"}}
+{"id":0,"method":"new_view","params":{}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"a\nb"}}}
+{"id":1,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    read_until(&mut rx, |msg| msg["id"] == json!(1));
+
+    let json = make_reader(r#"{"method":"edit","params":{"view_id":"view-id-1","method":"move_to_beginning_of_document","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"x\n"}}}
+{"id":2,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let update = last_update(&read_until(&mut rx, |msg| msg["id"] == json!(2)));
+    let ops = update["ops"].as_array().unwrap().iter()
+        .map(|op| (op["op"].clone(), op["n"].clone(), op["ln"].clone()))
+        .collect::<Vec<_>>();
+    // "a" is sent again because the cursor left it; "b" is copied
+    assert_eq!(ops, vec![(json!("ins"), json!(2), json!(1)),
+                         (json!("skip"), json!(1), json!(null)),
+                         (json!("copy"), json!(1), json!(3))]);
+}
+
+#[test]
+/// Tests that goto_line scrolls the frontend to the target line.
+fn test_goto_line_scrolls() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let text = "line\n".repeat(100);
+    let json = make_reader(format!(r#"{{"method":"client_started","params":{{}}}}
+{{"id":0,"method":"new_view","params":{{}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"insert","params":{{"chars":{}}}}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"move_to_beginning_of_document","params":[]}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"scroll","params":[0,10]}}}}
+{{"id":1,"method":"debug_get_contents","params":{{"view_id":"view-id-1"}}}}"#,
+                                   json!(text)));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    read_until(&mut rx, |msg| msg["id"] == json!(1));
+
+    let json = make_reader(r#"{"method":"edit","params":{"view_id":"view-id-1","method":"goto_line","params":{"line":50,"col":1}}}
+{"id":2,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let scrolls = read_until(&mut rx, |msg| msg["id"] == json!(2)).into_iter()
+        .filter(|msg| msg["method"] == json!("scroll_to"))
+        .map(|msg| msg["params"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(scrolls, vec![json!({"view_id": "view-id-1", "line": 50, "col": 1})]);
+}
+
+#[test]
+/// Tests that soft wrapped lines say where they start in their logical line.
+fn test_soft_wrap_lines() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"method":"set_theme","params":{"theme_name":"InspiredGitHub"}}
+{"id":0,"method":"new_view","params":{}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"one two three\nfour"}}}
+{"method":"modify_user_config","params":{"domain":{"user_override":"view-id-1"},"changes":{"wrap_width": 8}}}
+{"id":1,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    let update = last_update(&read_until(&mut rx, |msg| msg["id"] == json!(1)));
+    assert_eq!(update["wrap_width"], json!(8));
+    let ops = update["ops"].as_array().unwrap();
+    assert_eq!(ops.iter().map(|op| op["ln"].clone()).collect::<Vec<_>>(), vec![json!(1)]);
+    let lines = ops.iter()
+        .flat_map(|op| op["lines"].as_array().unwrap().iter())
+        .map(|line| (line["text"].clone(), line["wrap_offset"].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(lines, vec![(json!("one two "), json!(null)),
+                           (json!("three\n"), json!(8)),
+                           (json!("four"), json!(null))]);
+
+    // the op's number is that of its first line, from which the numbers of
+    // the wrapped lines after it follow
+    let json = make_reader(r#"{"method":"edit","params":{"view_id":"view-id-1","method":"move_to_beginning_of_document","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"x\n"}}}
+{"id":2,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let update = last_update(&read_until(&mut rx, |msg| msg["id"] == json!(2)));
+    let ops = update["ops"].as_array().unwrap().iter()
+        .map(|op| (op["op"].clone(), op["n"].clone(), op["ln"].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(ops, vec![(json!("ins"), json!(4), json!(1))]);
+}
+
+#[test]
+/// Tests that the lines hidden by a fold are sent as a placeholder line.
+fn test_fold_range() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"method":"set_theme","params":{"theme_name":"InspiredGitHub"}}
+{"id":0,"method":"new_view","params":{}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"a {\n  b\n  c\n}"}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"fold_range","params":{"start_line":0,"end_line":3}}}
+{"id":1,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    let update = last_update(&read_until(&mut rx, |msg| msg["id"] == json!(1)));
+    let ops = update["ops"].as_array().unwrap();
+    assert_eq!(ops.iter().map(|op| op["ln"].clone()).collect::<Vec<_>>(), vec![json!(1)]);
+    let lines = ops.iter()
+        .flat_map(|op| op["lines"].as_array().unwrap().iter())
+        .map(|line| (line["text"].clone(), line["fold"].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(lines, vec![(json!("a {\n"), json!(null)),
+                           (json!("\u{2026}\n"), json!([0, 3])),
+                           (json!("}"), json!(null))]);
+
+    // a fold moved by an edit above it is copied with its new number
+    let json = make_reader(r#"{"method":"edit","params":{"view_id":"view-id-1","method":"move_to_beginning_of_document","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"x\n"}}}
+{"id":2,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let update = last_update(&read_until(&mut rx, |msg| msg["id"] == json!(2)));
+    let ops = update["ops"].as_array().unwrap().iter()
+        .map(|op| (op["op"].clone(), op["n"].clone(), op["ln"].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(ops, vec![(json!("ins"), json!(2), json!(1)),
+                         (json!("skip"), json!(1), json!(null)),
+                         (json!("copy"), json!(2), json!(3))]);
+}
+
+#[test]
+fn test_split_view() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"method":"set_theme","params":{"theme_name":"InspiredGitHub"}}
+{"id":0,"method":"new_view","params":{}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"a\nb"}}}
+{"id":1,"method":"split_view","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let split = read_until(&mut rx, |msg| msg["id"] == json!(1)).pop().unwrap();
+    assert_eq!(split["result"], json!("view-id-3"));
+
+    let json = make_reader(r#"{"method":"edit","params":{"view_id":"view-id-3","method":"insert","params":{"chars":"c"}}}
+{"id":2,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let mut texts = vec![String::new(), String::new()];
+    for msg in read_until(&mut rx, |msg| msg["id"] == json!(2)) {
+        if msg["method"] == json!("update") {
+            let view = match msg["params"]["view_id"].as_str() {
+                Some("view-id-1") => 0,
+                Some("view-id-3") => 1,
+                other => panic!("update for {:?}", other),
+            };
+            let text = msg["params"]["update"]["ops"].as_array().unwrap().iter()
+                .filter(|op| op["op"] == json!("ins"))
+                .flat_map(|op| op["lines"].as_array().unwrap().iter())
+                .map(|line| line["text"].as_str().unwrap())
+                .collect::<String>();
+            if !text.is_empty() {
+                texts[view] = text;
+            }
+        }
+    }
+    // the edit made in the new view is shown in both
+    assert_eq!(texts, vec!["ca\n".to_string(), "ca\n".to_string()]);
+}
+
+#[test]
+fn test_split_view_find() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"method":"set_theme","params":{"theme_name":"InspiredGitHub"}}
+{"id":0,"method":"new_view","params":{}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"ab\nAb"}}}
+{"id":1,"method":"split_view","params":{"view_id":"view-id-1"}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"find","params":{"chars":"a","case_sensitive":false}}}
+{"method":"edit","params":{"view_id":"view-id-3","method":"find","params":{"chars":"A","case_sensitive":true}}}
+{"method":"edit","params":{"view_id":"view-id-3","method":"find_next","params":{"wrap_around":true}}}
+{"id":2,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    // the number of matches last reported for each view
+    let mut searches = vec![None, None];
+    for msg in read_until(&mut rx, |msg| msg["id"] == json!(2)) {
+        if msg["method"] == json!("find_status") {
+            let view = match msg["params"]["view_id"].as_str() {
+                Some("view-id-1") => 0,
+                Some("view-id-3") => 1,
+                other => panic!("find_status for {:?}", other),
+            };
+            if let Some(query) = msg["params"]["queries"].as_array().unwrap().first() {
+                searches[view] = Some(query["matches"].clone());
+            }
+        }
+    }
+    // each view keeps its own search: the case-sensitive one in the new
+    // view does not replace the first view's
+    assert_eq!(searches, vec![Some(json!(2)), Some(json!(1))]);
+}
+
+#[test]
+fn test_command_palette() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"method":"set_theme","params":{"theme_name":"InspiredGitHub"}}
+{"id":0,"method":"new_view","params":{}}
+{"method":"modify_user_config","params":{"domain":"general","changes":{"keybindings":{"undo":"ctrl+z"}}}}
+{"id":1,"method":"get_all_commands","params":{"query":"undo"}}
+{"id":2,"method":"execute_command_by_name","params":{"view_id":"view-id-1","name":"insert","args":{"chars":"ab"}}}
+{"id":3,"method":"execute_command_by_name","params":{"view_id":"view-id-1","name":"move_to_beginning_of_document"}}
+{"id":4,"method":"execute_command_by_name","params":{"view_id":"view-id-1","name":"no_such_command"}}
+{"id":5,"method":"execute_command_by_name","params":{"view_id":"view-id-1","name":"goto_line","args":{}}}
+{"id":6,"method":"execute_command_by_name","params":{"view_id":"view-id-1","name":"insert","args":{"chars":"c"}}}
+{"id":7,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    let mut responses = vec![Value::Null; 8];
+    for msg in read_until(&mut rx, |msg| msg["id"] == json!(7)) {
+        if let Some(id) = msg["id"].as_u64() {
+            responses[id as usize] = msg.clone();
+        }
+    }
+    let commands = responses[1]["result"].as_array().unwrap();
+    assert_eq!(commands[0], json!({"name": "undo", "description": "Undo",
+                                   "keybinding": "ctrl+z"}));
+    assert_eq!(responses[2]["result"], Value::Null);
+    assert_eq!(responses[4]["error"]["code"], json!(21));
+    assert_eq!(responses[5]["error"]["code"], json!(22));
+    assert_eq!(responses[7]["result"], json!("cab"));
+}
+
+#[test]
+fn test_keybinding_conflicts() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"method":"modify_user_config","params":{"domain":"general","changes":{"keybindings":{"undo":"ctrl+z","redo":"ctrl+z"}}}}
+{"method":"resolve_conflict","params":{"key":"ctrl+z","preferred_command":"redo"}}
+{"id":0,"method":"get_all_commands","params":{"query":"do"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    let msgs = read_until(&mut rx, |msg| msg["id"] == json!(0));
+    let conflicts = msgs.iter()
+        .filter(|msg| msg["method"] == json!("keybinding_conflicts"))
+        .map(|msg| msg["params"]["conflicts"].clone())
+        .collect::<Vec<_>>();
+    // reported once, before the conflict was resolved
+    assert_eq!(conflicts, vec![json!([{"key": "ctrl+z", "commands": ["redo", "undo"]}])]);
+    let commands = &msgs.last().unwrap()["result"];
+    let keybinding = |name: &str| commands.as_array().unwrap().iter()
+        .find(|command| command["name"] == json!(name))
+        .map(|command| command["keybinding"].clone())
+        .unwrap();
+    assert_eq!(keybinding("redo"), json!("ctrl+z"));
+    assert_eq!(keybinding("undo"), json!(null));
+}
+
+#[test]
+fn test_session() {
+    use std::fs;
+
+    let tmp = tempdir::TempDir::new("xi-test-session").unwrap();
+    let a = tmp.path().join("a.txt");
+    let b = tmp.path().join("b.txt");
+    let session = tmp.path().join("session.json");
+    fs::write(&a, "one\ntwo\nthree\nfour\n").unwrap();
+    fs::write(&b, "b\n").unwrap();
+    let path = |p: &std::path::Path| json!(p).to_string();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = format!(r#"{{"method":"client_started","params":{{}}}}
+{{"id":0,"method":"new_view","params":{{"file_path":{a}}}}}
+{{"id":1,"method":"new_view","params":{{"file_path":{b}}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"goto_line","params":{{"line":2,"col":1}}}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"fold_range","params":{{"start_line":0,"end_line":2}}}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"scroll","params":[1,3]}}}}
+{{"id":2,"method":"session_save","params":{{"path":{session}}}}}"#,
+                       a = path(&a), b = path(&b), session = path(&session));
+    let json = make_reader(json);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    read_until(&mut rx, |msg| msg["id"] == json!(2));
+    let saved: Value = serde_json::from_str(&fs::read_to_string(&session).unwrap()).unwrap();
+    assert_eq!(saved["views"][0], json!({"path": a, "selections": [[9, 9]],
+                                         "folds": [[0, 2]], "first_line": 1}));
+    assert_eq!(saved["views"][1]["path"], json!(b));
+
+    // a file deleted since is skipped, with an alert
+    fs::remove_file(&b).unwrap();
+    let resaved = tmp.path().join("resaved.json");
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(r#"{{"method":"client_started","params":{{}}}}
+{{"id":0,"method":"session_restore","params":{{"path":{session}}}}}"#,
+                                   session = path(&session)));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let json = make_reader(format!(r#"{{"id":1,"method":"session_save","params":{{"path":{resaved}}}}}"#,
+                                   resaved = path(&resaved)));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let msgs = read_until(&mut rx, |msg| msg["id"] == json!(1));
+    let restored = msgs.iter()
+        .find(|msg| msg["id"] == json!(0))
+        .map(|msg| msg["result"].clone());
+    let alerts = msgs.iter().filter(|msg| msg["method"] == json!("alert")).count();
+    assert_eq!(restored, Some(json!([{"view_id": "view-id-1", "path": a}])));
+    assert_eq!(alerts, 1);
+    let resaved: Value = serde_json::from_str(&fs::read_to_string(&resaved).unwrap()).unwrap();
+    assert_eq!(resaved, json!({"views": [saved["views"][0].clone()]}));
+}
+
+#[test]
+fn test_confirm_close() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{"confirm_close":true}}
+{"id":0,"method":"new_view","params":{}}
+{"id":1,"method":"has_unsaved_changes","params":{}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"a"}}}
+{"id":2,"method":"has_unsaved_changes","params":{}}
+{"method":"close_view","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    let msgs = read_until(&mut rx, |msg| msg["method"] == json!("confirm_close"));
+    let unsaved = msgs.iter()
+        .filter(|msg| msg["id"] == json!(1) || msg["id"] == json!(2))
+        .map(|msg| msg["result"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(unsaved, vec![json!(false), json!(true)]);
+    assert_eq!(msgs.last().unwrap()["params"],
+               json!({"view_id": "view-id-1", "buffer_id": 2, "path": null}));
+    // the view stays open until closing it is forced
+    assert_eq!(state.inner()._test_open_views(), vec![test_helpers::new_view_id(1)]);
+
+    let json = make_reader(
+        r#"{"method":"close_view","params":{"view_id":"view-id-1","force":true}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    assert_eq!(state.inner()._test_open_views(), Vec::new());
+}
+
+#[test]
+fn test_close_without_confirm() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    // a client that does not handle `confirm_close`
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"id":0,"method":"new_view","params":{}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"a"}}}
+{"method":"close_view","params":{"view_id":"view-id-1"}}
+{"id":1,"method":"has_unsaved_changes","params":{}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    for msg in read_until(&mut rx, |msg| msg["id"] == json!(1)) {
+        assert_ne!(msg["method"], json!("confirm_close"));
+    }
+    assert_eq!(state.inner()._test_open_views(), Vec::new());
+    assert_eq!(state.inner()._test_open_editors(), Vec::new());
+}
+
+#[test]
+fn test_auto_save() {
+    use std::fs;
+    use std::io::{BufReader, Read};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Ends the input only after a while, so that timers fire before.
+    struct SlowEof;
+
+    impl Read for SlowEof {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(300));
+            Ok(0)
+        }
+    }
+
+    let tmp = tempdir::TempDir::new("xi-test-auto-save").unwrap();
+    let path = tmp.path().join("a.txt");
+    fs::write(&path, "a").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = format!(r#"{{"method":"client_started","params":{{}}}}
+{{"method":"modify_user_config","params":{{"domain":"general","changes":{{"auto_save_delay_ms":50}}}}}}
+{{"id":0,"method":"new_view","params":{{"file_path":{path}}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"insert","params":{{"chars":"b"}}}}}}
+{{"method":"edit","params":{{"view_id":"view-id-1","method":"insert","params":{{"chars":"c"}}}}}}
+"#, path = json!(path));
+    let json = BufReader::new(make_reader(json).chain(SlowEof));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+
+    let msgs = read_until(&mut rx, |msg| msg["method"] == json!("auto_saved"));
+    // both edits are saved at once
+    assert_eq!(msgs.last().unwrap()["params"]["path"], json!(path));
+    assert!(rx.next_timeout(Duration::from_millis(0)).is_none());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "bca");
+}
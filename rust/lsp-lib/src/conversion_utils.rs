@@ -16,8 +16,14 @@
 //! and vice-versa
 
 use lsp_types::*;
+use serde_json::{self, Value};
+use std::fs;
 use types::LanguageResponseError;
-use xi_plugin_lib::{Cache, Error as PluginLibError, Hover as CoreHover, Range as CoreRange, View};
+use xi_core::snippet::Snippet;
+use xi_plugin_lib::{Cache, CompletionItem as CoreCompletionItem, CompletionKind,
+                    DiagnosticSeverity as CoreDiagnosticSeverity, DiagnosticSpan, EditPosition,
+                    Error as PluginLibError, Hover as CoreHover, Location as CoreLocation,
+                    Range as CoreRange, TextEdit as CoreTextEdit, View};
 
 pub(crate) fn marked_string_to_string(marked_string: &MarkedString) -> String {
     match *marked_string {
@@ -79,19 +85,26 @@ pub(crate) fn offset_of_position<C: Cache>(
     position: Position,
 ) -> Result<usize, PluginLibError> {
     let line_offset = view.offset_of_line(position.line as usize);
+    let line = view.get_line(position.line as usize)?;
+    let cur_len_utf8 = utf8_offset_of_utf16(line, position.character as usize);
 
+    Ok(cur_len_utf8 + line_offset?)
+}
+
+/// Converts `character`, a count of utf-16 code units into `line`, to a
+/// utf-8 offset. This is the inverse of `count_utf16`.
+pub(crate) fn utf8_offset_of_utf16(line: &str, character: usize) -> usize {
     let mut cur_len_utf16 = 0;
     let mut cur_len_utf8 = 0;
 
-    for u in view.get_line(position.line as usize)?.chars() {
-        if cur_len_utf16 >= (position.character as usize) {
+    for u in line.chars() {
+        if cur_len_utf16 >= character {
             break;
         }
         cur_len_utf16 += u.len_utf16();
         cur_len_utf8 += u.len_utf8();
     }
-
-    Ok(cur_len_utf8 + line_offset?)
+    cur_len_utf8
 }
 
 pub(crate) fn core_range_from_range<C: Cache>(
@@ -116,3 +129,253 @@ pub(crate) fn core_hover_from_hover<C: Cache>(
         },
     })
 }
+
+/// Get the first location from a definition response, which may be a single
+/// location, an array of them, or null.
+pub(crate) fn location_from_definition_response(
+    response: Value,
+) -> Result<Location, LanguageResponseError> {
+    let locations: Vec<Location> = match response {
+        Value::Null => Vec::new(),
+        Value::Array(_) => serde_json::from_value(response).map_err(invalid_response)?,
+        _ => vec![serde_json::from_value(response).map_err(invalid_response)?],
+    };
+    locations.into_iter().next().ok_or(LanguageResponseError::NullResponse)
+}
+
+/// Core locations count columns in utf-8, so the line is read from the
+/// file, which need not be open. If it can't be read, the utf-16 column is
+/// used as is.
+pub(crate) fn core_location_from_location(
+    location: Location,
+) -> Result<CoreLocation, LanguageResponseError> {
+    let path = location.uri.to_file_path().map_err(|_| {
+        LanguageResponseError::LanguageServerError(format!("not a file: {}", location.uri))
+    })?;
+    let line = location.range.start.line as usize;
+    let character = location.range.start.character as usize;
+    let col = fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| text.lines().nth(line).map(|l| utf8_offset_of_utf16(l, character)))
+        .unwrap_or(character);
+    Ok(CoreLocation { path, line, col })
+}
+
+pub(crate) fn completion_items_from_response(
+    response: Value,
+) -> Result<Vec<CompletionItem>, LanguageResponseError> {
+    let response: Option<CompletionResponse> =
+        serde_json::from_value(response).map_err(invalid_response)?;
+    match response {
+        Some(CompletionResponse::Array(items)) => Ok(items),
+        Some(CompletionResponse::List(list)) => Ok(list.items),
+        None => Err(LanguageResponseError::NullResponse),
+    }
+}
+
+pub(crate) fn core_completion_from_completion(item: CompletionItem) -> CoreCompletionItem {
+    let is_snippet = item.insert_text_format == Some(InsertTextFormat::Snippet);
+    let label = item.label;
+    let insert_text = item
+        .text_edit
+        .map(|edit| edit.new_text)
+        .or(item.insert_text)
+        .unwrap_or_else(|| label.clone());
+    // The core inserts completions literally, so tab stops are dropped
+    let insert_text = if is_snippet { Snippet::parse(&insert_text).text } else { insert_text };
+
+    CoreCompletionItem {
+        kind: item.kind.map(completion_kind).unwrap_or(CompletionKind::Text),
+        label,
+        insert_text,
+        detail: item.detail,
+    }
+}
+
+fn completion_kind(kind: CompletionItemKind) -> CompletionKind {
+    match kind {
+        CompletionItemKind::Method => CompletionKind::Method,
+        CompletionItemKind::Function | CompletionItemKind::Constructor => CompletionKind::Function,
+        CompletionItemKind::Field | CompletionItemKind::Property => CompletionKind::Field,
+        CompletionItemKind::Variable
+        | CompletionItemKind::Constant
+        | CompletionItemKind::Value => CompletionKind::Variable,
+        CompletionItemKind::Class
+        | CompletionItemKind::Interface
+        | CompletionItemKind::Enum
+        | CompletionItemKind::Struct
+        | CompletionItemKind::TypeParameter => CompletionKind::Type,
+        CompletionItemKind::Module => CompletionKind::Module,
+        CompletionItemKind::Keyword => CompletionKind::Keyword,
+        CompletionItemKind::Snippet => CompletionKind::Snippet,
+        _ => CompletionKind::Text,
+    }
+}
+
+/// Get the utf-8 offset in `line_prefix`, the text of a line before the
+/// caret, where the identifier being completed starts.
+pub(crate) fn completion_trigger_offset(line_prefix: &str) -> usize {
+    line_prefix
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| line_prefix.len())
+}
+
+/// Get a Core diagnostic from an LSP one. Diagnostics without a source are
+/// attributed to `default_source`.
+pub(crate) fn core_diagnostic_from_diagnostic<C: Cache>(
+    view: &mut View<C>,
+    diagnostic: Diagnostic,
+    default_source: &str,
+) -> Result<DiagnosticSpan, PluginLibError> {
+    let range = core_range_from_range(view, diagnostic.range)?;
+    let severity = match diagnostic.severity {
+        Some(DiagnosticSeverity::Warning) => CoreDiagnosticSeverity::Warning,
+        Some(DiagnosticSeverity::Information) => CoreDiagnosticSeverity::Information,
+        Some(DiagnosticSeverity::Hint) => CoreDiagnosticSeverity::Hint,
+        Some(DiagnosticSeverity::Error) | None => CoreDiagnosticSeverity::Error,
+    };
+    Ok(DiagnosticSpan {
+        start: range.start,
+        end: range.end,
+        severity,
+        message: diagnostic.message,
+        source: diagnostic.source.unwrap_or_else(|| default_source.to_string()),
+    })
+}
+
+fn core_edit_position<C: Cache>(
+    view: &mut View<C>,
+    position: Position,
+) -> Result<EditPosition, PluginLibError> {
+    let line = position.line as usize;
+    let col = utf8_offset_of_utf16(view.get_line(line)?, position.character as usize);
+    Ok(EditPosition { line, col })
+}
+
+/// Converts a language server's edit, whose columns count utf-16 code
+/// units, to one with utf-8 columns.
+pub(crate) fn core_text_edit_from_text_edit<C: Cache>(
+    view: &mut View<C>,
+    edit: TextEdit,
+) -> Result<CoreTextEdit, PluginLibError> {
+    Ok(CoreTextEdit {
+        start: core_edit_position(view, edit.range.start)?,
+        end: core_edit_position(view, edit.range.end)?,
+        new_text: edit.new_text,
+    })
+}
+
+/// Converts a language server's edits with `core_text_edit_from_text_edit`,
+/// in order of position. Servers may send edits in any order, but an edit
+/// that ends before it starts or that overlaps another is an error.
+pub(crate) fn core_text_edits_from_text_edits<C: Cache>(
+    view: &mut View<C>,
+    edits: Vec<TextEdit>,
+) -> Result<Vec<CoreTextEdit>, LanguageResponseError> {
+    let mut core_edits = edits
+        .into_iter()
+        .map(|edit| core_text_edit_from_text_edit(view, edit))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| LanguageResponseError::LanguageServerError(format!("{:?}", e)))?;
+    sort_text_edits(&mut core_edits)?;
+    Ok(core_edits)
+}
+
+fn sort_text_edits(edits: &mut Vec<CoreTextEdit>) -> Result<(), LanguageResponseError> {
+    let key = |position: &EditPosition| (position.line, position.col);
+    if let Some(edit) = edits.iter().find(|edit| key(&edit.end) < key(&edit.start)) {
+        let message = format!("edit ends before it starts: {:?}", edit);
+        return Err(LanguageResponseError::LanguageServerError(message));
+    }
+    // a stable sort, so that insertions at one position keep their order
+    edits.sort_by_key(|edit| key(&edit.start));
+    if edits.windows(2).any(|pair| key(&pair[1].start) < key(&pair[0].end)) {
+        return Err(LanguageResponseError::LanguageServerError("edits overlap".into()));
+    }
+    Ok(())
+}
+
+fn invalid_response(err: serde_json::Error) -> LanguageResponseError {
+    LanguageResponseError::LanguageServerError(format!("invalid response: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf16_offsets() {
+        let line = "let 😀 = \"é\";\n";
+        assert_eq!(count_utf16("let 😀"), 6);
+        assert_eq!(utf8_offset_of_utf16(line, 6), "let 😀".len());
+        assert_eq!(utf8_offset_of_utf16(line, 11), "let 😀 = \"é".len());
+        for (i, _) in line.char_indices() {
+            assert_eq!(utf8_offset_of_utf16(line, count_utf16(&line[..i])), i);
+        }
+        // past the end of the line
+        assert_eq!(utf8_offset_of_utf16("ab", 9), 2);
+    }
+
+    #[test]
+    fn trigger_offset() {
+        assert_eq!(completion_trigger_offset("let x = foo.ba"), 12);
+        assert_eq!(completion_trigger_offset("let x = foo."), 12);
+        assert_eq!(completion_trigger_offset("état"), 0);
+        assert_eq!(completion_trigger_offset(""), 0);
+    }
+
+    #[test]
+    fn text_edit_order() {
+        let edit = |start: (usize, usize), end: (usize, usize), new_text: &str| CoreTextEdit {
+            start: EditPosition { line: start.0, col: start.1 },
+            end: EditPosition { line: end.0, col: end.1 },
+            new_text: new_text.into(),
+        };
+        let mut edits = vec![edit((2, 0), (2, 1), "c"), edit((0, 4), (0, 4), "a"),
+                             edit((0, 4), (1, 0), "b")];
+        sort_text_edits(&mut edits).unwrap();
+        assert_eq!(edits, vec![edit((0, 4), (0, 4), "a"), edit((0, 4), (1, 0), "b"),
+                               edit((2, 0), (2, 1), "c")]);
+
+        assert!(sort_text_edits(&mut vec![edit((0, 0), (0, 2), "x"),
+                                          edit((0, 1), (1, 0), "y")]).is_err());
+        assert!(sort_text_edits(&mut vec![edit((1, 0), (0, 2), "x")]).is_err());
+    }
+
+    #[test]
+    fn definition_responses() {
+        let location = json!({
+            "uri": "file:///tmp/a.rs",
+            "range": {"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 8}},
+        });
+        let expected: Location = serde_json::from_value(location.clone()).unwrap();
+        let first = location_from_definition_response(location.clone()).unwrap();
+        assert_eq!(first, expected);
+        let first = location_from_definition_response(json!([location])).unwrap();
+        assert_eq!(first, expected);
+        assert!(location_from_definition_response(Value::Null).is_err());
+        assert!(location_from_definition_response(json!([])).is_err());
+    }
+
+    #[test]
+    fn completions() {
+        let response = json!({"isIncomplete": false, "items": [
+            {"label": "push", "kind": 2, "detail": "fn(&mut self, T)",
+             "insertText": "push(${1:value})$0", "insertTextFormat": 2},
+            {"label": "len"},
+        ]});
+        let items: Vec<_> = completion_items_from_response(response).unwrap()
+            .into_iter()
+            .map(core_completion_from_completion)
+            .collect();
+        assert_eq!(items[0].kind, CompletionKind::Method);
+        assert_eq!(items[0].insert_text, "push(value)");
+        assert_eq!(items[0].detail, Some("fn(&mut self, T)".into()));
+        assert_eq!(items[1].kind, CompletionKind::Text);
+        assert_eq!(items[1].insert_text, "len");
+    }
+}
@@ -22,10 +22,11 @@ use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::process;
 use result_queue::ResultQueue;
-use types::Callback;
+use semantic_tokens::{self, SemanticTokensLegend, SemanticTokensParams};
+use types::{Callback, LspResponse};
 use url::Url;
 use xi_core::ViewId;
-use xi_plugin_lib::CoreProxy;
+use xi_plugin_lib::{CoreProxy, DefinitionKind};
 
 /// A type to abstract communication with the language server
 pub struct LanguageServerClient {
@@ -39,6 +40,8 @@ pub struct LanguageServerClient {
     pub is_initialized: bool,
     pub opened_documents: HashMap<ViewId, Url>,
     pub server_capabilities: Option<ServerCapabilities>,
+    /// The legend of the server's semantic tokens, if it provides them.
+    pub semantic_tokens_legend: Option<SemanticTokensLegend>,
     pub file_extensions: Vec<String>,
 }
 
@@ -80,6 +83,7 @@ impl LanguageServerClient {
             status_items: HashSet::new(),
             language_id,
             server_capabilities: None,
+            semantic_tokens_legend: None,
             opened_documents: HashMap::new(),
             file_extensions,
         }
@@ -130,9 +134,7 @@ impl LanguageServerClient {
             "window/logMessage" => {
 
             },
-            "textDocument/publishDiagnostics" => {
-
-            },
+            "textDocument/publishDiagnostics" => self.handle_publish_diagnostics(params),
             "telemetry/event" => {
 
             },
@@ -140,6 +142,23 @@ impl LanguageServerClient {
         }        
     }
 
+    /// Queues published diagnostics for the view showing the document.
+    fn handle_publish_diagnostics(&mut self, params: Params) {
+        let params = serde_json::to_value(params)
+            .and_then(serde_json::from_value::<PublishDiagnosticsParams>);
+        let params = match params {
+            Ok(params) => params,
+            Err(err) => return warn!("invalid publishDiagnostics params: {}", err),
+        };
+        let view_id = self.opened_documents.iter()
+            .find(|(_, uri)| **uri == params.uri)
+            .map(|(view_id, _)| *view_id);
+        if let Some(view_id) = view_id {
+            self.result_queue.push_result(view_id, 0, LspResponse::Diagnostics(params.diagnostics));
+            self.core.schedule_idle(view_id);
+        }
+    }
+
     pub fn handle_misc_notification(&mut self, method: &str, params: Params) {
         match self.language_id.to_lowercase().as_ref() {
             "rust" => self.handle_rust_misc_notification(method, params),
@@ -212,8 +231,10 @@ impl LanguageServerClient {
             trace: Some(TraceOption::Verbose),
         };
 
-        let params = Params::from(serde_json::to_value(init_params).unwrap());
-        self.send_request("initialize", params, Box::new(on_init));
+        let mut params = serde_json::to_value(init_params).unwrap();
+        params["capabilities"]["textDocument"]["semanticTokens"] =
+            semantic_tokens::client_capability();
+        self.send_request("initialize", Params::from(params), Box::new(on_init));
     }
 
     /// Send textDocument/didOpen Notification to the Language Server
@@ -286,6 +307,83 @@ impl LanguageServerClient {
     ) where
         CB: 'static + Send + FnOnce(&mut LanguageServerClient, Result<Value, Error>),
     {
+        let params = self.text_document_position_params(view_id, position);
+        self.send_request("textDocument/hover", params, Box::new(on_result))
+    }
+
+    /// Send textDocument/definition, or textDocument/typeDefinition, Request
+    pub fn request_definition<CB>(
+        &mut self,
+        view_id: ViewId,
+        position: Position,
+        kind: DefinitionKind,
+        on_result: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&mut LanguageServerClient, Result<Value, Error>),
+    {
+        let method = match kind {
+            DefinitionKind::Definition => "textDocument/definition",
+            DefinitionKind::TypeDefinition => "textDocument/typeDefinition",
+        };
+        let params = self.text_document_position_params(view_id, position);
+        self.send_request(method, params, Box::new(on_result))
+    }
+
+    pub fn request_completion<CB>(
+        &mut self,
+        view_id: ViewId,
+        position: Position,
+        on_result: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&mut LanguageServerClient, Result<Value, Error>),
+    {
+        let completion_params = CompletionParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.opened_documents.get(&view_id).unwrap().clone(),
+            },
+            position,
+            context: None,
+        };
+
+        let params = Params::from(serde_json::to_value(completion_params).unwrap());
+        self.send_request("textDocument/completion", params, Box::new(on_result))
+    }
+
+    pub fn request_formatting<CB>(
+        &mut self,
+        view_id: ViewId,
+        options: FormattingOptions,
+        on_result: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&mut LanguageServerClient, Result<Value, Error>),
+    {
+        let formatting_params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.opened_documents.get(&view_id).unwrap().clone(),
+            },
+            options,
+        };
+
+        let params = Params::from(serde_json::to_value(formatting_params).unwrap());
+        self.send_request("textDocument/formatting", params, Box::new(on_result))
+    }
+
+    /// Send textDocument/semanticTokens/full Request
+    pub fn request_semantic_tokens<CB>(&mut self, view_id: ViewId, on_result: CB)
+    where
+        CB: 'static + Send + FnOnce(&mut LanguageServerClient, Result<Value, Error>),
+    {
+        let semantic_tokens_params = SemanticTokensParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.opened_documents.get(&view_id).unwrap().clone(),
+            },
+        };
+
+        let params = Params::from(serde_json::to_value(semantic_tokens_params).unwrap());
+        self.send_request("textDocument/semanticTokens/full", params, Box::new(on_result))
+    }
+
+    fn text_document_position_params(&self, view_id: ViewId, position: Position) -> Params {
         let text_document_position_params = TextDocumentPositionParams {
             text_document: TextDocumentIdentifier {
                 uri: self.opened_documents.get(&view_id).unwrap().clone(),
@@ -293,8 +391,7 @@ impl LanguageServerClient {
             position,
         };
 
-        let params = Params::from(serde_json::to_value(text_document_position_params).unwrap());
-        self.send_request("textDocument/hover", params, Box::new(on_result))
+        Params::from(serde_json::to_value(text_document_position_params).unwrap())
     }
 }
 
@@ -313,6 +410,27 @@ impl LanguageServerClient {
             _ => TextDocumentSyncKind::Full,
         }
     }
+
+    /// The characters that, as well as identifier characters, should
+    /// trigger a completion request when typed.
+    pub fn get_completion_trigger_characters(&self) -> Vec<String> {
+        self.server_capabilities
+            .as_ref()
+            .and_then(|c| c.completion_provider.as_ref())
+            .and_then(|c| c.trigger_characters.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn supports_semantic_tokens(&self) -> bool {
+        self.semantic_tokens_legend.is_some()
+    }
+
+    pub fn supports_completion(&self) -> bool {
+        self.server_capabilities
+            .as_ref()
+            .map(|c| c.completion_provider.is_some())
+            .unwrap_or(false)
+    }
 }
 
 /// Language Specific Notification handling implementations
@@ -13,6 +13,7 @@
 // limitations under the License.
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 
 #[macro_use]
@@ -37,6 +38,7 @@ pub mod conversion_utils;
 pub mod language_server_client;
 pub mod lsp_plugin;
 pub mod parse_helper;
+pub mod semantic_tokens;
 pub mod types;
 mod result_queue;
 mod utils;
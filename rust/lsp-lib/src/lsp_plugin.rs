@@ -15,11 +15,13 @@
 //! Implementation of Language Server Plugin
 
 use result_queue::ResultQueue;
+use semantic_tokens::{self, SemanticToken, SemanticTokenLayer, SemanticTokens,
+                      SEMANTIC_TOKEN_PRIORITY};
 use conversion_utils::*;
 use language_server_client::LanguageServerClient;
 use lsp_types::*;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -28,12 +30,14 @@ use url::Url;
 use utils::*;
 use xi_core::ConfigTable;
 use xi_core::ViewId;
-use xi_plugin_lib::{ChunkCache, CoreProxy, Plugin, View};
+use xi_plugin_lib::{ChunkCache, CompletionList, CoreProxy, DefinitionKind,
+                    Error as PluginLibError, Plugin, PluginCapability, View};
 use xi_rope::rope::RopeDelta;
 
 pub struct ViewInfo {
     version: u64,
     ls_identifier: String,
+    semantic_tokens: SemanticTokenLayer,
 }
 
 /// Represents the state of the Language Server Plugin
@@ -43,6 +47,9 @@ pub struct LspPlugin {
     core: Option<CoreProxy>,
     result_queue: ResultQueue,
     language_server_clients: HashMap<String, Arc<Mutex<LanguageServerClient>>>,
+    /// The diagnostic sources last published for each view, which are
+    /// cleared when the server publishes again.
+    diagnostic_sources: HashMap<ViewId, HashSet<String>>,
 }
 
 impl LspPlugin {
@@ -53,6 +60,7 @@ impl LspPlugin {
             result_queue: ResultQueue::new(),
             view_info: HashMap::new(),
             language_server_clients: HashMap::new(),
+            diagnostic_sources: HashMap::new(),
         }
     }
 }
@@ -64,15 +72,26 @@ impl Plugin for LspPlugin {
         self.core = Some(core)
     }
 
+    fn capabilities(&self) -> Vec<PluginCapability> {
+        // hovers are provided only for the views with a language server,
+        // which register for them in `new_view`
+        vec![
+            PluginCapability::DefinitionProvider,
+            PluginCapability::CompletionProvider,
+            PluginCapability::FormattingProvider,
+        ]
+    }
+
     fn update(
         &mut self,
         view: &mut View<Self::Cache>,
         delta: Option<&RopeDelta>,
-        _edit_type: String,
+        edit_type: String,
         _author: String,
     ) {
         let view_info = self.view_info.get_mut(&view.get_id());
         if let Some(view_info) = view_info {
+            let view_id = view.get_id();
             // This won't fail since we definitely have a client for the given
             // client identifier
             let ls_client = self
@@ -84,7 +103,17 @@ impl Plugin for LspPlugin {
             let sync_kind = ls_client.get_sync_kind();
             view_info.version += 1;
             if let Some(changes) = get_change_for_sync_kind(sync_kind, view, delta) {
-                ls_client.send_did_change(view.get_id(), changes, view_info.version);
+                ls_client.send_did_change(view_id, changes, view_info.version);
+                if ls_client.supports_semantic_tokens() {
+                    request_semantic_tokens(&mut ls_client, view_id, view_info.version);
+                }
+            }
+
+            if edit_type == "insert" && ls_client.supports_completion() {
+                let triggers = ls_client.get_completion_trigger_characters();
+                if let Some(position) = completion_position(delta, &triggers) {
+                    request_completion(&mut ls_client, view, position);
+                }
             }
         }
     }
@@ -100,7 +129,8 @@ impl Plugin for LspPlugin {
 
     fn did_close(&mut self, view: &View<Self::Cache>) {
         trace!("close view {}", view.get_id());
-        
+        self.diagnostic_sources.remove(&view.get_id());
+
         self.with_language_server_for_view(view, |ls_client| {
             ls_client.send_did_close(view.get_id());    
         });
@@ -131,11 +161,13 @@ impl Plugin for LspPlugin {
             let result = self.get_lsclient_from_workspace_root(language_id, &workspace_root_uri);
 
             if let Some((identifier, ls_client)) = result {
+                view.register_hover_provider();
                 self.view_info.insert(
                     view.get_id(),
                     ViewInfo {
                         version: 0,
                         ls_identifier: identifier,
+                        semantic_tokens: SemanticTokenLayer::default(),
                     },
                 );
                 let mut ls_client = ls_client.lock().unwrap();
@@ -145,18 +177,26 @@ impl Plugin for LspPlugin {
                 if !ls_client.is_initialized {
                     ls_client.send_initialize(workspace_root_uri, move |ls_client, result| {
                         if let Ok(result) = result {
+                            let legend = semantic_tokens::legend_from_initialize_result(&result);
                             let init_result: InitializeResult =
                                 serde_json::from_value(result).unwrap();
 
                             debug!("Init Result: {:?}", init_result);
 
                             ls_client.server_capabilities = Some(init_result.capabilities);
+                            ls_client.semantic_tokens_legend = legend;
                             ls_client.is_initialized = true;
                             ls_client.send_did_open(view_id, document_uri, document_text);
+                            if ls_client.supports_semantic_tokens() {
+                                request_semantic_tokens(ls_client, view_id, 0);
+                            }
                         }
                     });
                 } else {
                     ls_client.send_did_open(view_id, document_uri, document_text);
+                    if ls_client.supports_semantic_tokens() {
+                        request_semantic_tokens(&mut ls_client, view_id, 0);
+                    }
                 }
             }
         }
@@ -174,7 +214,7 @@ impl Plugin for LspPlugin {
         let view_id = view.get_id();
         let position_ls = get_position_of_offset(view, position);
 
-        self.with_language_server_for_view(view, |ls_client| {
+        let sent = self.with_language_server_for_view(view, |ls_client| {
 
                 match position_ls {
                     Ok(position) => ls_client.request_hover(
@@ -188,29 +228,85 @@ impl Plugin for LspPlugin {
                                     hover.ok_or(LanguageResponseError::NullResponse)
                                 });
                             
-                            ls_client.result_queue.push_result(request_id, LspResponse::Hover(res));
+                            ls_client.result_queue.push_result(view_id, request_id, LspResponse::Hover(res));
                             ls_client.core.schedule_idle(view_id);
                         },
                     ),
                     Err(err) => {
-                        ls_client.result_queue.push_result(request_id, LspResponse::Hover(Err(err.into())));
+                        ls_client.result_queue.push_result(view_id, request_id,
+                                                           LspResponse::Hover(Err(err.into())));
                         ls_client.core.schedule_idle(view_id);
                     }
                 }
                     
         });
+        // without a language server there is nothing to show, so the
+        // request is answered straight away
+        if sent.is_none() {
+            if let Some(core) = self.core.as_mut() {
+                let res = Err(LanguageResponseError::NullResponse.into());
+                core.display_hover(view_id, request_id, res);
+            }
+        }
     }
 
-    fn idle(&mut self, view: &mut View<Self::Cache>) {
-        let result = self.result_queue.pop_result();
-        if let Some((request_id, reponse)) = result {
-            match reponse {
-                LspResponse::Hover(res) => {
-                    let res = res.and_then(|h| core_hover_from_hover(view, h)).map_err(|e| e.into());
-                    self.with_language_server_for_view(view, |ls_client| 
-                            ls_client.core.display_hover(view.get_id(), request_id, res));
+    fn get_definition(
+        &mut self,
+        view: &mut View<Self::Cache>,
+        request_id: usize,
+        position: usize,
+        kind: DefinitionKind,
+    ) {
+        let view_id = view.get_id();
+        let position_ls = get_position_of_offset(view, position);
+
+        self.with_language_server_for_view(view, |ls_client| {
+            let position = match position_ls {
+                Ok(position) => position,
+                Err(err) => {
+                    let res = LspResponse::Definition(Err(err.into()));
+                    ls_client.result_queue.push_result(view_id, request_id, res);
+                    ls_client.core.schedule_idle(view_id);
+                    return;
                 }
-            }
+            };
+            ls_client.request_definition(view_id, position, kind, move |ls_client, result| {
+                let res = result
+                    .map_err(|e| LanguageResponseError::LanguageServerError(format!("{:?}", e)))
+                    .and_then(location_from_definition_response);
+                ls_client.result_queue.push_result(view_id, request_id, LspResponse::Definition(res));
+                ls_client.core.schedule_idle(view_id);
+            });
+        });
+    }
+
+    fn format_document(&mut self, view: &mut View<Self::Cache>, request_id: usize) {
+        let view_id = view.get_id();
+        let options = FormattingOptions {
+            tab_size: view.get_config().tab_size as u64,
+            insert_spaces: view.get_config().translate_tabs_to_spaces,
+            properties: HashMap::new(),
+        };
+
+        self.with_language_server_for_view(view, |ls_client| {
+            ls_client.request_formatting(view_id, options, move |ls_client, result| {
+                let res = result
+                    .map_err(|e| LanguageResponseError::LanguageServerError(format!("{:?}", e)))
+                    .and_then(|value| {
+                        let edits: Option<Vec<TextEdit>> = serde_json::from_value(value)
+                            .map_err(|e| LanguageResponseError::LanguageServerError(e.to_string()))?;
+                        Ok(edits.unwrap_or_default())
+                    });
+                ls_client.result_queue.push_result(view_id, request_id,
+                                                   LspResponse::Formatting(res));
+                ls_client.core.schedule_idle(view_id);
+            });
+        });
+    }
+
+    fn idle(&mut self, view: &mut View<Self::Cache>) {
+        while let Some((request_id, response)) = self.result_queue.pop_result(view.get_id()) {
+            self.handle_response(view, request_id, response);
         }
     }
 }
@@ -218,6 +314,94 @@ impl Plugin for LspPlugin {
 /// Util Methods
 impl LspPlugin {
 
+    /// Delivers a language server's response, queued for `view`.
+    fn handle_response(&mut self, view: &mut View<ChunkCache>, request_id: usize,
+                       response: LspResponse) {
+        let view_id = view.get_id();
+        match response {
+            LspResponse::Hover(res) => {
+                let res = res.and_then(|h| core_hover_from_hover(view, h)).map_err(|e| e.into());
+                self.with_language_server_for_view(view, |ls_client|
+                        ls_client.core.display_hover(view_id, request_id, res));
+            }
+            LspResponse::Definition(res) => {
+                let res = res.and_then(core_location_from_location).map_err(|e| e.into());
+                self.with_language_server_for_view(view, |ls_client|
+                        ls_client.core.display_definition(view_id, request_id, res));
+            }
+            LspResponse::Completion { position, result } => match result {
+                Ok(ref items) if items.is_empty() => view.hide_completions(),
+                Ok(items) => {
+                    let trigger_offset = match completion_trigger_offset_in_view(view, position) {
+                        Ok(trigger_offset) => trigger_offset,
+                        Err(err) => return warn!("completion failed: {:?}", err),
+                    };
+                    let list = CompletionList {
+                        items: items.into_iter().map(core_completion_from_completion).collect(),
+                        trigger_offset,
+                    };
+                    view.show_completions(&list);
+                }
+                Err(LanguageResponseError::NullResponse) => view.hide_completions(),
+                Err(err) => warn!("completion failed: {:?}", err),
+            },
+            LspResponse::Diagnostics(diagnostics) => {
+                let default_source = self.view_info.get(&view_id)
+                    .map(|info| info.ls_identifier.clone())
+                    .unwrap_or_else(|| "lsp".to_string());
+                let mut spans = Vec::with_capacity(diagnostics.len());
+                for diagnostic in diagnostics {
+                    match core_diagnostic_from_diagnostic(view, diagnostic, &default_source) {
+                        Ok(span) => spans.push(span),
+                        Err(err) => warn!("dropping diagnostic: {:?}", err),
+                    }
+                }
+                let sources = self.diagnostic_sources.entry(view_id).or_default();
+                for source in sources.drain() {
+                    view.clear_diagnostics(&source);
+                }
+                sources.extend(spans.iter().map(|span| span.source.clone()));
+                view.add_diagnostics(&spans);
+            }
+            LspResponse::Formatting(res) => {
+                let res = res.and_then(|edits| core_text_edits_from_text_edits(view, edits))
+                    .map_err(|e| e.into());
+                self.with_language_server_for_view(view, |ls_client|
+                        ls_client.core.format_edits(view_id, request_id, res));
+            }
+            LspResponse::SemanticTokens { version, result } => {
+                let tokens = match result {
+                    Ok(tokens) => SemanticToken::decode(&tokens.data),
+                    Err(err) => return warn!("semantic tokens failed: {:?}", err),
+                };
+                let legend = match self.with_language_server_for_view(
+                    view, |ls_client| ls_client.semantic_tokens_legend.clone()) {
+                    Some(Some(legend)) => legend,
+                    _ => return,
+                };
+                let view_info = match self.view_info.get_mut(&view_id) {
+                    Some(view_info) => view_info,
+                    None => return,
+                };
+                // tokens for an older version are superseded by a request
+                // made since
+                if view_info.version != version {
+                    return;
+                }
+                let (scopes, spans) = view_info.semantic_tokens.spans_for_tokens(
+                    &legend, &tokens, |line, character| {
+                        let position = Position { line: line as u64, character: character as u64 };
+                        offset_of_position(view, position).ok()
+                    });
+                if !scopes.is_empty() {
+                    view.add_scopes_with_priority(&scopes, SEMANTIC_TOKEN_PRIORITY);
+                }
+                let len = view.get_buf_size();
+                view.update_spans(0, len, &spans);
+            }
+        }
+    }
+
     /// Get the Language Server Client given the Workspace root
     /// This method checks if a language server is running at the specified root
     /// and returns it else it tries to spawn a new language server and returns a
@@ -314,4 +498,57 @@ impl LspPlugin {
             let mut ls_client = ls_client_arc.lock().unwrap();
             Some(f(&mut ls_client))
     }
-}
\ No newline at end of file
+}
+
+/// Returns the offset to request completions at, after an edit, if it
+/// typed an identifier character or one of `triggers`.
+fn completion_position(delta: Option<&RopeDelta>, triggers: &[String]) -> Option<usize> {
+    let delta = delta?;
+    let (iv, new_len) = delta.summary();
+    let text = String::from(delta.as_simple_insert()?);
+    let last = text.chars().last()?;
+    let is_trigger = last.is_alphanumeric() || last == '_'
+        || triggers.iter().any(|t| text.ends_with(t.as_str()));
+    if is_trigger { Some(iv.start() + new_len) } else { None }
+}
+
+fn completion_trigger_offset_in_view(view: &mut View<ChunkCache>, position: usize)
+                                    -> Result<usize, PluginLibError> {
+    let line_num = view.line_of_offset(position)?;
+    let line_start = view.offset_of_line(line_num)?;
+    let line = view.get_line(line_num)?;
+    let prefix = line.get(..position - line_start).unwrap_or(line);
+    Ok(line_start + completion_trigger_offset(prefix))
+}
+
+fn request_semantic_tokens(ls_client: &mut LanguageServerClient, view_id: ViewId, version: u64) {
+    ls_client.request_semantic_tokens(view_id, move |ls_client, result| {
+        let result = result
+            .map_err(|e| LanguageResponseError::LanguageServerError(format!("{:?}", e)))
+            .and_then(|value| {
+                let tokens: Option<SemanticTokens> = serde_json::from_value(value)
+                    .map_err(|e| LanguageResponseError::LanguageServerError(e.to_string()))?;
+                tokens.ok_or(LanguageResponseError::NullResponse)
+            });
+        let response = LspResponse::SemanticTokens { version, result };
+        ls_client.result_queue.push_result(view_id, 0, response);
+        ls_client.core.schedule_idle(view_id);
+    });
+}
+
+fn request_completion(ls_client: &mut LanguageServerClient, view: &mut View<ChunkCache>,
+                      position: usize) {
+    let view_id = view.get_id();
+    let position_ls = match get_position_of_offset(view, position) {
+        Ok(position_ls) => position_ls,
+        Err(err) => return warn!("completion failed: {:?}", err),
+    };
+    ls_client.request_completion(view_id, position_ls, move |ls_client, result| {
+        let result = result
+            .map_err(|e| LanguageResponseError::LanguageServerError(format!("{:?}", e)))
+            .and_then(completion_items_from_response);
+        let response = LspResponse::Completion { position, result };
+        ls_client.result_queue.push_result(view_id, 0, response);
+        ls_client.core.schedule_idle(view_id);
+    });
+}
@@ -15,9 +15,12 @@
 use std::collections::VecDeque;
 use types::LspResponse;
 use std::sync::{Arc, Mutex};
+use xi_core::ViewId;
 
+/// Responses from language servers, waiting for the plugin's next `idle`
+/// call on the view they belong to.
 #[derive(Clone, Debug, Default)]
-pub struct ResultQueue(Arc<Mutex<VecDeque<(usize, LspResponse)>>>);
+pub struct ResultQueue(Arc<Mutex<VecDeque<(ViewId, usize, LspResponse)>>>);
 
 impl ResultQueue {
 
@@ -25,13 +28,15 @@ impl ResultQueue {
         ResultQueue(Arc::new(Mutex::new(VecDeque::new())))
     }
 
-    pub fn push_result(&mut self, request_id: usize, response: LspResponse) {
+    pub fn push_result(&mut self, view_id: ViewId, request_id: usize, response: LspResponse) {
         let mut queue = self.0.lock().unwrap();
-        queue.push_back((request_id, response));
+        queue.push_back((view_id, request_id, response));
     }
 
-    pub fn pop_result(&mut self) -> Option<(usize, LspResponse)> {
+    /// Returns the oldest result for `view_id`.
+    pub fn pop_result(&mut self, view_id: ViewId) -> Option<(usize, LspResponse)> {
         let mut queue = self.0.lock().unwrap();
-        queue.pop_front()
+        let index = queue.iter().position(|&(id, _, _)| id == view_id)?;
+        queue.remove(index).map(|(_, request_id, response)| (request_id, response))
     }
 }
\ No newline at end of file
@@ -0,0 +1,270 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Semantic tokens, with which a language server colors the symbols of a
+//! document by what they are, such as the declaration of a function or a
+//! parameter. They are sent to the core as a layer of scopes of their own,
+//! whose styles take priority over those of syntax highlighting.
+//!
+//! These are from version 3.16 of the protocol, which the version of
+//! `languageserver-types` in use predates, so the types are defined here.
+
+use std::collections::HashMap;
+
+use lsp_types::TextDocumentIdentifier;
+use serde_json::{self, Value};
+use xi_core::plugin_rpc::ScopeSpan;
+
+/// The priority of the styles of semantic tokens, above the 200 of syntax
+/// highlighting.
+pub const SEMANTIC_TOKEN_PRIORITY: u16 = 300;
+
+/// The token types that `scope_for_token` has a scope for.
+const TOKEN_TYPES: &[&str] = &[
+    "namespace", "type", "class", "enum", "interface", "struct", "typeParameter",
+    "parameter", "variable", "property", "enumMember", "event", "function",
+    "method", "macro", "keyword", "modifier", "comment", "string", "number",
+    "regexp", "operator", "decorator", "label",
+];
+
+/// The token modifiers that `scope_for_token` takes into account.
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "definition", "readonly"];
+
+/// The names of the token types and modifiers a server uses, which tokens
+/// refer to by index.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensLegend {
+    pub token_types: Vec<String>,
+    pub token_modifiers: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// The tokens of a document, encoded five integers to a token.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokens {
+    #[serde(default)]
+    pub result_id: Option<String>,
+    pub data: Vec<u32>,
+}
+
+/// A token, positioned relative to the start of the one before it: on a
+/// later line, `delta_start` is from the start of the line. Positions and
+/// lengths are in utf-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SemanticToken {
+    pub delta_line: u32,
+    pub delta_start: u32,
+    pub length: u32,
+    pub token_type: u32,
+    pub token_modifiers_bitset: u32,
+}
+
+impl SemanticToken {
+    /// Decodes the tokens of `data`, ignoring any incomplete one at its end.
+    pub fn decode(data: &[u32]) -> Vec<SemanticToken> {
+        data.chunks(5)
+            .filter(|chunk| chunk.len() == 5)
+            .map(|chunk| SemanticToken {
+                delta_line: chunk[0],
+                delta_start: chunk[1],
+                length: chunk[2],
+                token_type: chunk[3],
+                token_modifiers_bitset: chunk[4],
+            })
+            .collect()
+    }
+}
+
+/// The client capability announcing support for semantic tokens.
+pub fn client_capability() -> Value {
+    json!({
+        "dynamicRegistration": false,
+        "requests": { "full": true },
+        "tokenTypes": TOKEN_TYPES,
+        "tokenModifiers": TOKEN_MODIFIERS,
+        "formats": ["relative"],
+    })
+}
+
+/// Returns the legend of the semantic tokens the server provides, if it
+/// does, from the result of its `initialize` request.
+pub fn legend_from_initialize_result(result: &Value) -> Option<SemanticTokensLegend> {
+    let legend = result.get("capabilities")?.get("semanticTokensProvider")?.get("legend")?;
+    serde_json::from_value(legend.clone()).ok()
+}
+
+/// Returns the scope that themes style a token of `token_type`, with
+/// `modifiers`, by; `None` if there is none.
+pub fn scope_for_token(token_type: &str, modifiers: &[&str]) -> Option<String> {
+    let scope = match token_type {
+        "namespace" => "entity.name.namespace",
+        "type" => "entity.name.type",
+        "class" => "entity.name.type.class",
+        "enum" => "entity.name.type.enum",
+        "interface" => "entity.name.type.interface",
+        "struct" => "entity.name.type.struct",
+        "typeParameter" => "entity.name.type.parameter",
+        "parameter" => "variable.parameter",
+        "variable" if modifiers.contains(&"readonly") => "variable.other.constant",
+        "variable" => "variable.other",
+        "property" => "variable.other.property",
+        "enumMember" => "constant.other.enum-member",
+        "event" => "variable.other.event",
+        "function" => "entity.name.function",
+        "method" => "entity.name.function.method",
+        "macro" => "entity.name.function.macro",
+        "keyword" => "keyword.other",
+        "modifier" => "storage.modifier",
+        "comment" => "comment",
+        "string" => "string.quoted",
+        "number" => "constant.numeric",
+        "regexp" => "string.regexp",
+        "operator" => "keyword.operator",
+        "decorator" => "entity.name.function.decorator",
+        "label" => "entity.name.label",
+        _ => return None,
+    };
+    if modifiers.contains(&"declaration") || modifiers.contains(&"definition") {
+        Some(format!("{}.declaration", scope))
+    } else {
+        Some(scope.to_owned())
+    }
+}
+
+/// The semantic tokens of a view, as a layer of scopes in the core.
+#[derive(Debug, Default)]
+pub struct SemanticTokenLayer {
+    /// The identifier of each scope already added to the core's layer.
+    scope_ids: HashMap<String, u32>,
+}
+
+impl SemanticTokenLayer {
+    /// Converts `tokens` to spans for the whole document. `offset_of`
+    /// returns the utf-8 offset of a line and a utf-16 column in it, or
+    /// `None` if it is outside the document. Returns the scope stacks to
+    /// add to the core's layer before the spans are sent, and the spans.
+    pub fn spans_for_tokens<F>(&mut self, legend: &SemanticTokensLegend,
+                               tokens: &[SemanticToken], mut offset_of: F)
+                               -> (Vec<Vec<String>>, Vec<ScopeSpan>)
+        where F: FnMut(usize, usize) -> Option<usize>
+    {
+        let mut new_scopes = Vec::new();
+        let mut spans = Vec::new();
+        let (mut line, mut start) = (0, 0);
+        for token in tokens {
+            if token.delta_line > 0 {
+                line += token.delta_line as usize;
+                start = token.delta_start as usize;
+            } else {
+                start += token.delta_start as usize;
+            }
+            let token_type = match legend.token_types.get(token.token_type as usize) {
+                Some(token_type) => token_type,
+                None => continue,
+            };
+            let modifiers = legend.token_modifiers.iter().take(32).enumerate()
+                .filter(|&(i, _)| token.token_modifiers_bitset & (1 << i) != 0)
+                .map(|(_, modifier)| modifier.as_str())
+                .collect::<Vec<_>>();
+            let scope = match scope_for_token(token_type, &modifiers) {
+                Some(scope) => scope,
+                None => continue,
+            };
+            let end = start + token.length as usize;
+            let (span_start, span_end) = match (offset_of(line, start), offset_of(line, end)) {
+                (Some(span_start), Some(span_end)) if span_start < span_end =>
+                    (span_start, span_end),
+                _ => continue,
+            };
+            let scope_id = match self.scope_ids.get(&scope) {
+                Some(&scope_id) => scope_id,
+                None => {
+                    let scope_id = self.scope_ids.len() as u32;
+                    self.scope_ids.insert(scope.clone(), scope_id);
+                    new_scopes.push(vec![scope]);
+                    scope_id
+                }
+            };
+            spans.push(ScopeSpan { start: span_start, end: span_end, scope_id });
+        }
+        (new_scopes, spans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_tokens() {
+        let tokens = SemanticToken::decode(&[2, 5, 3, 0, 3, 0, 5, 4, 1, 0, 7]);
+        assert_eq!(tokens, vec![
+            SemanticToken { delta_line: 2, delta_start: 5, length: 3,
+                            token_type: 0, token_modifiers_bitset: 3 },
+            SemanticToken { delta_line: 0, delta_start: 5, length: 4,
+                            token_type: 1, token_modifiers_bitset: 0 },
+        ]);
+    }
+
+    #[test]
+    fn token_scopes() {
+        assert_eq!(scope_for_token("function", &["declaration"]),
+                   Some("entity.name.function.declaration".into()));
+        assert_eq!(scope_for_token("variable", &["readonly"]),
+                   Some("variable.other.constant".into()));
+        assert_eq!(scope_for_token("parameter", &[]), Some("variable.parameter".into()));
+        assert_eq!(scope_for_token("unknownType", &[]), None);
+    }
+
+    #[test]
+    fn token_spans() {
+        let legend = SemanticTokensLegend {
+            token_types: vec!["function".into(), "parameter".into(), "unknownType".into()],
+            token_modifiers: vec!["declaration".into()],
+        };
+        // "fn foo(a: u8) {\n    foo(a);\n}", with lines starting at 0 and 16
+        let offset_of = |line: usize, col: usize| match line {
+            0 => Some(col),
+            1 => Some(16 + col),
+            _ => None,
+        };
+        let tokens = SemanticToken::decode(&[
+            0, 3, 3, 0, 1,  // foo, declared
+            0, 4, 1, 1, 0,  // a
+            1, 4, 3, 0, 0,  // foo
+            0, 4, 1, 2, 0,  // a, of an unknown type
+            1, 0, 1, 1, 0,  // outside the document
+        ]);
+        let mut layer = SemanticTokenLayer::default();
+        let (scopes, spans) = layer.spans_for_tokens(&legend, &tokens, offset_of);
+        assert_eq!(scopes, vec![vec!["entity.name.function.declaration".to_string()],
+                                vec!["variable.parameter".to_string()],
+                                vec!["entity.name.function".to_string()]]);
+        let spans = spans.iter().map(|s| (s.start, s.end, s.scope_id)).collect::<Vec<_>>();
+        assert_eq!(spans, vec![(3, 6, 0), (7, 8, 1), (20, 23, 2)]);
+
+        // scopes are only added once
+        let (scopes, spans) = layer.spans_for_tokens(&legend, &tokens[..2], offset_of);
+        assert!(scopes.is_empty());
+        let spans = spans.iter().map(|s| (s.start, s.end, s.scope_id)).collect::<Vec<_>>();
+        assert_eq!(spans, vec![(3, 6, 0), (7, 8, 1)]);
+    }
+}
@@ -14,6 +14,7 @@
 
 use jsonrpc_lite::Error as JsonRpcError;
 use language_server_client::LanguageServerClient;
+use semantic_tokens::SemanticTokens;
 use lsp_types::*;
 use serde_json;
 use serde_json::Value;
@@ -159,5 +160,13 @@ impl Into<RemoteError> for LanguageResponseError {
 
 #[derive(Debug)]
 pub enum LspResponse {
-    Hover(Result<Hover, LanguageResponseError>)
+    Hover(Result<Hover, LanguageResponseError>),
+    Definition(Result<Location, LanguageResponseError>),
+    /// Completions at `position`, the utf-8 offset they were requested at.
+    Completion { position: usize, result: Result<Vec<CompletionItem>, LanguageResponseError> },
+    /// The diagnostics published for a document, replacing any before.
+    Diagnostics(Vec<Diagnostic>),
+    Formatting(Result<Vec<TextEdit>, LanguageResponseError>),
+    /// The semantic tokens of the document at `version`.
+    SemanticTokens { version: u64, result: Result<SemanticTokens, LanguageResponseError> },
 }
\ No newline at end of file
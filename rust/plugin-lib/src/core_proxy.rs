@@ -14,7 +14,7 @@
 
 //! A proxy for the methods on Core
 use xi_core::plugins::PluginId;
-use xi_core::plugin_rpc::Hover;
+use xi_core::plugin_rpc::{Hover, Location, TextEdit};
 use xi_core::ViewId;
 use xi_rpc::{RpcCtx, RpcPeer, RemoteError};
 
@@ -81,6 +81,38 @@ impl CoreProxy {
         self.peer.send_rpc_notification("show_hover", &params);
     }
 
+    pub fn display_definition(
+        &mut self,
+        view_id: ViewId,
+        request_id: usize,
+        result: Result<Location, RemoteError>
+    ) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "request_id": request_id,
+            "result": result,
+            "view_id": view_id
+        });
+
+        self.peer.send_rpc_notification("show_definition", &params);
+    }
+
+    pub fn format_edits(
+        &mut self,
+        view_id: ViewId,
+        request_id: usize,
+        result: Result<Vec<TextEdit>, RemoteError>
+    ) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "request_id": request_id,
+            "result": result,
+            "view_id": view_id
+        });
+
+        self.peer.send_rpc_notification("format_edits", &params);
+    }
+
     pub fn schedule_idle(&mut self, view_id: ViewId) {
         let token: usize = view_id.into();
         self.peer.schedule_idle(token);
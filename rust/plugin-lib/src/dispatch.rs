@@ -18,7 +18,8 @@ use std::path::PathBuf;
 use serde_json::{self, Value};
 
 use xi_core::{ViewId, PluginPid, ConfigTable};
-use xi_core::plugin_rpc::{PluginBufferInfo, PluginUpdate, HostRequest, HostNotification};
+use xi_core::plugin_rpc::{PluginBufferInfo, PluginUpdate, HostRequest, HostNotification,
+                          Command, DefinitionKind};
 use xi_rpc::{RpcCtx, RemoteError, Handler as RpcHandler};
 use xi_trace::{self, trace, trace_block, trace_block_payload};
 use core_proxy::CoreProxy;
@@ -128,6 +129,29 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
         self.plugin.get_hover(v, request_id, position)
     }
 
+    fn do_get_definition(&mut self, view_id: ViewId, request_id: usize, position: usize,
+                         kind: DefinitionKind) {
+        let v = bail!(self.views.get_mut(&view_id), "get_definition", self.pid, view_id);
+        self.plugin.get_definition(v, request_id, position, kind)
+    }
+
+    fn do_streaming_request(&mut self, view_id: ViewId, token: String, method: String,
+                            params: Value) {
+        let v = bail!(self.views.get_mut(&view_id), "streaming_request", self.pid, view_id);
+        let stream = v.stream_sender(token);
+        self.plugin.streaming_request(v, stream, &method, params)
+    }
+
+    fn do_format_document(&mut self, view_id: ViewId, request_id: usize) {
+        let v = bail!(self.views.get_mut(&view_id), "format_document", self.pid, view_id);
+        self.plugin.format_document(v, request_id)
+    }
+
+    fn do_execute_command(&mut self, view_id: ViewId, command: Command) {
+        let v = bail!(self.views.get_mut(&view_id), "execute_command", self.pid, view_id);
+        self.plugin.execute_command(v, command)
+    }
+
     fn do_tracing_config(&mut self, enabled: bool) {
         use xi_trace;
 
@@ -155,6 +179,46 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
         return Ok(Value::from(1));
     }
 
+    fn do_get_inlay_hints(&mut self, view_id: ViewId, first_line: usize,
+                          last_line: usize) -> Result<Value, RemoteError> {
+        let v = bail_err!(self.views.get_mut(&view_id), "get_inlay_hints",
+                          self.pid, view_id);
+        let hints = self.plugin.get_inlay_hints(v, first_line, last_line);
+        Ok(json!(hints))
+    }
+
+    fn do_get_document_symbols(&mut self, view_id: ViewId) -> Result<Value, RemoteError> {
+        let v = bail_err!(self.views.get_mut(&view_id), "get_document_symbols",
+                          self.pid, view_id);
+        Ok(json!(self.plugin.get_document_symbols(v)))
+    }
+
+    fn do_get_code_actions(&mut self, view_id: ViewId, start: usize, end: usize)
+                           -> Result<Value, RemoteError> {
+        let v = bail_err!(self.views.get_mut(&view_id), "get_code_actions",
+                          self.pid, view_id);
+        Ok(json!(self.plugin.get_code_actions(v, start, end)))
+    }
+
+    fn do_get_document_highlights(&mut self, view_id: ViewId, position: usize)
+                                  -> Result<Value, RemoteError> {
+        let v = bail_err!(self.views.get_mut(&view_id), "get_document_highlights",
+                          self.pid, view_id);
+        Ok(json!(self.plugin.get_document_highlights(v, position)))
+    }
+
+    fn do_prepare_rename(&mut self, view_id: ViewId, position: usize)
+                         -> Result<Value, RemoteError> {
+        let v = bail_err!(self.views.get_mut(&view_id), "prepare_rename", self.pid, view_id);
+        self.plugin.prepare_rename(v, position).map(|range| json!(range))
+    }
+
+    fn do_rename(&mut self, view_id: ViewId, position: usize, new_name: &str)
+                 -> Result<Value, RemoteError> {
+        let v = bail_err!(self.views.get_mut(&view_id), "rename", self.pid, view_id);
+        self.plugin.rename(v, position, new_name).map(|edit| json!(edit))
+    }
+
     fn do_collect_trace(&self) -> Result<Value, RemoteError> {
         use xi_trace_dump::*;
 
@@ -192,6 +256,14 @@ impl<'a, P: Plugin> RpcHandler for Dispatcher<'a, P> {
                 self.do_tracing_config(enabled),
             GetHover {  view_id, request_id, position } =>
                 self.do_get_hover(view_id, request_id, position),
+            GetDefinition { view_id, request_id, position, kind } =>
+                self.do_get_definition(view_id, request_id, position, kind),
+            StreamingRequest { view_id, token, method, params } =>
+                self.do_streaming_request(view_id, token, method, params),
+            FormatDocument { view_id, request_id } =>
+                self.do_format_document(view_id, request_id),
+            ExecuteCommand { view_id, command } =>
+                self.do_execute_command(view_id, command),
             Ping ( .. ) => (),
         }
     }
@@ -205,6 +277,20 @@ impl<'a, P: Plugin> RpcHandler for Dispatcher<'a, P> {
                 self.do_update(params),
             CollectTrace ( .. ) =>
                 self.do_collect_trace(),
+            GetInlayHints { view_id, first_line, last_line } =>
+                self.do_get_inlay_hints(view_id, first_line, last_line),
+            GetDocumentSymbols { view_id } =>
+                self.do_get_document_symbols(view_id),
+            GetCodeActions { view_id, start, end } =>
+                self.do_get_code_actions(view_id, start, end),
+            GetDocumentHighlights { view_id, position } =>
+                self.do_get_document_highlights(view_id, position),
+            PrepareRename { view_id, position } =>
+                self.do_prepare_rename(view_id, position),
+            Rename { view_id, position, new_name } =>
+                self.do_rename(view_id, position, &new_name),
+            Capabilities ( .. ) =>
+                Ok(json!(self.plugin.capabilities())),
         }
     }
 
@@ -35,12 +35,14 @@ mod base_cache;
 mod view;
 mod dispatch;
 mod core_proxy;
+mod stream;
 
 use std::io;
 use std::path::Path;
 
-use xi_rpc::{RpcLoop, ReadError};
+use xi_rpc::{RpcLoop, ReadError, RemoteError};
 use xi_rope::rope::RopeDelta;
+use serde_json::Value;
 use xi_core::ConfigTable;
 use xi_core::plugin_rpc::{GetDataResponse, TextUnit};
 
@@ -50,7 +52,13 @@ pub use view::View;
 pub use state_cache::StateCache;
 pub use base_cache::ChunkCache;
 pub use core_proxy::CoreProxy;
-pub use xi_core::plugin_rpc::{Hover, Range};
+pub use stream::StreamSender;
+pub use xi_core::plugin_rpc::{CodeAction, CodeActionKind, Command, CompletionItem,
+                               CompletionKind, CompletionList, DefinitionKind,
+                               DiagnosticSeverity, DiagnosticSpan, DocumentHighlight,
+                               EditPosition, HighlightKind, Hover,
+                               InlayHint, InlayHintKind, Location, PluginCapability, Range,
+                               RenameRange, SymbolInfo, SymbolKind, TextEdit, WorkspaceEdit};
 
 /// Abstracts getting data from the peer. Mainly exists for mocking in tests.
 pub trait DataSource {
@@ -152,10 +160,97 @@ pub trait Plugin {
     #[allow(unused_variables)]
     fn idle(&mut self, view: &mut View<Self::Cache>) { }
 
+    /// Returns the optional features this plugin provides. The core only
+    /// sends feature requests such as `get_hover` or `get_inlay_hints` to
+    /// plugins that claim the feature (or, for hover and definitions, that
+    /// have registered for the view).
+    fn capabilities(&self) -> Vec<PluginCapability> {
+        Vec::new()
+    }
+
     /// Language Plugins specific methods
     
     #[allow(unused_variables)]
     fn get_hover(&mut self, view: &mut View<Self::Cache>, request_id: usize, position: usize) { }
+
+    /// Called by views where the plugin has registered as a definition
+    /// provider; the result is sent with `CoreProxy::display_definition`.
+    #[allow(unused_variables)]
+    fn get_definition(&mut self, view: &mut View<Self::Cache>, request_id: usize,
+                      position: usize, kind: DefinitionKind) { }
+
+    /// Returns the inlay hints for the lines `first_line..=last_line`.
+    /// Plugins that provide inlay hints implement this; the core sends at
+    /// most one request per view at a time.
+    #[allow(unused_variables)]
+    fn get_inlay_hints(&mut self, view: &mut View<Self::Cache>, first_line: usize,
+                       last_line: usize) -> Vec<InlayHint> {
+        Vec::new()
+    }
+
+    /// Returns the symbols defined in the buffer, for workspace symbol
+    /// search. Sent to plugins that claim
+    /// `PluginCapability::DocumentSymbolProvider`.
+    #[allow(unused_variables)]
+    fn get_document_symbols(&mut self, view: &mut View<Self::Cache>) -> Vec<SymbolInfo> {
+        Vec::new()
+    }
+
+    /// Returns the code actions offered for the range from `start` to `end`.
+    /// Sent to plugins that claim `PluginCapability::CodeActionProvider`,
+    /// when the selection moves or the buffer changes.
+    #[allow(unused_variables)]
+    fn get_code_actions(&mut self, view: &mut View<Self::Cache>, start: usize,
+                        end: usize) -> Vec<CodeAction> {
+        Vec::new()
+    }
+
+    /// Runs the command of a code action that this plugin offered, when the
+    /// user applies it. Its edit, if any, has already been made.
+    #[allow(unused_variables)]
+    fn execute_command(&mut self, view: &mut View<Self::Cache>, command: Command) { }
+
+    /// Returns the references to the symbol at `position`, to highlight
+    /// until the selection changes. Sent to plugins that claim
+    /// `PluginCapability::DocumentHighlightProvider`.
+    #[allow(unused_variables)]
+    fn get_document_highlights(&mut self, view: &mut View<Self::Cache>, position: usize)
+                               -> Vec<DocumentHighlight> {
+        Vec::new()
+    }
+
+    /// Returns the symbol that a rename at `position` would change, or
+    /// `None` if there is nothing to rename there. Sent to plugins that
+    /// claim `PluginCapability::RenameProvider`.
+    #[allow(unused_variables)]
+    fn prepare_rename(&mut self, view: &mut View<Self::Cache>, position: usize)
+                      -> Result<Option<RenameRange>, RemoteError> {
+        Ok(None)
+    }
+
+    /// Returns the edits, to every file, that rename the symbol at
+    /// `position` to `new_name`. The core makes and saves them.
+    #[allow(unused_variables)]
+    fn rename(&mut self, view: &mut View<Self::Cache>, position: usize, new_name: &str)
+              -> Result<WorkspaceEdit, RemoteError> {
+        Err(RemoteError::custom(404, "rename is not supported", None))
+    }
+
+    /// Handles a streaming request, such as an incremental search, whose
+    /// results are sent with `stream` as they become available. Plugins
+    /// that don't support `method` should complete it with an error.
+    #[allow(unused_variables)]
+    fn streaming_request(&mut self, view: &mut View<Self::Cache>, stream: StreamSender,
+                         method: &str, params: Value) {
+        let message = format!("unsupported streaming request {}", method);
+        stream.complete(Err(RemoteError::custom(404, message, None)));
+    }
+
+    /// Reformats the buffer. Sent to plugins that claim
+    /// `PluginCapability::FormattingProvider`, which should answer with
+    /// `CoreProxy::format_edits`.
+    #[allow(unused_variables)]
+    fn format_document(&mut self, view: &mut View<Self::Cache>, request_id: usize) { }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,58 @@
+// Copyright 2018 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Answering streaming requests from core.
+use serde_json::Value;
+
+use xi_core::ViewId;
+use xi_core::plugins::PluginId;
+use xi_rpc::{RemoteError, RpcPeer};
+
+/// Sends the results of a streaming request to core: any number of partial
+/// results, then the final one. It can be moved to another thread, so that
+/// long-running work does not block the plugin's other requests.
+pub struct StreamSender {
+    peer: RpcPeer,
+    plugin_id: PluginId,
+    view_id: ViewId,
+    token: String,
+}
+
+impl StreamSender {
+    pub (crate) fn new(peer: RpcPeer, plugin_id: PluginId, view_id: ViewId,
+                       token: String) -> Self {
+        StreamSender { peer, plugin_id, view_id, token }
+    }
+
+    pub fn send_partial(&self, value: Value) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "token": self.token,
+            "value": value,
+        });
+        self.peer.send_rpc_notification("partial_result", &params);
+    }
+
+    /// Completes the request; nothing more can be sent for it.
+    pub fn complete(self, result: Result<Value, RemoteError>) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "token": self.token,
+            "result": result,
+        });
+        self.peer.send_rpc_notification("complete", &params);
+    }
+}
@@ -12,18 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::path::{PathBuf, Path};
 use serde_json::{self, Value};
 use serde::Deserialize;
 
 use xi_core::{ViewId, PluginPid, BufferConfig, ConfigTable};
-use xi_core::plugin_rpc::{TextUnit, PluginEdit, GetDataResponse, ScopeSpan, PluginBufferInfo};
+use xi_core::plugin_rpc::{TextUnit, PluginEdit, GetDataResponse, ScopeSpan, PluginBufferInfo,
+                          GutterAnnotation, SyntaxContext, CompletionList, DiagnosticSpan};
 use xi_rope::rope::RopeDelta;
 use xi_trace::trace_block;
 
 use xi_rpc::RpcPeer;
 
 use super::{Cache, Error, DataSource};
+use stream::StreamSender;
 
 /// A type that acts as a proxy for a remote view. Provides access to
 /// a document cache, and implements various methods for querying and modifying
@@ -89,6 +92,11 @@ impl<C: Cache> View<C> {
         }
     }
 
+    /// Returns a `StreamSender` for answering the streaming request `token`.
+    pub (crate) fn stream_sender(&self, token: String) -> StreamSender {
+        StreamSender::new(self.peer.clone(), self.plugin_id, self.view_id, token)
+    }
+
     /// Returns the length of the view's buffer, in bytes.
     pub fn get_buf_size(&self) -> usize {
         self.buf_size
@@ -139,6 +147,19 @@ impl<C: Cache> View<C> {
         self.peer.send_rpc_notification("add_scopes", &params);
     }
 
+    /// Like `add_scopes`, also setting the priority of the styles of this
+    /// plugin's scopes. Syntax highlighting has priority 200; a higher
+    /// priority wins where the spans of several plugins overlap.
+    pub fn add_scopes_with_priority(&self, scopes: &[Vec<String>], priority: u16) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "scopes": scopes,
+            "priority": priority,
+        });
+        self.peer.send_rpc_notification("add_scopes", &params);
+    }
+
     pub fn edit(&self, delta: RopeDelta, priority: u64, after_cursor: bool,
                 new_undo_group: bool, author: String) {
 
@@ -164,6 +185,103 @@ impl<C: Cache> View<C> {
         self.peer.send_rpc_notification("update_spans", &params);
     }
 
+    /// Adds diagnostics, which are shown until cleared with
+    /// `clear_diagnostics` or until the text they apply to is deleted.
+    pub fn add_diagnostics(&self, spans: &[DiagnosticSpan]) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "spans": spans,
+        });
+        self.peer.send_rpc_notification("add_diagnostics", &params);
+    }
+
+    /// Removes the diagnostics this plugin has added from `source`.
+    pub fn clear_diagnostics(&self, source: &str) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "source": source,
+        });
+        self.peer.send_rpc_notification("clear_diagnostics", &params);
+    }
+
+    /// Registers this plugin as a definition provider for the view, so that
+    /// it receives `get_definition` requests.
+    pub fn register_definition_provider(&self) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+        });
+        self.peer.send_rpc_notification("register_definition_provider", &params);
+    }
+
+    /// Registers this plugin as a hover provider for the view, so that it
+    /// receives `get_hover` requests.
+    pub fn register_hover_provider(&self) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+        });
+        self.peer.send_rpc_notification("register_hover_provider", &params);
+    }
+
+    /// Reports that this plugin is working on `task`, such as indexing, so
+    /// that the frontend can show it as busy; `None` reports that it is
+    /// done.
+    pub fn report_progress(&self, task: Option<&str>, message: Option<&str>) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "task": task,
+            "message": message,
+        });
+        self.peer.send_rpc_notification("report_progress", &params);
+    }
+
+    /// Replaces this plugin's gutter annotations, keyed by line.
+    pub fn set_gutter_annotations(&self, lines: &BTreeMap<usize, Vec<GutterAnnotation>>) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "lines": lines,
+        });
+        self.peer.send_rpc_notification("set_gutter_annotations", &params);
+    }
+
+    /// Offers completions to the user, replacing any shown before.
+    pub fn show_completions(&self, list: &CompletionList) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "list": list,
+        });
+        self.peer.send_rpc_notification("show_completions", &params);
+    }
+
+    pub fn hide_completions(&self) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+        });
+        self.peer.send_rpc_notification("hide_completions", &params);
+    }
+
+    /// Returns the syntax around `offset`, from the scopes reported by
+    /// syntax plugins. The core returns an error if `offset` is past the
+    /// end of the buffer or inside a character.
+    pub fn get_syntax_context(&self, offset: usize) -> Result<SyntaxContext, Error> {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "offset": offset,
+        });
+        let result = self.peer.send_rpc_request("get_syntax_context", &params)
+            .map_err(Error::RpcError)?;
+        SyntaxContext::deserialize(result)
+            .map_err(|_| Error::WrongReturnType)
+    }
+
     pub fn schedule_idle(&self) {
         let token: usize = self.view_id.into();
         self.peer.schedule_idle(token);
@@ -282,6 +282,92 @@ impl<N: NodeInfo> Delta<N> {
         (Interval::new_closed_open(iv_start, iv_end), Delta::total_element_len(els))
     }
 
+    /// Compose a sequence of deltas into a single delta, where each delta
+    /// applies to the result of the one before it:
+    ///
+    /// `compose_many(&[d1, d2, d3]).apply(s) == d3.apply(&d2.apply(&d1.apply(s)))`
+    ///
+    /// The intermediate results are built in a pair of reused buffers, so this
+    /// is cheaper than composing the deltas one pair at a time.
+    ///
+    /// Panics if `deltas` is empty.
+    pub fn compose_many(deltas: &[Delta<N>]) -> Delta<N> {
+        let (first, rest) = deltas.split_first().expect("compose_many requires at least one delta");
+        let mut els = first.els.clone();
+        let mut scratch = Vec::with_capacity(els.len());
+        let mut prev_len = first.new_document_len();
+        for delta in rest {
+            debug_assert_eq!(delta.base_len, prev_len, "deltas must be composable in sequence");
+            scratch.clear();
+            Delta::compose_els(&els, delta, &mut scratch);
+            ::std::mem::swap(&mut els, &mut scratch);
+            prev_len = delta.new_document_len();
+        }
+        Delta { els, base_len: first.base_len }
+    }
+
+    /// Append to `out` the elements of `next` applied on top of `prev`,
+    /// expressed in terms of the base document of `prev`.
+    fn compose_els(prev: &[DeltaElement<N>], next: &Delta<N>, out: &mut Vec<DeltaElement<N>>) {
+        // Index into `prev`, and the offset in the intermediate document where
+        // that element begins. Copies in `next` are sorted, so these only advance.
+        let mut i = 0;
+        let mut pos = 0;
+        for el in &next.els {
+            let (mut beg, end) = match *el {
+                DeltaElement::Copy(beg, end) => (beg, end),
+                DeltaElement::Insert(ref n) => {
+                    out.push(DeltaElement::Insert(n.clone()));
+                    continue;
+                }
+            };
+            while i < prev.len() && beg < end {
+                let el_len = match prev[i] {
+                    DeltaElement::Copy(b, e) => e - b,
+                    DeltaElement::Insert(ref n) => n.len(),
+                };
+                if pos + el_len <= beg {
+                    pos += el_len;
+                    i += 1;
+                    continue;
+                }
+                let lo = beg - pos;
+                let hi = min(end - pos, el_len);
+                match prev[i] {
+                    DeltaElement::Copy(b, _) => {
+                        let merged = if let Some(&mut DeltaElement::Copy(_, ref mut le)) = out.last_mut() {
+                            if *le == b + lo {
+                                *le = b + hi;
+                                true
+                            } else {
+                                false
+                            }
+                        } else {
+                            false
+                        };
+                        if !merged {
+                            out.push(DeltaElement::Copy(b + lo, b + hi));
+                        }
+                    }
+                    DeltaElement::Insert(ref n) => {
+                        if lo == 0 && hi == el_len {
+                            out.push(DeltaElement::Insert(n.clone()));
+                        } else {
+                            out.push(DeltaElement::Insert(n.subseq(Interval::new_closed_open(lo, hi))));
+                        }
+                    }
+                }
+                if hi < el_len {
+                    // the rest of this element may be copied by a later range
+                    break;
+                }
+                beg = pos + hi;
+                pos += el_len;
+                i += 1;
+            }
+        }
+    }
+
     /// Returns the length of the new document. In other words, the length of
     /// the transformed string after this Delta is applied.
     ///
@@ -524,6 +610,17 @@ impl<'a, N: NodeInfo + 'a> Transformer<'a, N> {
         result
     }
 
+    /// Transform both endpoints of a range. The `after` parameter applies to
+    /// both endpoints, as in `transform`. A collapsed range (`start == end`)
+    /// is transformed as a single coordinate, so it always stays collapsed.
+    pub fn transform_range(&mut self, start: usize, end: usize, after: bool) -> (usize, usize) {
+        let new_start = self.transform(start, after);
+        if start == end {
+            return (new_start, new_start);
+        }
+        (new_start, self.transform(end, after))
+    }
+
     /// Determine whether a given interval is untouched by the transformation.
     pub fn interval_untouched(&mut self, iv: Interval) -> bool {
         let mut last_was_ins = true;
@@ -693,7 +790,7 @@ impl<'a, N: NodeInfo> Iterator for DeletionsIter<'a, N> {
 mod tests {
     use serde_json;
     use rope::{Rope, RopeInfo};
-    use delta::{Delta, Builder, DeltaRegion};
+    use delta::{Delta, Builder, DeltaRegion, Transformer};
     use interval::Interval;
     use test_helpers::find_deletions;
 
@@ -767,6 +864,60 @@ mod tests {
         assert_eq!("356789+ABCx", d4.apply_to_string(str2));
     }
 
+    #[test]
+    fn compose_many() {
+        let s = "hello world";
+        let d1 = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        let mut builder = Builder::new(6);
+        builder.replace(Interval::new_closed_open(0, 0), Rope::from("the "));
+        builder.delete(Interval::new_closed_open(2, 4));
+        let d2 = builder.build();
+        let mut builder = Builder::new(8);
+        builder.replace(Interval::new_closed_open(2, 5), Rope::from("ERE"));
+        builder.replace(Interval::new_closed_open(8, 8), Rope::from("!"));
+        let d3 = builder.build();
+
+        let step = d3.apply_to_string(&d2.apply_to_string(&d1.apply_to_string(s)));
+        assert_eq!("thEREeld!", step);
+        let composed = Delta::compose_many(&[d1.clone(), d2.clone(), d3.clone()]);
+        assert_eq!(s.len(), composed.base_len);
+        assert_eq!(step, composed.apply_to_string(s));
+        assert_eq!(step.len(), composed.new_document_len());
+
+        assert_eq!(d1.apply_to_string(s), Delta::compose_many(&[d1.clone()]).apply_to_string(s));
+        let identity = Builder::new(d1.new_document_len()).build();
+        assert_eq!(d1.apply_to_string(s),
+                   Delta::compose_many(&[d1, identity]).apply_to_string(s));
+    }
+
+    #[test]
+    fn compose_many_deletes_everything() {
+        let d1 = Delta::simple_edit(Interval::new_closed_open(0, 3), Rope::from("xyz"), 10);
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0, 10), Rope::from(""), 10);
+        let d3 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("new"), 0);
+        let composed = Delta::compose_many(&[d1, d2, d3]);
+        assert_eq!("new", composed.apply_to_string("0123456789"));
+    }
+
+    #[test]
+    fn transform_range() {
+        let mut builder = Builder::new(10);
+        builder.replace(Interval::new_closed_open(2, 2), Rope::from("ab"));
+        builder.delete(Interval::new_closed_open(4, 6));
+        let delta = builder.build();
+        assert_eq!("01ab236789", delta.apply_to_string("0123456789"));
+
+        let mut xform = Transformer::new(&delta);
+        assert_eq!((0, 8), xform.transform_range(0, 8, false));
+        assert_eq!((2, 8), xform.transform_range(2, 8, false));
+        assert_eq!((4, 8), xform.transform_range(2, 8, true));
+        assert_eq!((6, 6), xform.transform_range(4, 6, false));
+        // collapsed ranges stay collapsed
+        assert_eq!((2, 2), xform.transform_range(2, 2, false));
+        assert_eq!((4, 4), xform.transform_range(2, 2, true));
+        assert_eq!((6, 6), xform.transform_range(5, 5, true));
+    }
+
     #[test]
     fn iter_inserts() {
         let mut builder = Builder::new(10);
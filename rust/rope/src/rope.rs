@@ -609,6 +609,22 @@ impl Rope {
         }
     }
 
+    /// An iterator over the raw lines, as `&str` slices borrowed from the
+    /// rope's storage. Unlike `lines_raw`, this never allocates.
+    ///
+    /// A line that spans multiple leaves is yielded as several fragments;
+    /// every fragment but the first has `is_continuation` set, so callers
+    /// can concatenate them if needed.
+    pub fn lines_raw_fragments<T>(&self, range: T) -> LinesRawFragments
+        where T: RangeBounds<usize>
+    {
+        LinesRawFragments {
+            inner: self.iter_chunks(range),
+            fragment: "",
+            in_line: false,
+        }
+    }
+
     /// An iterator over the lines of a rope.
     ///
     /// Lines are ended with either Unix (`\n`) or MS-DOS (`\r\n`) style line endings.
@@ -634,6 +650,23 @@ impl Rope {
         leaf.as_bytes()[pos]
     }
 
+    /// Returns the codepoint starting at byte offset `offset`, or `None` if
+    /// `offset` is out of bounds or not on a codepoint boundary.
+    ///
+    /// Time complexity: O(log n)
+    pub fn char_at(&self, offset: usize) -> Option<char> {
+        if offset >= self.len() {
+            return None;
+        }
+        let cursor = Cursor::new(self, offset);
+        let (leaf, pos) = cursor.get_leaf()?;
+        if !leaf.is_char_boundary(pos) {
+            return None;
+        }
+        // leaves are always split on codepoint boundaries
+        leaf[pos..].chars().next()
+    }
+
     pub fn slice_to_cow<T>(&self, range: T) -> Cow<str>
         where T: RangeBounds<usize>
     {
@@ -925,6 +958,40 @@ impl<'a> Iterator for LinesRaw<'a> {
     }
 }
 
+/// A borrowed piece of a raw line, yielded by `LinesRawFragments`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineFragment<'a> {
+    pub text: &'a str,
+    /// `true` if this fragment continues the line of the previous fragment.
+    pub is_continuation: bool,
+}
+
+pub struct LinesRawFragments<'a> {
+    inner: ChunkIter<'a>,
+    fragment: &'a str,
+    /// Whether the last fragment yielded did not end its line.
+    in_line: bool,
+}
+
+impl<'a> Iterator for LinesRawFragments<'a> {
+    type Item = LineFragment<'a>;
+
+    fn next(&mut self) -> Option<LineFragment<'a>> {
+        while self.fragment.is_empty() {
+            self.fragment = self.inner.next()?;
+        }
+        let split = match memchr(b'\n', self.fragment.as_bytes()) {
+            Some(i) => i + 1,
+            None => self.fragment.len(),
+        };
+        let text = &self.fragment[..split];
+        self.fragment = &self.fragment[split..];
+        let is_continuation = self.in_line;
+        self.in_line = !text.ends_with('\n');
+        Some(LineFragment { text, is_continuation })
+    }
+}
+
 pub struct Lines<'a> {
     inner: LinesRaw<'a>,
 }
@@ -985,6 +1052,20 @@ mod tests {
         assert_eq!(0, a.lines_raw(..).count());
     }
 
+    #[test]
+    fn lines_raw_fragments_small() {
+        let a = Rope::from("a\nb\nc");
+        assert_eq!(vec!["a\n", "b\n", "c"],
+                   a.lines_raw_fragments(..).map(|f| f.text).collect::<Vec<_>>());
+        assert!(a.lines_raw_fragments(..).all(|f| !f.is_continuation));
+
+        let a = Rope::from("\n");
+        assert_eq!(vec!["\n"], a.lines_raw_fragments(..).map(|f| f.text).collect::<Vec<_>>());
+
+        let a = Rope::from("");
+        assert_eq!(0, a.lines_raw_fragments(..).count());
+    }
+
     #[test]
     fn lines_small() {
         let a = Rope::from("a\nb\nc");
@@ -1035,6 +1116,11 @@ mod tests {
         //println!("{:?}", r.iter_chunks().collect::<Vec<_>>());
 
         assert_eq!(vec![a.as_str(), b.as_str()], r.lines_raw(..).collect::<Vec<_>>());
+        let frags = r.lines_raw_fragments(..).collect::<Vec<_>>();
+        assert_eq!(frags.len(), 4);
+        assert_eq!(frags.iter().map(|f| f.is_continuation).collect::<Vec<_>>(),
+                   vec![false, true, false, true]);
+        assert_eq!(frags.iter().map(|f| f.text).collect::<String>(), String::from(&r));
         assert_eq!(vec![&a[..line_len], &b[..line_len]], r.lines(..).collect::<Vec<_>>());
         assert_eq!(String::from(&r).lines().collect::<Vec<_>>(),
                    r.lines(..).collect::<Vec<_>>());
@@ -1090,6 +1176,29 @@ mod tests {
         assert_eq!(None, b.next_codepoint_offset(9));
     }
 
+    #[test]
+    fn char_at_small() {
+        let a = Rope::from("a\u{00A1}\u{4E00}\u{1F4A9}");
+        assert_eq!(Some('a'), a.char_at(0));
+        assert_eq!(Some('\u{00A1}'), a.char_at(1));
+        assert_eq!(None, a.char_at(2));
+        assert_eq!(Some('\u{4E00}'), a.char_at(3));
+        assert_eq!(Some('\u{1F4A9}'), a.char_at(6));
+        assert_eq!(None, a.char_at(7));
+        assert_eq!(None, a.char_at(10));
+        assert_eq!(None, a.char_at(11));
+        assert_eq!(None, Rope::from("").char_at(0));
+    }
+
+    #[test]
+    fn char_at_leaf_boundaries() {
+        let s = "ab\u{4E00}".repeat(500);
+        let a = Rope::from(&s);
+        for (i, c) in s.char_indices() {
+            assert_eq!(Some(c), a.char_at(i));
+        }
+    }
+
     #[test]
     fn prev_grapheme_offset() {
         // A with ring, hangul, regional indicator "US"
@@ -309,6 +309,14 @@ impl<T: Clone + Default> Spans<T> {
         }
         *self = b.build();
     }
+
+    /// Returns the data of the span containing `offset`, if any, without
+    /// the allocation of taking a `subseq`.
+    pub fn data_at(&self, offset: usize) -> Option<&T> {
+        let cursor = Cursor::new(self, offset);
+        let (leaf, offset) = cursor.get_leaf()?;
+        leaf.spans.iter().find(|span| span.iv.contains(offset)).map(|span| &span.data)
+    }
 }
 
 impl<T: Clone + Default + fmt::Debug> fmt::Debug for Spans<T> {
@@ -437,4 +445,20 @@ mod tests {
 
         assert!(merged_iter.next().is_none());
     }
+
+    #[test]
+    fn data_at() {
+        // enough spans to fill several leaves
+        let mut sb = SpansBuilder::new(300);
+        for i in 0..100 {
+            sb.add_span(Interval::new_closed_open(i * 3, i * 3 + 2), i);
+        }
+        let spans = sb.build();
+        assert_eq!(spans.data_at(0), Some(&0));
+        assert_eq!(spans.data_at(1), Some(&0));
+        assert_eq!(spans.data_at(2), None);
+        assert_eq!(spans.data_at(250), Some(&83));
+        assert_eq!(spans.data_at(299), None);
+        assert_eq!(spans.data_at(300), None);
+    }
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use std::io;
+use std::process;
 
 #[macro_use]
 extern crate log;
@@ -22,6 +23,7 @@ extern crate xi_core_lib;
 extern crate xi_rpc;
 
 use xi_core_lib::XiCore;
+use xi_core_lib::lsp_server;
 use xi_rpc::RpcLoop;
 
 fn setup_logging() -> Result<(), fern::InitError> {
@@ -53,6 +55,11 @@ fn setup_logging() -> Result<(), fern::InitError> {
 }
 
 fn main() {
+    if std::env::args().skip(1).any(|arg| arg == "--lsp") {
+        run_lsp_server();
+        return;
+    }
+
     let mut state = XiCore::new();
     let stdin = io::stdin();
     let stdout = io::stdout();
@@ -67,3 +74,21 @@ fn main() {
         Err(err) => error!("xi-core exited with error:\n{:?}", err),
     }
 }
+
+/// Speaks the Language Server Protocol on stdio, instead of the xi protocol.
+fn run_lsp_server() {
+    if let Err(e) = setup_logging() {
+        eprintln!("[ERROR] setup_logging returned error, logging disabled: {:?}", e);
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let result = lsp_server::run(stdin.lock(), stdout.lock());
+    match result {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            error!("xi-core exited with error:\n{:?}", err);
+            process::exit(1);
+        }
+    }
+}
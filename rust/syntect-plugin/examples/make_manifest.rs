@@ -60,6 +60,8 @@ fn main() -> Result<(), io::Error> {
         activations: vec![PluginActivation::Autorun],
         commands: vec![],
         languages: lang_defs,
+        resource_limits: Default::default(),
+        restart_policy: Default::default(),
     };
 
 	let toml_str = toml::to_string(&mani).unwrap();
@@ -125,8 +125,12 @@ impl PluginState {
         }
     }
 
-    #[allow(unused)]
-    // Return true if there's any more work to be done.
+    /// Highlights the line at the frontier, which the state cache moves back
+    /// to the first line touched by an edit. Highlighting goes on downward
+    /// only until the state at the start of a line is the one cached from
+    /// before, after which the old spans are still valid.
+    ///
+    /// Returns `true` if there's any more work to be done.
     fn highlight_one_line(&mut self, ctx: &mut MyView) -> bool {
         if let Some(line_num) = ctx.get_frontier() {
             let (line_num, offset, state) = ctx.get_prev(line_num);
@@ -150,8 +154,10 @@ impl PluginState {
             };
             let mut converged = false;
             if let Some((ref new_state, new_line_num)) = new_frontier {
+                // the scope stack is compared too: the parse state alone
+                // doesn't determine which scopes are open at the line start
                 if let Some(old_state) = ctx.get(new_line_num) {
-                    converged = old_state.as_ref().unwrap().0 == new_state.as_ref().unwrap().0;
+                    converged = old_state == new_state;
                 }
             }
             if !converged {